@@ -3,6 +3,7 @@ import importlib.util
 import os
 import pickle
 import runpy
+import threading
 
 import pytest
 
@@ -93,3 +94,61 @@ def dummy_func():
 
     with pytest.raises(RuntimeError):
         runpy.run_path(call_serializer_file, init_globals={"func": dummy_func})
+
+
+def test_pickle_protocol(dummy_module, call_serializer_file):
+    result = runpy.run_path(
+        call_serializer_file,
+        init_globals={"func": dummy_module.dummy_func, "args": None, "pickle_protocol": 2},
+    )
+
+    pickled_bytes = base64.b64decode(result["pickled_data"])
+    assert pickled_bytes[:2] == b"\x80\x02"
+    assert pickle.loads(pickled_bytes)["func_name"] == "dummy_func"
+
+
+def test_cloudpickle_closure(call_serializer_file):
+    """
+    Closures can't be looked up by name in their module, so with cloudpickle they're sent by
+    value and the child entrypoint runs them directly.
+
+    """
+    pytest.importorskip("cloudpickle")
+
+    offset = 40
+
+    def add_offset(value: int) -> int:
+        return value + offset
+
+    result = runpy.run_path(
+        call_serializer_file,
+        init_globals={"func": add_offset, "args": (2,), "use_cloudpickle": True},
+    )
+
+    spec = importlib.util.find_spec("firehot.embedded.child_entrypoint")
+    assert spec is not None and spec.origin is not None
+    child_globals = runpy.run_path(
+        spec.origin, init_globals={"pickled_str": result["pickled_data"]}
+    )
+    assert child_globals["result"] == 42
+
+
+def test_unserializable_call_lists_culprits(dummy_module, call_serializer_file):
+    lock = threading.Lock()
+
+    with pytest.raises(TypeError) as exc_info:
+        runpy.run_path(
+            call_serializer_file,
+            init_globals={"func": dummy_module.dummy_func, "args": ("fine", lock)},
+        )
+
+    message = str(exc_info.value)
+    assert "args[1] (lock)" in message
+    assert "args[0]" not in message
+
+    # Closures need cloudpickle, which the error points out
+    def closure():
+        return lock
+
+    with pytest.raises(TypeError, match="func \\(function\\).*cloudpickle"):
+        runpy.run_path(call_serializer_file, init_globals={"func": closure, "args": None})
@@ -53,6 +53,13 @@ def test_exception_in_child_process(import_runner: Environment):
     assert "This is a deliberate test exception" in str(excinfo.value)
 
 
+def test_last_boot_duration(import_runner: Environment):
+    """Test that the time the environment took to boot is exposed."""
+    duration = import_runner.last_boot_duration()
+    assert duration is not None
+    assert duration > 0
+
+
 def test_stop_isolated(import_runner: Environment):
     """Test that we can stop an isolated process."""
 
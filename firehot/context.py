@@ -42,7 +42,13 @@ def resolve_package_metadata(package: str) -> tuple[str, str]:
 
 
 @contextmanager
-def isolate_imports(package: str, *, ignored_modules: list[str] | None = None):
+def isolate_imports(
+    package: str,
+    *,
+    ignored_modules: list[str] | None = None,
+    pickle_protocol: int | None = None,
+    use_cloudpickle: bool = False,
+):
     """
     Context manager that isolates imports for the given package path.
 
@@ -50,6 +56,10 @@ def isolate_imports(package: str, *, ignored_modules: list[str] | None = None):
                     virtual environment
     :param ignored_modules: Optional list of module names to ignore during hot reloading.
                           Changes to these modules will not trigger reloads.
+    :param pickle_protocol: Pickle protocol for calls sent to isolated processes. Defaults to
+                            the interpreter's default protocol.
+    :param use_cloudpickle: Serialize calls with cloudpickle when it's installed, which also
+                            handles closures and lambdas
     :yields: An Environment object that can be used to execute code in the isolated environment
 
     """
@@ -57,7 +67,9 @@ def isolate_imports(package: str, *, ignored_modules: list[str] | None = None):
     runner_id: str | None = None
     try:
         runner_id = start_import_runner_rs(package_name, package_path, ignored_modules)
-        yield Environment(runner_id)
+        yield Environment(
+            runner_id, pickle_protocol=pickle_protocol, use_cloudpickle=use_cloudpickle
+        )
     finally:
         if runner_id:
             stop_import_runner_rs(runner_id)
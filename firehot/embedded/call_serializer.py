@@ -19,6 +19,27 @@ if TYPE_CHECKING:
         pass
 
     args = (0,)
+    pickle_protocol: int | None = None
+    use_cloudpickle = False
+
+# Optional settings injected alongside func and args. The defaults match a plain `pickle.dumps`.
+try:
+    pickle_protocol
+except NameError:
+    pickle_protocol = None
+try:
+    use_cloudpickle
+except NameError:
+    use_cloudpickle = False
+
+# cloudpickle can serialize closures and lambdas by value. It's optional, so we fall back to
+# the stdlib when it isn't installed.
+serializer = pickle
+if use_cloudpickle:
+    try:
+        import cloudpickle as serializer
+    except ImportError:
+        serializer = pickle
 
 func_module_path_raw = None
 func_file_path = "null"
@@ -27,7 +48,7 @@ if hasattr(func, "__module__"):
     module_name = func.__module__
     if module_name != "__main__":
         func_module_path_raw = module_name
-    else:
+    elif serializer is pickle:
         # Handle functions from directly executed scripts
         try:
             # Get the file where the function is defined
@@ -47,9 +68,43 @@ payload: "SerializedCall" = {
     "args": args,
 }
 
+# Functions the child can't look up by name (closures, lambdas, functions of a script) have to
+# travel by value. Plain pickle refuses these, which is reported below.
+if func_module_path_raw is None or "<" in func.__qualname__:
+    payload["func"] = func
+
+try:
+    pickled_bytes = serializer.dumps(payload, protocol=pickle_protocol)
+except Exception as serialize_error:
+    # Pin down which parts of the call couldn't be serialized
+    candidates = []
+    if "func" in payload:
+        candidates.append(("func", func))
+    if isinstance(args, tuple):
+        for arg_index, arg in enumerate(args):
+            candidates.append((f"args[{arg_index}]", arg))
+    elif args is not None:
+        candidates.append(("args", args))
+
+    problems = []
+    for label, value in candidates:
+        try:
+            serializer.dumps(value, protocol=pickle_protocol)
+        except Exception as value_error:
+            problems.append(f"{label} ({type(value).__name__}): {value_error}")
+    if not problems:
+        problems.append(str(serialize_error))
+
+    hint = ""
+    if serializer is pickle:
+        hint = " Closures and lambdas can be serialized with cloudpickle installed and enabled."
+    raise TypeError(
+        f"Could not serialize the call to {func.__qualname__}: {'; '.join(problems)}.{hint}"
+    ) from serialize_error
+
 #
 # Exports
 # These variables are outputted into the local scope and read by Rust
 #
 
-pickled_data = base64.b64encode(pickle.dumps(payload)).decode("utf-8")
+pickled_data = base64.b64encode(pickled_bytes).decode("utf-8")
@@ -20,7 +20,7 @@ if TYPE_CHECKING:
     module_path = "path"
     pickled_str = "pickled_str"
 
-# These will imported dynamically by rust
+# Injected into our globals by the loader from the ForkRequest payload
 module_path: str
 pickled_str: str
 
@@ -52,7 +52,10 @@ data: "SerializedCall" = pickle.loads(pickled_bytes)
 # Technically we could just unpickle the data and pickle will automatically try to resolve the module, but
 # this lets us more explicitly handle errors and issue debugging logs.
 module_path = data["func_module_path"]
-if module_path:
+if data.get("func") is not None:
+    # Closures and lambdas were pickled by value, so there's nothing to look up
+    firehot_logger.info(f"Using function pickled by value: {data['func_qualname']}")
+elif module_path:
     firehot_logger.info(f"Importing module: {module_path}")
     sys.stdout.flush()
     # Try to import the module or reload it if already imported
@@ -64,7 +67,7 @@ else:
     raise Exception("No module path provided")
 
 # Resolve the function from the module
-func = getattr(sys.modules[module_path], data["func_name"])
+func = data.get("func") or getattr(sys.modules[module_path], data["func_name"])
 args = data["args"]
 
 # Run the function with args
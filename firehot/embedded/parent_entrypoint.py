@@ -6,21 +6,31 @@ Intended for embeddable usage in Rust, can only import stdlib modules.
 
 """
 
+import atexit
 import errno
 import fcntl
+import importlib
+import importlib.machinery
 import logging
 import os
+import resource
 import select
+import signal
+import struct
+import subprocess
 import sys
 import threading
+from base64 import b64encode
+from collections import deque
 from contextlib import contextmanager
-from dataclasses import asdict, dataclass
+from dataclasses import asdict, dataclass, field
 from json import dumps as json_dumps
 from json import loads as json_loads
 from json.decoder import JSONDecodeError
 from os import getenv
 from sys import _current_frames
-from time import sleep
+from tempfile import mkstemp
+from time import perf_counter, sleep
 from traceback import format_exc, format_stack
 
 from firehot.firehot import get_total_thread_count
@@ -39,17 +49,29 @@ except ImportError:
 # Messages
 #
 
+# Must match PROTOCOL_VERSION on the Rust side. Bump both when a message changes incompatibly.
+PROTOCOL_VERSION = 2
+
 
 class MessageType(StrEnum):
     FORK_REQUEST = "FORK_REQUEST"
     FORK_RESPONSE = "FORK_RESPONSE"
     CHILD_COMPLETE = "CHILD_COMPLETE"
     CHILD_ERROR = "CHILD_ERROR"
+    CHILD_EXITED = "CHILD_EXITED"
+    OUTPUT_CLOSED = "OUTPUT_CLOSED"
     UNKNOWN_COMMAND = "UNKNOWN_COMMAND"
     UNKNOWN_ERROR = "UNKNOWN_ERROR"
     IMPORT_ERROR = "IMPORT_ERROR"
     IMPORT_COMPLETE = "IMPORT_COMPLETE"
+    MODULE_IMPORTED = "MODULE_IMPORTED"
+    IMPORT_PROGRESS = "IMPORT_PROGRESS"
+    HELLO = "HELLO"
+    PING = "PING"
+    PONG = "PONG"
     EXIT_REQUEST = "EXIT_REQUEST"
+    RELOAD_REQUEST = "RELOAD_REQUEST"
+    RELOAD_RESPONSE = "RELOAD_RESPONSE"
 
 
 class MessageBase:
@@ -64,6 +86,10 @@ class ForkRequest(MessageBase):
     request_id: str
     code: str
     request_name: str
+    pickled_data: str | None = None
+    env: dict[str, str] = field(default_factory=dict)
+    # Seconds the function may run before `ForkTimeoutError` is raised inside it
+    timeout: float | None = None
     name: MessageType = MessageType.FORK_REQUEST
 
 
@@ -72,6 +98,22 @@ class ExitRequest(MessageBase):
     name: MessageType = MessageType.EXIT_REQUEST
 
 
+@dataclass
+class Ping(MessageBase):
+    request_id: str
+
+    name: MessageType = MessageType.PING
+
+
+@dataclass
+class ReloadRequest(MessageBase):
+    request_id: str
+    paths: list[str]
+    in_place: bool
+
+    name: MessageType = MessageType.RELOAD_REQUEST
+
+
 # Responses
 
 
@@ -85,9 +127,39 @@ class ForkResponse(MessageBase):
     name: MessageType = MessageType.FORK_RESPONSE
 
 
+@dataclass
+class ResourceUsage:
+    user_time: float
+    system_time: float
+    max_rss_bytes: int
+
+
+@dataclass
+class Pong(MessageBase):
+    request_id: str
+
+    name: MessageType = MessageType.PONG
+
+
+@dataclass
+class ReloadResponse(MessageBase):
+    request_id: str
+    modules: list[str]
+    reloaded: bool
+    error: str | None = None
+
+    name: MessageType = MessageType.RELOAD_RESPONSE
+
+
 @dataclass
 class ChildComplete(MessageBase):
     result: str | None
+    result_json: str | None = None
+    # A small `bytes` result, base64 encoded
+    result_bytes: str | None = None
+    # File holding a large `bytes` result, removed by the Rust side once read
+    result_path: str | None = None
+    rusage: ResourceUsage | None = None
 
     name: MessageType = MessageType.CHILD_COMPLETE
 
@@ -96,10 +168,28 @@ class ChildComplete(MessageBase):
 class ChildError(MessageBase):
     error: str
     traceback: str | None
+    rusage: ResourceUsage | None = None
+    # "exception" when the function raised, "timeout" when it ran past its timeout, and
+    # "interrupted" when it didn't handle the interrupt from `finish_isolated`
+    kind: str = "exception"
 
     name: MessageType = MessageType.CHILD_ERROR
 
 
+@dataclass
+class ChildExited(MessageBase):
+    child_pid: int
+    exit_code: int | None
+    signal: int | None
+
+    name: MessageType = MessageType.CHILD_EXITED
+
+
+@dataclass
+class OutputClosed(MessageBase):
+    name: MessageType = MessageType.OUTPUT_CLOSED
+
+
 @dataclass
 class UnknownCommandError(MessageBase):
     command: str
@@ -119,6 +209,7 @@ class UnknownError(MessageBase):
 class ImportError(MessageBase):
     error: str
     traceback: str | None
+    module: str | None = None
 
     name: MessageType = MessageType.IMPORT_ERROR
 
@@ -128,26 +219,291 @@ class ImportComplete(MessageBase):
     name: MessageType = MessageType.IMPORT_COMPLETE
 
 
+@dataclass
+class Hello(MessageBase):
+    protocol_version: int
+
+    name: MessageType = MessageType.HELLO
+
+
+@dataclass
+class ModuleImported(MessageBase):
+    module: str
+    # Wall time spent in this module's import, excluding dependencies already loaded by an
+    # earlier module in the preload list
+    duration_ms: float = 0.0
+
+    name: MessageType = MessageType.MODULE_IMPORTED
+
+
+@dataclass
+class ImportProgress(MessageBase):
+    module: str
+    # Position of the module in this batch of imports, counting from zero
+    index: int
+    total: int
+
+    name: MessageType = MessageType.IMPORT_PROGRESS
+
+
 MESSAGES = {
     MessageType.FORK_REQUEST: ForkRequest,
     MessageType.FORK_RESPONSE: ForkResponse,
     MessageType.CHILD_COMPLETE: ChildComplete,
     MessageType.CHILD_ERROR: ChildError,
+    MessageType.CHILD_EXITED: ChildExited,
+    MessageType.OUTPUT_CLOSED: OutputClosed,
     MessageType.UNKNOWN_COMMAND: UnknownCommandError,
     MessageType.UNKNOWN_ERROR: UnknownError,
     MessageType.IMPORT_ERROR: ImportError,
     MessageType.IMPORT_COMPLETE: ImportComplete,
+    MessageType.MODULE_IMPORTED: ModuleImported,
+    MessageType.IMPORT_PROGRESS: ImportProgress,
+    MessageType.HELLO: Hello,
+    MessageType.PING: Ping,
+    MessageType.PONG: Pong,
     MessageType.EXIT_REQUEST: ExitRequest,
+    MessageType.RELOAD_REQUEST: ReloadRequest,
+    MessageType.RELOAD_RESPONSE: ReloadResponse,
 }
 
 
-def write_message(message: MessageBase):
-    sys.stdout.write(f"{json_dumps(asdict(message))}\n")
+#
+# Framing
+#
+
+# "ndjson" (default) or "length_prefixed", chosen by the Rust side when it spawns the loader
+MESSAGE_FRAMING = getenv("FIREHOT_MESSAGE_FRAMING", "ndjson")
+
+# "error" (default) exits after reporting failed preloads, "warn" keeps serving without them
+IMPORT_FAILURE_POLICY = getenv("FIREHOT_IMPORT_FAILURES", "error")
+
+# JSON list of modules imported before reporting ready. The other preloads are imported in a
+# background thread afterwards. Empty imports everything up front.
+CRITICAL_MODULES = getenv("FIREHOT_CRITICAL_MODULES", "")
+
+# Prefix tagging each line a fork writes with its PID and stream, as a `str.format` template.
+# The Rust side parses lines back with the same template.
+MULTIPLEX_FORMAT = getenv("FIREHOT_MULTIPLEX_FORMAT", "[PID:{pid}:{stream}]")
+
+# "fork" (default) runs each call in a fork of the loader, "spawn" in a fresh interpreter for
+# platforms without `os.fork` or C extensions that don't survive one
+EXECUTION_MODE = getenv("FIREHOT_EXECUTION_MODE", "fork")
+
+LENGTH_PREFIX = struct.Struct(">I")
+
+# Binary results up to this size are sent inline, base64 encoded. Larger ones are written to a
+# temporary file, since messages have a length limit on the Rust side.
+INLINE_RESULT_BYTES = 64 * 1024
+
+# Private directory for those result files. Rust only reads results back from here.
+RESULT_DIR = getenv("FIREHOT_RESULT_DIR")
+
+# Private duplicate of the original stdout that carries length-prefixed frames. See
+# `setup_protocol_channel` for why we don't write frames to fd 1 directly.
+PROTOCOL_FD: int | None = None
+# Spawned calls run this script in their own process, but still report as children of the loader
+LOADER_PID = int(getenv("FIREHOT_LOADER_PID") or os.getpid())
+
+
+def setup_protocol_channel() -> None:
+    """
+    In length-prefixed mode every byte on the protocol stream has to belong to a frame. Imports
+    and user code can print (or write to fd 1 from C), so we keep a private duplicate of stdout
+    for our frames and point fd 1 at stderr, where stray output is treated as plain log lines.
+
+    """
+    global PROTOCOL_FD
+
+    if MESSAGE_FRAMING != "length_prefixed" or PROTOCOL_FD is not None:
+        return
+
     sys.stdout.flush()
+    PROTOCOL_FD = os.dup(1)
+    os.dup2(2, 1)
+
+
+# Forks share the loader's output streams. Writes larger than PIPE_BUF aren't atomic, so
+# concurrent forks could interleave their frames and lines. Every write to a shared stream holds
+# an exclusive flock on this file, which the kernel drops if the holder dies mid-write.
+PROTOCOL_LOCK_PATH: str | None = None
+# The lock file as opened by this process, along with its PID. flock locks belong to an open
+# file, so a descriptor inherited over fork would be shared with the loader instead of locking
+# against it.
+PROTOCOL_LOCK_FD: tuple[int, int] | None = None
+# flock doesn't exclude threads of the same process from each other
+PROTOCOL_THREAD_LOCK = threading.Lock()
+
 
+def setup_protocol_lock() -> None:
+    global PROTOCOL_LOCK_PATH
 
-def read_message() -> MessageBase | None:
-    line = sys.stdin.readline().strip()
+    fd, PROTOCOL_LOCK_PATH = mkstemp(prefix="firehot-lock-")
+    os.close(fd)
+    atexit.register(remove_protocol_lock, os.getpid())
+
+
+def remove_protocol_lock(owner_pid: int) -> None:
+    # Forks run atexit handlers too, but only the loader should clean up
+    if os.getpid() != owner_pid or PROTOCOL_LOCK_PATH is None:
+        return
+    try:
+        os.unlink(PROTOCOL_LOCK_PATH)
+    except FileNotFoundError:
+        pass
+
+
+def reset_protocol_thread_lock() -> None:
+    # Another thread may have held the lock at the time of the fork, and it won't release it
+    # in the child
+    global PROTOCOL_THREAD_LOCK
+
+    PROTOCOL_THREAD_LOCK = threading.Lock()
+
+
+os.register_at_fork(after_in_child=reset_protocol_thread_lock)
+
+
+@contextmanager
+def protocol_write_lock():
+    """
+    Hold a shared stream for one whole write, against other threads and other forks.
+
+    """
+    global PROTOCOL_LOCK_FD
+
+    with PROTOCOL_THREAD_LOCK:
+        if PROTOCOL_LOCK_PATH is None:
+            yield
+            return
+
+        if PROTOCOL_LOCK_FD is None or PROTOCOL_LOCK_FD[0] != os.getpid():
+            PROTOCOL_LOCK_FD = (os.getpid(), os.open(PROTOCOL_LOCK_PATH, os.O_RDWR))
+        lock_fd = PROTOCOL_LOCK_FD[1]
+
+        fcntl.flock(lock_fd, fcntl.LOCK_EX)
+        try:
+            yield
+        finally:
+            fcntl.flock(lock_fd, fcntl.LOCK_UN)
+
+
+def write_all(fd: int, data: bytes) -> None:
+    while data:
+        written = os.write(fd, data)
+        data = data[written:]
+
+
+def write_frame(payload: bytes) -> None:
+    assert PROTOCOL_FD is not None
+    with protocol_write_lock():
+        write_all(PROTOCOL_FD, LENGTH_PREFIX.pack(len(payload)) + payload)
+
+
+def write_message(message: MessageBase):
+    with deferring_child_exits():
+        with WRITE_LOCK:
+            send_message(message)
+
+
+@contextmanager
+def deferring_child_exits():
+    """
+    Keep the SIGCHLD handler from reporting while we write. It would block on the write locks
+    we're holding, so the exits it collects are reported once we're done instead.
+
+    """
+    global WRITING_MESSAGE
+
+    # Signal handlers only run on the main thread, so only its writes can be interrupted by
+    # one. Writes from the background imports are kept whole by the lock instead.
+    on_main_thread = threading.current_thread() is threading.main_thread()
+    if on_main_thread:
+        WRITING_MESSAGE = True
+    try:
+        yield
+    finally:
+        if on_main_thread:
+            WRITING_MESSAGE = False
+
+    # Report forks that were reaped while the write above was in progress
+    if on_main_thread and PENDING_CHILD_EXITS and os.getpid() == LOADER_PID:
+        flush_child_exits()
+
+
+def send_message(message: MessageBase):
+    payload = json_dumps(asdict(message))
+
+    if PROTOCOL_FD is None:
+        if os.getpid() != LOADER_PID:
+            # A fork's stdout is its own pipe to the multiplexer, which locks when relaying.
+            # Holding the lock here could block the relay while the pipe is full.
+            sys.stdout.write(f"{payload}\n")
+            sys.stdout.flush()
+            return
+
+        with protocol_write_lock():
+            sys.stdout.write(f"{payload}\n")
+            sys.stdout.flush()
+        return
+
+    # Forked children write straight to the protocol channel instead of through the
+    # multiplexed stdout, so we tag the frame with the same prefix the multiplexer would add
+    if os.getpid() != LOADER_PID:
+        payload = f"{multiplex_prefix(os.getpid(), 'stdout')}{payload}"
+
+    write_frame(payload.encode())
+
+
+def multiplex_prefix(pid: int, stream_name: str) -> str:
+    return MULTIPLEX_FORMAT.format(pid=pid, stream=stream_name)
+
+
+# Bytes read from stdin that don't make up a whole frame yet. We buffer them ourselves rather
+# than going through sys.stdin, so `select` tells us the truth about whether a frame is waiting.
+STDIN_BUFFER = bytearray()
+
+
+def read_frame(timeout: float | None = None) -> str | None:
+    """
+    Read the next frame from stdin. Returns None at EOF, or when `timeout` passes first.
+
+    """
+    while True:
+        frame = take_buffered_frame()
+        if frame is not None:
+            return frame
+
+        if timeout is not None and not select.select([0], [], [], timeout)[0]:
+            return None
+        data = os.read(0, 65536)
+        if not data:
+            return None
+        STDIN_BUFFER.extend(data)
+
+
+def take_buffered_frame() -> str | None:
+    if MESSAGE_FRAMING != "length_prefixed":
+        end = STDIN_BUFFER.find(b"\n")
+        if end == -1:
+            return None
+        line = STDIN_BUFFER[:end]
+        del STDIN_BUFFER[: end + 1]
+        return line.decode().strip()
+
+    if len(STDIN_BUFFER) < LENGTH_PREFIX.size:
+        return None
+    (length,) = LENGTH_PREFIX.unpack_from(STDIN_BUFFER)
+    end = LENGTH_PREFIX.size + length
+    if len(STDIN_BUFFER) < end:
+        return None
+    payload = STDIN_BUFFER[LENGTH_PREFIX.size : end]
+    del STDIN_BUFFER[:end]
+    return payload.decode()
+
+
+def read_message(timeout: float | None = None) -> MessageBase | None:
+    line = read_frame(timeout)
     if not line:
         return None
 
@@ -170,6 +526,71 @@ def read_message() -> MessageBase | None:
     return MESSAGES[message_type](**payload)
 
 
+#
+# Child reaping
+#
+
+# Exit statuses collected by the SIGCHLD handler that haven't been reported yet. The handler
+# can interrupt a write_message call, so it only reports directly when no write is in progress.
+PENDING_CHILD_EXITS: list[ChildExited] = []
+WRITING_MESSAGE = False
+WRITE_LOCK = threading.Lock()
+
+# Popen objects of calls started by `spawn_call`, until the handler reaps them. A dropped Popen
+# that's still running lands in `subprocess._active`, whose cleanup would reap it behind our back.
+SPAWNED_CALLS: dict[int, subprocess.Popen] = {}
+
+
+def reap_children(signum=None, frame=None) -> None:
+    """
+    SIGCHLD handler for the loader. Forks are our children, so nobody else can wait on them,
+    and without this every finished fork would linger as a zombie.
+
+    """
+    while True:
+        try:
+            pid, status = os.waitpid(-1, os.WNOHANG)
+        except ChildProcessError:
+            break
+        if pid == 0:
+            break
+
+        spawned = SPAWNED_CALLS.pop(pid, None)
+        if spawned is not None:
+            # Already reaped, so the Popen must not try again
+            spawned.returncode = os.waitstatus_to_exitcode(status)
+
+        PENDING_CHILD_EXITS.append(
+            ChildExited(
+                child_pid=pid,
+                exit_code=os.WEXITSTATUS(status) if os.WIFEXITED(status) else None,
+                signal=os.WTERMSIG(status) if os.WIFSIGNALED(status) else None,
+            )
+        )
+
+    if not WRITING_MESSAGE:
+        flush_child_exits()
+
+
+def flush_child_exits() -> None:
+    while PENDING_CHILD_EXITS:
+        exited = PENDING_CHILD_EXITS.pop(0)
+        close_child_output(exited.child_pid)
+        write_message(exited)
+
+
+def close_child_output(pid: int) -> None:
+    """
+    Tell Rust it has seen all of a reaped child's output. Its stdout in newline mode arrives
+    ahead of the ChildExited message, but stderr is a separate pipe, so mark the end there too.
+    The child is gone, so everything it wrote is already ahead of the marker.
+
+    """
+    payload = json_dumps(asdict(OutputClosed()))
+    with deferring_child_exits(), protocol_write_lock():
+        write_all(2, f"{multiplex_prefix(pid, 'stderr')}{payload}\n".encode())
+
+
 #
 # Logging
 #
@@ -197,6 +618,8 @@ class MultiplexedStream:
         self.original_fd_dup = None
         self.monitor_thread = None
         self.active = False
+        # The start of a line that hasn't been terminated yet
+        self.partial_line = b""
         self._instances[stream_name] = self
 
     def start_redirection(self) -> None:
@@ -239,31 +662,58 @@ class MultiplexedStream:
                 if not ready:
                     continue
 
-                try:
-                    data = os.read(self.read_fd, 4096)
-                    if not data:  # EOF
-                        break
-
-                    # Format the data with PID and stream name
-                    formatted_data = b""
-                    for line in data.splitlines(True):  # Keep line endings
-                        if line.strip():  # Skip empty lines
-                            prefix = f"[PID:{self.pid}:{self.stream_name}]".encode()
-                            formatted_data += prefix + line
-
-                    # Write the formatted data to the original descriptor
-                    os.write(self.original_fd_dup, formatted_data)
-                except (IOError, OSError) as e:
-                    if e.errno != errno.EAGAIN:  # Not just a would-block error
-                        # Write error to original stdout for debugging
-                        error_msg = f"[ERROR] MultiplexedStream exception: {str(e)}\n".encode()
-                        os.write(self.original_fd_dup, error_msg)
+                if self._relay_available() is None:
+                    break
+
+            # Output written right before the stop, like the fork's result message, can still
+            # be sitting in the pipe when the loop sees the flag
+            while self._relay_available():
+                pass
+
+            if self.partial_line:
+                self._write_lines([self.partial_line + b"\n"])
+                self.partial_line = b""
         finally:
             # Only close the read_fd here; write_fd will be closed in stop_redirection
             if self.read_fd is not None:
                 os.close(self.read_fd)
                 self.read_fd = None
 
+    def _relay_available(self) -> bool | None:
+        """
+        Relay one read from the pipe. Returns False when the pipe is empty for now and None
+        once it's closed, or broken in a way retrying won't fix.
+
+        """
+        try:
+            data = os.read(self.read_fd, 4096)
+            if not data:  # EOF
+                return None
+
+            # Lines longer than a read arrive in pieces, but only get one prefix
+            *lines, self.partial_line = (self.partial_line + data).split(b"\n")
+            self._write_lines([line + b"\n" for line in lines])
+        except (IOError, OSError) as e:
+            if e.errno == errno.EAGAIN:  # Just a would-block error
+                return False
+            # Write error to original stdout for debugging. Errors like EBADF or EIO will
+            # recur on every read, so give up on the pipe instead of spinning on it.
+            error_msg = f"[ERROR] MultiplexedStream exception: {str(e)}\n".encode()
+            os.write(self.original_fd_dup, error_msg)
+            return None
+        return True
+
+    def _write_lines(self, lines: list[bytes]) -> None:
+        """Write complete lines to the original descriptor, tagged with our PID and stream."""
+        prefix = multiplex_prefix(self.pid, self.stream_name).encode()
+        formatted_data = b"".join(prefix + line for line in lines if line.strip())
+        if not formatted_data:
+            return
+
+        # Other forks write to the same descriptor
+        with protocol_write_lock():
+            write_all(self.original_fd_dup, formatted_data)
+
     def stop_redirection(self) -> None:
         """Stop redirection and restore original file descriptors."""
         if not self.active:
@@ -329,6 +779,24 @@ def build_firehot_logger():
     return logger
 
 
+def collect_resource_usage() -> ResourceUsage:
+    """
+    Resource usage of the current process. Counters are reset on fork, so when called from
+    a forked child this only covers the work done since the fork.
+
+    """
+    usage = resource.getrusage(resource.RUSAGE_SELF)
+
+    # ru_maxrss is reported in kilobytes on Linux but in bytes on macOS
+    max_rss_bytes = usage.ru_maxrss if sys.platform == "darwin" else usage.ru_maxrss * 1024
+
+    return ResourceUsage(
+        user_time=usage.ru_utime,
+        system_time=usage.ru_stime,
+        max_rss_bytes=max_rss_bytes,
+    )
+
+
 def check_thread_safety() -> None:
     """
     Check if we're running with multiple threads and warn about potential fork() dangers.
@@ -394,13 +862,14 @@ def check_thread_safety() -> None:
         )
 
 
-def track_and_execute_import(module_name: str, firehot_logger: logging.Logger) -> None:
+def track_and_execute_import(module_name: str, firehot_logger: logging.Logger) -> float:
     """
     Execute a single import and track any thread count changes.
 
     :param module_name: The name of the module to import
     :param firehot_logger: Logger instance to use for warnings
 
+    :returns: How long the import took, in milliseconds
     :raises Exception: If the import fails
 
     """
@@ -408,8 +877,11 @@ def track_and_execute_import(module_name: str, firehot_logger: logging.Logger) -
     pre_import_thread_count = get_total_thread_count()
     pre_import_python_threads = threading.active_count()
 
-    # Execute the import
+    # Execute the import. Modules are imported one after another, so anything this pulls in
+    # transitively is charged to this module and costs nothing for later modules that share it.
+    import_start = perf_counter()
     __import__(module_name)
+    duration_ms = (perf_counter() - import_start) * 1000
 
     # Get thread count after import
     post_import_thread_count = get_total_thread_count()
@@ -424,19 +896,24 @@ def track_and_execute_import(module_name: str, firehot_logger: logging.Logger) -
             f"  - C/native threads: {pre_import_thread_count - pre_import_python_threads} -> {post_import_thread_count - post_import_python_threads}"
         )
 
+    return duration_ms
 
-def execute_dynamic_imports(dynamic_imports: str, firehot_logger: logging.Logger) -> None:
+
+def execute_dynamic_imports(dynamic_imports: str, firehot_logger: logging.Logger) -> list[str]:
     """
     Parse and execute a list of dynamic imports, tracking thread creation for each import.
 
     :param dynamic_imports: JSON string containing a list of module names to import
     :param firehot_logger: Logger instance to use for warnings
+    :returns: The modules deferred past the critical modules, still to be imported
 
-    :raises ImportError: If imports cannot be parsed or executed
+    Every module is attempted and each failure is reported individually, so one broken
+    dependency doesn't hide the others. Failures are fatal unless the import failure
+    policy is "warn".
 
     """
     if not dynamic_imports:
-        return
+        return []
 
     # Parse the JSON list of module names
     try:
@@ -447,22 +924,349 @@ def execute_dynamic_imports(dynamic_imports: str, firehot_logger: logging.Logger
         write_message(ImportError(error=str(e), traceback=format_exc()))
         sys.exit(1)
 
+    critical_modules, deferred_modules = split_critical_modules(module_list)
+    failed_imports = import_modules(critical_modules, firehot_logger)
+    if failed_imports and IMPORT_FAILURE_POLICY != "warn":
+        sys.exit(1)
+
+    return deferred_modules
+
+
+def split_critical_modules(module_list: list[str]) -> tuple[list[str], list[str]]:
+    """
+    Split the preloads into the critical modules (and their submodules), which are imported
+    before reporting ready, and the rest. Without critical modules everything is critical.
+
+    """
+    if not CRITICAL_MODULES:
+        return module_list, []
+
+    critical = json_loads(CRITICAL_MODULES)
+    critical_modules = [
+        module_name
+        for module_name in module_list
+        if any(
+            module_name == prefix or module_name.startswith(f"{prefix}.") for prefix in critical
+        )
+    ]
+    deferred_modules = [
+        module_name for module_name in module_list if module_name not in critical_modules
+    ]
+    return critical_modules, deferred_modules
+
+
+def import_modules(module_list: list[str], firehot_logger: logging.Logger) -> int:
+    """
+    Import each module in turn, reporting its timing or failure.
+
+    :returns: How many of the modules failed to import
+
+    """
     # Track thread counts for each import
-    for module_name in module_list:
+    failed_imports = 0
+    for index, module_name in enumerate(module_list):
+        # Sent before the import starts, so a progress bar can show what's loading
+        write_message(ImportProgress(module=module_name, index=index, total=len(module_list)))
         try:
-            track_and_execute_import(module_name, firehot_logger)
+            duration_ms = track_and_execute_import(module_name, firehot_logger)
         except Exception as e:
-            write_message(ImportError(error=str(e), traceback=format_exc()))
+            failed_imports += 1
+            write_message(ImportError(error=str(e), traceback=format_exc(), module=module_name))
+            continue
+
+        # Lets the Rust side report where the boot stalled if a later import hangs
+        write_message(ModuleImported(module=module_name, duration_ms=duration_ms))
+
+    return failed_imports
+
+
+# Thread importing the preloads deferred past the critical modules, until a fork or reload
+# waits for it
+BACKGROUND_IMPORTS: threading.Thread | None = None
+
+
+def start_background_imports(module_list: list[str], firehot_logger: logging.Logger) -> None:
+    """
+    Import the deferred preloads in a background thread. The boot has already been reported,
+    so failures are only reported, whatever the import failure policy. Sends a second
+    ImportComplete once every module has been attempted.
+
+    """
+    global BACKGROUND_IMPORTS
+
+    def run():
+        import_modules(module_list, firehot_logger)
+        write_message(ImportComplete())
+
+    BACKGROUND_IMPORTS = threading.Thread(target=run, name="firehot-imports", daemon=True)
+    BACKGROUND_IMPORTS.start()
+
+
+def background_imports_running() -> bool:
+    return BACKGROUND_IMPORTS is not None and BACKGROUND_IMPORTS.is_alive()
+
+
+def wait_for_background_imports() -> None:
+    """
+    Block until the background imports are done. A fork taken mid-import would inherit
+    half-initialized modules and a thread that no longer exists, so forks and reloads wait.
+    Called from the main thread, which is also where the SIGCHLD handler has to be installed.
+
+    """
+    global BACKGROUND_IMPORTS
+
+    if BACKGROUND_IMPORTS is None:
+        return
+
+    BACKGROUND_IMPORTS.join()
+    BACKGROUND_IMPORTS = None
+    signal.signal(signal.SIGCHLD, reap_children)
+
+
+def reload_modules(request: ReloadRequest) -> ReloadResponse:
+    """
+    Find the loaded modules whose source is one of the requested files, and reload them in place
+    if asked to. Forks made afterwards see the new code, while running forks keep the old one.
+
+    C extensions can't be reloaded, so any among them leaves every module untouched and reports
+    an error. The caller is expected to reboot the loader instead.
+
+    """
+    paths = {os.path.realpath(path) for path in request.paths}
+    modules = sorted(
+        (module_name, module)
+        for module_name, module in list(sys.modules.items())
+        if isinstance(getattr(module, "__file__", None), str)
+        and os.path.realpath(module.__file__) in paths
+    )
+    module_names = [module_name for module_name, _ in modules]
+    if not request.in_place or not modules:
+        return ReloadResponse(request_id=request.request_id, modules=module_names, reloaded=False)
+
+    extensions = [
+        module_name
+        for module_name, module in modules
+        if isinstance(getattr(module, "__loader__", None), importlib.machinery.ExtensionFileLoader)
+    ]
+    if extensions:
+        return ReloadResponse(
+            request_id=request.request_id,
+            modules=module_names,
+            reloaded=False,
+            error=f"Extension modules can't be reloaded in place: {', '.join(extensions)}",
+        )
+
+    try:
+        for _, module in modules:
+            importlib.reload(module)
+    except Exception as e:
+        return ReloadResponse(
+            request_id=request.request_id,
+            modules=module_names,
+            reloaded=False,
+            error=f"{type(e).__name__}: {e}",
+        )
+    return ReloadResponse(request_id=request.request_id, modules=module_names, reloaded=True)
+
+
+class ForkTimeoutError(TimeoutError):
+    """
+    Raised inside a forked function that runs past the timeout from its ForkRequest.
+
+    """
+
+
+def raise_fork_timeout(timeout: float):
+    raise ForkTimeoutError(f"Function timed out after {timeout:g}s")
+
+
+def build_child_complete(result) -> ChildComplete:
+    """
+    Report a function's return value. Binary values are kept as bytes rather than their
+    `str`, and large ones go through a temporary file so they don't have to fit in a message.
+
+    """
+    rusage = collect_resource_usage()
+    if not isinstance(result, (bytes, bytearray, memoryview)):
+        return ChildComplete(result=str(result), result_json=encode_result(result), rusage=rusage)
+
+    data = bytes(result)
+    if len(data) <= INLINE_RESULT_BYTES:
+        return ChildComplete(
+            result=str(result), result_bytes=b64encode(data).decode(), rusage=rusage
+        )
+
+    # Named by PID so Rust can clean up after a fork that dies before reporting the file
+    fd, path = mkstemp(prefix=f"firehot-result-{os.getpid()}-", dir=RESULT_DIR)
+    with os.fdopen(fd, "wb") as result_file:
+        result_file.write(data)
+    return ChildComplete(result=None, result_path=path, rusage=rusage)
+
+
+def encode_result(result) -> str | None:
+    """
+    JSON-encode a function's return value so Rust can deserialize it into a typed value.
+
+    :returns: The JSON string, or None if the value isn't JSON serializable
+    """
+    try:
+        return json_dumps(result)
+    except (TypeError, ValueError):
+        return None
+
+
+def run_call(code_to_execute, pickled_data, timeout, firehot_logger: logging.Logger) -> None:
+    """
+    Run a call in the child process it was handed to, report how it went and exit.
+
+    """
+    # `finish_isolated` interrupts the function with SIGINT, so it can wrap up cleanly
+    signal.signal(signal.SIGINT, signal.default_int_handler)
+
+    # Set up stream redirection to catch all output from the child process
+    # NOTE: We can't run this before the child process has launched, since it spawns
+    # a thread that will affect our fork() behavior.
+    with MultiplexedStream.setup_stream_redirection():
+        try:
+            # Set up globals and locals for execution
+            exec_globals = globals().copy()
+            exec_locals = {}
+
+            # The child script reads its serialized call from `pickled_str`
+            if pickled_data is not None:
+                exec_globals["pickled_str"] = pickled_data
+
+            firehot_logger.info("Will execute code in child process...")
+            sys.stdout.flush()
+
+            # Execute the code. The timer raises inside the function, so it can catch
+            # the error and clean up. It takes over SIGALRM, so the function's own alarms
+            # would replace it.
+            if timeout:
+                signal.signal(signal.SIGALRM, lambda signum, frame: raise_fork_timeout(timeout))
+                signal.setitimer(signal.ITIMER_REAL, timeout)
+            try:
+                exec(code_to_execute, exec_globals, exec_locals)
+            finally:
+                if timeout:
+                    signal.setitimer(signal.ITIMER_REAL, 0)
+
+            firehot_logger.info("Executed code in child process")
+            sys.stdout.flush()
+
+            # By convention, the result is stored in the 'result' variable
+            if "result" in exec_locals:
+                write_message(build_child_complete(exec_locals["result"]))
+            else:
+                write_message(ChildComplete(result=None, rusage=collect_resource_usage()))
+
+            sys.exit(0)
+        except (Exception, KeyboardInterrupt) as e:
+            # Report the error
+            if isinstance(e, ForkTimeoutError):
+                kind = "timeout"
+            elif isinstance(e, KeyboardInterrupt):
+                kind = "interrupted"
+            else:
+                kind = "exception"
+            write_message(
+                ChildError(
+                    error=str(e),
+                    traceback=format_exc(),
+                    rusage=collect_resource_usage(),
+                    kind=kind,
+                )
+            )
             sys.exit(1)
 
 
+def loader_source() -> str:
+    """
+    Source of this script. The Rust side runs it with `python -c`, so there's no file to point
+    a new interpreter at.
+
+    """
+    return sys.orig_argv[sys.orig_argv.index("-c") + 1]
+
+
+def spawn_call(code_to_execute, pickled_data, env, timeout) -> int:
+    """
+    Run a call in a fresh interpreter running this same script instead of a fork. Nothing the
+    loader imported carries over, so each call pays for its own imports. Output is multiplexed
+    and results are reported exactly like a fork's, and the SIGCHLD handler reaps it. Unix-only,
+    like the rest of the loader.
+
+    :returns: PID of the new process
+
+    """
+    child_env = {**os.environ, **(env or {})}
+    child_env["FIREHOT_LOADER_PID"] = str(LOADER_PID)
+    child_env["FIREHOT_SPAWNED_CALL"] = "1"
+    pass_fds = ()
+    if PROTOCOL_FD is not None:
+        child_env["FIREHOT_PROTOCOL_FD"] = str(PROTOCOL_FD)
+        pass_fds = (PROTOCOL_FD,)
+    if PROTOCOL_LOCK_PATH is not None:
+        child_env["FIREHOT_PROTOCOL_LOCK"] = PROTOCOL_LOCK_PATH
+
+    # Hold SIGCHLD until the Popen is registered, or a call that exits right away gets reaped
+    # before the handler knows about it
+    signal.pthread_sigmask(signal.SIG_BLOCK, {signal.SIGCHLD})
+    try:
+        # The call goes over stdin, since payloads can outgrow what an environment variable holds
+        process = subprocess.Popen(
+            [sys.executable, "-c", loader_source()],
+            env=child_env,
+            stdin=subprocess.PIPE,
+            pass_fds=pass_fds,
+        )
+        SPAWNED_CALLS[process.pid] = process
+    finally:
+        signal.pthread_sigmask(signal.SIG_UNBLOCK, {signal.SIGCHLD})
+
+    call = {"code": code_to_execute, "pickled_data": pickled_data, "timeout": timeout}
+    process.stdin.write(json_dumps(call).encode())
+    process.stdin.close()
+    return process.pid
+
+
+def run_spawned_call(firehot_logger: logging.Logger) -> None:
+    """
+    Entrypoint of an interpreter started by `spawn_call`.
+
+    """
+    global PROTOCOL_FD, PROTOCOL_LOCK_PATH
+
+    call = json_loads(sys.stdin.read())
+    protocol_fd = os.environ.pop("FIREHOT_PROTOCOL_FD", None)
+    if protocol_fd is not None:
+        PROTOCOL_FD = int(protocol_fd)
+    PROTOCOL_LOCK_PATH = os.environ.pop("FIREHOT_PROTOCOL_LOCK", None)
+
+    # User code shouldn't see our bookkeeping, or pass it on to its own subprocesses
+    os.environ.pop("FIREHOT_SPAWNED_CALL", None)
+    os.environ.pop("FIREHOT_LOADER_PID", None)
+
+    run_call(call["code"], call["pickled_data"], call["timeout"], firehot_logger)
+
+
 def main():
     dynamic_imports = sys.argv[1] if len(sys.argv) > 1 else ""
     firehot_logger = build_firehot_logger()
 
+    if getenv("FIREHOT_SPAWNED_CALL"):
+        run_spawned_call(firehot_logger)
+
+    # Must happen before the imports, since those are free to print
+    setup_protocol_channel()
+    setup_protocol_lock()
+
+    # Lets the Rust side refuse to talk to a loader built for a different protocol
+    write_message(Hello(protocol_version=PROTOCOL_VERSION))
+
     # Execute the dynamic imports
     try:
-        execute_dynamic_imports(dynamic_imports, firehot_logger)
+        deferred_modules = execute_dynamic_imports(dynamic_imports, firehot_logger)
     except Exception as e:
         write_message(ImportError(error=str(e), traceback=format_exc()))
         sys.exit(1)
@@ -470,65 +1274,80 @@ def main():
     # Signal that imports are complete
     write_message(ImportComplete())
 
+    # Installed after the imports so we never wait on processes that module code spawned.
+    # With deferred imports that's once they're done, see `wait_for_background_imports`.
+    if deferred_modules:
+        start_background_imports(deferred_modules, firehot_logger)
+    else:
+        signal.signal(signal.SIGCHLD, reap_children)
+
     # Function to handle forking and executing code
-    def handle_fork_request(code_to_execute):
+    def handle_fork_request(code_to_execute, pickled_data=None, env=None, timeout=None):
+        wait_for_background_imports()
+
+        if EXECUTION_MODE == "spawn":
+            return spawn_call(code_to_execute, pickled_data, env, timeout)
+
         # Check thread safety before forking
         check_thread_safety()
 
         pid = os.fork()
         if pid == 0:
-            # Child process
-
-            # Set up stream redirection to catch all output from the child process
-            # NOTE: We can't run this before the child process has launched, since it spawns
-            # a thread that will affect our fork() behavior.
-            with MultiplexedStream.setup_stream_redirection():
-                try:
-                    # Set up globals and locals for execution
-                    exec_globals = globals().copy()
-                    exec_locals = {}
-
-                    firehot_logger.info("Will execute code in forked process...")
-                    sys.stdout.flush()
-
-                    # Execute the code
-                    exec(code_to_execute, exec_globals, exec_locals)
-
-                    firehot_logger.info("Executed code in forked process")
-                    sys.stdout.flush()
-
-                    # By convention, the result is stored in the 'result' variable
-                    if "result" in exec_locals:
-                        write_message(ChildComplete(result=str(exec_locals["result"])))
-                    else:
-                        write_message(ChildComplete(result=None))
-
-                    sys.exit(0)
-                except Exception as e:
-                    # Report the error
-                    write_message(ChildError(error=str(e), traceback=format_exc()))
-                    sys.exit(1)
+            # Child process. Subprocesses started by user code must be waited on by their
+            # own callers, not by the loader's reaper.
+            signal.signal(signal.SIGCHLD, signal.SIG_DFL)
+            PENDING_CHILD_EXITS.clear()
+
+            # Only this fork sees the variables, so there's nothing to restore afterwards
+            if env:
+                os.environ.update(env)
+
+            run_call(code_to_execute, pickled_data, timeout, firehot_logger)
         else:
             # Parent process. The PID will represent the child process.
             return pid
 
+    # Fork and reload requests that arrived while the background imports were still running.
+    # Only they have to wait for the imports, so pings and exits are answered in the meantime.
+    deferred_commands: deque[MessageBase] = deque()
+
     # Main loop - wait for commands on stdin
     while True:
         try:
-            command = read_message()
-            if not command:
-                sleep(0.1)
-                continue
+            if deferred_commands and not background_imports_running():
+                command = deferred_commands.popleft()
+            else:
+                # Check back on the imports regularly while requests are waiting on them
+                command = read_message(timeout=0.05 if deferred_commands else None)
+                if not command:
+                    if not deferred_commands:
+                        sleep(0.1)
+                    continue
+                if isinstance(command, (ForkRequest, ReloadRequest)) and (
+                    deferred_commands or background_imports_running()
+                ):
+                    deferred_commands.append(command)
+                    continue
 
             if isinstance(command, ForkRequest):
-                fork_pid = handle_fork_request(command.code)
-                write_message(
-                    ForkResponse(
-                        request_id=command.request_id,
-                        request_name=command.request_name,
-                        child_pid=fork_pid,
+                # A fork that exits right away is only reported once it's been announced, so
+                # Rust can treat output from a PID that exits unannounced as unmatched
+                with deferring_child_exits():
+                    fork_pid = handle_fork_request(
+                        command.code, command.pickled_data, command.env, command.timeout
                     )
-                )
+                    write_message(
+                        ForkResponse(
+                            request_id=command.request_id,
+                            request_name=command.request_name,
+                            child_pid=fork_pid,
+                        )
+                    )
+            elif isinstance(command, Ping):
+                write_message(Pong(request_id=command.request_id))
+            elif isinstance(command, ReloadRequest):
+                wait_for_background_imports()
+                write_message(reload_modules(command))
             elif isinstance(command, ExitRequest):
                 firehot_logger.info("Exiting loader process")
                 sys.stdout.flush()
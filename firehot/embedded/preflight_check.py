@@ -0,0 +1,41 @@
+"""
+Preflight check for hotreload. Resolves the detected third-party modules with
+`importlib.util.find_spec` so we can report missing dependencies before paying for the
+full loader boot.
+
+Intended for embeddable usage in Rust, can only import stdlib modules.
+
+"""
+
+import importlib.util
+import sys
+from json import dumps as json_dumps
+from json import loads as json_loads
+
+
+def is_module_available(module_name: str) -> bool:
+    """
+    Check whether a module can be resolved without importing it.
+
+    For dotted paths we only resolve the top-level package. `find_spec("a.b")` would
+    import `a` to access its `__path__`, which defeats the point of a side-effect free check.
+
+    """
+    top_level = module_name.split(".")[0]
+    try:
+        return importlib.util.find_spec(top_level) is not None
+    except (ImportError, ValueError):
+        return False
+
+
+def main():
+    module_list = json_loads(sys.argv[1]) if len(sys.argv) > 1 else []
+    missing = sorted(
+        module_name for module_name in module_list if not is_module_available(module_name)
+    )
+    sys.stdout.write(f"{json_dumps(missing)}\n")
+    sys.stdout.flush()
+
+
+if __name__ == "__main__":
+    main()
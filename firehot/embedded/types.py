@@ -4,14 +4,17 @@ injected scripts need to be run standalone.
 
 """
 
-from typing import TypedDict
+from typing import Callable, TypedDict
 
-SerializedCall = TypedDict(
-    "SerializedCall",
-    {
-        "func_module_path": str | None,
-        "func_name": str,
-        "func_qualname": str,
-        "args": tuple,
-    },
-)
+
+class _SerializedCallRequired(TypedDict):
+    func_module_path: str | None
+    func_name: str
+    func_qualname: str
+    args: tuple
+
+
+class SerializedCall(_SerializedCallRequired, total=False):
+    # The function itself, pickled by value. Only set for functions that can't be looked up
+    # by name in their module, like closures and lambdas.
+    func: Callable
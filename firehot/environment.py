@@ -2,12 +2,21 @@ from dataclasses import dataclass
 from typing import Any, Callable
 from uuid import UUID
 
+from firehot.firehot import (
+    cancel_isolated as cancel_isolated_rs,
+)
+from firehot.firehot import (
+    captured_output as captured_output_rs,
+)
 from firehot.firehot import (
     communicate_isolated as communicate_isolated_rs,
 )
 from firehot.firehot import (
     exec_isolated as exec_isolated_rs,
 )
+from firehot.firehot import (
+    last_boot_duration as last_boot_duration_rs,
+)
 from firehot.firehot import (
     stop_isolated as stop_isolated_rs,
 )
@@ -32,25 +41,54 @@ class Environment:
 
     """
 
-    def __init__(self, runner_id: str):
+    def __init__(
+        self,
+        runner_id: str,
+        *,
+        pickle_protocol: int | None = None,
+        use_cloudpickle: bool = False,
+    ):
         """
         Initialize the Environment with a runner ID.
 
         :param runner_id: The unique identifier for this runner
+        :param pickle_protocol: Pickle protocol for function calls sent to isolated processes.
+                                Defaults to the interpreter's default protocol.
+        :param use_cloudpickle: Serialize calls with cloudpickle when it's installed, so closures
+                                and lambdas can be executed too
         """
         self.runner_id = runner_id
-
-    def exec(self, func: Callable, *args: Any, name: str | None = None) -> IsolatedProcess:
+        self.pickle_protocol = pickle_protocol
+        self.use_cloudpickle = use_cloudpickle
+
+    def exec(
+        self,
+        func: Callable,
+        *args: Any,
+        name: str | None = None,
+        env: dict[str, str] | None = None,
+    ) -> IsolatedProcess:
         """
         Execute a function in the isolated environment.
 
         :param func: The function to execute. A function should fully contain its content, including imports
         :param args: Arguments to pass to the function
         :param name: Optional name for the process
+        :param env: Environment variables to set in the isolated process before the function runs
         :returns: An IsolatedProcess instance representing the execution
         """
         process_name = name or NAME_REGISTRY.reserve_random_name()
-        exec_id = UUID(exec_isolated_rs(self.runner_id, process_name, func, args))
+        exec_id = UUID(
+            exec_isolated_rs(
+                self.runner_id,
+                process_name,
+                func,
+                args,
+                env,
+                self.pickle_protocol,
+                self.use_cloudpickle,
+            )
+        )
         return IsolatedProcess(process_uuid=exec_id, process_name=process_name)
 
     def stop_isolated(self, isolate: IsolatedProcess):
@@ -70,18 +108,48 @@ class Environment:
         """
         stop_isolated_rs(self.runner_id, str(isolate.process_uuid))
 
-    def communicate_isolated(self, isolate: IsolatedProcess) -> str:
+    def cancel_isolated(self, isolate: IsolatedProcess) -> bool:
+        """
+        Cancel an isolated process that is still running. Unlike `stop_isolated`, the process
+        stays tracked, so `communicate_isolated` raises a cancellation error for it.
+
+        :param isolate: The IsolatedProcess instance to cancel
+        :returns: True if the process was cancelled, False if it had already finished
+        """
+        return cancel_isolated_rs(self.runner_id, str(isolate.process_uuid))
+
+    def communicate_isolated(self, isolate: IsolatedProcess, timeout: float | None = None) -> str:
         """
         Communicate with an isolated process to get its output.
 
         :param isolate: Either an IsolatedProcess instance or a UUID object
+        :param timeout: Seconds to wait before raising. The process keeps running after a
+                        timeout, so it can be waited on again or stopped with `stop_isolated`.
         :returns: The output from the isolated process
         """
         # Handle both IsolatedProcess objects and raw UUIDs
-        return communicate_isolated_rs(self.runner_id, str(isolate.process_uuid))
+        return communicate_isolated_rs(self.runner_id, str(isolate.process_uuid), timeout)
+
+    def captured_output(self, isolate: IsolatedProcess) -> list[str]:
+        """
+        Get the lines an isolated process printed. These are kept after the process finishes,
+        so when `communicate_isolated` raises they show what happened leading up to the error.
+
+        :param isolate: The IsolatedProcess instance to inspect
+        :returns: The most recent lines printed by the process, oldest first
+        """
+        return captured_output_rs(self.runner_id, str(isolate.process_uuid))
 
     def update_environment(self):
         """
         Update the environment by checking for import changes and restarting if necessary.
         """
         return update_environment_rs(self.runner_id)
+
+    def last_boot_duration(self) -> float | None:
+        """
+        How long the last boot took to spawn the loader and import its dependencies.
+
+        :returns: The duration in seconds, or None if the environment hasn't booted yet
+        """
+        return last_boot_duration_rs(self.runner_id)
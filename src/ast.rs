@@ -1,15 +1,24 @@
 use anyhow::{anyhow, Result};
-use log::{debug, info, trace};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, info, trace, warn};
+use rayon::prelude::*;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
 };
 use walkdir::WalkDir;
+use zip::ZipArchive;
 
 use rustpython_parser::ast::{
-    Mod, Stmt, StmtAsyncFunctionDef, StmtClassDef, StmtFunctionDef, StmtIf, StmtWhile,
+    CmpOp, Constant, ExceptHandler, Expr, Mod, Stmt, StmtAsyncFor, StmtAsyncFunctionDef,
+    StmtAsyncWith, StmtClassDef, StmtFor, StmtFunctionDef, StmtIf, StmtMatch, StmtTry, StmtTryStar,
+    StmtWhile, StmtWith,
 };
-use rustpython_parser::{parse, Mode};
+use rustpython_parser::{parse, source_code::RandomLocator, Mode, ParseError};
 
 use sha2::{Digest, Sha256};
 
@@ -28,24 +37,211 @@ pub struct ImportInfo {
     pub is_relative: bool,
     /// Whether this is a simple import (import X) or a from import (from X import Y)
     pub is_from_import: bool,
+    /// Whether this is `from X import *`. The module is still imported, but which names
+    /// it brings into scope can't be known from the source. `names` holds just `*`.
+    pub is_star: bool,
     /// Users sometimes nest package imports within functions to avoid circular imports
     /// of initialization dependencies. We track the level of the import here so we can
     /// make sure to load root packages before nested packages.
     pub import_level: u32,
+    /// Number of leading dots for relative imports (`from ..x import y` is 2), 0 otherwise
+    pub relative_level: u32,
+    /// Absolute module path of the import. This is `module` for absolute imports. Relative
+    /// imports are resolved against the importing file's package once we know where the file
+    /// lives, and stay `None` if they reach above the package root.
+    pub resolved_module: Option<String>,
+}
+
+/// How third-party imports are reported to the loader
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportGranularity {
+    /// Collapse `import a.b.c` to its top-level package `a`. Importing a submodule always
+    /// imports its parents, so this avoids tracking `a` and `a.b.c` as distinct dependencies
+    /// that churn the import delta. `from a.b import c` keeps its full `a.b` path.
+    #[default]
+    TopLevel,
+    /// Report every module exactly as it was written in the source
+    FullPath,
+}
+
+/// Directories that never contain project sources. Walking into a local virtualenv would
+/// otherwise parse every installed package.
+pub const DEFAULT_EXCLUDED_DIRS: &[&str] = &[
+    ".venv",
+    "venv",
+    "site-packages",
+    "__pycache__",
+    ".git",
+    "node_modules",
+];
+
+/// Outcome of scanning every Python file in a project
+#[derive(Debug, Clone, Default)]
+pub struct ScanResult {
+    /// Third-party modules imported by the files we were able to parse
+    pub third_party_imports: HashSet<String>,
+    /// Files that couldn't be read or parsed along with the reason, sorted by path
+    pub parse_failures: Vec<(PathBuf, String)>,
+    /// The parse failures that were syntax errors, with where in the file they occurred
+    pub syntax_errors: Vec<SyntaxError>,
+    /// First-party modules mapped to the first-party modules they import. Only filled in
+    /// when the manager is asked to build the import graph.
+    pub import_graph: BTreeMap<String, BTreeSet<String>>,
+    /// Circular imports between first-party modules, each listed in import order starting
+    /// from its alphabetically first module
+    pub import_cycles: Vec<Vec<String>>,
+}
+
+impl ScanResult {
+    /// Record a file we had to skip
+    fn add_failure(&mut self, path: PathBuf, error: anyhow::Error) {
+        if let Some(syntax_error) = error.downcast_ref::<SyntaxError>() {
+            self.syntax_errors.push(syntax_error.clone());
+        }
+        self.parse_failures.push((path, error.to_string()));
+    }
+
+    fn sort_failures(&mut self) {
+        self.parse_failures.sort();
+        self.syntax_errors.sort();
+    }
+}
+
+/// A file that isn't valid Python, located so editors can jump straight to the problem
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SyntaxError {
+    /// Path of the file, as it was handed to the parser
+    pub path: PathBuf,
+    /// One-indexed line of the offending token
+    pub line: usize,
+    /// One-indexed column of the offending token, in characters
+    pub column: usize,
+    /// The parser's description of what went wrong
+    pub message: String,
+}
+
+impl SyntaxError {
+    fn from_parse_error(source: &str, error: ParseError) -> Self {
+        let location = RandomLocator::new(source).locate(error.offset);
+        Self {
+            path: PathBuf::from(&error.source_path),
+            line: location.row.to_usize(),
+            column: location.column.to_usize(),
+            message: error.error.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to parse {}:{}:{}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Result of scanning a single file, before it's merged into the manager's caches
+enum FileScan {
+    /// The file matches our cache, so these are the previously collected imports
+    Unchanged {
+        stamp: Option<FileStamp>,
+        imports: Vec<ImportInfo>,
+    },
+    /// The file is new or changed and was parsed again
+    Parsed {
+        hash: String,
+        stamp: Option<FileStamp>,
+        imports: Vec<ImportInfo>,
+    },
+}
+
+impl FileScan {
+    fn imports(&self) -> &[ImportInfo] {
+        match self {
+            FileScan::Unchanged { imports, .. } | FileScan::Parsed { imports, .. } => imports,
+        }
+    }
+
+    fn into_imports(self) -> Vec<ImportInfo> {
+        match self {
+            FileScan::Unchanged { imports, .. } | FileScan::Parsed { imports, .. } => imports,
+        }
+    }
+}
+
+/// Modification time and size of a file, used to skip reading files that haven't changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    fn read(file_path: &str) -> Option<Self> {
+        let metadata = fs::metadata(file_path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Options that control which parts of a module `collect_imports` traverses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollectOptions {
+    /// Also collect imports guarded by `if TYPE_CHECKING:`. These are skipped by default,
+    /// since they're only evaluated by type checkers.
+    pub include_type_checking: bool,
+    /// Version of the interpreter the imports will run on, as (major, minor, micro). When
+    /// set, `if sys.version_info ...` checks are evaluated and only the branch that runs is
+    /// collected. Otherwise both branches are.
+    pub python_version: Option<(u32, u32, u32)>,
 }
 
 /// Manage AST parsing and import tracking for a project
 pub struct ProjectAstManager {
     /// Mapping of file paths to their content SHA256 hash
     file_hashes: HashMap<String, String>,
+    /// Mapping of file paths to their mtime and size when last scanned. When these match we
+    /// can skip reading the file altogether.
+    file_stamps: HashMap<String, FileStamp>,
+    /// Number of files that have been parsed, for observing cache effectiveness
+    parse_count: AtomicUsize,
     /// Mapping of file paths to their imports. This includes both first party and third party imports.
     file_imports: HashMap<String, Vec<ImportInfo>>,
-    /// The name of the project
+    /// The name of the project, used as the root package when resolving relative imports
     package_name: String,
+    /// Every package whose imports count as first party. Always includes `package_name`.
+    package_names: HashSet<String>,
+    /// Module prefixes that are third party even though they live under a first-party package
+    third_party_prefixes: HashSet<String>,
     /// The root path of the project
     project_path: String,
     /// Set of modules to ignore when determining third-party imports
     ignored_modules: HashSet<String>,
+    /// Top-level standard library modules, which are never reported as third party
+    stdlib_modules: HashSet<String>,
+    /// Whether submodule imports are reported by their top-level package
+    import_granularity: ImportGranularity,
+    /// Options passed along to `collect_imports` for every file
+    collect_options: CollectOptions,
+    /// Directory names that are skipped entirely while walking the project
+    excluded_dirs: HashSet<String>,
+    /// Glob patterns, relative to the project root, of files that are never scanned
+    exclude_globs: Vec<String>,
+    /// `exclude_globs` compiled for matching
+    exclude_glob_set: GlobSet,
+    /// Whether files are parsed in parallel
+    parallel: bool,
+    /// Whether scans record which first-party modules import each other
+    build_import_graph: bool,
 }
 
 impl ProjectAstManager {
@@ -61,11 +257,150 @@ impl ProjectAstManager {
         );
         Self {
             file_hashes: HashMap::new(),
+            file_stamps: HashMap::new(),
+            parse_count: AtomicUsize::new(0),
             file_imports: HashMap::new(),
             package_name: project_name.to_string(),
+            package_names: HashSet::from([project_name.to_string()]),
+            third_party_prefixes: HashSet::new(),
             project_path: project_path.to_string(),
             ignored_modules: ignored_modules.unwrap_or_default(),
+            stdlib_modules: HashSet::new(),
+            import_granularity: ImportGranularity::default(),
+            collect_options: CollectOptions::default(),
+            excluded_dirs: DEFAULT_EXCLUDED_DIRS
+                .iter()
+                .map(|dir| dir.to_string())
+                .collect(),
+            exclude_globs: Vec::new(),
+            exclude_glob_set: GlobSet::empty(),
+            parallel: true,
+            build_import_graph: false,
+        }
+    }
+
+    /// Parse files across all cores (the default) or one at a time
+    pub fn set_parallel(&mut self, parallel: bool) {
+        self.parallel = parallel;
+    }
+
+    /// Whether scans build the first-party import graph
+    pub fn build_import_graph(&self) -> bool {
+        self.build_import_graph
+    }
+
+    /// Record which first-party modules import each other during scans, and warn about any
+    /// circular imports between them. Cycles often work by accident of import order, so
+    /// they're worth surfacing before a reload shuffles that order.
+    pub fn set_build_import_graph(&mut self, build: bool) {
+        self.build_import_graph = build;
+    }
+
+    /// Directory names that are skipped while walking the project
+    pub fn excluded_dirs(&self) -> &HashSet<String> {
+        &self.excluded_dirs
+    }
+
+    /// Replace the directory names skipped while walking the project. Matching is on the
+    /// directory name alone, at any depth below the project root.
+    pub fn set_excluded_dirs<I, S>(&mut self, excluded_dirs: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.excluded_dirs = excluded_dirs.into_iter().map(Into::into).collect();
+    }
+
+    /// Glob patterns of files that are skipped while walking the project
+    pub fn exclude_globs(&self) -> &[String] {
+        &self.exclude_globs
+    }
+
+    /// Replace the glob patterns of files skipped while walking the project, like
+    /// `**/migrations/*.py` for files that pull in heavy or broken imports. Patterns are
+    /// matched against paths relative to the project root. Fails without changing anything
+    /// if a pattern is invalid.
+    pub fn set_exclude_globs<I, S>(&mut self, patterns: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let patterns: Vec<String> = patterns.into_iter().map(Into::into).collect();
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(
+                Glob::new(pattern)
+                    .map_err(|e| anyhow!("Invalid exclude glob {}: {}", pattern, e))?,
+            );
+        }
+        self.exclude_glob_set = builder.build()?;
+        self.exclude_globs = patterns;
+
+        // Files excluded from now on shouldn't keep contributing their cached imports
+        let excluded: Vec<String> = self
+            .file_imports
+            .keys()
+            .filter(|path| self.is_excluded_file(Path::new(path)))
+            .cloned()
+            .collect();
+        for path in excluded {
+            self.file_imports.remove(&path);
+            self.file_hashes.remove(&path);
+            self.file_stamps.remove(&path);
         }
+        Ok(())
+    }
+
+    /// Whether a file matches one of the exclude globs
+    fn is_excluded_file(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.project_path).unwrap_or(path);
+        self.exclude_glob_set.is_match(relative)
+    }
+
+    /// Whether imports guarded by `if TYPE_CHECKING:` are collected
+    pub fn include_type_checking(&self) -> bool {
+        self.collect_options.include_type_checking
+    }
+
+    /// Collect imports guarded by `if TYPE_CHECKING:` for users that want them loaded eagerly.
+    /// This changes what a file contributes, so any cached results are discarded.
+    pub fn set_include_type_checking(&mut self, include: bool) {
+        if self.collect_options.include_type_checking != include {
+            self.collect_options.include_type_checking = include;
+            self.file_hashes.clear();
+            self.file_stamps.clear();
+        }
+    }
+
+    /// Interpreter version used to evaluate `sys.version_info` checks, if known
+    pub fn python_version(&self) -> Option<(u32, u32, u32)> {
+        self.collect_options.python_version
+    }
+
+    /// Set the interpreter version the project runs on, so version-gated imports like
+    /// `if sys.version_info >= (3, 11): import tomllib` only contribute the branch that runs.
+    /// This changes what a file contributes, so any cached results are discarded.
+    pub fn set_python_version(&mut self, version: Option<(u32, u32, u32)>) {
+        if self.collect_options.python_version != version {
+            self.collect_options.python_version = version;
+            self.file_hashes.clear();
+            self.file_stamps.clear();
+        }
+    }
+
+    /// Get how third-party imports are reported
+    pub fn import_granularity(&self) -> ImportGranularity {
+        self.import_granularity
+    }
+
+    /// Change how third-party imports are reported. Applies to the next processing pass.
+    pub fn set_import_granularity(&mut self, granularity: ImportGranularity) {
+        self.import_granularity = granularity;
+    }
+
+    /// Total number of files parsed by this manager. Files served from the cache don't count.
+    pub fn parse_count(&self) -> usize {
+        self.parse_count.load(Ordering::Relaxed)
     }
 
     /// Get the project name
@@ -73,6 +408,60 @@ impl ProjectAstManager {
         &self.package_name
     }
 
+    /// Every package treated as first party
+    pub fn package_names(&self) -> &HashSet<String> {
+        &self.package_names
+    }
+
+    /// Treat imports of any of these packages as first party, for projects that expose several
+    /// top-level packages. The project name is always kept.
+    pub fn set_package_names<I, S>(&mut self, package_names: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.package_names = package_names.into_iter().map(Into::into).collect();
+        self.package_names.insert(self.package_name.clone());
+    }
+
+    /// Module prefixes that are always treated as third party
+    pub fn third_party_prefixes(&self) -> &HashSet<String> {
+        &self.third_party_prefixes
+    }
+
+    /// Treat modules under these prefixes as third party even when they fall under a
+    /// first-party package, like a library vendored at `mypkg.vendor.requests`. This is the
+    /// inverse of the ignored modules. A prefix matches itself and its submodules.
+    pub fn set_third_party_prefixes<I, S>(&mut self, prefixes: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.third_party_prefixes = prefixes.into_iter().map(Into::into).collect();
+    }
+
+    /// Whether a module falls under one of the forced third-party prefixes
+    fn is_forced_third_party(&self, module: &str) -> bool {
+        self.third_party_prefixes
+            .iter()
+            .any(|prefix| is_module_or_submodule(module, prefix))
+    }
+
+    /// Standard library modules excluded from the third-party imports
+    pub fn stdlib_modules(&self) -> &HashSet<String> {
+        &self.stdlib_modules
+    }
+
+    /// Exclude these top-level standard library modules (and their submodules) from the
+    /// third-party imports. Empty by default, which keeps every import.
+    pub fn set_stdlib_modules<I, S>(&mut self, stdlib_modules: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.stdlib_modules = stdlib_modules.into_iter().map(Into::into).collect();
+    }
+
     /// Get the project path
     pub fn get_project_path(&self) -> &str {
         &self.project_path
@@ -80,47 +469,142 @@ impl ProjectAstManager {
 
     /// Process all Python files in the project and extract third-party imports.
     /// This will have the side-effect of updating `self.file_imports` with ALL imports,
-    /// but will only return third-party imports.
+    /// but will only return third-party imports. Files that fail to parse are logged and
+    /// skipped, see `process_all_py_files_with_failures` to inspect them.
     pub fn process_all_py_files(&mut self) -> Result<HashSet<String>> {
-        let mut third_party_imports = HashSet::new();
+        Ok(self
+            .process_all_py_files_with_failures()?
+            .third_party_imports)
+    }
+
+    /// Same as `process_all_py_files`, but also returns the files that couldn't be parsed.
+    /// A single broken file (say, one using syntax from a newer Python) shouldn't take down
+    /// the whole boot, so we keep scanning and report the failures at the end.
+    pub fn process_all_py_files_with_failures(&mut self) -> Result<ScanResult> {
+        let mut result = ScanResult::default();
         info!("Processing all Python files in: {}", self.project_path);
 
-        // Walk through all files in the project
-        for entry in WalkDir::new(&self.project_path)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-        {
-            let path = entry.path();
-            if let Some(extension) = path.extension() {
-                if extension != "py" {
+        // Reading and parsing is independent per file, so it runs across all cores. The
+        // package name is fixed at construction, so nothing here depends on scan order.
+        let paths = self.find_py_files();
+        let scan_path = |path: PathBuf| {
+            let scan = path
+                .to_str()
+                .ok_or_else(|| anyhow!("Path is not valid UTF-8"))
+                .and_then(|path_str| self.scan_py_file(path_str));
+            (path, scan)
+        };
+        let scans: Vec<(PathBuf, Result<FileScan>)> = if self.parallel {
+            paths.into_par_iter().map(scan_path).collect()
+        } else {
+            paths.into_iter().map(scan_path).collect()
+        };
+
+        // Merge serially, in walk order, so caches and failures are deterministic
+        let mut walked = Vec::new();
+        for (path, scan) in scans {
+            let path_str = path.to_string_lossy().to_string();
+
+            let imports = match scan {
+                Ok(scan) => self.apply_file_scan(&path_str, scan),
+                Err(e) => {
+                    warn!("Skipping {}: {}", path_str, e);
+                    result.add_failure(path, e);
                     continue;
                 }
+            };
+            debug!("Found {} imports in {}", imports.len(), path_str);
+            walked.push((path, imports));
+        }
 
-                let path_str = path.to_str().ok_or_else(|| {
-                    anyhow::anyhow!("Failed to convert path to string: {:?}", path)
-                })?;
-                debug!("Processing Python file: {}", path_str);
-
-                // Process the file
-                let imports = self.process_py_file(path_str)?;
-                debug!("Found {} imports in {}", imports.len(), path_str);
-
-                // Add third-party imports to the result
-                for import in &imports {
-                    if self.is_third_party_import(import) {
-                        debug!("Found third-party import: {:?}", import);
-                        third_party_imports.insert(import.module.clone());
-                    } else {
-                        trace!("Skipping first-party import: {:?}", import);
-                    }
+        // Files we didn't walk are still loaded if an `__init__.py` pulls them in
+        let reexported = self.scan_reexported_files(&walked);
+        let mut file_imports = walked;
+        let mut reached = HashSet::new();
+        for (path, scan) in reexported {
+            let path_str = path.to_string_lossy().to_string();
+            let imports = self.apply_file_scan(&path_str, scan);
+            reached.insert(path_str);
+            file_imports.push((path, imports));
+        }
+
+        // Excluded files that are no longer re-exported stop counting towards the baseline
+        let stale: Vec<String> = self
+            .file_imports
+            .keys()
+            .filter(|path| !reached.contains(*path) && self.is_excluded_file(Path::new(path)))
+            .cloned()
+            .collect();
+        for path in stale {
+            self.file_imports.remove(&path);
+            self.file_hashes.remove(&path);
+            self.file_stamps.remove(&path);
+        }
+
+        for (_, imports) in &file_imports {
+            // Add third-party imports to the result
+            for import in imports {
+                if self.is_third_party_import(import) {
+                    debug!("Found third-party import: {:?}", import);
+                    result
+                        .third_party_imports
+                        .insert(self.preload_module_name(import));
+                } else {
+                    trace!("Skipping first-party import: {:?}", import);
                 }
             }
         }
 
-        info!("Found {} third-party imports", third_party_imports.len());
-        trace!("Third-party imports: {:?}", third_party_imports);
-        Ok(third_party_imports)
+        if self.build_import_graph {
+            result.import_graph = self.first_party_import_graph(&file_imports);
+            result.import_cycles = import_cycles(&result.import_graph);
+            for cycle in &result.import_cycles {
+                warn!(
+                    "Circular import between first-party modules: {} -> {}",
+                    cycle.join(" -> "),
+                    cycle[0]
+                );
+            }
+        }
+
+        result.sort_failures();
+
+        info!(
+            "Found {} third-party imports",
+            result.third_party_imports.len()
+        );
+        if !result.parse_failures.is_empty() {
+            warn!(
+                "{} Python files could not be parsed",
+                result.parse_failures.len()
+            );
+        }
+        trace!("Third-party imports: {:?}", result.third_party_imports);
+        Ok(result)
+    }
+
+    /// Every `.py` file in the project, skipping the excluded directories
+    fn find_py_files(&self) -> Vec<PathBuf> {
+        WalkDir::new(&self.project_path)
+            .into_iter()
+            // Prune during traversal so we never descend into excluded trees
+            .filter_entry(|e| {
+                e.depth() == 0
+                    || !e.file_type().is_dir()
+                    || !self
+                        .excluded_dirs
+                        .contains(e.file_name().to_string_lossy().as_ref())
+            })
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| {
+                e.path()
+                    .extension()
+                    .is_some_and(|extension| extension == "py")
+            })
+            .filter(|e| !self.is_excluded_file(e.path()))
+            .map(|e| e.into_path())
+            .collect()
     }
 
     /// Compute the delta of imports between the current state and the previous state
@@ -133,128 +617,997 @@ impl ProjectAstManager {
     /// Returns (added modules, removed modules)
     pub fn compute_import_delta(&mut self) -> Result<(HashSet<String>, HashSet<String>)> {
         // Copy previous imports
-        let previous_imports: HashSet<String> = self
-            .file_imports
-            .values()
-            .flatten()
-            .filter(|imp| self.is_third_party_import(imp))
-            .map(|imp| imp.module.clone())
-            .collect();
+        let previous_imports = self.baseline_third_party_imports();
 
         // Get current imports
         let current_imports = self.process_all_py_files()?;
 
-        // Calculate added and removed imports
-        let added: HashSet<String> = current_imports
-            .difference(&previous_imports)
-            .cloned()
+        Ok(import_delta(&previous_imports, &current_imports))
+    }
+
+    /// Same as `compute_import_delta`, but without updating any caches. The next
+    /// `compute_import_delta` still compares against the same baseline, so this can be used to
+    /// preview what a reload would change before committing to it.
+    pub fn peek_import_delta(&self) -> Result<(HashSet<String>, HashSet<String>)> {
+        let previous_imports = self.baseline_third_party_imports();
+
+        let scan_path = |path: PathBuf| -> Option<(PathBuf, Vec<ImportInfo>)> {
+            let path_str = path.to_str()?;
+            match self.scan_py_file(path_str) {
+                Ok(scan) => Some((path, scan.into_imports())),
+                Err(e) => {
+                    debug!("Skipping {} while peeking at imports: {}", path_str, e);
+                    None
+                }
+            }
+        };
+        let paths = self.find_py_files();
+        let mut scanned: Vec<(PathBuf, Vec<ImportInfo>)> = if self.parallel {
+            paths.into_par_iter().filter_map(scan_path).collect()
+        } else {
+            paths.into_iter().filter_map(scan_path).collect()
+        };
+        let reexported = self.scan_reexported_files(&scanned);
+        scanned.extend(
+            reexported
+                .into_iter()
+                .map(|(path, scan)| (path, scan.into_imports())),
+        );
+
+        let current_imports: HashSet<String> = scanned
+            .iter()
+            .flat_map(|(_, imports)| imports)
+            .filter(|imp| self.is_third_party_import(imp))
+            .map(|imp| self.preload_module_name(imp))
             .collect();
 
-        let removed: HashSet<String> = previous_imports
-            .difference(&current_imports)
-            .cloned()
+        Ok(import_delta(&previous_imports, &current_imports))
+    }
+
+    /// Re-scan only the given files, like the one an editor just saved, and merge their
+    /// imports into the per-file cache that `compute_import_delta` compares against. Relative
+    /// paths are taken from the project root. Deleted files stop contributing, while files the
+    /// walk would skip and files that fail to parse keep whatever they contributed before.
+    /// Returns the third-party modules added and removed by these files.
+    pub fn process_files(
+        &mut self,
+        paths: &[PathBuf],
+    ) -> Result<(HashSet<String>, HashSet<String>)> {
+        let previous_imports = self.baseline_third_party_imports();
+
+        for path in paths {
+            let path = Path::new(&self.project_path).join(path);
+            let path_str = path
+                .to_str()
+                .ok_or_else(|| anyhow!("Path is not valid UTF-8: {}", path.display()))?
+                .to_string();
+
+            if !path.exists() {
+                debug!("{} no longer exists, dropping its imports", path_str);
+                self.file_imports.remove(&path_str);
+                self.file_hashes.remove(&path_str);
+                self.file_stamps.remove(&path_str);
+                continue;
+            }
+
+            let relative = path.strip_prefix(&self.project_path).unwrap_or(&path);
+            if path.extension().is_none_or(|extension| extension != "py")
+                || self.is_excluded_dir_entry(relative)
+                || self.is_excluded_file(&path)
+            {
+                debug!("Skipping {}, which isn't part of the scan", path_str);
+                continue;
+            }
+
+            match self.scan_py_file(&path_str) {
+                Ok(scan) => {
+                    let imports = self.apply_file_scan(&path_str, scan);
+                    debug!("Found {} imports in {}", imports.len(), path_str);
+                }
+                Err(e) => warn!("Skipping {}: {}", path_str, e),
+            }
+        }
+
+        let current_imports = self.baseline_third_party_imports();
+        Ok(import_delta(&previous_imports, &current_imports))
+    }
+
+    /// Scan the files that `__init__.py` re-exports pull in but the walk skipped, say because
+    /// they match an exclude glob. Importing the package imports them too, so their third-party
+    /// imports are still eagerly loaded. Relative imports are followed transitively from every
+    /// `__init__.py`, and each file is visited once so cyclic re-exports terminate.
+    fn scan_reexported_files(
+        &self,
+        walked: &[(PathBuf, Vec<ImportInfo>)],
+    ) -> Vec<(PathBuf, FileScan)> {
+        let walked_imports: HashMap<&Path, &[ImportInfo]> = walked
+            .iter()
+            .map(|(path, imports)| (path.as_path(), imports.as_slice()))
             .collect();
 
-        debug!("Import delta - added: {:?}, removed: {:?}", added, removed);
-        Ok((added, removed))
+        let mut visited: HashSet<PathBuf> = HashSet::new();
+        let mut queue: Vec<PathBuf> = walked
+            .iter()
+            .filter(|(path, _)| path.file_name().is_some_and(|name| name == "__init__.py"))
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut reexported = Vec::new();
+
+        while let Some(path) = queue.pop() {
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+
+            let imports = match walked_imports.get(path.as_path()) {
+                Some(imports) => imports.to_vec(),
+                None => {
+                    let Some(path_str) = path.to_str() else {
+                        continue;
+                    };
+                    match self.scan_py_file(path_str) {
+                        Ok(scan) => {
+                            debug!("Scanning {} since it is re-exported", path_str);
+                            let imports = scan.imports().to_vec();
+                            reexported.push((path.clone(), scan));
+                            imports
+                        }
+                        Err(e) => {
+                            debug!("Skipping re-exported {}: {}", path_str, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            queue.extend(
+                imports
+                    .iter()
+                    .filter(|import| import.is_relative)
+                    .flat_map(|import| self.relative_import_files(import)),
+            );
+        }
+
+        reexported
+    }
+
+    /// Project files a resolved relative import loads. This is the module itself plus, since
+    /// `from . import x` may name a submodule, any imported name that is one.
+    fn relative_import_files(&self, import: &ImportInfo) -> Vec<PathBuf> {
+        let Some(resolved) = &import.resolved_module else {
+            return Vec::new();
+        };
+
+        std::iter::once(resolved.clone())
+            .chain(
+                import
+                    .names
+                    .iter()
+                    .filter(|name| name.as_str() != "*")
+                    .map(|name| format!("{}.{}", resolved, name)),
+            )
+            .filter_map(|module| self.module_file(&module))
+            .collect()
+    }
+
+    /// The file that defines a first-party module, either `<module>.py` or a package's
+    /// `<module>/__init__.py`
+    fn module_file(&self, module: &str) -> Option<PathBuf> {
+        let mut parts = module.split('.');
+        if parts.next()? != self.package_name {
+            return None;
+        }
+
+        let module_path = parts.fold(PathBuf::from(&self.project_path), |path, part| {
+            path.join(part)
+        });
+        let package_init = module_path.join("__init__.py");
+        if package_init.is_file() {
+            return Some(package_init);
+        }
+        let module_file = module_path.with_extension("py");
+        module_file.is_file().then_some(module_file)
+    }
+
+    /// Third-party imports as of the last scan, without scanning again
+    pub fn third_party_imports(&self) -> HashSet<String> {
+        self.baseline_third_party_imports()
+    }
+
+    /// Third-party imports as of the last scan
+    fn baseline_third_party_imports(&self) -> HashSet<String> {
+        self.file_imports
+            .values()
+            .flatten()
+            .filter(|imp| self.is_third_party_import(imp))
+            .map(|imp| self.preload_module_name(imp))
+            .collect()
+    }
+
+    /// Process a single Python file and extract its imports, updating the caches
+    pub fn process_py_file(&mut self, file_path: &str) -> Result<Vec<ImportInfo>> {
+        let scan = self.scan_py_file(file_path)?;
+        Ok(self.apply_file_scan(file_path, scan))
     }
 
-    /// Process a single Python file and extract its imports
-    fn process_py_file(&mut self, file_path: &str) -> Result<Vec<ImportInfo>> {
+    /// Read and parse a single file against our caches, without updating them. This only
+    /// needs shared access, so many files can be scanned in parallel.
+    fn scan_py_file(&self, file_path: &str) -> Result<FileScan> {
         debug!("Processing Python file: {}", file_path);
 
+        // Cheapest check first: an identical mtime and size means we don't need to read it
+        let stamp = FileStamp::read(file_path);
+        if stamp.is_some() && stamp.as_ref() == self.file_stamps.get(file_path) {
+            if let Some(imports) = self.file_imports.get(file_path) {
+                trace!(
+                    "File {} has the same mtime and size, using cached imports",
+                    file_path
+                );
+                return Ok(FileScan::Unchanged {
+                    stamp,
+                    imports: imports.clone(),
+                });
+            }
+        }
+
         // Calculate hash of the file content
         let new_hash = self.calculate_file_hash(file_path)?;
 
-        // Check if we have already processed this file and if the content has changed
+        // Check if we have already processed this file and if the content has changed.
+        // This catches files that were touched or rewritten without any real change.
         if let Some(old_hash) = self.file_hashes.get(file_path) {
             if old_hash == &new_hash {
                 // File hasn't changed, return cached imports
                 debug!("File {} hasn't changed, using cached imports", file_path);
-                return Ok(self
-                    .file_imports
-                    .get(file_path)
-                    .cloned()
-                    .unwrap_or_default());
+                return Ok(FileScan::Unchanged {
+                    stamp,
+                    imports: self
+                        .file_imports
+                        .get(file_path)
+                        .cloned()
+                        .unwrap_or_default(),
+                });
             }
         }
 
         // File is new or has changed, parse it
         debug!("Parsing file: {}", file_path);
-        let source = fs::read_to_string(file_path)?;
+        let source = read_python_source(file_path)?;
+        let package_parts = Path::new(file_path)
+            .strip_prefix(&self.project_path)
+            .ok()
+            .map(|relative_path| self.package_parts(relative_path));
+        let imports = self.parse_imports(&source, file_path, package_parts.as_deref())?;
+
+        Ok(FileScan::Parsed {
+            hash: new_hash,
+            stamp,
+            imports,
+        })
+    }
+
+    /// Parse a file's source and collect its imports. Relative imports are resolved when we
+    /// know which package the file belongs to.
+    fn parse_imports(
+        &self,
+        source: &str,
+        file_path: &str,
+        package_parts: Option<&[String]>,
+    ) -> Result<Vec<ImportInfo>> {
         trace!("File content size: {} bytes", source.len());
 
-        let parsed = parse(&source, Mode::Module, file_path)
-            .map_err(|e| anyhow!("Failed to parse {}: {:?}", file_path, e))?;
+        self.parse_count.fetch_add(1, Ordering::Relaxed);
+        let parsed = parse(source, Mode::Module, file_path)
+            .map_err(|e| SyntaxError::from_parse_error(source, e))?;
+
+        // Extract statements from the module. Anything we can't take statements from is
+        // returned as an error, which skips just this file and reports it as a parse failure.
+        let stmts = module_body(&parsed, file_path)?;
+        debug!("Extracted {} statements from {}", stmts.len(), file_path);
+
+        // Collect imports
+        let mut imports = collect_imports_with_options(stmts, &self.collect_options);
+        debug!("Collected {} imports from {}", imports.len(), file_path);
+
+        if let Some(package_parts) = package_parts {
+            resolve_relative_imports(&mut imports, package_parts);
+        }
+        Ok(imports)
+    }
+
+    /// The dotted package a file belongs to, as path segments, given its path relative to the
+    /// project root. The project path is the root of our package, so `sub/module.py` is in the
+    /// `<package>.sub` package.
+    fn package_parts(&self, relative_path: &Path) -> Vec<String> {
+        let mut package_parts = vec![self.package_name.clone()];
+        if let Some(parent) = relative_path.parent() {
+            package_parts.extend(
+                parent
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().to_string()),
+            );
+        }
+        package_parts
+    }
+
+    /// Dotted module name of a file, given its path relative to the project root
+    fn file_module_name(&self, relative_path: &Path) -> String {
+        let mut parts = self.package_parts(relative_path);
+        if let Some(stem) = relative_path.file_stem() {
+            if stem != "__init__" {
+                parts.push(stem.to_string_lossy().to_string());
+            }
+        }
+        parts.join(".")
+    }
+
+    /// Which scanned modules each scanned module imports. `from pkg import name` counts as
+    /// importing `pkg.name` when that's a module, and `pkg` otherwise. The implicit import
+    /// of parent packages isn't recorded, since every submodule would otherwise form a cycle
+    /// with an `__init__.py` that re-exports it.
+    fn first_party_import_graph(
+        &self,
+        file_imports: &[(PathBuf, Vec<ImportInfo>)],
+    ) -> BTreeMap<String, BTreeSet<String>> {
+        let modules: BTreeMap<String, &[ImportInfo]> = file_imports
+            .iter()
+            .map(|(path, imports)| {
+                let relative = path.strip_prefix(&self.project_path).unwrap_or(path);
+                (self.file_module_name(relative), imports.as_slice())
+            })
+            .collect();
+
+        modules
+            .iter()
+            .map(|(module, imports)| {
+                let targets = imports
+                    .iter()
+                    .filter_map(|import| {
+                        import.resolved_module.as_deref().map(|base| (import, base))
+                    })
+                    .flat_map(|(import, base)| {
+                        if !import.is_from_import || import.is_star {
+                            return vec![base.to_string()];
+                        }
+                        import
+                            .names
+                            .iter()
+                            .map(|name| {
+                                let submodule = format!("{}.{}", base, name);
+                                if modules.contains_key(&submodule) {
+                                    submodule
+                                } else {
+                                    base.to_string()
+                                }
+                            })
+                            .collect()
+                    })
+                    .filter(|target| target != module && modules.contains_key(target))
+                    .collect();
+                (module.clone(), targets)
+            })
+            .collect()
+    }
+
+    /// Scan the Python files of a project packed into a zip archive, without extracting it.
+    /// The archive root stands in for the project path, and files are parsed exactly as they
+    /// would be on disk, honoring the excluded directories and globs. Archives don't change
+    /// under us, so nothing is cached and the import baseline is left alone.
+    pub fn process_zip_archive<R: Read + Seek>(
+        &self,
+        archive: &mut ZipArchive<R>,
+    ) -> Result<ScanResult> {
+        let mut result = ScanResult::default();
+        info!("Processing Python files in a zip archive");
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let Some(path) = entry.enclosed_name() else {
+                warn!("Skipping unsafe archive entry {}", entry.name());
+                continue;
+            };
+            if !entry.is_file()
+                || path.extension().is_none_or(|extension| extension != "py")
+                || self.is_excluded_dir_entry(&path)
+                || self.is_excluded_file(&path)
+            {
+                continue;
+            }
+
+            let path_str = path.to_string_lossy().to_string();
+            let mut bytes = Vec::new();
+            let imports = entry
+                .read_to_end(&mut bytes)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| {
+                    let source = decode_python_source(&path_str, &bytes);
+                    let package_parts = self.package_parts(&path);
+                    self.parse_imports(&source, &path_str, Some(&package_parts))
+                });
+            let imports = match imports {
+                Ok(imports) => imports,
+                Err(e) => {
+                    warn!("Skipping {}: {}", path_str, e);
+                    result.add_failure(path, e);
+                    continue;
+                }
+            };
+
+            result.third_party_imports.extend(
+                imports
+                    .iter()
+                    .filter(|import| self.is_third_party_import(import))
+                    .map(|import| self.preload_module_name(import)),
+            );
+        }
+
+        result.sort_failures();
+        info!(
+            "Found {} third-party imports in the archive",
+            result.third_party_imports.len()
+        );
+        Ok(result)
+    }
+
+    /// Whether a path relative to the project root passes through an excluded directory
+    fn is_excluded_dir_entry(&self, relative_path: &Path) -> bool {
+        relative_path
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .any(|component| {
+                self.excluded_dirs
+                    .contains(component.as_os_str().to_string_lossy().as_ref())
+            })
+    }
+
+    /// Merge the result of `scan_py_file` back into our caches
+    fn apply_file_scan(&mut self, file_path: &str, scan: FileScan) -> Vec<ImportInfo> {
+        match scan {
+            FileScan::Unchanged { stamp, imports } => {
+                if let Some(stamp) = stamp {
+                    self.file_stamps.insert(file_path.to_string(), stamp);
+                }
+                imports
+            }
+            FileScan::Parsed {
+                hash,
+                stamp,
+                imports,
+            } => {
+                // Update caches
+                self.file_hashes.insert(file_path.to_string(), hash);
+                match stamp {
+                    Some(stamp) => self.file_stamps.insert(file_path.to_string(), stamp),
+                    None => self.file_stamps.remove(file_path),
+                };
+                self.file_imports
+                    .insert(file_path.to_string(), imports.clone());
+                imports
+            }
+        }
+    }
+
+    /// Calculate SHA256 hash of file content
+    fn calculate_file_hash(&self, file_path: &str) -> Result<String> {
+        let content = fs::read(file_path)?;
+        let mut hasher = Sha256::new();
+        hasher.update(&content);
+        let hash = hasher.finalize();
+        let hash_str = format!("{:x}", hash);
+        trace!("Calculated hash for {}: {}", file_path, hash_str);
+        Ok(hash_str)
+    }
+
+    /// The module name we hand to the loader for an import, according to our granularity
+    fn preload_module_name(&self, imp: &ImportInfo) -> String {
+        match self.import_granularity {
+            // Preloading the top level of a vendored module would load our own package
+            ImportGranularity::TopLevel
+                if !imp.is_from_import && !self.is_forced_third_party(&imp.module) =>
+            {
+                imp.module
+                    .split('.')
+                    .next()
+                    .unwrap_or(&imp.module)
+                    .to_string()
+            }
+            _ => imp.module.clone(),
+        }
+    }
+
+    /// Check if an import is a third-party import
+    fn is_third_party_import(&self, imp: &ImportInfo) -> bool {
+        trace!("Checking if import is third party: {:?}", imp);
+        trace!("Package names: {:?}", self.package_names);
+
+        // If the module is in the ignored list, it's not considered third-party
+        if self.ignored_modules.contains(&imp.module) {
+            return false;
+        }
+
+        // Standard library modules are already available to the interpreter
+        let top_level = imp.module.split('.').next().unwrap_or(&imp.module);
+        if self.stdlib_modules.contains(top_level) {
+            return false;
+        }
+
+        let is_third_party = !imp.is_relative
+            && (self.is_forced_third_party(&imp.module)
+                || !self
+                    .package_names
+                    .iter()
+                    .any(|package| is_module_or_submodule(&imp.module, package)));
+
+        trace!("Is third party: {}", is_third_party);
+        is_third_party
+    }
+}
+
+/// Whether `module` is `package` itself or one of its submodules. A plain prefix match would
+/// also count `pkg_extra` as part of `pkg`.
+fn is_module_or_submodule(module: &str, package: &str) -> bool {
+    module == package || module.starts_with(&format!("{package}."))
+}
+
+/// Stable SHA256 of a set of modules, independent of iteration order. Two scans that find
+/// the same imports hash the same, however the files were touched in between.
+pub fn import_hash(modules: &HashSet<String>) -> String {
+    let mut sorted: Vec<&String> = modules.iter().collect();
+    sorted.sort();
+
+    let mut hasher = Sha256::new();
+    for module in sorted {
+        hasher.update(module.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// The top-level statements of a parsed file. Parsing in module mode should always give us a
+/// `Module`, but an interactive body is just as usable. Expression and function type ASTs
+/// don't have statements to collect imports from.
+fn module_body<'a>(parsed: &'a Mod, file_path: &str) -> Result<&'a [Stmt]> {
+    match parsed {
+        Mod::Module(module) => Ok(&module.body),
+        Mod::Interactive(interactive) => Ok(&interactive.body),
+        Mod::Expression(_) | Mod::FunctionType(_) => Err(anyhow!(
+            "Unexpected AST format for module in file {}",
+            file_path
+        )),
+    }
+}
+
+/// Read a Python source file as text. A UTF-8 BOM is stripped and a PEP 263 coding
+/// declaration of Latin-1 is honored. Anything else that isn't valid UTF-8 is decoded lossily,
+/// so one oddly encoded file can't abort a scan of the whole project.
+fn read_python_source(file_path: &str) -> Result<String> {
+    let bytes = fs::read(file_path)?;
+    Ok(decode_python_source(file_path, &bytes))
+}
+
+/// Decode the raw bytes of a Python source file, see `read_python_source`
+fn decode_python_source(file_path: &str, bytes: &[u8]) -> String {
+    if let Some(bytes) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        debug!("Stripping the UTF-8 BOM from {}", file_path);
+        return decode_utf8(file_path, bytes, "utf-8-sig");
+    }
+
+    match declared_encoding(bytes) {
+        Some(encoding)
+            if matches!(
+                encoding.as_str(),
+                "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" | "l1"
+            ) =>
+        {
+            warn!("Decoding {} as {}", file_path, encoding);
+            // Latin-1 maps every byte to the code point of the same value
+            bytes.iter().map(|&byte| byte as char).collect()
+        }
+        Some(encoding) if !matches!(encoding.as_str(), "utf-8" | "utf8") => {
+            warn!(
+                "{} declares the unsupported encoding {}, decoding it as UTF-8",
+                file_path, encoding
+            );
+            decode_utf8(file_path, bytes, &encoding)
+        }
+        _ => decode_utf8(file_path, bytes, "utf-8"),
+    }
+}
+
+/// Decode UTF-8, replacing invalid sequences with a warning that names the file
+fn decode_utf8(file_path: &str, bytes: &[u8], encoding: &str) -> String {
+    match String::from_utf8_lossy(bytes) {
+        std::borrow::Cow::Borrowed(source) => source.to_string(),
+        std::borrow::Cow::Owned(source) => {
+            warn!(
+                "{} is not valid {}, replacing the undecodable bytes",
+                file_path, encoding
+            );
+            source
+        }
+    }
+}
+
+/// The encoding named by a PEP 263 coding comment, which must be on one of the first two
+/// lines. Lowercased, with `_` normalized to `-`.
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    bytes.split(|&byte| byte == b'\n').take(2).find_map(|line| {
+        let line = String::from_utf8_lossy(line);
+        let comment = line.trim_start().strip_prefix('#')?;
+        let (_, rest) = comment.split_once("coding")?;
+        let rest = rest.strip_prefix([':', '='])?.trim_start();
+        let encoding: String = rest
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            .collect();
+        (!encoding.is_empty()).then(|| encoding.to_lowercase().replace('_', "-"))
+    })
+}
+
+/// Modules in `current` but not `previous`, and the other way around
+fn import_delta(
+    previous: &HashSet<String>,
+    current: &HashSet<String>,
+) -> (HashSet<String>, HashSet<String>) {
+    let added: HashSet<String> = current.difference(previous).cloned().collect();
+    let removed: HashSet<String> = previous.difference(current).cloned().collect();
+
+    debug!("Import delta - added: {:?}, removed: {:?}", added, removed);
+    (added, removed)
+}
+
+/// Every cycle in an import graph, one per strongly connected component. Each is walked from
+/// its alphabetically first module along the shortest path back to it, so a large tangle is
+/// reported as one representative loop rather than every loop through it.
+fn import_cycles(graph: &BTreeMap<String, BTreeSet<String>>) -> Vec<Vec<String>> {
+    // Tarjan's algorithm, with an explicit stack so deep import chains can't overflow
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut lowlink: HashMap<&str, usize> = HashMap::new();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut on_stack: HashSet<&str> = HashSet::new();
+    let mut components: Vec<Vec<&str>> = Vec::new();
+
+    for root in graph.keys() {
+        if index.contains_key(root.as_str()) {
+            continue;
+        }
+        let mut work: Vec<(&str, Vec<&str>)> = Vec::new();
+        let visit = |module: &'_ str| -> Vec<&'_ str> {
+            graph
+                .get(module)
+                .into_iter()
+                .flatten()
+                .map(|target| target.as_str())
+                .rev()
+                .collect()
+        };
+        let next_index = index.len();
+        index.insert(root, next_index);
+        lowlink.insert(root, next_index);
+        stack.push(root);
+        on_stack.insert(root);
+        work.push((root, visit(root)));
+
+        while let Some((module, pending)) = work.last_mut() {
+            let module = *module;
+            if let Some(target) = pending.pop() {
+                if !index.contains_key(target) {
+                    let next_index = index.len();
+                    index.insert(target, next_index);
+                    lowlink.insert(target, next_index);
+                    stack.push(target);
+                    on_stack.insert(target);
+                    work.push((target, visit(target)));
+                } else if on_stack.contains(target) {
+                    let low = lowlink[module].min(index[target]);
+                    lowlink.insert(module, low);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some((parent, _)) = work.last() {
+                let low = lowlink[parent].min(lowlink[module]);
+                lowlink.insert(parent, low);
+            }
+            if lowlink[module] == index[module] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(member);
+                    component.push(member);
+                    if member == module {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+
+    let mut cycles: Vec<Vec<String>> = components
+        .into_iter()
+        .filter(|component| component.len() > 1)
+        .filter_map(|component| {
+            let members: HashSet<&str> = component.iter().copied().collect();
+            let start = *component.iter().min()?;
+            shortest_cycle(graph, &members, start)
+        })
+        .collect();
+    cycles.sort();
+    cycles
+}
+
+/// Shortest path from `start` back to itself that stays within `members`
+fn shortest_cycle(
+    graph: &BTreeMap<String, BTreeSet<String>>,
+    members: &HashSet<&str>,
+    start: &str,
+) -> Option<Vec<String>> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = std::collections::VecDeque::from([start]);
+    while let Some(module) = queue.pop_front() {
+        for target in graph.get(module).into_iter().flatten() {
+            let target = target.as_str();
+            if target == start {
+                let mut cycle = vec![module.to_string()];
+                let mut current = module;
+                while current != start {
+                    current = previous[current];
+                    cycle.push(current.to_string());
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if members.contains(target) && !previous.contains_key(target) {
+                previous.insert(target, module);
+                queue.push_back(target);
+            }
+        }
+    }
+    None
+}
+
+/// Determine the importable package name of a project. Projects with a `src/` layout use the
+/// package directory beneath `src/`. Otherwise we read `project.name` (PEP 621) and then
+/// `tool.poetry.name` from the project's pyproject.toml, then `metadata.name` from setup.cfg,
+/// then the only package directory at the project root, and finally fall back to the name of
+/// the project directory. Distribution names are normalized to their import form, so
+/// `my-package` becomes `my_package`.
+///
+/// When `src/` holds several packages this returns the first in sorted order; use
+/// `detect_package_names` to get all of them.
+pub fn detect_package_name(project_path: &str) -> String {
+    package_dirs(&Path::new(project_path).join("src"))
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| declared_package_name(project_path))
+}
+
+/// Every first-party package of a project. A `src/` layout contributes each package beneath
+/// `src/`. Otherwise we take the declared package name along with any package directories
+/// at the project root, which covers monorepos that expose several sibling packages. A
+/// project root that is itself a package only contributes its declared name, since its
+/// subdirectories are subpackages.
+pub fn detect_package_names(project_path: &str) -> BTreeSet<String> {
+    let root = Path::new(project_path);
+
+    let src_packages = package_dirs(&root.join("src"));
+    if !src_packages.is_empty() {
+        debug!("Using packages from src/ layout: {:?}", src_packages);
+        return src_packages;
+    }
+
+    let mut packages = BTreeSet::from([declared_package_name(project_path)]);
+    if !root.join("__init__.py").is_file() {
+        packages.extend(package_dirs(root));
+    }
+    debug!("Detected first-party packages: {:?}", packages);
+    packages
+}
+
+/// Names of the directories directly beneath `dir` that contain an `__init__.py`. Empty when
+/// `dir` doesn't exist.
+fn package_dirs(dir: &Path) -> BTreeSet<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeSet::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && path.join("__init__.py").is_file())
+        .filter_map(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .collect()
+}
 
-        // Extract statements from the module
-        let stmts: &[Stmt] = match &parsed {
-            Mod::Module(module) => {
-                debug!(
-                    "Extracted {} statements from {}",
-                    module.body.len(),
-                    file_path
-                );
-                &module.body
-            }
-            _ => {
-                return Err(anyhow!(
-                    "Unexpected AST format for module in file {}",
-                    file_path
-                ))
-            }
-        };
+/// Same as `detect_package_name`, for a project packed into a zip archive. The manifests are
+/// read from the archive root, and `archive_name` (usually the archive's file stem) stands
+/// in for the directory name when nothing in the archive names the package.
+pub fn detect_package_name_in_zip<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    archive_name: &str,
+) -> String {
+    let src_package = archive
+        .file_names()
+        .filter_map(|name| {
+            let package = name.strip_prefix("src/")?.strip_suffix("/__init__.py")?;
+            (!package.contains('/')).then(|| package.to_string())
+        })
+        .min();
+    if let Some(package) = src_package {
+        return package;
+    }
 
-        // Collect imports
-        let imports = collect_imports(stmts);
-        debug!("Collected {} imports from {}", imports.len(), file_path);
+    let mut read_entry = |name: &str| -> Option<String> {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .ok()?
+            .read_to_string(&mut content)
+            .ok()?;
+        Some(content)
+    };
+    let pyproject = read_entry("pyproject.toml");
+    let setup_cfg = read_entry("setup.cfg");
+
+    manifest_package_name(pyproject, setup_cfg)
+        .or_else(|| single_package_entry(archive))
+        .unwrap_or_else(|| {
+            debug!(
+                "No package name declared in the archive, using {}",
+                archive_name
+            );
+            archive_name.to_string()
+        })
+        .replace('-', "_")
+}
 
-        // Update caches
-        self.file_hashes.insert(file_path.to_string(), new_hash);
-        self.file_imports
-            .insert(file_path.to_string(), imports.clone());
+/// The package name declared in pyproject.toml or setup.cfg, or the project directory name
+fn declared_package_name(project_path: &str) -> String {
+    let project_path = Path::new(project_path);
+
+    let declared_name = manifest_package_name(
+        fs::read_to_string(project_path.join("pyproject.toml")).ok(),
+        fs::read_to_string(project_path.join("setup.cfg")).ok(),
+    );
+
+    let name = declared_name
+        .or_else(|| single_package_dir(project_path))
+        .unwrap_or_else(|| {
+            debug!(
+                "No package name declared in pyproject.toml or setup.cfg, using the directory name"
+            );
+            project_path
+                .canonicalize()
+                .unwrap_or_else(|_| project_path.to_path_buf())
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+
+    name.replace('-', "_")
+}
 
-        Ok(imports)
+/// `single_package_dir` for a project packed into a zip archive
+fn single_package_entry<R: Read + Seek>(archive: &ZipArchive<R>) -> Option<String> {
+    if archive.index_for_name("__init__.py").is_some() {
+        return None;
     }
+    let packages: BTreeSet<&str> = archive
+        .file_names()
+        .filter_map(|name| name.strip_suffix("/__init__.py"))
+        .filter(|package| !package.contains('/'))
+        .collect();
+    if packages.len() != 1 {
+        return None;
+    }
+    let package = packages.into_iter().next()?.to_string();
+    debug!(
+        "No package name declared in the archive, using the only package directory {}",
+        package
+    );
+    Some(package)
+}
 
-    /// Calculate SHA256 hash of file content
-    fn calculate_file_hash(&self, file_path: &str) -> Result<String> {
-        let content = fs::read(file_path)?;
-        let mut hasher = Sha256::new();
-        hasher.update(&content);
-        let hash = hasher.finalize();
-        let hash_str = format!("{:x}", hash);
-        trace!("Calculated hash for {}: {}", file_path, hash_str);
-        Ok(hash_str)
+/// The one package directory at the root of a project without metadata. The checkout's own
+/// directory name is often something like `src` or a commit hash, so the package it holds is
+/// a better guess. A root that is itself a package, or that holds several, has no single answer.
+fn single_package_dir(project_path: &Path) -> Option<String> {
+    if project_path.join("__init__.py").is_file() {
+        return None;
+    }
+    let packages = package_dirs(project_path);
+    if packages.len() != 1 {
+        return None;
     }
+    let package = packages.into_iter().next()?;
+    debug!(
+        "No package name declared, using the only package directory {}",
+        package
+    );
+    Some(package)
+}
 
-    /// Check if an import is a third-party import
-    fn is_third_party_import(&self, imp: &ImportInfo) -> bool {
-        trace!("Checking if import is third party: {:?}", imp);
-        trace!("Package name: {}", self.package_name);
+/// The package name declared by the contents of a pyproject.toml, falling back to a
+/// setup.cfg
+fn manifest_package_name(pyproject: Option<String>, setup_cfg: Option<String>) -> Option<String> {
+    pyproject
+        .and_then(|content| match content.parse::<toml::Table>() {
+            Ok(table) => Some(table),
+            Err(e) => {
+                debug!("Failed to parse pyproject.toml: {}", e);
+                None
+            }
+        })
+        .and_then(|table| {
+            let project_name = table
+                .get("project")
+                .and_then(|project| project.get("name"))
+                .and_then(|name| name.as_str());
+            let poetry_name = table
+                .get("tool")
+                .and_then(|tool| tool.get("poetry"))
+                .and_then(|poetry| poetry.get("name"))
+                .and_then(|name| name.as_str());
+
+            project_name.or(poetry_name).map(|name| name.to_string())
+        })
+        .or_else(|| setup_cfg.and_then(|content| setup_cfg_name(&content)))
+}
 
-        // If the module is in the ignored list, it's not considered third-party
-        if self.ignored_modules.contains(&imp.module) {
-            return false;
+/// The `name` key of the `[metadata]` section of a setup.cfg. Follows configparser: keys
+/// are case insensitive, either `=` or `:` separates them from values, and lines starting
+/// with `#` or `;` are comments.
+fn setup_cfg_name(content: &str) -> Option<String> {
+    let mut in_metadata = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
         }
 
-        let is_third_party = !imp.is_relative && !imp.module.starts_with(&self.package_name);
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            in_metadata = section.trim() == "metadata";
+            continue;
+        }
 
-        trace!("Is third party: {}", is_third_party);
-        is_third_party
+        if !in_metadata {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(['=', ':']) {
+            if key.trim().eq_ignore_ascii_case("name") && !value.trim().is_empty() {
+                return Some(value.trim().to_string());
+            }
+        }
     }
+    None
 }
 
 /// Recursively traverse AST statements to collect import information.
 /// This does a nested traversal though all the possible imports in a file, like those
 /// embedded within functions.
 pub fn collect_imports(stmts: &[Stmt]) -> Vec<ImportInfo> {
-    collect_imports_with_level(stmts, 0)
+    collect_imports_with_options(stmts, &CollectOptions::default())
+}
+
+/// Same as `collect_imports`, with control over which blocks are traversed
+pub fn collect_imports_with_options(stmts: &[Stmt], options: &CollectOptions) -> Vec<ImportInfo> {
+    collect_imports_with_level(stmts, 0, options)
 }
 
 /// Internal function that tracks the nesting level of imports.
 /// Level 0 is the top level of the module, and it increases with each nesting.
-fn collect_imports_with_level(stmts: &[Stmt], level: u32) -> Vec<ImportInfo> {
+fn collect_imports_with_level(
+    stmts: &[Stmt],
+    level: u32,
+    options: &CollectOptions,
+) -> Vec<ImportInfo> {
     let mut imports = Vec::new();
     for stmt in stmts {
         trace!("Processing statement: {:?}", stmt);
@@ -267,7 +1620,10 @@ fn collect_imports_with_level(stmts: &[Stmt], level: u32) -> Vec<ImportInfo> {
                         names: vec![alias.name.to_string()],
                         is_relative: false,
                         is_from_import: false,
+                        is_star: false,
                         import_level: level,
+                        relative_level: 0,
+                        resolved_module: Some(alias.name.to_string()),
                     });
                 }
             }
@@ -283,17 +1639,23 @@ fn collect_imports_with_level(stmts: &[Stmt], level: u32) -> Vec<ImportInfo> {
                         .iter()
                         .map(|alias| alias.name.to_string())
                         .collect();
+                    let rel_level = import_from.level.map_or(0, |level| level.to_u32());
+                    let is_star = imported.iter().any(|name| name == "*");
                     imports.push(ImportInfo {
                         module: module_name.to_string(),
                         names: imported,
-                        is_relative: import_from.level.is_some_and(|level| level.to_u32() > 0),
+                        is_relative: rel_level > 0,
                         is_from_import: true,
+                        is_star,
                         import_level: level,
+                        relative_level: rel_level,
+                        resolved_module: (rel_level == 0).then(|| module_name.to_string()),
                     });
                 } else {
                     // Handle case where module is None (likely for relative imports like "from . import x")
                     debug!("Module is None, handling relative import");
-                    if import_from.level.is_some() && import_from.level.unwrap().to_u32() > 0 {
+                    let rel_level = import_from.level.map_or(0, |level| level.to_u32());
+                    if rel_level > 0 {
                         // This is a relative import
                         let imported: Vec<String> = import_from
                             .names
@@ -301,40 +1663,206 @@ fn collect_imports_with_level(stmts: &[Stmt], level: u32) -> Vec<ImportInfo> {
                             .map(|alias| alias.name.to_string())
                             .collect();
                         // Use a placeholder module name based on the relative level
-                        let rel_level = import_from.level.unwrap().to_u32();
                         let module_name = ".".repeat(rel_level as usize);
                         debug!("Created relative import with module: {}", module_name);
+                        let is_star = imported.iter().any(|name| name == "*");
                         imports.push(ImportInfo {
                             module: module_name,
                             names: imported,
                             is_relative: true,
                             is_from_import: true,
+                            is_star,
                             import_level: level,
+                            relative_level: rel_level,
+                            resolved_module: None,
                         });
                     }
                 }
             }
             Stmt::If(inner) => {
                 let if_stmt: &StmtIf = inner;
-                imports.extend(collect_imports_with_level(&if_stmt.body, level + 1));
-                imports.extend(collect_imports_with_level(&if_stmt.orelse, level + 1));
+                // Only one side of a `sys.version_info` check runs on the target interpreter,
+                // and the other usually imports a module that doesn't exist there
+                let version_check = options
+                    .python_version
+                    .and_then(|version| evaluate_version_check(&if_stmt.test, version));
+
+                // Imports guarded by `if TYPE_CHECKING:` only exist for type checkers and
+                // are never executed at runtime, so there's nothing to preload
+                if version_check == Some(false) {
+                    debug!("Skipping version-gated block at level {}", level);
+                } else if options.include_type_checking || !is_type_checking_guard(&if_stmt.test) {
+                    imports.extend(collect_imports_with_level(
+                        &if_stmt.body,
+                        level + 1,
+                        options,
+                    ));
+                } else {
+                    debug!("Skipping TYPE_CHECKING block at level {}", level);
+                }
+
+                if version_check == Some(true) {
+                    debug!("Skipping version-gated else block at level {}", level);
+                } else {
+                    imports.extend(collect_imports_with_level(
+                        &if_stmt.orelse,
+                        level + 1,
+                        options,
+                    ));
+                }
             }
             Stmt::While(inner) => {
                 let while_stmt: &StmtWhile = inner;
-                imports.extend(collect_imports_with_level(&while_stmt.body, level + 1));
-                imports.extend(collect_imports_with_level(&while_stmt.orelse, level + 1));
+                imports.extend(collect_imports_with_level(
+                    &while_stmt.body,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &while_stmt.orelse,
+                    level + 1,
+                    options,
+                ));
+            }
+            Stmt::For(inner) => {
+                let for_stmt: &StmtFor = inner;
+                imports.extend(collect_imports_with_level(
+                    &for_stmt.body,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &for_stmt.orelse,
+                    level + 1,
+                    options,
+                ));
+            }
+            Stmt::AsyncFor(inner) => {
+                let for_stmt: &StmtAsyncFor = inner;
+                imports.extend(collect_imports_with_level(
+                    &for_stmt.body,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &for_stmt.orelse,
+                    level + 1,
+                    options,
+                ));
+            }
+            Stmt::Match(inner) => {
+                let match_stmt: &StmtMatch = inner;
+                for case in &match_stmt.cases {
+                    imports.extend(collect_imports_with_level(&case.body, level + 1, options));
+                }
+            }
+            Stmt::Try(inner) => {
+                // Commonly used for optional dependencies, like `try: import ujson as json`
+                // with a fallback to the stdlib in the except handler
+                let try_stmt: &StmtTry = inner;
+                imports.extend(collect_imports_with_level(
+                    &try_stmt.body,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_except_handler_imports(
+                    &try_stmt.handlers,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &try_stmt.orelse,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &try_stmt.finalbody,
+                    level + 1,
+                    options,
+                ));
+            }
+            Stmt::TryStar(inner) => {
+                let try_stmt: &StmtTryStar = inner;
+                imports.extend(collect_imports_with_level(
+                    &try_stmt.body,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_except_handler_imports(
+                    &try_stmt.handlers,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &try_stmt.orelse,
+                    level + 1,
+                    options,
+                ));
+                imports.extend(collect_imports_with_level(
+                    &try_stmt.finalbody,
+                    level + 1,
+                    options,
+                ));
+            }
+            Stmt::Expr(inner) => {
+                if let Some(import) = dynamic_import(&inner.value, level) {
+                    imports.push(import);
+                }
+            }
+            Stmt::Assign(inner) => {
+                if let Some(import) = dynamic_import(&inner.value, level) {
+                    imports.push(import);
+                }
+            }
+            Stmt::AnnAssign(inner) => {
+                if let Some(import) = inner
+                    .value
+                    .as_ref()
+                    .and_then(|value| dynamic_import(value, level))
+                {
+                    imports.push(import);
+                }
+            }
+            Stmt::With(inner) => {
+                // For example `with suppress(ImportError): import optional_pkg`
+                let with_stmt: &StmtWith = inner;
+                imports.extend(collect_imports_with_level(
+                    &with_stmt.body,
+                    level + 1,
+                    options,
+                ));
+            }
+            Stmt::AsyncWith(inner) => {
+                let with_stmt: &StmtAsyncWith = inner;
+                imports.extend(collect_imports_with_level(
+                    &with_stmt.body,
+                    level + 1,
+                    options,
+                ));
             }
             Stmt::FunctionDef(inner) => {
                 let func_def: &StmtFunctionDef = inner;
-                imports.extend(collect_imports_with_level(&func_def.body, level + 1));
+                imports.extend(collect_imports_with_level(
+                    &func_def.body,
+                    level + 1,
+                    options,
+                ));
             }
             Stmt::AsyncFunctionDef(inner) => {
                 let func_def: &StmtAsyncFunctionDef = inner;
-                imports.extend(collect_imports_with_level(&func_def.body, level + 1));
+                imports.extend(collect_imports_with_level(
+                    &func_def.body,
+                    level + 1,
+                    options,
+                ));
             }
             Stmt::ClassDef(inner) => {
                 let class_def: &StmtClassDef = inner;
-                imports.extend(collect_imports_with_level(&class_def.body, level + 1));
+                imports.extend(collect_imports_with_level(
+                    &class_def.body,
+                    level + 1,
+                    options,
+                ));
             }
             _ => {}
         }
@@ -343,7 +1871,190 @@ fn collect_imports_with_level(stmts: &[Stmt], level: u32) -> Vec<ImportInfo> {
     imports
 }
 
+/// Fill in `resolved_module` for relative imports, given the package of the importing file.
+/// A single dot refers to that package and every extra dot walks up one parent.
+pub fn resolve_relative_imports(imports: &mut [ImportInfo], package_parts: &[String]) {
+    for import in imports.iter_mut().filter(|import| import.is_relative) {
+        let parents = import.relative_level.saturating_sub(1) as usize;
+        if import.relative_level == 0 || parents >= package_parts.len() {
+            debug!(
+                "Relative import {:?} reaches above the package root",
+                import.module
+            );
+            continue;
+        }
+
+        let mut resolved = package_parts[..package_parts.len() - parents].join(".");
+        // `from . import x` has no module of its own, just our placeholder dots
+        if !import.module.starts_with('.') {
+            resolved.push('.');
+            resolved.push_str(&import.module);
+        }
+        import.resolved_module = Some(resolved);
+    }
+}
+
+/// Whether an `if` test is the `TYPE_CHECKING` (or `typing.TYPE_CHECKING`) guard
+fn is_type_checking_guard(test: &Expr) -> bool {
+    match test {
+        Expr::Name(name) => name.id.as_str() == "TYPE_CHECKING",
+        Expr::Attribute(attribute) => {
+            attribute.attr.as_str() == "TYPE_CHECKING"
+                && matches!(
+                    attribute.value.as_ref(),
+                    Expr::Name(name) if matches!(name.id.as_str(), "typing" | "typing_extensions")
+                )
+        }
+        _ => false,
+    }
+}
+
+/// Evaluate an `if` test that compares `sys.version_info` against literals, like
+/// `sys.version_info >= (3, 11)`, `sys.version_info[:2] < (3, 8)` or
+/// `sys.version_info[0] == 3`. Returns None for anything that can't be evaluated statically.
+fn evaluate_version_check(test: &Expr, version: (u32, u32, u32)) -> Option<bool> {
+    let Expr::Compare(compare) = test else {
+        return None;
+    };
+    let ([op], [comparator]) = (compare.ops.as_slice(), compare.comparators.as_slice()) else {
+        return None;
+    };
+    let version = [version.0, version.1, version.2];
+
+    let ordering = match compare.left.as_ref() {
+        // The full `version_info` also carries a release level and serial, so it sorts after
+        // a tuple holding the same three numbers
+        left if is_version_info(left) => {
+            let right = int_tuple_literal(comparator)?;
+            version
+                .as_slice()
+                .cmp(&right)
+                .then(std::cmp::Ordering::Greater)
+        }
+        Expr::Subscript(subscript) if is_version_info(&subscript.value) => {
+            match subscript.slice.as_ref() {
+                Expr::Slice(slice) if slice.lower.is_none() && slice.step.is_none() => {
+                    let upper = int_literal(slice.upper.as_deref()?)? as usize;
+                    let right = int_tuple_literal(comparator)?;
+                    version.get(..upper)?.cmp(&right)
+                }
+                index => {
+                    let left = version.get(int_literal(index)? as usize)?;
+                    left.cmp(&int_literal(comparator)?)
+                }
+            }
+        }
+        _ => return None,
+    };
+
+    match op {
+        CmpOp::Lt => Some(ordering.is_lt()),
+        CmpOp::LtE => Some(ordering.is_le()),
+        CmpOp::Gt => Some(ordering.is_gt()),
+        CmpOp::GtE => Some(ordering.is_ge()),
+        CmpOp::Eq => Some(ordering.is_eq()),
+        CmpOp::NotEq => Some(ordering.is_ne()),
+        _ => None,
+    }
+}
+
+/// `sys.version_info`, or a bare `version_info` imported from `sys`
+fn is_version_info(expr: &Expr) -> bool {
+    match expr {
+        Expr::Name(name) => name.id.as_str() == "version_info",
+        Expr::Attribute(attribute) => {
+            attribute.attr.as_str() == "version_info"
+                && matches!(attribute.value.as_ref(), Expr::Name(name) if name.id.as_str() == "sys")
+        }
+        _ => false,
+    }
+}
+
+fn int_literal(expr: &Expr) -> Option<u32> {
+    match expr {
+        Expr::Constant(constant) => match &constant.value {
+            Constant::Int(value) => value.to_string().parse().ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn int_tuple_literal(expr: &Expr) -> Option<Vec<u32>> {
+    match expr {
+        Expr::Tuple(tuple) => tuple.elts.iter().map(int_literal).collect(),
+        _ => None,
+    }
+}
+
+/// Detect `importlib.import_module("name")` (or a bare `import_module("name")`) calls.
+/// Only string literals are resolved, since anything dynamic can't be known until runtime.
+/// Relative names are skipped as well, because they depend on the `package` argument.
+fn dynamic_import(expr: &Expr, level: u32) -> Option<ImportInfo> {
+    let call = match expr {
+        Expr::Call(call) => call,
+        // Comprehensions, async ones included, evaluate their element like any other call
+        Expr::ListComp(comprehension) => return dynamic_import(&comprehension.elt, level),
+        Expr::SetComp(comprehension) => return dynamic_import(&comprehension.elt, level),
+        Expr::GeneratorExp(comprehension) => return dynamic_import(&comprehension.elt, level),
+        Expr::DictComp(comprehension) => return dynamic_import(&comprehension.value, level),
+        _ => return None,
+    };
+
+    let is_import_module = match call.func.as_ref() {
+        Expr::Attribute(attribute) => {
+            attribute.attr.as_str() == "import_module"
+                && matches!(attribute.value.as_ref(), Expr::Name(name) if name.id.as_str() == "importlib")
+        }
+        Expr::Name(name) => name.id.as_str() == "import_module",
+        _ => false,
+    };
+    if !is_import_module {
+        return None;
+    }
+
+    let module_name = match call.args.first() {
+        Some(Expr::Constant(constant)) => match &constant.value {
+            Constant::Str(value) => value,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if module_name.is_empty() || module_name.starts_with('.') {
+        return None;
+    }
+
+    debug!("Found dynamic import of {} at level {}", module_name, level);
+    Some(ImportInfo {
+        module: module_name.clone(),
+        names: vec![module_name.clone()],
+        is_relative: false,
+        is_from_import: false,
+        is_star: false,
+        import_level: level,
+        relative_level: 0,
+        resolved_module: Some(module_name.clone()),
+    })
+}
+
+/// Collect the imports from the bodies of `except` handlers
+fn collect_except_handler_imports(
+    handlers: &[ExceptHandler],
+    level: u32,
+    options: &CollectOptions,
+) -> Vec<ImportInfo> {
+    handlers
+        .iter()
+        .flat_map(|handler| match handler {
+            ExceptHandler::ExceptHandler(handler) => {
+                collect_imports_with_level(&handler.body, level, options)
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -385,6 +2096,190 @@ mod tests {
         assert_eq!(hash1, hash2);
     }
 
+    #[test]
+    fn test_detect_package_name_pep621() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            r#"
+[project]
+name = "my-project"
+version = "0.1.0"
+
+[tool.poetry]
+name = "ignored"
+"#,
+        );
+
+        assert_eq!(
+            detect_package_name(temp_dir.path().to_str().unwrap()),
+            "my_project"
+        );
+    }
+
+    #[test]
+    fn test_detect_package_name_poetry() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            r#"
+[build-system]
+requires = ["poetry-core"]
+
+[tool.poetry]
+name = "poetry_project"
+version = "0.1.0"
+"#,
+        );
+
+        assert_eq!(
+            detect_package_name(temp_dir.path().to_str().unwrap()),
+            "poetry_project"
+        );
+    }
+
+    #[test]
+    fn test_detect_package_name_not_first_key() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            r#"
+[project]
+version = "0.1.0"
+description = """
+A description that spans
+multiple lines
+"""
+authors = [
+    { name = "Someone Else", email = "someone@example.com" },
+]
+name = "late_name"
+"#,
+        );
+
+        assert_eq!(
+            detect_package_name(temp_dir.path().to_str().unwrap()),
+            "late_name"
+        );
+    }
+
+    #[test]
+    fn test_detect_package_name_setup_cfg() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "setup.cfg",
+            r#"
+[bdist_wheel]
+name = not_this_one
+
+[metadata]
+; Legacy setuptools project
+Name: legacy-project
+version = 1.0
+
+[options]
+packages = find:
+"#,
+        );
+
+        assert_eq!(
+            detect_package_name(temp_dir.path().to_str().unwrap()),
+            "legacy_project"
+        );
+
+        // A name in pyproject.toml still takes precedence
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            "[project]\nname = \"modern_project\"\n",
+        );
+        assert_eq!(
+            detect_package_name(temp_dir.path().to_str().unwrap()),
+            "modern_project"
+        );
+    }
+
+    #[test]
+    fn test_detect_package_name_directory_fallback() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("fallback_project");
+        fs::create_dir(&project_dir).unwrap();
+        fs::write(
+            project_dir.join("pyproject.toml"),
+            "[tool.ruff]\nline-length = 100\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            detect_package_name(project_dir.to_str().unwrap()),
+            "fallback_project"
+        );
+    }
+
+    #[test]
+    fn test_detect_package_name_single_package_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("3f9c2ab");
+        fs::create_dir_all(project_dir.join("myapp")).unwrap();
+        fs::write(project_dir.join("myapp").join("__init__.py"), "").unwrap();
+        // Plain directories without an `__init__.py` aren't packages
+        fs::create_dir(project_dir.join("scripts")).unwrap();
+
+        let project_path = project_dir.to_str().unwrap();
+        assert_eq!(detect_package_name(project_path), "myapp");
+        assert_eq!(
+            detect_package_names(project_path),
+            BTreeSet::from(["myapp".to_string()])
+        );
+
+        // With several packages there's no telling which one is the project
+        fs::create_dir(project_dir.join("tools")).unwrap();
+        fs::write(project_dir.join("tools").join("__init__.py"), "").unwrap();
+        assert_eq!(detect_package_name(project_path), "3f9c2ab");
+    }
+
+    #[test]
+    fn test_detect_package_name_src_layout() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            "[project]\nname = \"my-distribution\"\n",
+        );
+        fs::create_dir_all(temp_dir.path().join("src/my_package")).unwrap();
+        create_temp_py_file(&temp_dir, "src/my_package/__init__.py", "");
+        create_temp_py_file(&temp_dir, "src/my_package/core.py", "import os");
+        // Directories without an __init__.py aren't packages
+        fs::create_dir_all(temp_dir.path().join("src/assets")).unwrap();
+
+        let project_path = temp_dir.path().to_str().unwrap();
+        assert_eq!(detect_package_name(project_path), "my_package");
+        assert_eq!(
+            detect_package_names(project_path),
+            BTreeSet::from(["my_package".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_detect_package_names_src_layout_multiple_packages() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("src/pkg_a")).unwrap();
+        fs::create_dir_all(temp_dir.path().join("src/pkg_b")).unwrap();
+        create_temp_py_file(&temp_dir, "src/pkg_b/__init__.py", "");
+        create_temp_py_file(&temp_dir, "src/pkg_a/__init__.py", "");
+
+        let project_path = temp_dir.path().to_str().unwrap();
+        assert_eq!(
+            detect_package_names(project_path),
+            BTreeSet::from(["pkg_a".to_string(), "pkg_b".to_string()])
+        );
+        assert_eq!(detect_package_name(project_path), "pkg_a");
+    }
+
     #[test]
     fn test_collect_imports_module() {
         let python_code = "import os\nimport sys";
@@ -441,6 +2336,31 @@ mod tests {
         assert_eq!(imports[1].is_from_import, true);
     }
 
+    #[test]
+    fn test_collect_star_imports() {
+        let python_code = "from os.path import *\nfrom sys import argv";
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_temp_py_file(&temp_dir, "star_imports.py", python_code);
+
+        let source = fs::read_to_string(file_path).unwrap();
+        let parsed = parse(&source, Mode::Module, "star_imports.py").unwrap();
+
+        let stmts = match &parsed {
+            Mod::Module(module) => &module.body,
+            _ => panic!("Expected Module"),
+        };
+
+        let imports = collect_imports(stmts);
+
+        assert_eq!(imports.len(), 2);
+        assert_eq!(imports[0].module, "os.path");
+        assert!(imports[0].is_star);
+        assert!(imports[0].is_from_import);
+
+        assert_eq!(imports[1].module, "sys");
+        assert!(!imports[1].is_star);
+    }
+
     #[test]
     fn test_collect_imports_alias() {
         let python_code = "import os as operating_system\nfrom sys import argv as arguments";
@@ -471,7 +2391,7 @@ mod tests {
 
     #[test]
     fn test_collect_imports_relative() {
-        let python_code = "from . import module1\nfrom .. import module2";
+        let python_code = "from . import module1, module2 as alias\nfrom .. import module3";
         let temp_dir = TempDir::new().unwrap();
         let file_path = create_temp_py_file(&temp_dir, "relative_imports.py", python_code);
 
@@ -483,20 +2403,31 @@ mod tests {
             _ => panic!("Expected Module"),
         };
 
-        let imports = collect_imports(stmts);
-
-        // Debugging to understand the actual structure
-        println!("Relative imports found: {:#?}", imports);
-
-        // For now, just check that we find something, we'll refine this test
-        // after seeing the actual output structure
-        assert!(!imports.is_empty());
+        let mut imports = collect_imports(stmts);
+        assert_eq!(imports.len(), 2);
 
-        // All these should be relative from imports
+        // Without a module of their own, the dots stand in for it
+        assert_eq!(imports[0].module, ".");
+        assert_eq!(imports[0].names, vec!["module1", "module2"]);
+        assert_eq!(imports[0].relative_level, 1);
+        assert_eq!(imports[1].module, "..");
+        assert_eq!(imports[1].names, vec!["module3"]);
+        assert_eq!(imports[1].relative_level, 2);
         for import in &imports {
             assert_eq!(import.is_from_import, true);
             assert_eq!(import.is_relative, true);
+            assert!(!import.is_star);
+            assert_eq!(import.resolved_module, None);
         }
+
+        // Resolving against the file's package points at the parent packages themselves
+        let package_parts = vec!["my_package".to_string(), "api".to_string()];
+        resolve_relative_imports(&mut imports, &package_parts);
+        assert_eq!(
+            imports[0].resolved_module.as_deref(),
+            Some("my_package.api")
+        );
+        assert_eq!(imports[1].resolved_module.as_deref(), Some("my_package"));
     }
 
     #[test]
@@ -553,6 +2484,214 @@ def function():
         assert_eq!(imports_by_module.get("re").unwrap().import_level, 4);
     }
 
+    #[test]
+    fn test_collect_imports_try_except() {
+        let python_code = r#"
+try:
+    import ujson as json
+except ImportError:
+    import json
+else:
+    import orjson
+finally:
+    import logging
+
+try:
+    import trio
+except* ValueError:
+    import anyio
+"#;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_temp_py_file(&temp_dir, "try_imports.py", python_code);
+
+        let source = fs::read_to_string(file_path).unwrap();
+        let parsed = parse(&source, Mode::Module, "try_imports.py").unwrap();
+
+        let stmts = match &parsed {
+            Mod::Module(module) => &module.body,
+            _ => panic!("Expected Module"),
+        };
+
+        let imports = collect_imports(stmts);
+        let modules: Vec<&str> = imports.iter().map(|imp| imp.module.as_str()).collect();
+
+        // Both the fast path and the fallback should be discovered
+        assert_eq!(
+            modules,
+            vec!["ujson", "json", "orjson", "logging", "trio", "anyio"]
+        );
+        assert!(imports.iter().all(|imp| imp.import_level == 1));
+    }
+
+    #[test]
+    fn test_collect_imports_with_blocks() {
+        let python_code = r#"
+from contextlib import suppress
+
+async def handler():
+    async with session() as client:
+        with suppress(ImportError):
+            import optional_pkg
+"#;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_temp_py_file(&temp_dir, "with_imports.py", python_code);
+
+        let source = fs::read_to_string(file_path).unwrap();
+        let parsed = parse(&source, Mode::Module, "with_imports.py").unwrap();
+
+        let stmts = match &parsed {
+            Mod::Module(module) => &module.body,
+            _ => panic!("Expected Module"),
+        };
+
+        let imports = collect_imports(stmts);
+
+        assert_eq!(imports.len(), 2);
+        assert_eq!(imports[0].module, "contextlib");
+        assert_eq!(imports[1].module, "optional_pkg");
+        // Inside the function, the async with, and the with
+        assert_eq!(imports[1].import_level, 3);
+    }
+
+    #[test]
+    fn test_collect_imports_async_blocks() {
+        let python_code = r#"
+import importlib
+
+async def create_pool(dsn):
+    async with connect(dsn) as connection:
+        try:
+            import asyncpg
+        except ImportError:
+            asyncpg = None
+        async for row in connection.cursor():
+            import orjson
+        else:
+            import ujson
+        for attempt in range(3):
+            from tenacity import retry
+        match dsn.scheme:
+            case "postgres":
+                import psycopg
+            case _:
+                import sqlite3
+        drivers = [importlib.import_module("aiomysql") async for _ in connection.drivers()]
+    return asyncpg
+"#;
+        let parsed = parse(python_code, Mode::Module, "pool.py").unwrap();
+        let stmts = match &parsed {
+            Mod::Module(module) => &module.body,
+            _ => panic!("Expected Module"),
+        };
+
+        let imports = collect_imports(stmts);
+        let modules: Vec<(&str, u32)> = imports
+            .iter()
+            .map(|imp| (imp.module.as_str(), imp.import_level))
+            .collect();
+        assert_eq!(
+            modules,
+            vec![
+                ("importlib", 0),
+                ("asyncpg", 3),
+                ("orjson", 3),
+                ("ujson", 3),
+                ("tenacity", 3),
+                ("psycopg", 3),
+                ("sqlite3", 3),
+                ("aiomysql", 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_imports_import_module() {
+        let temp_dir = TempDir::new().unwrap();
+        let python_code = r#"
+import importlib
+from importlib import import_module
+
+importlib.import_module("requests")
+pandas = import_module("pandas")
+
+def load_plugin(name):
+    plugin: object = importlib.import_module("numpy.linalg")
+    # Dynamic names can't be resolved statically
+    dynamic = importlib.import_module(name)
+    relative = import_module(".relative", "pkg")
+    return plugin, dynamic, relative
+"#;
+        create_temp_py_file(&temp_dir, "plugins.py", python_code);
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+
+        let expected: HashSet<String> = ["importlib", "requests", "pandas", "numpy"]
+            .iter()
+            .map(|module| module.to_string())
+            .collect();
+        assert_eq!(third_party_imports, expected);
+    }
+
+    #[test]
+    fn test_resolve_relative_imports() {
+        let temp_dir = TempDir::new().unwrap();
+        let nested_dir = temp_dir.path().join("api").join("handlers");
+        fs::create_dir_all(&nested_dir).unwrap();
+
+        let module_path = nested_dir.join("users.py");
+        fs::write(
+            &module_path,
+            r#"
+from . import sibling
+from .helpers import format_user
+from ..models import User
+from .... import too_far
+import requests
+"#,
+        )
+        .unwrap();
+        let init_path = temp_dir.path().join("api").join("__init__.py");
+        fs::write(&init_path, "from .handlers.users import router").unwrap();
+
+        let mut manager =
+            ProjectAstManager::new("my_package", temp_dir.path().to_str().unwrap(), None);
+
+        let imports = manager
+            .process_py_file(module_path.to_str().unwrap())
+            .unwrap();
+        let resolved: Vec<Option<&str>> = imports
+            .iter()
+            .map(|import| import.resolved_module.as_deref())
+            .collect();
+        assert_eq!(
+            resolved,
+            vec![
+                Some("my_package.api.handlers"),
+                Some("my_package.api.handlers.helpers"),
+                Some("my_package.api.models"),
+                None,
+                Some("requests"),
+            ]
+        );
+        assert_eq!(imports[2].relative_level, 2);
+
+        // Packages resolve relative to themselves
+        let imports = manager
+            .process_py_file(init_path.to_str().unwrap())
+            .unwrap();
+        assert_eq!(
+            imports[0].resolved_module.as_deref(),
+            Some("my_package.api.handlers.users")
+        );
+
+        // Resolved relative imports are still first-party
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        let expected: HashSet<String> = ["requests".to_string()].into_iter().collect();
+        assert_eq!(third_party_imports, expected);
+    }
+
     #[test]
     fn test_collect_same_module_and_import_name() {
         let python_code = "import time\nfrom time import time as time_func";
@@ -594,7 +2733,10 @@ def function():
             names: vec!["function".to_string()],
             is_relative: false,
             is_from_import: false,
+            is_star: false,
             import_level: 0,
+            relative_level: 0,
+            resolved_module: None,
         };
         assert!(!manager.is_third_party_import(&first_party));
 
@@ -604,7 +2746,10 @@ def function():
             names: vec!["function".to_string()],
             is_relative: true,
             is_from_import: false,
+            is_star: false,
             import_level: 0,
+            relative_level: 0,
+            resolved_module: None,
         };
         assert!(!manager.is_third_party_import(&relative));
 
@@ -614,11 +2759,90 @@ def function():
             names: vec!["get".to_string()],
             is_relative: false,
             is_from_import: false,
+            is_star: false,
             import_level: 0,
+            relative_level: 0,
+            resolved_module: None,
         };
         assert!(manager.is_third_party_import(&third_party));
     }
 
+    #[test]
+    fn test_sibling_packages_are_first_party() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            "[project]\nname = \"monorepo\"\n",
+        );
+        fs::create_dir_all(temp_dir.path().join("pkg_a")).unwrap();
+        fs::create_dir_all(temp_dir.path().join("pkg_b")).unwrap();
+        create_temp_py_file(&temp_dir, "pkg_a/__init__.py", "");
+        create_temp_py_file(
+            &temp_dir,
+            "pkg_a/core.py",
+            "from pkg_b.helpers import helper\nimport requests\nimport pkg_a_extras",
+        );
+        create_temp_py_file(&temp_dir, "pkg_b/__init__.py", "");
+        create_temp_py_file(
+            &temp_dir,
+            "pkg_b/helpers.py",
+            "import pkg_a.core\nimport numpy",
+        );
+
+        let project_path = temp_dir.path().to_str().unwrap();
+        let package_names = detect_package_names(project_path);
+        assert_eq!(
+            package_names,
+            BTreeSet::from([
+                "monorepo".to_string(),
+                "pkg_a".to_string(),
+                "pkg_b".to_string()
+            ])
+        );
+
+        let mut manager = ProjectAstManager::new("monorepo", project_path, None);
+        manager.set_package_names(package_names);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+
+        assert_eq!(
+            third_party_imports,
+            HashSet::from([
+                "requests".to_string(),
+                "numpy".to_string(),
+                "pkg_a_extras".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_set_package_names_keeps_project_name() {
+        let mut manager = ProjectAstManager::new("my_package", "/test/path", None);
+        manager.set_package_names(["other_package"]);
+
+        assert_eq!(
+            manager.package_names(),
+            &HashSet::from(["my_package".to_string(), "other_package".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_stdlib_imports_are_not_third_party() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "main.py",
+            "import os\nimport os.path\nfrom json import dumps\nimport requests",
+        );
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        manager.set_stdlib_modules(["os", "json", "sys"]);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+
+        assert_eq!(third_party_imports, HashSet::from(["requests".to_string()]));
+    }
+
     #[test]
     fn test_process_py_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -676,6 +2900,34 @@ def function():
         assert_ne!(&original_hash, modified_hash);
     }
 
+    #[test]
+    fn test_unchanged_files_are_not_reparsed() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "first.py", "import requests");
+        let second_path = create_temp_py_file(&temp_dir, "second.py", "import flask");
+        create_temp_py_file(&temp_dir, "third.py", "import numpy");
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        manager.process_all_py_files().unwrap();
+        assert_eq!(manager.parse_count(), 3);
+
+        // Nothing changed, so the delta is served entirely from the cache
+        let (added, removed) = manager.compute_import_delta().unwrap();
+        assert!(added.is_empty() && removed.is_empty());
+        assert_eq!(manager.parse_count(), 3);
+
+        fs::write(&second_path, "import flask\nimport pandas").unwrap();
+        let (added, removed) = manager.compute_import_delta().unwrap();
+        assert!(added.contains("pandas"));
+        assert!(removed.is_empty());
+        assert_eq!(
+            manager.parse_count(),
+            4,
+            "Only the modified file should be re-parsed"
+        );
+    }
+
     #[test]
     fn test_compute_import_delta() {
         let temp_dir = TempDir::new().unwrap();
@@ -717,6 +2969,493 @@ def function():
         assert!(removed.contains("requests"));
     }
 
+    #[test]
+    fn test_peek_import_delta_keeps_baseline() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_temp_py_file(&temp_dir, "main.py", "import requests");
+
+        let mut manager =
+            ProjectAstManager::new("testpkg", temp_dir.path().to_str().unwrap(), None);
+        manager.process_all_py_files().unwrap();
+
+        fs::write(&file_path, "import requests\nimport flask").unwrap();
+
+        // Peeking reports the new import, as many times as we like
+        for _ in 0..2 {
+            let (added, removed) = manager.peek_import_delta().unwrap();
+            assert_eq!(added, HashSet::from(["flask".to_string()]));
+            assert!(removed.is_empty());
+        }
+
+        // The real delta still sees the change, since peeking didn't move the baseline
+        let (added, removed) = manager.compute_import_delta().unwrap();
+        assert_eq!(added, HashSet::from(["flask".to_string()]));
+        assert!(removed.is_empty());
+        let (added, _) = manager.peek_import_delta().unwrap();
+        assert!(added.is_empty());
+    }
+
+    #[test]
+    fn test_import_granularity() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "submodules.py",
+            "import a.b.c\nimport numpy\nimport numpy.linalg\nfrom x.y import z",
+        );
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        assert_eq!(manager.import_granularity(), ImportGranularity::TopLevel);
+
+        // Plain submodule imports collapse to their package, from-imports keep their path
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        let expected: HashSet<String> = ["a", "numpy", "x.y"]
+            .iter()
+            .map(|module| module.to_string())
+            .collect();
+        assert_eq!(third_party_imports, expected);
+
+        // Nothing changed on disk, so the normalized modules shouldn't register as a delta
+        let (added, removed) = manager.compute_import_delta().unwrap();
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+
+        manager.set_import_granularity(ImportGranularity::FullPath);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        let expected: HashSet<String> = ["a.b.c", "numpy", "numpy.linalg", "x.y"]
+            .iter()
+            .map(|module| module.to_string())
+            .collect();
+        assert_eq!(third_party_imports, expected);
+    }
+
+    #[test]
+    fn test_type_checking_imports_skipped() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "typed.py",
+            r#"
+import typing
+from typing import TYPE_CHECKING
+
+if TYPE_CHECKING:
+    import heavy_typing_only
+else:
+    import runtime_fallback
+
+if typing.TYPE_CHECKING:
+    from other_stubs import Protocol
+
+def handler():
+    if TYPE_CHECKING:
+        import nested_typing_only
+    import requests
+"#,
+        );
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+
+        let expected: HashSet<String> = ["typing", "runtime_fallback", "requests"]
+            .iter()
+            .map(|module| module.to_string())
+            .collect();
+        assert_eq!(third_party_imports, expected);
+
+        // Users can opt back into eager loading of type-only imports
+        manager.set_include_type_checking(true);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("heavy_typing_only"));
+        assert!(third_party_imports.contains("other_stubs"));
+        assert!(third_party_imports.contains("nested_typing_only"));
+    }
+
+    #[test]
+    fn test_version_gated_imports() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "compat.py",
+            r#"
+import sys
+from sys import version_info
+
+if sys.version_info >= (3, 11):
+    import tomllib
+else:
+    import tomli
+
+if sys.version_info[:2] < (3, 9):
+    import old_backport
+elif version_info[0] == 3:
+    import py3_only
+else:
+    import future_only
+
+if sys.version_info >= (3, 11, 0, "final", 0):
+    import unevaluated
+"#,
+        );
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+
+        // Without a known interpreter version both branches are kept
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("tomllib") && third_party_imports.contains("tomli"));
+
+        manager.set_python_version(Some((3, 11, 4)));
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        let expected: HashSet<String> = ["sys", "tomllib", "py3_only", "unevaluated"]
+            .iter()
+            .map(|module| module.to_string())
+            .collect();
+        assert_eq!(third_party_imports, expected);
+
+        manager.set_python_version(Some((3, 8, 10)));
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("tomli"));
+        assert!(third_party_imports.contains("old_backport"));
+        assert!(!third_party_imports.contains("tomllib"));
+        assert!(!third_party_imports.contains("py3_only"));
+    }
+
+    #[test]
+    fn test_import_graph_reports_cycles() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "__init__.py", "from .a import helper\n");
+        create_temp_py_file(&temp_dir, "a.py", "from . import b\n\ndef helper(): ...\n");
+        create_temp_py_file(
+            &temp_dir,
+            "b.py",
+            "from test_package.a import helper\nimport os\n",
+        );
+        create_temp_py_file(&temp_dir, "c.py", "from . import a\nimport requests\n");
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let result = manager.process_all_py_files_with_failures().unwrap();
+        assert!(result.import_graph.is_empty());
+        assert!(result.import_cycles.is_empty());
+
+        manager.set_build_import_graph(true);
+        let result = manager.process_all_py_files_with_failures().unwrap();
+
+        let edges = |targets: &[&str]| -> BTreeSet<String> {
+            targets.iter().map(|target| target.to_string()).collect()
+        };
+        assert_eq!(
+            result.import_graph["test_package"],
+            edges(&["test_package.a"])
+        );
+        assert_eq!(
+            result.import_graph["test_package.a"],
+            edges(&["test_package.b"])
+        );
+        assert_eq!(
+            result.import_graph["test_package.b"],
+            edges(&["test_package.a"])
+        );
+        assert_eq!(
+            result.import_graph["test_package.c"],
+            edges(&["test_package.a"])
+        );
+
+        assert_eq!(
+            result.import_cycles,
+            vec![vec![
+                "test_package.a".to_string(),
+                "test_package.b".to_string()
+            ]]
+        );
+        assert!(result.third_party_imports.contains("requests"));
+    }
+
+    #[test]
+    fn test_process_files_rescans_only_given_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let edited = create_temp_py_file(&temp_dir, "edited.py", "import requests\n");
+        create_temp_py_file(&temp_dir, "other.py", "import flask\n");
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        manager.process_all_py_files().unwrap();
+        let parses = manager.parse_count();
+
+        // Only the edited file is handed over, so the other change goes unnoticed
+        fs::write(&edited, "import httpx\n").unwrap();
+        fs::write(temp_dir.path().join("other.py"), "import django\n").unwrap();
+        let (added, removed) = manager
+            .process_files(&[PathBuf::from("edited.py")])
+            .unwrap();
+        assert_eq!(added, HashSet::from(["httpx".to_string()]));
+        assert_eq!(removed, HashSet::from(["requests".to_string()]));
+        assert_eq!(manager.parse_count(), parses + 1);
+
+        // The merged cache is the baseline for a full pass, which then picks up the rest
+        let (added, removed) = manager.compute_import_delta().unwrap();
+        assert_eq!(added, HashSet::from(["django".to_string()]));
+        assert_eq!(removed, HashSet::from(["flask".to_string()]));
+
+        // Deleted files stop contributing
+        fs::remove_file(&edited).unwrap();
+        let (added, removed) = manager.process_files(&[edited]).unwrap();
+        assert!(added.is_empty());
+        assert_eq!(removed, HashSet::from(["httpx".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_failures_do_not_abort_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "valid.py",
+            "import requests\nfrom flask import Flask",
+        );
+        let broken_path = create_temp_py_file(&temp_dir, "broken.py", "import os\ndef broken(:\n");
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let result = manager.process_all_py_files_with_failures().unwrap();
+
+        let expected: HashSet<String> = ["requests", "flask"]
+            .iter()
+            .map(|module| module.to_string())
+            .collect();
+        assert_eq!(result.third_party_imports, expected);
+
+        assert_eq!(result.parse_failures.len(), 1);
+        assert_eq!(result.parse_failures[0].0, broken_path);
+        assert!(result.parse_failures[0].1.contains("Failed to parse"));
+
+        // The syntax error points at the offending token
+        assert_eq!(result.syntax_errors.len(), 1);
+        let syntax_error = &result.syntax_errors[0];
+        assert_eq!(syntax_error.path, broken_path);
+        assert_eq!((syntax_error.line, syntax_error.column), (2, 12));
+        assert!(!syntax_error.message.is_empty());
+        assert_eq!(
+            result.parse_failures[0].1,
+            format!(
+                "Failed to parse {}:2:12: {}",
+                broken_path.display(),
+                syntax_error.message
+            )
+        );
+
+        // The plain variant skips the broken file as well
+        assert_eq!(manager.process_all_py_files().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_module_body_of_other_ast_formats() {
+        let interactive = parse("import requests\n", Mode::Interactive, "<stdin>").unwrap();
+        let stmts = module_body(&interactive, "<stdin>").unwrap();
+        assert_eq!(collect_imports(stmts)[0].module, "requests");
+
+        // Expressions have no statements, so the file is reported instead of scanned
+        let expression = parse("__import__('requests')", Mode::Expression, "expr.py").unwrap();
+        let error = module_body(&expression, "expr.py").unwrap_err();
+        assert!(error.to_string().contains("Unexpected AST format"));
+    }
+
+    #[test]
+    fn test_bom_prefixed_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("bom.py");
+        fs::write(&file_path, b"\xEF\xBB\xBFimport requests\n").unwrap();
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let imports = manager
+            .process_py_file(file_path.to_str().unwrap())
+            .unwrap();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].module, "requests");
+    }
+
+    #[test]
+    fn test_latin1_coding_declaration() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("latin1.py");
+        // 0xE9 is "é" in Latin-1 and not valid UTF-8 on its own
+        fs::write(
+            &file_path,
+            b"# -*- coding: latin-1 -*-\nimport requests\nGREETING = 'caf\xE9'\n",
+        )
+        .unwrap();
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let result = manager.process_all_py_files_with_failures().unwrap();
+        assert!(
+            result.parse_failures.is_empty(),
+            "{:?}",
+            result.parse_failures
+        );
+        assert_eq!(
+            result.third_party_imports,
+            HashSet::from(["requests".to_string()])
+        );
+
+        assert_eq!(
+            read_python_source(file_path.to_str().unwrap()).unwrap(),
+            "# -*- coding: latin-1 -*-\nimport requests\nGREETING = 'café'\n"
+        );
+    }
+
+    #[test]
+    fn test_excluded_dirs_are_not_walked() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "app.py", "import requests");
+
+        let site_packages = temp_dir
+            .path()
+            .join(".venv/lib/python3.11/site-packages/fake");
+        fs::create_dir_all(&site_packages).unwrap();
+        fs::write(site_packages.join("module.py"), "import installed_only").unwrap();
+
+        let vendor = temp_dir.path().join("vendor");
+        fs::create_dir_all(&vendor).unwrap();
+        fs::write(vendor.join("module.py"), "import vendored_only").unwrap();
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("requests"));
+        assert!(!third_party_imports.contains("installed_only"));
+        assert!(third_party_imports.contains("vendored_only"));
+
+        // The exclude list is configurable
+        manager.set_excluded_dirs(["vendor"]);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("installed_only"));
+        assert!(!third_party_imports.contains("vendored_only"));
+    }
+
+    #[test]
+    fn test_exclude_globs_skip_matching_files() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("app/migrations")).unwrap();
+        fs::create_dir_all(temp_dir.path().join("migrations")).unwrap();
+        create_temp_py_file(&temp_dir, "app/models.py", "import requests");
+        create_temp_py_file(
+            &temp_dir,
+            "app/migrations/0001_initial.py",
+            "import migration_only",
+        );
+        create_temp_py_file(
+            &temp_dir,
+            "migrations/0002_data.py",
+            "import root_migration",
+        );
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("migration_only"));
+
+        manager.set_exclude_globs(["**/migrations/*.py"]).unwrap();
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert_eq!(third_party_imports, HashSet::from(["requests".to_string()]));
+        // Excluded files no longer count towards the baseline either
+        assert_eq!(
+            manager.peek_import_delta().unwrap(),
+            (HashSet::new(), HashSet::new())
+        );
+
+        // Invalid patterns are rejected without touching the current ones
+        assert!(manager.set_exclude_globs(["[unclosed"]).is_err());
+        assert_eq!(manager.exclude_globs(), ["**/migrations/*.py"]);
+    }
+
+    #[test]
+    fn test_init_reexports_are_followed() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("vendored")).unwrap();
+        create_temp_py_file(&temp_dir, "__init__.py", "from .heavy import Thing");
+        create_temp_py_file(
+            &temp_dir,
+            "heavy.py",
+            "import requests\nfrom .vendored import shim",
+        );
+        // Re-exports back into the package form a cycle
+        create_temp_py_file(&temp_dir, "vendored/__init__.py", "");
+        create_temp_py_file(
+            &temp_dir,
+            "vendored/shim.py",
+            "import urllib3\nfrom .. import heavy\nfrom ..heavy import Thing",
+        );
+        create_temp_py_file(&temp_dir, "vendored/unused.py", "import numpy");
+
+        let mut manager =
+            ProjectAstManager::new("test_package", temp_dir.path().to_str().unwrap(), None);
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert!(third_party_imports.contains("requests"));
+
+        // Excluded files are still scanned when `__init__.py` transitively loads them
+        manager.set_exclude_globs(["vendored/**"]).unwrap();
+        let third_party_imports = manager.process_all_py_files().unwrap();
+        assert_eq!(
+            third_party_imports,
+            HashSet::from(["requests".to_string(), "urllib3".to_string()])
+        );
+        assert_eq!(
+            manager.peek_import_delta().unwrap(),
+            (HashSet::new(), HashSet::new())
+        );
+
+        // Dropping the re-export drops what it pulled in
+        create_temp_py_file(&temp_dir, "__init__.py", "");
+        let (added, removed) = manager.compute_import_delta().unwrap();
+        assert!(added.is_empty());
+        assert_eq!(removed, HashSet::from(["urllib3".to_string()]));
+        assert_eq!(
+            manager.compute_import_delta().unwrap(),
+            (HashSet::new(), HashSet::new())
+        );
+    }
+
+    #[test]
+    fn test_parallel_scan_matches_serial() {
+        let temp_dir = TempDir::new().unwrap();
+        for index in 0..64 {
+            let package_dir = temp_dir.path().join(format!("package_{}", index % 8));
+            fs::create_dir_all(&package_dir).unwrap();
+            fs::write(
+                package_dir.join(format!("module_{}.py", index)),
+                format!(
+                    "import dependency_{}\nfrom shared.utils import helper\nfrom test_package import local\n",
+                    index % 10
+                ),
+            )
+            .unwrap();
+        }
+        create_temp_py_file(&temp_dir, "broken_a.py", "def broken(:\n");
+        create_temp_py_file(&temp_dir, "broken_b.py", "class (:\n");
+
+        let project_path = temp_dir.path().to_str().unwrap();
+
+        let mut serial = ProjectAstManager::new("test_package", project_path, None);
+        serial.set_parallel(false);
+        let serial_result = serial.process_all_py_files_with_failures().unwrap();
+
+        let mut parallel = ProjectAstManager::new("test_package", project_path, None);
+        let parallel_result = parallel.process_all_py_files_with_failures().unwrap();
+
+        assert_eq!(serial_result.third_party_imports.len(), 11);
+        assert_eq!(
+            serial_result.third_party_imports,
+            parallel_result.third_party_imports
+        );
+        assert_eq!(serial_result.parse_failures.len(), 2);
+        assert_eq!(serial_result.parse_failures, parallel_result.parse_failures);
+        assert_eq!(serial.file_imports, parallel.file_imports);
+    }
+
     #[test]
     fn test_ignored_modules() {
         let temp_dir = TempDir::new().unwrap();
@@ -807,4 +3546,125 @@ from . import local_module
             "local_module should not be included"
         );
     }
+
+    #[test]
+    fn test_zip_archive_matches_disk() {
+        let files = [
+            ("pyproject.toml", "[project]\nname = \"zipped-app\"\n"),
+            ("main.py", "import requests\nfrom .sub import helper"),
+            ("sub/__init__.py", ""),
+            (
+                "sub/helper.py",
+                "from numpy import array\nfrom . import sibling",
+            ),
+            ("sub/broken.py", "def broken(:"),
+            ("venv/lib.py", "import excluded_dir"),
+            ("notes.txt", "import not_python"),
+        ];
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            let path = temp_dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        let mut archive = ZipArchive::new(writer.finish().unwrap()).unwrap();
+
+        let project_path = temp_dir.path().to_str().unwrap();
+        let package_name = detect_package_name(project_path);
+        assert_eq!(package_name, "zipped_app");
+        assert_eq!(
+            detect_package_name_in_zip(&mut archive, "archive"),
+            package_name
+        );
+
+        let mut manager = ProjectAstManager::new(&package_name, project_path, None);
+        let from_disk = manager.process_all_py_files_with_failures().unwrap();
+        let from_zip = manager.process_zip_archive(&mut archive).unwrap();
+
+        assert_eq!(
+            from_zip.third_party_imports,
+            HashSet::from(["requests".to_string(), "numpy".to_string()])
+        );
+        assert_eq!(from_zip.third_party_imports, from_disk.third_party_imports);
+        assert_eq!(
+            from_zip
+                .parse_failures
+                .iter()
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>(),
+            vec![PathBuf::from("sub/broken.py")]
+        );
+        assert_eq!(from_disk.parse_failures.len(), 1);
+    }
+
+    #[test]
+    fn test_zip_archive_package_name_fallbacks() {
+        let archive_with = |names: &[&str]| {
+            let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+            for name in names {
+                writer
+                    .start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+            }
+            ZipArchive::new(writer.finish().unwrap()).unwrap()
+        };
+
+        let mut src_layout = archive_with(&["src/beta/__init__.py", "src/alpha/__init__.py"]);
+        assert_eq!(
+            detect_package_name_in_zip(&mut src_layout, "archive"),
+            "alpha"
+        );
+
+        let mut bare = archive_with(&["module.py"]);
+        assert_eq!(detect_package_name_in_zip(&mut bare, "my-app"), "my_app");
+
+        let mut single_package = archive_with(&["app/__init__.py", "app/core/__init__.py"]);
+        assert_eq!(
+            detect_package_name_in_zip(&mut single_package, "archive"),
+            "app"
+        );
+
+        let mut several_packages = archive_with(&["app/__init__.py", "tools/__init__.py"]);
+        assert_eq!(
+            detect_package_name_in_zip(&mut several_packages, "archive"),
+            "archive"
+        );
+    }
+
+    #[test]
+    fn test_third_party_prefixes() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "main.py",
+            "import mypkg.vendor.requests\n\
+             from mypkg.vendor.requests.adapters import HTTPAdapter\n\
+             from mypkg.vendor_tools import helper\n\
+             from mypkg.core import run\n\
+             import mypkg_extras",
+        );
+
+        let mut manager = ProjectAstManager::new("mypkg", temp_dir.path().to_str().unwrap(), None);
+        assert_eq!(
+            manager.process_all_py_files().unwrap(),
+            HashSet::from(["mypkg_extras".to_string()])
+        );
+
+        manager.set_third_party_prefixes(["mypkg.vendor"]);
+        assert_eq!(
+            manager.process_all_py_files().unwrap(),
+            HashSet::from([
+                "mypkg.vendor.requests".to_string(),
+                "mypkg.vendor.requests.adapters".to_string(),
+                "mypkg_extras".to_string(),
+            ])
+        );
+    }
 }
@@ -1,5 +1,11 @@
 use log::{debug, trace, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
 /// A generic data structure for asynchronously resolving values with blocking capability.
 /// This allows a thread to wait for a value to be resolved, even if the resolution happens
@@ -10,6 +16,10 @@ pub struct AsyncResolve<T: Clone> {
     value: Arc<Mutex<Option<T>>>,
     /// Condition variable for notification when value is resolved
     condition: Arc<(Mutex<bool>, Condvar)>,
+    /// Wakers of pending futures, keyed by the future that registered them
+    wakers: Arc<Mutex<HashMap<u64, Waker>>>,
+    /// Source of the keys in `wakers`
+    next_waker_id: Arc<AtomicU64>,
 }
 
 impl<T: Clone> AsyncResolve<T> {
@@ -19,6 +29,8 @@ impl<T: Clone> AsyncResolve<T> {
         Self {
             value: Arc::new(Mutex::new(None)),
             condition: Arc::new((Mutex::new(false), Condvar::new())),
+            wakers: Arc::new(Mutex::new(HashMap::new())),
+            next_waker_id: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -63,7 +75,24 @@ impl<T: Clone> AsyncResolve<T> {
         debug!("Notifying all waiters");
         condvar.notify_all();
         debug!("All waiters notified");
-        // completed guard is dropped here, releasing the mutex
+        drop(completed);
+
+        // Wake pending futures. They register under the value mutex, so any future that
+        // saw no value is already in the map by now.
+        match self.wakers.lock() {
+            Ok(mut wakers) => wakers.drain().for_each(|(_, waker)| waker.wake()),
+            Err(err) => warn!("Failed to acquire wakers mutex for notification: {:?}", err),
+        }
+    }
+
+    /// A future that completes with the value once it's resolved, for callers on an async
+    /// runtime that shouldn't block a thread in `wait`. Dropping the future before then
+    /// just unregisters it.
+    pub fn future(&self) -> ResolveFuture<T> {
+        ResolveFuture {
+            resolver: self.clone(),
+            waker_id: self.next_waker_id.fetch_add(1, Ordering::Relaxed),
+        }
     }
 
     /// Blocks until the value is resolved or returns immediately if already resolved
@@ -154,6 +183,31 @@ impl<T: Clone> AsyncResolve<T> {
         }
     }
 
+    /// Like `wait`, but gives up after `timeout`. Returns `Ok(None)` if the value was not
+    /// resolved in time; the value can still be resolved and waited on afterwards.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Option<T>, String> {
+        debug!("Waiting for AsyncResolve value with timeout {:?}", timeout);
+
+        let (mutex, condvar) = &*self.condition;
+        let completed = mutex
+            .lock()
+            .map_err(|e| format!("Failed to lock completion mutex: {:?}", e))?;
+
+        // Resolution flips `completed` under this mutex, so checking it here can't miss
+        // a notification that lands between the check and the wait
+        let (completed, wait_result) = condvar
+            .wait_timeout_while(completed, timeout, |completed| !*completed)
+            .map_err(|e| format!("Failed to wait on condvar: {:?}", e))?;
+
+        if wait_result.timed_out() && !*completed {
+            debug!("Timed out waiting for AsyncResolve value");
+            return Ok(None);
+        }
+        drop(completed);
+
+        Ok(self.get())
+    }
+
     /// Non-blocking check if value is resolved
     pub fn is_resolved(&self) -> bool {
         trace!("Checking if AsyncResolve is resolved");
@@ -205,6 +259,44 @@ impl<T: Clone> Default for AsyncResolve<T> {
     }
 }
 
+/// Future returned by `AsyncResolve::future`
+pub struct ResolveFuture<T: Clone> {
+    resolver: AsyncResolve<T>,
+    waker_id: u64,
+}
+
+impl<T: Clone> Future for ResolveFuture<T> {
+    type Output = Result<T, String>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let value = self
+            .resolver
+            .value
+            .lock()
+            .map_err(|e| format!("Failed to lock value mutex: {:?}", e))?;
+        if let Some(value) = &*value {
+            return Poll::Ready(Ok(value.clone()));
+        }
+
+        // Register while still holding the value mutex, so a concurrent `resolve` can't set
+        // the value and wake everyone between our check and the registration
+        self.resolver
+            .wakers
+            .lock()
+            .map_err(|e| format!("Failed to lock wakers mutex: {:?}", e))?
+            .insert(self.waker_id, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T: Clone> Drop for ResolveFuture<T> {
+    fn drop(&mut self) {
+        if let Ok(mut wakers) = self.resolver.wakers.lock() {
+            wakers.remove(&self.waker_id);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -236,6 +328,28 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn test_wait_timeout() {
+        let resolver = AsyncResolve::<i32>::new();
+        assert_eq!(
+            resolver.wait_timeout(Duration::from_millis(20)).unwrap(),
+            None
+        );
+
+        let resolver_clone = resolver.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            resolver_clone.resolve(42);
+        });
+
+        assert_eq!(
+            resolver.wait_timeout(Duration::from_secs(5)).unwrap(),
+            Some(42)
+        );
+
+        handle.join().unwrap();
+    }
+
     #[test]
     fn test_is_resolved() {
         let resolver = AsyncResolve::<i32>::new();
@@ -253,4 +367,30 @@ mod tests {
         resolver.resolve("hello".to_string());
         assert_eq!(resolver.get(), Some("hello".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_future_resolves_from_another_thread() {
+        let resolver = AsyncResolve::new();
+
+        let resolver_clone = resolver.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            resolver_clone.resolve(42);
+        });
+
+        assert_eq!(resolver.future().await.unwrap(), 42);
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_future_unregisters() {
+        let resolver = AsyncResolve::<i32>::new();
+
+        let pending = tokio::time::timeout(Duration::from_millis(20), resolver.future()).await;
+        assert!(pending.is_err());
+        assert!(resolver.wakers.lock().unwrap().is_empty());
+
+        resolver.resolve(42);
+        assert_eq!(resolver.future().await.unwrap(), 42);
+    }
 }
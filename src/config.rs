@@ -0,0 +1,937 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::ast::ImportGranularity;
+use crate::environment::Environment;
+use crate::error::HotReloadError;
+use crate::messages::io::{self, Framing, DEFAULT_MAX_FRAME_LENGTH};
+use crate::multiplex_logs::MultiplexFormat;
+use crate::scripts::PYTHON_LOADER_SCRIPT;
+
+/// What the loader does when a preloaded module fails to import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportFailurePolicy {
+    /// Abort the boot, reporting every module that failed
+    #[default]
+    Error,
+    /// Boot without the failed modules and report them as warnings
+    Warn,
+}
+
+impl ImportFailurePolicy {
+    /// Value passed to the Python loader through `FIREHOT_IMPORT_FAILURES`
+    pub fn as_env_value(&self) -> &'static str {
+        match self {
+            ImportFailurePolicy::Error => "error",
+            ImportFailurePolicy::Warn => "warn",
+        }
+    }
+}
+
+/// How fork output and lifecycle events are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Colored lines tagged with the fork name, for reading in a terminal
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregators. Output lines look like
+    /// `{"pid": 123, "uuid": "...", "name": "...", "stream": "stdout", "message": "..."}` and
+    /// lifecycle events carry an `event` field such as `fork_started` or `fork_exited`.
+    Json,
+}
+
+/// What `update_environment` does with running forks when the imports change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReloadMode {
+    /// Stop every fork along with the old loader before booting the new one
+    #[default]
+    Restart,
+    /// Boot the new loader next to the old one. New forks go to the new loader, and the old
+    /// one is retired once its in-flight forks have finished.
+    Drain,
+}
+
+/// How the loader runs each isolated call. Both modes are Unix-only, since the loader relies
+/// on `SIGCHLD`, interval timers and fd inheritance either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// Fork the loader, so the call starts with every preloaded module already imported
+    #[default]
+    Fork,
+    /// Start a fresh interpreter for every call. Slower, since nothing preloaded carries
+    /// over, but works with C extensions that don't survive a fork.
+    Spawn,
+}
+
+impl ExecutionMode {
+    /// Value passed to the Python loader through `FIREHOT_EXECUTION_MODE`
+    pub fn as_env_value(&self) -> &'static str {
+        match self {
+            ExecutionMode::Fork => "fork",
+            ExecutionMode::Spawn => "spawn",
+        }
+    }
+}
+
+/// How long `boot_main` waits for the preloaded imports before giving up on the loader
+pub const DEFAULT_BOOT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How many times we try to start the loader when spawning it fails transiently
+pub const DEFAULT_SPAWN_ATTEMPTS: u32 = 3;
+
+/// Longest line we keep from the loader or a fork before truncating it
+pub const DEFAULT_MAX_LINE_LENGTH: usize = DEFAULT_MAX_FRAME_LENGTH;
+
+/// Longest message we accept from the loader, which bounds the size of a fork's result
+pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = io::DEFAULT_MAX_MESSAGE_LENGTH;
+
+/// Runtime configuration for an Environment. Everything here has a sensible default, so
+/// callers only need to touch the options they care about (usually through EnvironmentBuilder).
+#[derive(Debug, Clone, Default)]
+pub struct EnvironmentConfig {
+    /// Python interpreter to launch, either a path or a command name looked up on PATH.
+    /// Takes precedence over the virtualenv interpreter when both are set.
+    pub interpreter: Option<PathBuf>,
+    /// Virtualenv directory to run the loader (and therefore all forks) inside of
+    pub venv: Option<PathBuf>,
+    /// How messages are framed on the loader's stdin/stdout. Defaults to NDJSON.
+    pub framing: Framing,
+    /// Whether submodule imports are preloaded by their top-level package or full path
+    pub import_granularity: ImportGranularity,
+    /// Also preload imports guarded by `if TYPE_CHECKING:`, which are skipped by default
+    pub include_type_checking_imports: bool,
+    /// Directory names to skip while scanning the project. Defaults to `DEFAULT_EXCLUDED_DIRS`.
+    pub excluded_dirs: Option<HashSet<String>>,
+    /// Glob patterns, relative to the project root, of files to skip while scanning
+    pub exclude_globs: Vec<String>,
+    /// Upper bound on loading the preloaded imports. Defaults to `DEFAULT_BOOT_TIMEOUT`.
+    pub boot_timeout: Option<Duration>,
+    /// Attempts at spawning the loader when the OS is temporarily out of resources, like
+    /// `EAGAIN` on a busy machine. Defaults to `DEFAULT_SPAWN_ATTEMPTS`.
+    pub spawn_attempts: Option<u32>,
+    /// Longest line kept from the loader's output, in bytes. Defaults to
+    /// `DEFAULT_MAX_LINE_LENGTH`.
+    pub max_line_length: Option<usize>,
+    /// Longest protocol message accepted from the loader, in bytes. A fork whose result is
+    /// larger fails instead of returning it. Defaults to `DEFAULT_MAX_MESSAGE_LENGTH`.
+    pub max_message_length: Option<usize>,
+    /// Extra environment variables for the loader, inherited by every fork
+    pub env_vars: HashMap<String, String>,
+    /// Modules to preload even though the scan doesn't find them, like lazy runtime imports
+    pub extra_preload: HashSet<String>,
+    /// Modules that are never preloaded, even when the scan finds them
+    pub excluded_preload: HashSet<String>,
+    /// Preloads the loader imports before reporting ready. When set, `boot_main` returns once
+    /// these are loaded and the loader imports the rest in a background thread. Forks wait
+    /// for the background imports to finish. Empty imports everything up front.
+    pub critical_modules: HashSet<String>,
+    /// Requirements files, relative to the project root, whose distributions are preloaded
+    /// alongside the scanned imports. Catches dependencies that are only used through entry
+    /// points and never imported directly.
+    pub requirements_files: Vec<PathBuf>,
+    /// Also preload the dependencies declared in the project's pyproject.toml
+    pub preload_pyproject_dependencies: bool,
+    /// Whether a failed preload aborts the boot or is only reported
+    pub import_failure_policy: ImportFailurePolicy,
+    /// Relaunch the loader with the same modules when it dies on its own
+    pub auto_restart: bool,
+    /// Whether a reload stops running forks or lets them finish on the old loader
+    pub reload_mode: ReloadMode,
+    /// Whether isolated calls run in forks of the loader or in fresh interpreters
+    pub execution_mode: ExecutionMode,
+    /// Reload edited pure-Python modules inside the running loader with `importlib.reload`,
+    /// instead of rebooting it. C extensions and changes to the imports still reboot.
+    pub partial_reload: bool,
+    /// Fork a no-op call while booting, so a loader that can't fork fails the boot instead
+    /// of the first `exec_isolated`
+    pub verify_fork: bool,
+    /// Additional top-level packages whose imports are first party, alongside the project name
+    pub first_party_packages: HashSet<String>,
+    /// Module prefixes preloaded as third party even though they sit under a first-party
+    /// package, like a vendored `mypkg.vendor.requests`
+    pub third_party_prefixes: HashSet<String>,
+    /// Loader script to run instead of the embedded one. It has to speak the same protocol.
+    pub loader_script: Option<PathBuf>,
+    /// Keep standard library imports in the preload set. They're dropped by default since
+    /// the interpreter already has most of them loaded.
+    pub preload_stdlib: bool,
+    /// Interpreter version as (major, minor, micro), used to evaluate `sys.version_info`
+    /// checks around imports. Queried from the interpreter at boot when unset.
+    pub python_version: Option<(u32, u32, u32)>,
+    /// How fork output and lifecycle events are printed
+    pub log_format: LogFormat,
+    /// Prefix the loader tags fork output with. Only needs changing alongside a custom
+    /// loader script that tags lines differently.
+    pub multiplex_format: MultiplexFormat,
+    /// Working directory of the loader (and therefore every fork) and of the helper
+    /// processes we run with the interpreter. Environments default this to the project path.
+    pub cwd: Option<PathBuf>,
+}
+
+impl EnvironmentConfig {
+    /// Apply the preload overrides to the third-party modules detected in the project
+    pub fn preload_modules(&self, detected: HashSet<String>) -> HashSet<String> {
+        detected
+            .into_iter()
+            .chain(self.extra_preload.iter().cloned())
+            .filter(|module| !self.excluded_preload.contains(module))
+            .collect()
+    }
+
+    /// How long to wait for the loader to finish its imports
+    pub fn boot_timeout(&self) -> Duration {
+        self.boot_timeout.unwrap_or(DEFAULT_BOOT_TIMEOUT)
+    }
+
+    /// How many times to try spawning the loader, at least once
+    pub fn spawn_attempts(&self) -> u32 {
+        self.spawn_attempts.unwrap_or(DEFAULT_SPAWN_ATTEMPTS).max(1)
+    }
+
+    /// Longest line we keep from the loader before truncating it
+    pub fn max_line_length(&self) -> usize {
+        self.max_line_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH)
+    }
+
+    /// Longest message we accept from the loader before failing the fork that sent it
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+            .unwrap_or(DEFAULT_MAX_MESSAGE_LENGTH)
+    }
+
+    /// The interpreter we should launch for the loader and any helper processes
+    pub fn interpreter(&self) -> PathBuf {
+        if let Some(interpreter) = &self.interpreter {
+            return interpreter.clone();
+        }
+        match &self.venv {
+            Some(venv) => venv_interpreter(venv),
+            None => PathBuf::from("python"),
+        }
+    }
+
+    /// Resolve the configured interpreter to an executable on disk, so a missing python
+    /// or a broken virtualenv surfaces as a clear error instead of a generic spawn failure
+    pub fn resolve_interpreter(&self) -> Result<PathBuf, String> {
+        if let Some(venv) = &self.venv {
+            if !venv.is_dir() {
+                return Err(format!("Virtualenv {} does not exist", venv.display()));
+            }
+            if self.interpreter.is_none() && !venv_interpreter(venv).is_file() {
+                return Err(format!(
+                    "Virtualenv {} has no python interpreter at {}",
+                    venv.display(),
+                    venv_interpreter(venv).display()
+                ));
+            }
+        }
+
+        let interpreter = self.interpreter();
+        find_executable(&interpreter).ok_or_else(|| {
+            if interpreter.components().count() > 1 {
+                format!(
+                    "Python interpreter {} does not exist",
+                    interpreter.display()
+                )
+            } else {
+                format!(
+                    "Python interpreter '{}' was not found on PATH",
+                    interpreter.display()
+                )
+            }
+        })
+    }
+
+    /// Build a Command for the configured interpreter. When a virtualenv is configured we
+    /// mirror what `activate` does, so packages resolve against the venv instead of whatever
+    /// environment the host process was launched from.
+    pub fn python_command(&self) -> Result<Command, String> {
+        let mut command = Command::new(self.resolve_interpreter()?);
+        let mut python_path: Option<Vec<PathBuf>> = None;
+        if let Some(venv) = &self.venv {
+            command.env("VIRTUAL_ENV", venv);
+            command.env_remove("PYTHONHOME");
+
+            // Scripts installed into the venv take priority over the host's PATH
+            let bin_dir = venv_interpreter(venv)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| venv.clone());
+            command.env("PATH", prepend_paths(vec![bin_dir], env::var_os("PATH"))?);
+
+            // The venv's site-packages are picked up automatically by its own interpreter, but
+            // an explicit interpreter outside the venv still needs them on PYTHONPATH. Existing
+            // entries stay first so project paths keep shadowing installed packages.
+            let site_packages = venv_site_packages(venv);
+            if !site_packages.is_empty() {
+                let mut paths = inherited_python_path();
+                paths.extend(site_packages);
+                python_path = Some(paths);
+            }
+        }
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+
+            // Relative PYTHONPATH entries resolve against the working directory, and
+            // `python -c` puts the working directory itself first on sys.path. Anchor both to
+            // the directory we were launched from, so modules resolve the same as without a
+            // cwd override.
+            let launch_dir = env::current_dir()
+                .map_err(|e| format!("Failed to read current directory: {}", e))?;
+            let mut paths: Vec<PathBuf> = python_path
+                .take()
+                .unwrap_or_else(inherited_python_path)
+                .into_iter()
+                .map(|path| launch_dir.join(path))
+                .collect();
+            paths.push(launch_dir);
+            python_path = Some(paths);
+        }
+        if let Some(paths) = python_path {
+            let joined =
+                env::join_paths(paths).map_err(|e| format!("Invalid PYTHONPATH: {}", e))?;
+            command.env("PYTHONPATH", joined);
+        }
+        // Applied after the venv so callers can override anything we set for them
+        command.envs(&self.env_vars);
+        command.env("FIREHOT_MESSAGE_FRAMING", self.framing.as_env_value());
+        command.env(
+            "FIREHOT_IMPORT_FAILURES",
+            self.import_failure_policy.as_env_value(),
+        );
+        command.env("FIREHOT_EXECUTION_MODE", self.execution_mode.as_env_value());
+        if !self.critical_modules.is_empty() {
+            let mut critical: Vec<&String> = self.critical_modules.iter().collect();
+            critical.sort();
+            let critical = serde_json::to_string(&critical)
+                .map_err(|e| format!("Failed to serialize critical modules: {}", e))?;
+            command.env("FIREHOT_CRITICAL_MODULES", critical);
+        }
+        if !self.multiplex_format.is_default() {
+            command.env("FIREHOT_MULTIPLEX_FORMAT", self.multiplex_format.template());
+        }
+        Ok(command)
+    }
+
+    /// Source of the loader script: the override when one is configured, otherwise the
+    /// script embedded in this build. The override is read on every launch so edits to it are
+    /// picked up by the next restart.
+    pub fn loader_script_source(&self) -> Result<Cow<'static, str>, String> {
+        match &self.loader_script {
+            Some(path) => fs::read_to_string(path)
+                .map(Cow::Owned)
+                .map_err(|e| format!("Failed to read loader script {}: {}", path.display(), e)),
+            None => Ok(Cow::Borrowed(PYTHON_LOADER_SCRIPT)),
+        }
+    }
+
+    /// Ask the configured interpreter for its standard library modules, so the list always
+    /// matches the Python version we launch. Requires Python 3.10+ for
+    /// `sys.stdlib_module_names`.
+    pub fn stdlib_modules(&self) -> Result<HashSet<String>, String> {
+        let output = self
+            .python_command()?
+            .arg("-c")
+            .arg("import sys; print('\\n'.join(sys.stdlib_module_names))")
+            .output()
+            .map_err(|e| format!("Failed to query standard library modules: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to query standard library modules: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Ask the configured interpreter for its version as (major, minor, micro)
+    pub fn interpreter_version(&self) -> Result<(u32, u32, u32), String> {
+        let output = self
+            .python_command()?
+            .arg("-c")
+            .arg("import sys; print('%d.%d.%d' % sys.version_info[:3])")
+            .output()
+            .map_err(|e| format!("Failed to query the interpreter version: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to query the interpreter version: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let version = String::from_utf8_lossy(&output.stdout);
+        let parts: Vec<u32> = version
+            .trim()
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Unexpected interpreter version {:?}: {}", version.trim(), e))?;
+        match parts.as_slice() {
+            [major, minor, micro] => Ok((*major, *minor, *micro)),
+            _ => Err(format!(
+                "Unexpected interpreter version {:?}",
+                version.trim()
+            )),
+        }
+    }
+}
+
+/// Entries of our own PYTHONPATH, which the interpreter would otherwise inherit as-is
+fn inherited_python_path() -> Vec<PathBuf> {
+    env::var_os("PYTHONPATH")
+        .map(|existing| env::split_paths(&existing).collect())
+        .unwrap_or_default()
+}
+
+/// Join `first` ahead of the entries of an existing PATH-style variable
+fn prepend_paths(first: Vec<PathBuf>, existing: Option<OsString>) -> Result<OsString, String> {
+    let mut paths = first;
+    if let Some(existing) = existing {
+        paths.extend(env::split_paths(&existing));
+    }
+    env::join_paths(paths).map_err(|e| format!("Invalid PATH for virtualenv: {}", e))
+}
+
+/// Locate an executable. Bare command names are searched for on PATH, anything with a
+/// directory component is checked as-is.
+pub fn find_executable(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_path_buf());
+    }
+
+    let path_var = env::var_os("PATH")?;
+    env::split_paths(&path_var).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if cfg!(windows) {
+            let candidate = candidate.with_extension("exe");
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+        None
+    })
+}
+
+/// Resolve the python executable within a virtualenv directory
+pub fn venv_interpreter(venv: &Path) -> PathBuf {
+    if cfg!(windows) {
+        venv.join("Scripts").join("python.exe")
+    } else {
+        venv.join("bin").join("python")
+    }
+}
+
+/// The site-packages directories of a virtualenv. On unix these live under a versioned
+/// `lib/pythonX.Y` directory, so we list whatever versions are present.
+pub fn venv_site_packages(venv: &Path) -> Vec<PathBuf> {
+    if cfg!(windows) {
+        let site_packages = venv.join("Lib").join("site-packages");
+        return if site_packages.is_dir() {
+            vec![site_packages]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let mut site_packages: Vec<PathBuf> = fs::read_dir(venv.join("lib"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("python"))
+                .map(|entry| entry.path().join("site-packages"))
+                .filter(|path| path.is_dir())
+                .collect()
+        })
+        .unwrap_or_default();
+    site_packages.sort();
+    site_packages
+}
+
+/// Fluent builder for an Environment
+pub struct EnvironmentBuilder {
+    project_name: String,
+    project_path: String,
+    ignored_modules: Option<HashSet<String>>,
+    config: EnvironmentConfig,
+}
+
+impl EnvironmentBuilder {
+    pub fn new(project_name: &str, project_path: &str) -> Self {
+        Self {
+            project_name: project_name.to_string(),
+            project_path: project_path.to_string(),
+            ignored_modules: None,
+            config: EnvironmentConfig::default(),
+        }
+    }
+
+    /// Modules that should never be treated as third-party imports
+    pub fn ignored_modules(mut self, ignored_modules: HashSet<String>) -> Self {
+        self.ignored_modules = Some(ignored_modules);
+        self
+    }
+
+    /// Python interpreter to launch, either a path or a command name on PATH
+    pub fn interpreter(mut self, interpreter: impl Into<PathBuf>) -> Self {
+        self.config.interpreter = Some(interpreter.into());
+        self
+    }
+
+    /// Run the loader with the interpreter of the given virtualenv directory
+    pub fn venv(mut self, venv: impl Into<PathBuf>) -> Self {
+        self.config.venv = Some(venv.into());
+        self
+    }
+
+    /// Message framing between Rust and the loader. Length-prefixed framing is robust to
+    /// payloads of any size or content, at the cost of a stdout that isn't human readable.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.config.framing = framing;
+        self
+    }
+
+    /// Granularity of the third-party modules we detect and preload
+    pub fn import_granularity(mut self, granularity: ImportGranularity) -> Self {
+        self.config.import_granularity = granularity;
+        self
+    }
+
+    /// Eagerly preload imports that are only guarded for type checkers
+    pub fn include_type_checking_imports(mut self, include: bool) -> Self {
+        self.config.include_type_checking_imports = include;
+        self
+    }
+
+    /// Directory names to skip while scanning the project, replacing the defaults
+    pub fn exclude_dirs<I, S>(mut self, excluded_dirs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.excluded_dirs = Some(excluded_dirs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Skip files matching these glob patterns while scanning, like `**/migrations/*.py`.
+    /// Patterns are relative to the project root.
+    pub fn exclude_globs<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config
+            .exclude_globs
+            .extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Fail `boot_main` if the preloaded imports take longer than this
+    pub fn boot_timeout(mut self, timeout: Duration) -> Self {
+        self.config.boot_timeout = Some(timeout);
+        self
+    }
+
+    /// Try spawning the loader up to this many times, backing off between attempts, when the
+    /// failure looks transient. Permanent errors like a missing interpreter fail right away.
+    pub fn spawn_attempts(mut self, attempts: u32) -> Self {
+        self.config.spawn_attempts = Some(attempts);
+        self
+    }
+
+    /// Truncate output lines longer than this many bytes, so a runaway print can't exhaust
+    /// our memory
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.config.max_line_length = Some(max_line_length);
+        self
+    }
+
+    /// Fail forks whose messages to us, usually their pickled result, are longer than this
+    /// many bytes. Unlike output lines these are never truncated, since a partial message
+    /// can't be decoded.
+    pub fn max_message_length(mut self, max_message_length: usize) -> Self {
+        self.config.max_message_length = Some(max_message_length);
+        self
+    }
+
+    /// Preload these modules in addition to the ones detected in the project
+    pub fn extra_preload<I, S>(mut self, modules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config
+            .extra_preload
+            .extend(modules.into_iter().map(Into::into));
+        self
+    }
+
+    /// Preload the distributions listed in a requirements file, relative to the project root.
+    /// Can be called more than once for projects that split their requirements.
+    pub fn preload_requirements(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.requirements_files.push(path.into());
+        self
+    }
+
+    /// Preload the dependencies declared in the project's pyproject.toml
+    pub fn preload_pyproject_dependencies(mut self, preload: bool) -> Self {
+        self.config.preload_pyproject_dependencies = preload;
+        self
+    }
+
+    /// Never preload these modules, even if the project imports them
+    pub fn exclude_preload<I, S>(mut self, modules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config
+            .excluded_preload
+            .extend(modules.into_iter().map(Into::into));
+        self
+    }
+
+    /// Report the loader ready as soon as these modules (and their submodules) are imported,
+    /// and import the remaining preloads in the background
+    pub fn critical_modules<I, S>(mut self, modules: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config
+            .critical_modules
+            .extend(modules.into_iter().map(Into::into));
+        self
+    }
+
+    /// Whether modules that fail to import abort the boot or are only reported as warnings
+    pub fn import_failure_policy(mut self, policy: ImportFailurePolicy) -> Self {
+        self.config.import_failure_policy = policy;
+        self
+    }
+
+    /// Transparently relaunch the loader when it exits unexpectedly, e.g. after a segfault in
+    /// a C extension or an OOM kill
+    pub fn auto_restart(mut self, auto_restart: bool) -> Self {
+        self.config.auto_restart = auto_restart;
+        self
+    }
+
+    /// Treat these packages as part of the project too, on top of the ones
+    /// `ast::detect_package_names` finds in the project directory. For packages that live
+    /// elsewhere, like a sibling checkout on the path.
+    pub fn first_party_packages<I, S>(mut self, packages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config
+            .first_party_packages
+            .extend(packages.into_iter().map(Into::into));
+        self
+    }
+
+    /// Always treat modules under these prefixes as third party, for libraries vendored
+    /// inside our own package. The inverse of `ignored_modules`.
+    pub fn third_party_prefixes<I, S>(mut self, prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config
+            .third_party_prefixes
+            .extend(prefixes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run a customized loader script instead of the embedded one. Start from a copy of
+    /// `firehot/embedded/parent_entrypoint.py`, since the loader has to speak our protocol.
+    pub fn loader_script(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.loader_script = Some(path.into());
+        self
+    }
+
+    /// Keep standard library imports like `os` and `json` in the preload set
+    pub fn preload_stdlib(mut self, preload_stdlib: bool) -> Self {
+        self.config.preload_stdlib = preload_stdlib;
+        self
+    }
+
+    /// Print fork output as colored text (the default) or as JSON objects
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.config.log_format = log_format;
+        self
+    }
+
+    /// Tag fork output with a different prefix, given as a template with `{pid}` and `{stream}`
+    /// placeholders like `[PID:{pid}:{stream}]`. The template is validated at boot.
+    pub fn multiplex_format(mut self, template: impl Into<String>) -> Self {
+        self.config.multiplex_format = MultiplexFormat::new(template);
+        self
+    }
+
+    /// Evaluate `sys.version_info` checks against this version instead of asking the
+    /// interpreter at boot
+    pub fn python_version(mut self, major: u32, minor: u32, micro: u32) -> Self {
+        self.config.python_version = Some((major, minor, micro));
+        self
+    }
+
+    /// Run the loader, and so every fork, from this directory instead of the project path
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.config.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Whether `update_environment` stops running forks or lets them finish on the old loader
+    pub fn reload_mode(mut self, reload_mode: ReloadMode) -> Self {
+        self.config.reload_mode = reload_mode;
+        self
+    }
+
+    /// Run isolated calls in forks of the loader (the default) or in fresh interpreters
+    pub fn execution_mode(mut self, execution_mode: ExecutionMode) -> Self {
+        self.config.execution_mode = execution_mode;
+        self
+    }
+
+    /// Reload edited pure-Python modules in place rather than rebooting the loader, see
+    /// `Environment::reload_files`
+    pub fn partial_reload(mut self, partial_reload: bool) -> Self {
+        self.config.partial_reload = partial_reload;
+        self
+    }
+
+    /// Check that the loader can fork before `boot_main` returns, see
+    /// `Environment::verify_fork`
+    pub fn verify_fork(mut self, verify_fork: bool) -> Self {
+        self.config.verify_fork = verify_fork;
+        self
+    }
+
+    /// Set an environment variable for the loader and the forks it spawns
+    pub fn env_var(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.env_vars.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> Environment {
+        Environment::with_config(
+            &self.project_name,
+            &self.project_path,
+            self.ignored_modules,
+            self.config,
+        )
+    }
+
+    /// Build the Environment and boot it, see `Environment::try_new_booted`
+    pub fn build_booted(self) -> Result<Environment, HotReloadError> {
+        self.build().into_booted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_venv_interpreter_resolution() {
+        let config = EnvironmentConfig {
+            venv: Some(PathBuf::from("/tmp/project/.venv")),
+            ..Default::default()
+        };
+
+        if cfg!(windows) {
+            assert_eq!(
+                config.interpreter(),
+                PathBuf::from("/tmp/project/.venv/Scripts/python.exe")
+            );
+        } else {
+            assert_eq!(
+                config.interpreter(),
+                PathBuf::from("/tmp/project/.venv/bin/python")
+            );
+        }
+
+        // Without a venv we use whatever python is on the PATH
+        assert_eq!(
+            EnvironmentConfig::default().interpreter(),
+            PathBuf::from("python")
+        );
+    }
+
+    #[test]
+    fn test_resolve_interpreter() {
+        // The default interpreter is looked up on PATH
+        let resolved = EnvironmentConfig::default().resolve_interpreter().unwrap();
+        assert!(resolved.is_absolute() || resolved.components().count() > 1);
+
+        // An explicit interpreter wins over the venv
+        let venv = tempfile::TempDir::new().unwrap();
+        let config = EnvironmentConfig {
+            interpreter: Some(resolved.clone()),
+            venv: Some(venv.path().to_path_buf()),
+            ..Default::default()
+        };
+        assert_eq!(config.resolve_interpreter().unwrap(), resolved);
+
+        let missing = EnvironmentConfig {
+            interpreter: Some(PathBuf::from("python-does-not-exist")),
+            ..Default::default()
+        };
+        assert_eq!(
+            missing.resolve_interpreter().unwrap_err(),
+            "Python interpreter 'python-does-not-exist' was not found on PATH"
+        );
+    }
+
+    #[test]
+    fn test_venv_layout_resolution() {
+        let venv = tempfile::TempDir::new().unwrap();
+        let interpreter = venv_interpreter(venv.path());
+        fs::create_dir_all(interpreter.parent().unwrap()).unwrap();
+        fs::write(&interpreter, "").unwrap();
+
+        let site_packages = if cfg!(windows) {
+            venv.path().join("Lib").join("site-packages")
+        } else {
+            venv.path()
+                .join("lib")
+                .join("python3.11")
+                .join("site-packages")
+        };
+        fs::create_dir_all(&site_packages).unwrap();
+
+        let config = EnvironmentConfig {
+            venv: Some(venv.path().to_path_buf()),
+            ..Default::default()
+        };
+        assert_eq!(config.resolve_interpreter().unwrap(), interpreter);
+        assert_eq!(venv_site_packages(venv.path()), vec![site_packages.clone()]);
+
+        let command = config.python_command().unwrap();
+        assert_eq!(command.get_program(), interpreter.as_os_str());
+
+        let env_value = |key: &str| {
+            command
+                .get_envs()
+                .find(|(name, _)| *name == key)
+                .and_then(|(_, value)| value)
+                .unwrap()
+                .to_os_string()
+        };
+        assert_eq!(env_value("VIRTUAL_ENV"), venv.path().as_os_str());
+        let path: Vec<PathBuf> = env::split_paths(&env_value("PATH")).collect();
+        assert_eq!(path[0], interpreter.parent().unwrap());
+        let python_path: Vec<PathBuf> = env::split_paths(&env_value("PYTHONPATH")).collect();
+        assert!(python_path.contains(&site_packages));
+    }
+
+    #[test]
+    fn test_builder_stores_options() {
+        let environment = EnvironmentBuilder::new("test_package", "/tmp/project")
+            .interpreter("/usr/bin/python3")
+            .venv("/tmp/project/.venv")
+            .exclude_dirs(["build", "dist"])
+            .boot_timeout(Duration::from_secs(5))
+            .env_var("APP_ENV", "test")
+            .env_var("DEBUG", "1")
+            .build();
+
+        let config = &environment.config;
+        assert_eq!(config.interpreter, Some(PathBuf::from("/usr/bin/python3")));
+        assert_eq!(config.venv, Some(PathBuf::from("/tmp/project/.venv")));
+        assert_eq!(
+            config.excluded_dirs,
+            Some(HashSet::from(["build".to_string(), "dist".to_string()]))
+        );
+        assert_eq!(config.boot_timeout(), Duration::from_secs(5));
+        assert_eq!(
+            config.env_vars,
+            HashMap::from([
+                ("APP_ENV".to_string(), "test".to_string()),
+                ("DEBUG".to_string(), "1".to_string()),
+            ])
+        );
+
+        // The exclusions are applied to the project scan, not just stored
+        assert_eq!(
+            environment.ast_manager.excluded_dirs(),
+            &HashSet::from(["build".to_string(), "dist".to_string()])
+        );
+
+        // Without the builder everything falls back to the defaults
+        let defaults = Environment::new("test_package", "/tmp/project", None)
+            .config
+            .clone();
+        assert_eq!(defaults.boot_timeout(), DEFAULT_BOOT_TIMEOUT);
+        assert!(defaults.env_vars.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_venv_errors() {
+        let missing = EnvironmentConfig {
+            venv: Some(PathBuf::from("/nonexistent/firehot/.venv")),
+            ..Default::default()
+        };
+        assert_eq!(
+            missing.resolve_interpreter().unwrap_err(),
+            "Virtualenv /nonexistent/firehot/.venv does not exist"
+        );
+
+        // A directory without an interpreter isn't a usable venv
+        let empty = tempfile::TempDir::new().unwrap();
+        let config = EnvironmentConfig {
+            venv: Some(empty.path().to_path_buf()),
+            ..Default::default()
+        };
+        let err = config.resolve_interpreter().unwrap_err();
+        assert!(err.contains("has no python interpreter"), "{}", err);
+    }
+
+    #[test]
+    fn test_interpreter_version() {
+        let (major, minor, _) = EnvironmentConfig::default().interpreter_version().unwrap();
+        assert_eq!(major, 3);
+        assert!(minor >= 8);
+    }
+
+    #[test]
+    fn test_stdlib_modules_match_interpreter() {
+        let stdlib = EnvironmentConfig::default().stdlib_modules().unwrap();
+        assert!(stdlib.contains("os"));
+        assert!(stdlib.contains("json"));
+        assert!(!stdlib.contains("requests"));
+    }
+
+    #[test]
+    fn test_loader_script_source() {
+        let config = EnvironmentConfig::default();
+        assert_eq!(config.loader_script_source().unwrap(), PYTHON_LOADER_SCRIPT);
+
+        let missing = EnvironmentConfig {
+            loader_script: Some(PathBuf::from("/nonexistent/firehot/loader.py")),
+            ..Default::default()
+        };
+        let err = missing.loader_script_source().unwrap_err();
+        assert!(
+            err.starts_with("Failed to read loader script /nonexistent/firehot/loader.py"),
+            "{}",
+            err
+        );
+    }
+}
@@ -1,30 +1,217 @@
 use anstream::eprintln;
 use anyhow::{anyhow, Result};
-use log::{debug, error, info, warn};
+use log::{debug, error, info, trace, warn};
 use owo_colors::OwoColorize;
+use serde::de::DeserializeOwned;
 use serde_json::{self};
-use std::collections::HashSet;
-use std::io::{BufReader, Write};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use libc;
-use std::io::BufRead;
 use uuid::Uuid;
 
-use crate::ast::ProjectAstManager;
+use crate::ast::{detect_package_names, import_hash, ProjectAstManager};
 use crate::async_resolve::AsyncResolve;
-use crate::layer::{ForkResult, Layer, ProcessResult};
-use crate::messages::{ExitRequest, ForkRequest, Message};
-use crate::scripts::{PYTHON_CHILD_SCRIPT, PYTHON_LOADER_SCRIPT};
+use crate::config::{EnvironmentConfig, ReloadMode};
+use crate::error::{HotReloadError, ImportFailure};
+use crate::layer::{BackgroundImports, ForkResult, ImportTiming, Layer, ProcessResult};
+use crate::messages::io::{write_message, FrameReader, Framing};
+use crate::messages::{
+    ExitRequest, ForkRequest, ImportProgress, Message, Ping, ReloadRequest, ReloadResponse,
+    SUPPORTED_PROTOCOL_VERSIONS,
+};
+use crate::metrics::Metrics;
+use crate::process::is_process_running;
+use crate::requirements;
+use crate::resources::ResourceTotals;
+use crate::scripts::{PYTHON_CHILD_SCRIPT, PYTHON_PREFLIGHT_SCRIPT};
+
+/// How long forks get to exit after SIGTERM during `shutdown` before we escalate to SIGKILL
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// How long `is_alive` waits for the loader to answer a ping
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long `finish_isolated` waits for the loader to report a fork it killed. SIGKILL can't
+/// be ignored, so only a loader that stopped reaping its forks takes longer.
+const KILL_REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unanswered pings in a row before a loader that is still running is considered unhealthy
+const MAX_FAILED_PINGS: u32 = 3;
+
+/// Outcome of `Environment::shutdown`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// UUIDs of forks that exited on SIGTERM within the grace period
+    pub terminated: Vec<String>,
+    /// UUIDs of forks that outlived the grace period and required SIGKILL
+    pub killed: Vec<String>,
+    /// Whether a running loader process was stopped
+    pub loader_stopped: bool,
+}
+
+/// Number of modules listed in the slowest-imports summary logged after each boot
+const SLOWEST_IMPORTS_LOGGED: usize = 10;
+
+/// Most of the loader's stderr kept when it fails to boot. Tracebacks end with the
+/// exception, so it's the start of the output that gets dropped.
+const MAX_BOOT_STDERR_BYTES: usize = 16 * 1024;
+
+/// How long we wait for the loader's stderr to close after it fails to boot. A subprocess it
+/// started could hold the pipe open indefinitely.
+const BOOT_STDERR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often a draining loader is checked for forks that are still running
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Wait before retrying a loader spawn that failed transiently, doubled on each retry
+const SPAWN_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A function ready to run in isolation, as handed to `exec_batch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedCall {
+    /// The pickled call, as passed to `exec_isolated`
+    pub pickled_data: String,
+    /// Label for the fork's output
+    pub name: String,
+}
+
+impl PreparedCall {
+    pub fn new(pickled_data: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            pickled_data: pickled_data.into(),
+            name: name.into(),
+        }
+    }
+}
+
+/// Per-call settings for `exec_isolated_with_options`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecOptions {
+    /// Environment variables set in the fork before the function runs. They only affect that
+    /// fork, and their values are never logged.
+    pub env: HashMap<String, String>,
+    /// Raise `TimeoutError` inside the function once this passes, so it can catch it and clean
+    /// up. Waiting on a fork that let the error propagate reports `ProcessResult::TimedOut`. A
+    /// function that swallows the error keeps running, so keep a timeout on the wait (or
+    /// `stop_isolated`) as a backstop. The timer is the fork's `SIGALRM`/`ITIMER_REAL`, so it
+    /// replaces any alarm the function sets itself and vice versa.
+    pub timeout: Option<Duration>,
+}
+
+/// A fork request from `exec_isolated_async` that is still waiting on the loader. Dropping
+/// it while `abandoned` removes the fork's resolvers.
+struct PendingFork<'a> {
+    layer: &'a Arc<Mutex<Layer>>,
+    process_uuid: &'a str,
+    abandoned: bool,
+}
+
+impl Drop for PendingFork<'_> {
+    fn drop(&mut self) {
+        if !self.abandoned {
+            return;
+        }
+        debug!("Fork request {} was dropped", self.process_uuid);
+        let Ok(layer_guard) = self.layer.lock() else {
+            return;
+        };
+        if let Ok(mut fork_resolvers) = layer_guard.fork_resolvers.lock() {
+            fork_resolvers.remove(self.process_uuid);
+        }
+        if let Ok(mut completion_resolvers) = layer_guard.completion_resolvers.lock() {
+            completion_resolvers.remove(self.process_uuid);
+        };
+    }
+}
+
+/// Starts the loader process from its fully configured command
+type LoaderSpawner = Box<dyn Fn(&mut Command) -> io::Result<Child> + Send + Sync>;
+
+/// Called after the loader has been restarted automatically
+pub type RestartCallback = Box<dyn Fn() + Send + Sync>;
+
+/// What changed in a reload performed by `update_environment`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportDelta {
+    /// ID of the environment that was reloaded
+    pub environment_id: String,
+    /// Modules the new loader imports that the old one didn't
+    pub added: HashSet<String>,
+    /// Modules the old loader imported that the new one doesn't
+    pub removed: HashSet<String>,
+}
+
+/// How `reload_files` brought the loader up to date with edited sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleReload {
+    /// The loader hasn't imported any of the files, so forks pick up the edits on their own
+    Unchanged,
+    /// These modules were reloaded inside the running loader
+    Partial(Vec<String>),
+    /// The loader was rebooted, either because partial reloads are off or because one of the
+    /// modules couldn't be reloaded in place
+    Full,
+}
+
+/// Called after `update_environment` rebuilt the loader
+pub type ReloadCallback = Box<dyn Fn(&ImportDelta) + Send + Sync>;
+/// Called as each preloaded module starts importing during a boot
+pub type ImportProgressCallback = Box<dyn Fn(&ImportProgress) + Send + Sync>;
+
+/// A freshly booted loader, along with what was learned while it imported its modules
+struct LaunchedLayer {
+    layer: Layer,
+    import_failures: Vec<ImportFailure>,
+    import_timings: Vec<ImportTiming>,
+}
 
 /// Runner for isolated Python code execution
 pub struct Environment {
     pub id: String,
     pub layer: Option<Arc<Mutex<Layer>>>, // The current layer that is tied to this environment
     pub ast_manager: ProjectAstManager,   // Project AST manager for this environment
+    pub config: EnvironmentConfig,        // Runtime options, usually set through EnvironmentBuilder
+
+    // Resource usage aggregated over every fork for the lifetime of this environment
+    resource_totals: Arc<Mutex<ResourceTotals>>,
 
+    // Boot and fork counters for the lifetime of this environment
+    metrics: Arc<Mutex<Metrics>>,
+
+    // Modules that failed to import during the last boot, under the warn import policy
+    import_failures: Vec<ImportFailure>,
+
+    // Per-module import durations from the last boot, in import order
+    import_timings: Vec<ImportTiming>,
+
+    // Liveness of the current loader, as seen by `is_alive`. An unhealthy loader is rebuilt
+    // by the next `update_environment`.
+    failed_pings: AtomicU32,
+    unhealthy: AtomicBool,
+
+    // Modules the current loader was booted with, reused when it's restarted automatically
+    booted_modules: HashSet<String>,
+    on_restart: Option<RestartCallback>,
+    on_reload: Option<ReloadCallback>,
+    on_import_progress: Option<ImportProgressCallback>,
+    spawner: LoaderSpawner,
+
+    // Loaders replaced by a drain reload that still have forks running, and the results of
+    // forks collected from loaders that have since been retired
+    draining_layers: Arc<Mutex<Vec<Arc<Mutex<Layer>>>>>,
+    drained_results: Arc<Mutex<HashMap<String, ProcessResult>>>,
+
+    // Whether the project has been scanned, which establishes the baseline that
+    // `update_environment` compares against
     first_scan: bool,
     test_mode: bool, // Whether to run in test mode (buffer output instead of printing)
 }
@@ -35,36 +222,95 @@ impl Environment {
         project_path: &str,
         ignored_modules: Option<HashSet<String>>,
     ) -> Self {
+        Self::with_config(
+            project_name,
+            project_path,
+            ignored_modules,
+            EnvironmentConfig::default(),
+        )
+    }
+
+    /// Create an Environment and boot it right away, returning a runner that's ready to fork.
+    /// A missing interpreter is reported as `InterpreterNotFound` before anything is spawned.
+    /// Use `new` and `boot_main` to defer the boot instead.
+    pub fn try_new_booted(project_name: &str, project_path: &str) -> Result<Self, HotReloadError> {
+        Self::new(project_name, project_path, None).into_booted()
+    }
+
+    /// Check the interpreter exists and boot, see `try_new_booted`
+    pub(crate) fn into_booted(mut self) -> Result<Self, HotReloadError> {
+        self.config
+            .resolve_interpreter()
+            .map_err(HotReloadError::InterpreterNotFound)?;
+        self.boot_main()?;
+        Ok(self)
+    }
+
+    /// Create a new Environment with explicit runtime configuration
+    pub fn with_config(
+        project_name: &str,
+        project_path: &str,
+        ignored_modules: Option<HashSet<String>>,
+        mut config: EnvironmentConfig,
+    ) -> Self {
+        // Scripts expect to find their data files relative to the project root
+        if config.cwd.is_none() {
+            config.cwd = Some(PathBuf::from(project_path));
+        }
+
         // Create a new AST manager for this project
-        let ast_manager = ProjectAstManager::new(project_name, project_path, ignored_modules);
+        let mut ast_manager = ProjectAstManager::new(project_name, project_path, ignored_modules);
+        ast_manager.set_import_granularity(config.import_granularity);
+        ast_manager.set_include_type_checking(config.include_type_checking_imports);
+        if let Some(excluded_dirs) = &config.excluded_dirs {
+            ast_manager.set_excluded_dirs(excluded_dirs.iter().cloned());
+        }
+        if let Err(e) = ast_manager.set_exclude_globs(config.exclude_globs.iter().cloned()) {
+            warn!("Ignoring exclude globs: {}", e);
+        }
+        // Every package the project itself provides is first party, not just the one named
+        // after it, along with any the caller listed
+        let mut package_names = detect_package_names(project_path);
+        package_names.extend(config.first_party_packages.iter().cloned());
+        ast_manager.set_package_names(package_names);
+        ast_manager.set_third_party_prefixes(config.third_party_prefixes.iter().cloned());
         info!("Created AST manager for project: {}", project_name);
 
         Self {
             id: Uuid::new_v4().to_string(),
             layer: None,
             ast_manager,
+            config,
+            resource_totals: Arc::new(Mutex::new(ResourceTotals::new())),
+            metrics: Arc::new(Mutex::new(Metrics::new())),
+            import_failures: Vec::new(),
+            import_timings: Vec::new(),
+            failed_pings: AtomicU32::new(0),
+            unhealthy: AtomicBool::new(false),
+            booted_modules: HashSet::new(),
+            on_restart: None,
+            on_import_progress: None,
+            spawner: Box::new(Command::spawn),
+            on_reload: None,
+            draining_layers: Arc::new(Mutex::new(Vec::new())),
+            drained_results: Arc::new(Mutex::new(HashMap::new())),
             first_scan: false,
             test_mode: false,
         }
     }
 
-    /// Create a new Environment in test mode (buffers output instead of printing)
+    /// Create a new Environment in test mode (buffers output instead of printing). Standard
+    /// library imports are preloaded like any other, since tests lean on them as stand-ins
+    /// for third-party packages.
     pub fn new_for_test(
         project_name: &str,
         project_path: &str,
         ignored_modules: Option<HashSet<String>>,
     ) -> Self {
-        // Create a new AST manager for this project
-        let ast_manager = ProjectAstManager::new(project_name, project_path, ignored_modules);
-        info!("Created AST manager for project: {}", project_name);
-
-        Self {
-            id: Uuid::new_v4().to_string(),
-            layer: None,
-            ast_manager,
-            first_scan: false,
-            test_mode: true,
-        }
+        let mut environment = Self::new(project_name, project_path, ignored_modules);
+        environment.test_mode = true;
+        environment.config.preload_stdlib = true;
+        environment
     }
 
     /// Get the buffered output from the layer (if in test mode)
@@ -86,29 +332,320 @@ impl Environment {
         }
     }
 
+    /// Snapshot of the resource usage accumulated by all forks of this environment,
+    /// including forks that ran on layers that have since been rebuilt.
+    pub fn resource_totals(&self) -> ResourceTotals {
+        self.resource_totals
+            .lock()
+            .map(|totals| totals.clone())
+            .unwrap_or_default()
+    }
+
+    /// Snapshot of the boot and fork counters of this environment, across every loader it
+    /// has run.
+    pub fn metrics_snapshot(&self) -> Metrics {
+        self.metrics
+            .lock()
+            .map(|metrics| metrics.clone())
+            .unwrap_or_default()
+    }
+
+    /// How long the last successful boot took to spawn the loader and import its modules, or
+    /// None before the first one. Restarts and rebuilds count as boots.
+    pub fn last_boot_duration(&self) -> Option<Duration> {
+        self.metrics
+            .lock()
+            .ok()
+            .and_then(|metrics| metrics.last_boot_duration)
+    }
+
+    /// Modules that failed to import during the last boot. Only populated when the import
+    /// failure policy is `Warn`, since otherwise the boot itself fails with these.
+    pub fn import_failures(&self) -> &[ImportFailure] {
+        &self.import_failures
+    }
+
+    /// Per-module import durations from the last boot, in the order the modules were imported
+    pub fn import_timings(&self) -> &[ImportTiming] {
+        &self.import_timings
+    }
+
+    /// Progress of the imports the current loader defers past `critical_modules`. Complete
+    /// straight away when nothing was deferred.
+    pub fn background_imports(&self) -> BackgroundImports {
+        self.layer
+            .as_ref()
+            .and_then(|layer| {
+                let layer = layer.lock().ok()?;
+                let background_imports = layer.background_imports.lock().ok()?;
+                Some(background_imports.clone())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Modules the current loader was asked to preload, after the preload overrides
+    pub fn requested_modules(&self) -> &HashSet<String> {
+        &self.booted_modules
+    }
+
+    /// The requested modules that the loader actually imported, including the ones imported
+    /// in the background so far. Under the `Warn` import policy this leaves out the
+    /// `import_failures`, so together with `requested_modules` it tells how many dependencies
+    /// are warm.
+    pub fn loaded_modules(&self) -> HashSet<String> {
+        let background_imports = self.background_imports();
+        let imported: HashSet<&str> = self
+            .import_timings
+            .iter()
+            .chain(&background_imports.timings)
+            .map(|timing| timing.module.as_str())
+            .collect();
+
+        // The loader skips packages that a requested submodule imports anyway, so importing
+        // `numpy.linalg` counts for `numpy` as well
+        self.booted_modules
+            .iter()
+            .filter(|module| {
+                imported.contains(module.as_str())
+                    || imported.iter().any(|imported| {
+                        imported
+                            .strip_prefix(module.as_str())
+                            .is_some_and(|rest| rest.starts_with('.'))
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Hash of the modules the current loader was booted with, see `ast::import_hash`.
+    /// `None` until the first boot. Handy for telling whether two boots preloaded the same
+    /// imports.
+    pub fn import_hash(&self) -> Option<String> {
+        self.first_scan.then(|| import_hash(&self.booted_modules))
+    }
+
+    /// Preflight check that every detected third-party import can be resolved by the
+    /// interpreter. This only locates the modules (no imports are executed), so it's much
+    /// faster than a full boot and is safe to run in CI or from an editor. Intended to be
+    /// called before `boot_main`.
+    ///
+    /// Returns the modules that could not be found, sorted by name.
+    pub fn check_imports_available(&mut self) -> Result<Vec<String>, HotReloadError> {
+        let third_party_modules = self.preload_modules()?;
+
+        info!(
+            "Checking availability of {} modules",
+            third_party_modules.len()
+        );
+        let missing = find_missing_modules(&self.config, &third_party_modules)
+            .map_err(|e| format!("Failed to run import preflight: {}", e))?;
+
+        if !missing.is_empty() {
+            warn!("Modules not available in the environment: {:?}", missing);
+        }
+
+        Ok(missing)
+    }
+
+    /// Scan the project and return the modules the loader should import: the detected
+    /// third-party imports with the configured extra and excluded modules applied.
+    pub fn preload_modules(&mut self) -> Result<HashSet<String>, HotReloadError> {
+        let detected = self
+            .ast_manager
+            .process_all_py_files()
+            .map_err(|e| format!("Failed to process Python files: {}", e))?;
+        Ok(self.preload_set(detected))
+    }
+
+    /// Everything we preload for the detected imports: the declared dependencies are added
+    /// and then the preload overrides are applied
+    fn preload_set(&self, detected: HashSet<String>) -> HashSet<String> {
+        let mut modules = detected;
+        modules.extend(self.declared_dependency_modules());
+        self.config.preload_modules(modules)
+    }
+
+    /// Top-level modules of the dependencies declared in the configured requirements files,
+    /// and in pyproject.toml when enabled. Files that can't be read are skipped with a warning.
+    fn declared_dependency_modules(&self) -> HashSet<String> {
+        let project_path = Path::new(self.ast_manager.get_project_path());
+        let read = |path: &Path| match fs::read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                None
+            }
+        };
+
+        let mut distributions = Vec::new();
+        for requirements_file in &self.config.requirements_files {
+            if let Some(content) = read(&project_path.join(requirements_file)) {
+                distributions.extend(requirements::parse_requirements(&content));
+            }
+        }
+        if self.config.preload_pyproject_dependencies {
+            if let Some(content) = read(&project_path.join("pyproject.toml")) {
+                distributions.extend(requirements::pyproject_dependencies(&content));
+            }
+        }
+
+        let modules: HashSet<String> = distributions
+            .iter()
+            .map(|distribution| requirements::import_name(distribution))
+            .collect();
+        if !modules.is_empty() {
+            debug!("Preloading declared dependencies: {:?}", modules);
+        }
+        modules
+    }
+
+    /// Query the interpreter for its standard library once, so those imports aren't
+    /// preloaded. Older interpreters without `sys.stdlib_module_names` just keep them.
+    fn load_stdlib_modules(&mut self) {
+        if self.config.preload_stdlib || !self.ast_manager.stdlib_modules().is_empty() {
+            return;
+        }
+        match self.config.stdlib_modules() {
+            Ok(stdlib_modules) => self.ast_manager.set_stdlib_modules(stdlib_modules),
+            Err(e) => warn!("Preloading standard library imports: {}", e),
+        }
+    }
+
+    /// Settle which interpreter version `sys.version_info` checks are evaluated against.
+    /// If it can't be determined, imports on both sides of those checks are kept.
+    fn load_python_version(&mut self) {
+        if self.ast_manager.python_version().is_some() {
+            return;
+        }
+        let version = match self.config.python_version {
+            Some(version) => version,
+            None => match self.config.interpreter_version() {
+                Ok(version) => version,
+                Err(e) => {
+                    warn!("Collecting imports from every version check: {}", e);
+                    return;
+                }
+            },
+        };
+        self.ast_manager.set_python_version(Some(version));
+    }
+
     //
     // Main process management
     //
 
-    pub fn boot_main(&mut self) -> Result<(), String> {
+    pub fn boot_main(&mut self) -> Result<(), HotReloadError> {
         info!(
             "Processing Python files in: {}",
             self.ast_manager.get_project_path()
         );
-        let third_party_modules = self
-            .ast_manager
-            .process_all_py_files()
-            .map_err(|e| format!("Failed to process Python files: {}", e))?;
+        self.load_stdlib_modules();
+        self.load_python_version();
+        let third_party_modules = self.preload_modules()?;
+        // The scan above is the baseline for the next import delta
+        self.first_scan = true;
+
+        self.import_failures.clear();
+        self.import_timings.clear();
+        let launched = self.launch_layer(&third_party_modules)?;
+        self.import_failures = launched.import_failures;
+        self.import_timings = launched.import_timings;
+        self.booted_modules = third_party_modules;
+
+        // Store the layer in the environment
+        self.layer = Some(Arc::new(Mutex::new(launched.layer)));
+        self.failed_pings.store(0, Ordering::SeqCst);
+        self.unhealthy.store(false, Ordering::SeqCst);
+
+        if self.config.verify_fork {
+            if let Err(error) = self.verify_fork() {
+                error!("{}", error);
+                if let Some(layer) = self.layer.take() {
+                    kill_layer(&layer);
+                }
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a no-op call in isolation and wait for it to report back, to confirm the loader
+    /// can fork at all. Sandboxes that forbid `fork` or a loader stuck before answering would
+    /// otherwise only show up on the first real `exec_isolated`. Gives up after the boot
+    /// timeout.
+    pub fn verify_fork(&self) -> Result<(), HotReloadError> {
+        let timeout = self.config.boot_timeout();
+        let start = Instant::now();
+
+        let pickled_data = crate::pickle::serialized_call("builtins", "str", &[]);
+        let (process_uuid, fork_resolver) = self.send_fork_request(
+            &pickled_data,
+            "firehot-verify-fork",
+            &ExecOptions::default(),
+        )?;
+
+        match fork_resolver.wait_timeout(timeout) {
+            Ok(Some(ForkResult::Complete(_))) => {}
+            Ok(Some(ForkResult::Error(error))) => {
+                return Err(HotReloadError::ForkUnavailable(error));
+            }
+            Ok(None) => {
+                return Err(HotReloadError::ForkUnavailable(format!(
+                    "no fork response after {:?}",
+                    timeout
+                )));
+            }
+            Err(e) => return Err(HotReloadError::ForkUnavailable(e)),
+        }
+
+        let remaining = timeout.saturating_sub(start.elapsed());
+        let result = self.communicate_isolated_with_timeout(&process_uuid, Some(remaining));
+        let _ = self.stop_isolated(&process_uuid);
+        match result {
+            Ok(_) => {
+                debug!("Verified fork in {:?}", start.elapsed());
+                Ok(())
+            }
+            Err(HotReloadError::Timeout { .. }) => Err(HotReloadError::ForkUnavailable(format!(
+                "fork didn't report a result after {:?}",
+                timeout
+            ))),
+            Err(error) => Err(HotReloadError::ForkUnavailable(error.to_string())),
+        }
+    }
 
+    /// Spawn a loader that imports `third_party_modules`, wait for the imports to finish and
+    /// start monitoring it. Shared by the initial boot and automatic restarts.
+    fn launch_layer(
+        &self,
+        third_party_modules: &HashSet<String>,
+    ) -> Result<LaunchedLayer, HotReloadError> {
         let start_time = Instant::now();
 
+        // Fork output that doesn't parse would only show up as raw loader lines, and results
+        // would never arrive
+        self.config
+            .multiplex_format
+            .validate()
+            .map_err(|e| format!("Invalid multiplex format: {}", e))?;
+
         // Spawn Python subprocess to load modules
         info!(
             "Spawning Python subprocess to load {} modules",
             third_party_modules.len()
         );
-        let mut child = spawn_python_loader(&third_party_modules)
-            .map_err(|e| format!("Failed to spawn Python loader: {}", e))?;
+        // Forks write large binary results here. Only files in this directory are read back.
+        let result_dir = tempfile::Builder::new()
+            .prefix("firehot-results-")
+            .tempdir()
+            .map_err(|e| format!("Failed to create result directory: {}", e))?;
+        let mut child = spawn_python_loader(
+            &self.config,
+            third_party_modules,
+            result_dir.path(),
+            &self.spawner,
+        )?;
 
         let stdin = child
             .stdin
@@ -126,38 +663,118 @@ impl Environment {
             .take()
             .ok_or_else(|| "Failed to capture stderr for python process".to_string())?;
 
-        let reader = BufReader::new(stdout);
-        let mut lines_iter = reader.lines();
+        // Loader messages follow the configured framing. Stderr is always plain log lines.
+        // Messages can't be truncated without corrupting them, so stdout gets the message cap
+        // and the monitor trims any output lines on it down to the line cap.
+        let mut frames_iter = FrameReader::new(BufReader::new(stdout), self.config.framing)
+            .with_max_length(self.config.max_message_length());
 
         // Create a stderr reader
-        let stderr_reader = BufReader::new(stderr);
-        let stderr_lines_iter = stderr_reader.lines();
+        let stderr_lines_iter = FrameReader::new(BufReader::new(stderr), Framing::NewlineDelimited)
+            .with_max_length(self.config.max_line_length());
+
+        // A module that hangs at import time would otherwise block the read below forever.
+        // The watchdog kills the loader once the timeout passes, which closes stdout and
+        // ends the loop.
+        let boot_timeout = self.config.boot_timeout();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let (boot_done_tx, boot_done_rx) = mpsc::channel::<()>();
+        let watchdog = {
+            let timed_out = Arc::clone(&timed_out);
+            let loader_pid = child.id() as libc::pid_t;
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = boot_done_rx.recv_timeout(boot_timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    unsafe {
+                        libc::kill(loader_pid, libc::SIGKILL);
+                    }
+                }
+            })
+        };
 
         // Wait for the ImportComplete message
         info!("Waiting for import completion...");
         let mut imports_loaded = false;
-        for line in &mut lines_iter {
-            let line = line.map_err(|e| format!("Failed to read line: {}", e))?;
+        let mut last_imported: Option<String> = None;
+        let mut import_failures: Vec<ImportFailure> = Vec::new();
+        let mut import_timings: Vec<ImportTiming> = Vec::new();
+        // A failed handshake or read, either of which leaves the loader running
+        let mut boot_error: Option<HotReloadError> = None;
+        let mut handshake_done = false;
+        for line in &mut frames_iter {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    boot_error = Some(format!("Failed to read line: {}", e).into());
+                    break;
+                }
+            };
 
             // Parse the line as a message
             if let Ok(message) = serde_json::from_str::<Message>(&line) {
+                // The loader has to introduce itself before anything else, or we can't trust
+                // that we understand the rest of what it sends
+                if !handshake_done {
+                    let found = match &message {
+                        Message::Hello(hello) => Some(hello.protocol_version),
+                        _ => None,
+                    };
+                    if !found.is_some_and(|version| SUPPORTED_PROTOCOL_VERSIONS.contains(&version))
+                    {
+                        boot_error = Some(HotReloadError::ProtocolMismatch {
+                            found,
+                            supported: SUPPORTED_PROTOCOL_VERSIONS,
+                        });
+                        break;
+                    }
+                    handshake_done = true;
+                }
+
                 match message {
                     Message::ImportComplete(_) => {
                         info!("Imports loaded successfully");
                         imports_loaded = true;
                         break;
                     }
+                    Message::Hello(hello) => {
+                        debug!("Loader speaks protocol version {}", hello.protocol_version);
+                    }
+                    Message::ImportProgress(progress) => {
+                        trace!(
+                            "Importing module {} ({}/{})",
+                            progress.module,
+                            progress.index + 1,
+                            progress.total
+                        );
+                        if let Some(callback) = &self.on_import_progress {
+                            callback(&progress);
+                        }
+                    }
+                    Message::ModuleImported(imported) => {
+                        debug!(
+                            "Imported module {} in {:.1}ms",
+                            imported.module, imported.duration_ms
+                        );
+                        import_timings.push(ImportTiming {
+                            module: imported.module.clone(),
+                            duration: Duration::from_secs_f64(
+                                imported.duration_ms.max(0.0) / 1000.0,
+                            ),
+                        });
+                        last_imported = Some(imported.module);
+                    }
                     Message::ImportError(error) => {
+                        let failure = ImportFailure {
+                            module: error.module,
+                            error: error.error,
+                            traceback: error.traceback,
+                        };
                         error!(
-                            "Import error: {}: {}",
-                            error.error,
-                            error.traceback.clone().unwrap_or_default()
+                            "Import error: {}\n{}",
+                            failure,
+                            failure.traceback.as_deref().unwrap_or_default()
                         );
-                        return Err(format!(
-                            "Import error: {}: {}",
-                            error.error,
-                            error.traceback.unwrap_or_default()
-                        ));
+                        import_failures.push(failure);
                     }
                     _ => {
                         // Log other message types for debugging
@@ -170,13 +787,66 @@ impl Environment {
             }
         }
 
+        let _ = boot_done_tx.send(());
+        let _ = watchdog.join();
+
+        if timed_out.load(Ordering::SeqCst) {
+            log_boot_stderr(drain_boot_stderr(stderr_lines_iter));
+            let _ = child.wait();
+            let error = HotReloadError::Timeout {
+                operation: format!(
+                    "imports to load ({})",
+                    match &last_imported {
+                        Some(module) => format!("last imported module: {}", module),
+                        None => "no modules finished importing".to_string(),
+                    }
+                ),
+                after: boot_timeout,
+            };
+            error!("{}", error);
+            return Err(error);
+        }
+
+        if let Some(error) = boot_error {
+            error!("{}", error);
+            let _ = child.kill();
+            log_boot_stderr(drain_boot_stderr(stderr_lines_iter));
+            let _ = child.wait();
+            return Err(error);
+        }
+
+        if !imports_loaded && !import_failures.is_empty() {
+            // The loader exits on its own after reporting every failed module, but one that
+            // lingers mustn't hold up the error
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(HotReloadError::ImportFailed(import_failures));
+        }
+
         if !imports_loaded {
-            error!("Python loader did not report successful imports");
-            return Err("Python loader did not report successful imports".to_string());
+            // Stdout closed early, so the loader is on its way out. Whatever it printed last
+            // explains why. It could still be stuck with stdout closed, so don't wait on it
+            // to exit by itself.
+            let stderr = drain_boot_stderr(stderr_lines_iter);
+            let _ = child.kill();
+            let _ = child.wait();
+            let error = HotReloadError::LoaderExited { stderr };
+            error!("{}", error);
+            return Err(error);
+        }
+
+        // Under the warn policy the loader keeps going without the modules that failed
+        for failure in &import_failures {
+            warn!("Booted without module that failed to import: {}", failure);
         }
 
+        log_slowest_imports(&import_timings);
+
         // Calculate total setup time and log completion
         let elapsed = start_time.elapsed();
+        if let Ok(mut metrics) = self.metrics.lock() {
+            metrics.record_boot(elapsed);
+        }
         let elapsed_ms = elapsed.as_millis();
 
         eprintln!(
@@ -200,96 +870,379 @@ impl Environment {
 
         let mut layer = if self.test_mode {
             // Use the test mode constructor
-            Layer::new_for_test(child, stdin, lines_iter, stderr_lines_iter)
+            Layer::new_for_test(child, stdin, frames_iter, stderr_lines_iter)
         } else {
             // Use the standard constructor
-            Layer::new(child, stdin, lines_iter, stderr_lines_iter)
+            Layer::new(child, stdin, frames_iter, stderr_lines_iter)
         };
 
+        // Share our running totals so usage is tracked across layer rebuilds
+        layer.resource_totals = Arc::clone(&self.resource_totals);
+        layer.metrics = Arc::clone(&self.metrics);
+        layer.log_format = self.config.log_format;
+        layer.multiplex_format = self.config.multiplex_format.clone();
+        layer.max_line_length = self.config.max_line_length();
+        layer.result_dir = Some(result_dir);
+        // The loader only reports on background imports when it deferred some
+        let deferred = deferred_modules(third_party_modules, &self.config.critical_modules);
+        if deferred.is_empty() {
+            layer.background_imports.lock().unwrap().complete = true;
+        } else {
+            info!("Importing {} modules in the background", deferred.len());
+        }
+
         // Start the monitor thread
         layer.start_monitor_thread();
 
-        // Store the layer in the environment
-        self.layer = Some(Arc::new(Mutex::new(layer)));
+        Ok(LaunchedLayer {
+            layer,
+            import_failures,
+            import_timings,
+        })
+    }
 
-        Ok(())
+    /// Replace how the loader process is started, so tests can make spawning fail
+    #[cfg(test)]
+    fn set_spawner<F>(&mut self, spawner: F)
+    where
+        F: Fn(&mut Command) -> io::Result<Child> + Send + Sync + 'static,
+    {
+        self.spawner = Box::new(spawner);
     }
 
-    pub fn stop_main(&self) -> Result<bool, String> {
-        // Check if environment is initialized
-        let layer = match self.layer.as_ref() {
-            Some(env) => env,
-            None => {
-                info!("No environment to stop.");
+    /// Register a callback that runs after the loader was restarted automatically, so callers
+    /// can re-establish any state that lived in the old process
+    pub fn on_restart<F>(&mut self, callback: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_restart = Some(Box::new(callback));
+    }
+
+    /// Register a callback that runs after `update_environment` rebuilt the loader, with the
+    /// modules that were added and removed. Rebuilds of an unhealthy loader report an empty
+    /// delta. The callback runs once the new loader is up and no layer lock is held, so it's
+    /// free to call back into the environment.
+    pub fn on_reload<F>(&mut self, callback: F)
+    where
+        F: Fn(&ImportDelta) + Send + Sync + 'static,
+    {
+        self.on_reload = Some(Box::new(callback));
+    }
+
+    /// Register a callback that runs as each preloaded module starts importing, for boots,
+    /// rebuilds and automatic restarts alike. Enough to drive a progress bar, since every
+    /// call carries the module's index and the total. Runs on the thread that is booting.
+    pub fn on_import_progress<F>(&mut self, callback: F)
+    where
+        F: Fn(&ImportProgress) + Send + Sync + 'static,
+    {
+        self.on_import_progress = Some(Box::new(callback));
+    }
+
+    /// Print a one line explanation of why the loader was rebuilt, like
+    /// `Reloaded: +pandas, -lxml`, alongside the fork output. Suppressed when logging is off or
+    /// configured to show errors only.
+    fn print_reload_summary(&self, added: &HashSet<String>, removed: &HashSet<String>) {
+        if !log::log_enabled!(log::Level::Warn) {
+            return;
+        }
+
+        let summary = format!("{} {}", "↻".cyan().bold(), reload_summary(added, removed));
+        if let Some(Ok(layer)) = self.layer.as_ref().map(|layer| layer.lock()) {
+            layer.write_output_line(summary);
+        }
+    }
+
+    /// Tell the reload callback about a finished rebuild, returning the delta it was given
+    fn notify_reload(&self, added: HashSet<String>, removed: HashSet<String>) -> ImportDelta {
+        let delta = ImportDelta {
+            environment_id: self.id.clone(),
+            added,
+            removed,
+        };
+        if let Some(callback) = &self.on_reload {
+            callback(&delta);
+        }
+        delta
+    }
+
+    /// With `auto_restart` enabled, replace a loader that has died with a fresh one importing
+    /// the same modules. Returns whether a restart happened.
+    fn restart_if_exited(&self, layer: &Arc<Mutex<Layer>>) -> Result<bool, HotReloadError> {
+        if !self.config.auto_restart {
+            return Ok(false);
+        }
+
+        // Claim the restart, so concurrent callers don't race to replace the same loader. The
+        // lock isn't held while the new one boots, since that can take up to the boot timeout.
+        {
+            let mut layer_guard = layer
+                .lock()
+                .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+            let exited = !layer_guard.stopping
+                && !layer_guard.restarting
+                && (layer_guard.loader_exited.load(Ordering::SeqCst)
+                    || matches!(layer_guard.child.try_wait(), Ok(Some(_))));
+            if !exited {
                 return Ok(false);
             }
+            layer_guard.restarting = true;
+        }
+
+        warn!("Python loader exited unexpectedly, restarting it");
+        let launched = match self.launch_layer(&self.booted_modules) {
+            Ok(launched) => launched,
+            Err(e) => {
+                // Let the next call try again
+                if let Ok(mut layer_guard) = layer.lock() {
+                    layer_guard.restarting = false;
+                }
+                return Err(e);
+            }
         };
+        for failure in &launched.import_failures {
+            warn!(
+                "Restarted without module that failed to import: {}",
+                failure
+            );
+        }
 
-        info!("Stopping main runner process");
+        let mut layer_guard = layer
+            .lock()
+            .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+        let mut new_layer = launched.layer;
+        if layer_guard.stopping {
+            // The environment was stopped while the replacement booted
+            kill_layer_processes(&mut new_layer);
+            new_layer.stop_monitor_thread();
+            return Ok(false);
+        }
 
-        let env_guard = layer
+        // Forks of the dead loader will never report back, so stop any still running rather
+        // than leave them and the monitor threads behind
+        kill_layer_processes(&mut layer_guard);
+        for (_, resolver) in layer_guard
+            .completion_resolvers
             .lock()
-            .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+            .map_err(|e| format!("Failed to lock completion resolvers: {}", e))?
+            .drain()
+        {
+            if !resolver.is_resolved() {
+                resolver.resolve(ProcessResult::Error(
+                    "Python loader exited before the process completed".to_string(),
+                ));
+            }
+        }
+        layer_guard.stop_monitor_thread();
+        *layer_guard = new_layer;
+        drop(layer_guard);
+
+        self.failed_pings.store(0, Ordering::SeqCst);
+        self.unhealthy.store(false, Ordering::SeqCst);
+        if let Some(callback) = &self.on_restart {
+            callback();
+        }
+        Ok(true)
+    }
 
-        // First, stop all child processes
-        info!("Stopping all child processes before terminating main process");
-        let child_uuids = {
-            let forked_processes = env_guard
-                .forked_processes
-                .lock()
-                .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+    /// Ping the loader and wait up to `DEFAULT_PING_TIMEOUT` for it to answer
+    pub fn is_alive(&self) -> bool {
+        self.is_alive_with_timeout(DEFAULT_PING_TIMEOUT)
+    }
 
-            // Create a clone of all keys to avoid borrowing issues
-            forked_processes.keys().cloned().collect::<Vec<String>>()
+    /// Same as `is_alive`, with an explicit timeout. A loader that has exited, or that misses
+    /// several pings in a row, marks the environment unhealthy.
+    pub fn is_alive_with_timeout(&self, timeout: Duration) -> bool {
+        let layer = match self.layer.as_ref() {
+            Some(layer) => layer,
+            None => return false,
         };
 
-        // Drop the env_guard temporarily so we can call stop_isolated
-        drop(env_guard);
+        let ping_id = Uuid::new_v4().to_string();
+        let resolver = AsyncResolve::new();
+        let sent = {
+            let mut layer_guard = match layer.lock() {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("Failed to lock layer mutex: {}", e);
+                    return false;
+                }
+            };
 
-        // Stop each child process
-        for uuid in child_uuids {
-            info!("Stopping child process with UUID: {}", uuid);
-            if let Err(e) = self.stop_isolated(&uuid) {
-                warn!("Failed to stop child process {}: {}", uuid, e);
+            // No need to wait on a pong from a process we already know is gone
+            if let Ok(Some(status)) = layer_guard.child.try_wait() {
+                warn!("Python loader exited unexpectedly with {}", status);
+                self.unhealthy.store(true, Ordering::SeqCst);
+                return false;
             }
-        }
 
-        // Re-acquire the env_guard
-        let mut env_guard = layer
-            .lock()
-            .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+            match layer_guard.pong_resolvers.lock() {
+                Ok(mut pong_resolvers) => {
+                    pong_resolvers.insert(ping_id.clone(), resolver.clone());
+                }
+                Err(e) => {
+                    error!("Failed to lock pong resolvers: {}", e);
+                    return false;
+                }
+            }
+            let ping = Message::Ping(Ping::new(ping_id.clone()));
+            write_message(&mut layer_guard.stdin, &ping, self.config.framing)
+                .map_err(|e| warn!("Failed to send ping to the loader: {}", e))
+                .is_ok()
+        };
 
-        // Now send ExitRequest to the parent process to allow it to clean up gracefully
-        info!("Sending ExitRequest to parent process");
-        let exit_request = ExitRequest::new();
-        let exit_json = serde_json::to_string(&Message::ExitRequest(exit_request))
-            .map_err(|e| format!("Failed to serialize exit request: {}", e))?;
-
-        // Send the message to the parent process
-        if let Err(e) = writeln!(env_guard.stdin, "{}", exit_json) {
-            warn!("Failed to write exit request to parent stdin: {}", e);
-        } else if let Err(e) = env_guard.stdin.flush() {
-            warn!("Failed to flush parent stdin: {}", e);
-        } else {
-            // Give it a moment to process the exit request
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
+        let answered = sent && matches!(resolver.wait_timeout(timeout), Ok(Some(())));
+        let exited = match layer.lock() {
+            Ok(mut layer_guard) => {
+                if let Ok(mut pong_resolvers) = layer_guard.pong_resolvers.lock() {
+                    pong_resolvers.remove(&ping_id);
+                }
+                !answered && matches!(layer_guard.child.try_wait(), Ok(Some(_)))
+            }
+            Err(_) => false,
+        };
 
-        // Now kill the main child process if it hasn't already exited
-        info!("Killing child process to unblock monitor thread");
-        if let Err(e) = env_guard.child.kill() {
-            warn!("Failed to kill child process: {}", e);
+        if answered {
+            self.failed_pings.store(0, Ordering::SeqCst);
+            return true;
+        }
+        if exited {
+            warn!("Python loader exited unexpectedly");
+            self.unhealthy.store(true, Ordering::SeqCst);
+            return false;
         }
 
-        // Wait for the process to exit
-        info!("Waiting for child process to exit");
-        if let Err(e) = env_guard.child.wait() {
-            warn!("Failed to wait for child process: {}", e);
-        } else {
-            info!("Child process exited successfully");
+        let failed_pings = self.failed_pings.fetch_add(1, Ordering::SeqCst) + 1;
+        warn!(
+            "Python loader did not answer ping within {:?} ({} in a row)",
+            timeout, failed_pings
+        );
+        if failed_pings >= MAX_FAILED_PINGS {
+            self.unhealthy.store(true, Ordering::SeqCst);
         }
+        false
+    }
 
-        // Now it's safe to stop the monitor thread, since the child process stdout
+    /// Whether the loader is believed to be working. Turns false once `is_alive` finds it dead
+    /// or unresponsive, and true again after the next successful boot.
+    pub fn is_healthy(&self) -> bool {
+        self.layer.is_some() && !self.unhealthy.load(Ordering::SeqCst)
+    }
+
+    pub fn stop_main(&self) -> Result<bool, HotReloadError> {
+        self.stop_main_with_grace(DEFAULT_SHUTDOWN_GRACE)
+    }
+
+    /// Same as `stop_main`, with an explicit grace period. Forks get SIGTERM and the loader an
+    /// `ExitRequest`, and whichever of them is still running once `grace` passes is killed.
+    pub fn stop_main_with_grace(&self, grace: Duration) -> Result<bool, HotReloadError> {
+        // Loaders still draining after a reload go down along with the current one
+        let draining: Vec<Arc<Mutex<Layer>>> = self
+            .draining_layers
+            .lock()
+            .map_err(|e| format!("Failed to lock draining layers: {}", e))?
+            .drain(..)
+            .collect();
+        for layer in &draining {
+            Self::stop_layer(layer, grace, self.config.framing)?;
+        }
+        self.drained_results
+            .lock()
+            .map_err(|e| format!("Failed to lock drained results: {}", e))?
+            .clear();
+
+        // Check if environment is initialized
+        let layer = match self.layer.as_ref() {
+            Some(env) => env,
+            None => {
+                info!("No environment to stop.");
+                return Ok(false);
+            }
+        };
+
+        Self::stop_layer(layer, grace, self.config.framing)?;
+        Ok(true)
+    }
+
+    /// Stop the forks of a single loader and then the loader itself, see `stop_main_with_grace`
+    fn stop_layer(
+        layer: &Arc<Mutex<Layer>>,
+        grace: Duration,
+        framing: Framing,
+    ) -> Result<(), HotReloadError> {
+        info!("Stopping main runner process");
+
+        let mut env_guard = layer
+            .lock()
+            .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+        env_guard.stopping = true;
+
+        // First, stop all child processes
+        info!("Stopping all child processes before terminating main process");
+        let child_uuids = {
+            let forked_processes = env_guard
+                .forked_processes
+                .lock()
+                .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+
+            // Create a clone of all entries to avoid borrowing issues
+            forked_processes
+                .iter()
+                .map(|(uuid, pid)| (uuid.clone(), *pid))
+                .collect::<Vec<(String, i32)>>()
+        };
+        let running_children: Vec<(String, i32)> = child_uuids
+            .iter()
+            .filter(|(_, pid)| !env_guard.has_exited(*pid))
+            .cloned()
+            .collect();
+
+        // Stop each child process
+        for (uuid, _) in &child_uuids {
+            info!("Stopping child process with UUID: {}", uuid);
+            if let Err(e) = Self::stop_fork(&env_guard, uuid) {
+                warn!("Failed to stop child process {}: {}", uuid, e);
+            }
+        }
+        drop(env_guard);
+
+        // Forks that ignore SIGTERM would outlive the loader as orphans
+        wait_or_kill_forks(running_children, Instant::now() + grace);
+
+        // Re-acquire the env_guard
+        let mut env_guard = layer
+            .lock()
+            .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+
+        // Now send ExitRequest to the parent process to allow it to clean up gracefully
+        info!("Sending ExitRequest to parent process");
+        let exit_request = Message::ExitRequest(ExitRequest::new());
+
+        // Send the message to the parent process, falling back to SIGTERM if it can't be read
+        if let Err(e) = write_message(&mut env_guard.stdin, &exit_request, framing) {
+            warn!(
+                "Failed to write exit request to parent stdin, sending SIGTERM: {}",
+                e
+            );
+            unsafe {
+                libc::kill(env_guard.child.id() as libc::pid_t, libc::SIGTERM);
+            }
+        }
+
+        // Give the loader the rest of the grace period before killing it, which also
+        // unblocks the monitor thread
+        info!("Waiting for child process to exit");
+        match wait_or_kill(&mut env_guard.child, Instant::now() + grace) {
+            Ok(false) => info!("Child process exited successfully"),
+            Ok(true) => warn!(
+                "Loader did not exit within {:?} of the exit request and was killed",
+                grace
+            ),
+            Err(e) => warn!("Failed to wait for child process: {}", e),
+        }
+
+        // Now it's safe to stop the monitor thread, since the child process stdout
         // has been closed and the reader.next() in the monitor thread should return None
         info!("Now stopping monitor thread");
         env_guard.stop_monitor_thread();
@@ -316,16 +1269,171 @@ impl Environment {
         completion_resolvers.clear();
         drop(completion_resolvers);
 
+        env_guard
+            .pending_lines
+            .lock()
+            .map_err(|e| format!("Failed to lock pending lines: {}", e))?
+            .clear();
+
+        env_guard
+            .process_output
+            .lock()
+            .map_err(|e| format!("Failed to lock process output: {}", e))?
+            .clear();
+
         info!("Main runner process stopped");
-        Ok(true)
+        Ok(())
+    }
+
+    /// Orderly teardown of everything owned by this environment: forks are asked to exit with
+    /// SIGTERM and given `DEFAULT_SHUTDOWN_GRACE` before being killed, and then the loader and
+    /// its monitor threads are stopped. This is the one call an embedding app should make on exit.
+    pub fn shutdown(&mut self) -> Result<ShutdownReport, HotReloadError> {
+        self.shutdown_with_grace(DEFAULT_SHUTDOWN_GRACE)
+    }
+
+    /// Same as `shutdown`, with an explicit grace period for forks to exit after SIGTERM
+    pub fn shutdown_with_grace(
+        &mut self,
+        grace: Duration,
+    ) -> Result<ShutdownReport, HotReloadError> {
+        let mut report = ShutdownReport::default();
+
+        let layer = match self.layer.as_ref() {
+            Some(layer) => Arc::clone(layer),
+            None => {
+                info!("No environment to shut down.");
+                return Ok(report);
+            }
+        };
+
+        let pending = {
+            let layer_guard = layer
+                .lock()
+                .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+            let forked_processes = layer_guard
+                .forked_processes
+                .lock()
+                .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+
+            forked_processes
+                .iter()
+                .map(|(uuid, pid)| (uuid.clone(), *pid))
+                .filter(|(uuid, pid)| {
+                    // Already reaped by the loader, so there's nothing left to signal
+                    if layer_guard.has_exited(*pid) {
+                        report.terminated.push(uuid.clone());
+                        return false;
+                    }
+                    true
+                })
+                .collect::<Vec<(String, i32)>>()
+        };
+
+        // Ask every fork to exit and give them the grace period to comply
+        info!("Sending SIGTERM to {} forked processes", pending.len());
+        for (uuid, pid) in &pending {
+            if unsafe { libc::kill(*pid, libc::SIGTERM) } != 0 {
+                let err = std::io::Error::last_os_error();
+                debug!("Failed to send SIGTERM to {} (PID {}): {}", uuid, pid, err);
+            }
+        }
+
+        let (terminated, killed) = wait_or_kill_forks(pending, Instant::now() + grace);
+        report.terminated.extend(terminated);
+        report.killed = killed;
+
+        // Anyone still blocked in communicate_isolated should hear that the fork is gone
+        {
+            let layer_guard = layer
+                .lock()
+                .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+            let mut completion_resolvers = layer_guard
+                .completion_resolvers
+                .lock()
+                .map_err(|e| format!("Failed to lock completion resolvers: {}", e))?;
+            for (_, resolver) in completion_resolvers.drain() {
+                if !resolver.is_resolved() {
+                    resolver.resolve(ProcessResult::Error(
+                        "Process was terminated during shutdown".to_string(),
+                    ));
+                }
+            }
+            drop(completion_resolvers);
+
+            layer_guard
+                .forked_processes
+                .lock()
+                .map_err(|e| format!("Failed to lock forked processes: {}", e))?
+                .clear();
+            layer_guard
+                .forked_names
+                .lock()
+                .map_err(|e| format!("Failed to lock forked names: {}", e))?
+                .clear();
+        }
+
+        // With no forks left, this only has the loader and monitor threads to stop
+        report.loader_stopped = self.stop_main_with_grace(grace)?;
+        self.layer = None;
+
+        info!(
+            "Shutdown complete: {} terminated, {} killed",
+            report.terminated.len(),
+            report.killed.len()
+        );
+        Ok(report)
+    }
+
+    /// The modules `update_environment` would add and remove if it ran now, without
+    /// restarting anything or changing the baseline it compares against. Useful for showing
+    /// why a reload is about to happen.
+    pub fn pending_import_delta(
+        &self,
+    ) -> Result<(HashSet<String>, HashSet<String>), HotReloadError> {
+        let (added, removed) = self
+            .ast_manager
+            .peek_import_delta()
+            .map_err(|e| format!("Failed to compute import delta: {}", e))?;
+        Ok(self.without_pinned_modules(added, removed))
+    }
+
+    /// Modules pinned in or out by the preload overrides don't change what the loader imports
+    fn without_pinned_modules(
+        &self,
+        mut added: HashSet<String>,
+        mut removed: HashSet<String>,
+    ) -> (HashSet<String>, HashSet<String>) {
+        let is_pinned = |module: &String| {
+            self.config.extra_preload.contains(module)
+                || self.config.excluded_preload.contains(module)
+        };
+        added.retain(|module| !is_pinned(module));
+        removed.retain(|module| !is_pinned(module));
+        (added, removed)
+    }
+
+    pub fn update_environment(&mut self) -> Result<bool, HotReloadError> {
+        Ok(self.update_environment_with_delta()?.is_some())
     }
 
-    pub fn update_environment(&mut self) -> Result<bool, String> {
+    /// Same as `update_environment`, but returns what the rebuild changed, or `None` when
+    /// the loader was left alone. However many files changed since the last check, they're
+    /// covered by this one delta.
+    pub fn update_environment_with_delta(&mut self) -> Result<Option<ImportDelta>, HotReloadError> {
         info!("Checking for environment updates...");
 
+        // A dead loader has to be replaced whether or not the imports changed
+        if self.layer.is_some() && self.unhealthy.load(Ordering::SeqCst) {
+            warn!("Python loader is unhealthy, rebuilding the environment");
+            self.stop_main()?;
+            self.boot_main()?;
+            return Ok(Some(self.notify_reload(HashSet::new(), HashSet::new())));
+        }
+
         // Check for any changes to the imports
         if !self.first_scan {
-            return Ok(false); // Nothing to update if we haven't even scanned yet
+            return Ok(None); // Nothing to update if we haven't even scanned yet
         }
 
         // Get the delta
@@ -333,11 +1441,20 @@ impl Environment {
             .ast_manager
             .compute_import_delta()
             .map_err(|e| format!("Failed to compute import delta: {}", e))?;
+        let (added, removed) = self.without_pinned_modules(added, removed);
 
         // Check if imports have changed
         if added.is_empty() && removed.is_empty() {
             info!("No changes to imports detected");
-            return Ok(false);
+            return Ok(None);
+        }
+
+        // The delta is against the previous scan, which can drift from what the loader was
+        // booted with. If the loader already has exactly these imports there's nothing to do.
+        let current = self.preload_set(self.ast_manager.third_party_imports());
+        if self.import_hash().as_deref() == Some(import_hash(&current).as_str()) {
+            info!("Imports match the running loader, skipping the rebuild");
+            return Ok(None);
         }
 
         info!(
@@ -345,6 +1462,20 @@ impl Environment {
             added, removed
         );
 
+        self.rebuild_layer()?;
+
+        info!("Environment updated successfully");
+        self.print_reload_summary(&added, &removed);
+        Ok(Some(self.notify_reload(added, removed)))
+    }
+
+    /// Replace the loader with a freshly booted one, handling running forks according to
+    /// the reload mode
+    fn rebuild_layer(&mut self) -> Result<(), HotReloadError> {
+        if self.config.reload_mode == ReloadMode::Drain {
+            return self.drain_reload();
+        }
+
         // Stop any existing processes
         if let Some(env) = self.layer.as_ref() {
             let forked_processes = {
@@ -377,10 +1508,178 @@ impl Environment {
         }
 
         // Boot a new layer
-        self.boot_main()?;
+        self.boot_main()
+    }
 
-        info!("Environment updated successfully");
-        Ok(true)
+    /// Bring the loader up to date with edits to these source files. Only modules the loader
+    /// has already imported are affected, since forks import everything else fresh. With
+    /// `partial_reload` enabled they're reloaded in place with `importlib.reload`, which keeps
+    /// the loader and everything else it imported. Otherwise, or when a module can't be
+    /// reloaded in place (like a C extension), the loader is rebooted.
+    pub fn reload_files(&mut self, paths: &[PathBuf]) -> Result<ModuleReload, HotReloadError> {
+        if self.layer.is_none() {
+            return Ok(ModuleReload::Unchanged);
+        }
+
+        let in_place = self.config.partial_reload;
+        let Some(response) = self.request_reload(paths, in_place)? else {
+            warn!("The loader did not answer the reload request, rebooting it");
+            self.rebuild_layer()?;
+            self.notify_reload(HashSet::new(), HashSet::new());
+            return Ok(ModuleReload::Full);
+        };
+        if response.modules.is_empty() {
+            debug!("No loaded modules come from {:?}", paths);
+            return Ok(ModuleReload::Unchanged);
+        }
+
+        if response.reloaded {
+            info!("Reloaded {:?} in place", response.modules);
+            self.notify_reload(HashSet::new(), HashSet::new());
+            return Ok(ModuleReload::Partial(response.modules));
+        }
+
+        match &response.error {
+            Some(error) => warn!("Falling back to a full reload: {}", error),
+            None => info!("Rebooting the loader to reload {:?}", response.modules),
+        }
+        self.rebuild_layer()?;
+        self.notify_reload(HashSet::new(), HashSet::new());
+        Ok(ModuleReload::Full)
+    }
+
+    /// Ask the loader which of its modules were loaded from `paths`, reloading them if
+    /// `in_place` is set. Returns None if the loader doesn't answer in time.
+    fn request_reload(
+        &self,
+        paths: &[PathBuf],
+        in_place: bool,
+    ) -> Result<Option<ReloadResponse>, HotReloadError> {
+        let layer = self.layer.as_ref().ok_or(HotReloadError::NotBooted)?;
+
+        let request_id = Uuid::new_v4().to_string();
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        let resolver = AsyncResolve::new();
+        {
+            let mut layer_guard = layer
+                .lock()
+                .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+            layer_guard
+                .reload_resolvers
+                .lock()
+                .unwrap()
+                .insert(request_id.clone(), resolver.clone());
+            let request =
+                Message::ReloadRequest(ReloadRequest::new(request_id.clone(), paths, in_place));
+            write_message(&mut layer_guard.stdin, &request, self.config.framing)
+                .map_err(|e| format!("Failed to send reload request: {}", e))?;
+        }
+
+        let response = resolver.wait_timeout(self.config.boot_timeout());
+        if let Ok(layer_guard) = layer.lock() {
+            layer_guard
+                .reload_resolvers
+                .lock()
+                .unwrap()
+                .remove(&request_id);
+        }
+
+        Ok(response.ok().flatten())
+    }
+
+    /// Boot a new loader and move the current one aside, leaving its forks running. It's
+    /// retired in the background once they finish, see `retire_when_drained`.
+    fn drain_reload(&mut self) -> Result<(), HotReloadError> {
+        let previous = self.layer.take();
+        if let Err(e) = self.boot_main() {
+            // Keep serving from the old loader rather than ending up with none
+            self.layer = previous;
+            return Err(e);
+        }
+
+        if let Some(previous) = previous {
+            self.draining_layers
+                .lock()
+                .map_err(|e| format!("Failed to lock draining layers: {}", e))?
+                .push(Arc::clone(&previous));
+            self.retire_when_drained(previous);
+        }
+        Ok(())
+    }
+
+    /// Stop a drained loader from a background thread once its forks have all finished, so
+    /// it doesn't linger while the environment is idle and no caller waits on the stop. The
+    /// results are kept, so callers can still collect them with `communicate_isolated`.
+    fn retire_when_drained(&self, layer: Arc<Mutex<Layer>>) {
+        let draining_layers = Arc::clone(&self.draining_layers);
+        let drained_results = Arc::clone(&self.drained_results);
+        let framing = self.config.framing;
+        thread::spawn(move || {
+            let still_draining = || {
+                draining_layers
+                    .lock()
+                    .is_ok_and(|draining| draining.iter().any(|other| Arc::ptr_eq(other, &layer)))
+            };
+            while has_running_forks(&layer) {
+                // Stopped along with the environment in the meantime
+                if !still_draining() {
+                    return;
+                }
+                thread::sleep(DRAIN_POLL_INTERVAL);
+            }
+
+            let retired = {
+                let Ok(mut draining) = draining_layers.lock() else {
+                    return;
+                };
+                // Already stopped along with the environment
+                let Some(index) = draining.iter().position(|other| Arc::ptr_eq(other, &layer))
+                else {
+                    return;
+                };
+
+                // Results are recorded before the loader leaves the draining list, so lookups
+                // always find them in one place or the other
+                if let Err(e) = record_drained_results(&layer, &drained_results) {
+                    warn!("Failed to record results of a drained loader: {}", e);
+                }
+                draining.remove(index)
+            };
+
+            info!("Retiring drained loader");
+            if let Err(e) = Self::stop_layer(&retired, DEFAULT_SHUTDOWN_GRACE, framing) {
+                warn!("Failed to retire drained loader: {}", e);
+            }
+        });
+    }
+
+    /// The loader that owns a fork: the current one, or a loader that is still draining.
+    /// Unknown forks map to the current loader so lookups report them as missing there.
+    fn layer_for_process(&self, process_uuid: &str) -> Result<Arc<Mutex<Layer>>, HotReloadError> {
+        let current = self.layer.as_ref().ok_or(HotReloadError::NotBooted)?;
+
+        let draining = self
+            .draining_layers
+            .lock()
+            .map_err(|e| format!("Failed to lock draining layers: {}", e))?;
+        for layer in draining.iter() {
+            let layer_guard = layer
+                .lock()
+                .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+            let owns_process = layer_guard
+                .forked_processes
+                .lock()
+                .map_err(|e| format!("Failed to lock forked processes: {}", e))?
+                .contains_key(process_uuid);
+            if owns_process {
+                return Ok(Arc::clone(layer));
+            }
+        }
+
+        Ok(Arc::clone(current))
     }
 
     //
@@ -389,15 +1688,86 @@ impl Environment {
 
     /// This function executes code in a forked process (not in the main process
     /// that spawned our hotreloader) so we can get the local function and closure variables.
-    pub fn exec_isolated(&self, pickled_data: &str, name: &str) -> Result<String, String> {
+    /// `name` labels the fork's output. An empty name is replaced with a generated one.
+    pub fn exec_isolated(&self, pickled_data: &str, name: &str) -> Result<String, HotReloadError> {
+        self.exec_isolated_with_options(pickled_data, name, &ExecOptions::default())
+    }
+
+    /// Same as `exec_isolated`, with per-call environment variables and timeout
+    pub fn exec_isolated_with_options(
+        &self,
+        pickled_data: &str,
+        name: &str,
+        options: &ExecOptions,
+    ) -> Result<String, HotReloadError> {
+        let (process_uuid, fork_resolver) = self.send_fork_request(pickled_data, name, options)?;
+
+        // Wait for the fork to complete
+        debug!("Waiting for fork status for process {}...", process_uuid);
+        Self::fork_outcome(process_uuid, fork_resolver.wait())
+    }
+
+    /// Run `func_name` from a module the loader can already import, like one in the project
+    /// package, with `args` passed positionally. The call is serialized directly instead of
+    /// pickling a function object, so there's no helper interpreter or temporary module
+    /// involved. Returns the process UUID, as with `exec_isolated`.
+    pub fn exec_module_function(
+        &self,
+        module_path: &str,
+        func_name: &str,
+        args: &[serde_json::Value],
+    ) -> Result<String, HotReloadError> {
+        let pickled_data = crate::pickle::serialized_call(module_path, func_name, args);
+        self.exec_isolated(&pickled_data, &format!("{}.{}", module_path, func_name))
+    }
+
+    /// Async version of `exec_isolated`. Resolves with the process UUID once the loader has
+    /// forked, without blocking a runtime thread while waiting for the fork. Sending the
+    /// request is still synchronous, and with `auto_restart` it can boot a replacement for a
+    /// dead loader first, which blocks for as long as the boot takes.
+    ///
+    /// Dropping the future before the fork starts unregisters it, so nothing waits on the
+    /// fork or its result.
+    pub async fn exec_isolated_async(
+        &self,
+        pickled_data: &str,
+        name: &str,
+    ) -> Result<String, HotReloadError> {
+        let (process_uuid, fork_resolver) =
+            self.send_fork_request(pickled_data, name, &ExecOptions::default())?;
+        let layer = self.layer.as_ref().ok_or(HotReloadError::NotBooted)?;
+        let mut pending = PendingFork {
+            layer,
+            process_uuid: &process_uuid,
+            abandoned: true,
+        };
+
+        debug!("Awaiting fork status for process {}...", process_uuid);
+        let fork_result = fork_resolver.future().await;
+        pending.abandoned = false;
+        drop(pending);
+        Self::fork_outcome(process_uuid, fork_result)
+    }
+
+    /// Register resolvers for a new fork and ask the loader to create it. Returns the
+    /// process UUID and the resolver that fires once the fork is running.
+    fn send_fork_request(
+        &self,
+        pickled_data: &str,
+        name: &str,
+        options: &ExecOptions,
+    ) -> Result<(String, AsyncResolve<ForkResult>), HotReloadError> {
         // Check if environment is initialized
-        let environment = self
-            .layer
-            .as_ref()
-            .ok_or_else(|| "Environment not initialized. Call boot_main first.".to_string())?;
+        let environment = self.layer.as_ref().ok_or(HotReloadError::NotBooted)?;
+        self.restart_if_exited(environment)?;
 
         // Generate a process UUID
         let process_uuid = Uuid::new_v4().to_string();
+        let name = if name.trim().is_empty() {
+            default_fork_name(&process_uuid)
+        } else {
+            name.to_string()
+        };
 
         // Send the code to the forked process
         let mut env_guard = environment
@@ -421,108 +1791,292 @@ impl Environment {
         completion_resolvers.insert(process_uuid.clone(), completion_resolver.clone());
         drop(completion_resolvers);
 
-        let exec_code = format!(
-            r#"
-pickled_str = "{}"
-{}
-            "#,
-            pickled_data, PYTHON_CHILD_SCRIPT,
-        );
-
-        // Create a ForkRequest message
+        // Create a ForkRequest message. The loader exposes the payload to the child
+        // script as `pickled_str`.
         let fork_request = ForkRequest {
             request_id: process_uuid.clone(),
-            request_name: name.to_string(),
-            code: exec_code,
+            request_name: name,
+            code: PYTHON_CHILD_SCRIPT.to_string(),
+            pickled_data: Some(pickled_data.to_string()),
+            env: options.env.clone(),
+            timeout: options.timeout.map(|timeout| timeout.as_secs_f64()),
         };
 
-        let fork_json = serde_json::to_string(&Message::ForkRequest(fork_request))
-            .map_err(|e| format!("Failed to serialize fork request: {}", e))?;
-
         // Send the message to the child process
-        writeln!(env_guard.stdin, "{}", fork_json)
-            .map_err(|e| format!("Failed to write to child stdin: {}", e))?;
-        env_guard
-            .stdin
-            .flush()
-            .map_err(|e| format!("Failed to flush child stdin: {}", e))?;
+        write_message(
+            &mut env_guard.stdin,
+            &Message::ForkRequest(fork_request),
+            self.config.framing,
+        )
+        .map_err(|e| format!("Failed to write to child stdin: {}", e))?;
 
         // Release the lock so we don't block other operations
         drop(env_guard);
 
-        // Wait for the fork to complete
-        debug!("Waiting for fork status for process {}...", process_uuid);
-        match fork_resolver.wait() {
+        Ok((process_uuid, fork_resolver))
+    }
+
+    fn fork_outcome(
+        process_uuid: String,
+        fork_result: Result<ForkResult, String>,
+    ) -> Result<String, HotReloadError> {
+        match fork_result {
             Ok(ForkResult::Complete(_)) => {
                 debug!("Fork completed successfully for process {}", process_uuid);
                 Ok(process_uuid)
             }
             Ok(ForkResult::Error(error)) => {
                 error!("Fork error for process {}: {}", process_uuid, error);
-                Err(error)
+                Err(error.into())
             }
             Err(e) => {
                 warn!("Error waiting for fork status: {}", e);
-                Err("Fork operation failed with unknown error".to_string())
+                Err("Fork operation failed with unknown error".into())
             }
         }
     }
 
     /// Stop an isolated process by UUID
-    pub fn stop_isolated(&self, process_uuid: &str) -> Result<bool, String> {
+    pub fn stop_isolated(&self, process_uuid: &str) -> Result<bool, HotReloadError> {
         // Check if environment is initialized
-        let environment = self
-            .layer
-            .as_ref()
-            .ok_or_else(|| "Environment not initialized. Call boot_main first.".to_string())?;
+        let environment = self.layer_for_process(process_uuid)?;
+
+        // A fork of a retired loader has already exited, only its result is left
+        if self
+            .drained_results
+            .lock()
+            .map_err(|e| format!("Failed to lock drained results: {}", e))?
+            .remove(process_uuid)
+            .is_some()
+        {
+            return Ok(true);
+        }
 
         info!("Stopping isolated process: {}", process_uuid);
         let env_guard = environment
             .lock()
             .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+        Self::stop_fork(&env_guard, process_uuid)
+    }
 
-        // Check if the process UUID exists
-        let forked_processes = env_guard
+    /// Cancel a running isolated process. The fork is killed but stays tracked, so waiting
+    /// on it returns `ProcessResult::Cancelled` rather than an error from the kill. Returns
+    /// false if the process had already finished.
+    pub fn cancel_isolated(&self, process_uuid: &str) -> Result<bool, HotReloadError> {
+        let environment = self.layer_for_process(process_uuid)?;
+
+        // Forks of a retired loader have all finished
+        if self
+            .drained_results
+            .lock()
+            .map_err(|e| format!("Failed to lock drained results: {}", e))?
+            .contains_key(process_uuid)
+        {
+            return Ok(false);
+        }
+
+        let env_guard = environment
+            .lock()
+            .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+        let pid = env_guard
             .forked_processes
             .lock()
-            .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+            .map_err(|e| format!("Failed to lock forked processes: {}", e))?
+            .get(process_uuid)
+            .copied()
+            .ok_or_else(|| HotReloadError::ProcessNotFound(process_uuid.to_string()))?;
 
-        if !forked_processes.contains_key(process_uuid) {
-            warn!("No forked process found with UUID: {}", process_uuid);
-            return Ok(false); // Nothing to stop
+        // Resolve before killing, so the exit the loader reports afterwards isn't taken as
+        // the outcome. Results that still arrive don't replace an existing resolution.
+        {
+            let completion_resolvers = env_guard
+                .completion_resolvers
+                .lock()
+                .map_err(|e| format!("Failed to lock completion resolvers: {}", e))?;
+            match completion_resolvers.get(process_uuid) {
+                Some(resolver) if !resolver.is_resolved() => {
+                    resolver.resolve(ProcessResult::Cancelled)
+                }
+                _ => return Ok(false),
+            }
         }
 
-        let pid = forked_processes[process_uuid];
-        info!("Found process with PID: {}", pid);
-        drop(forked_processes);
+        info!("Cancelling isolated process {} (PID {})", process_uuid, pid);
+        if !env_guard.has_exited(pid) && unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+            let err = std::io::Error::last_os_error();
+            warn!("Failed to send SIGKILL to PID {}: {}", pid, err);
+        }
 
-        // Try to kill the process by PID
-        unsafe {
-            if libc::kill(pid, libc::SIGTERM) == 0 {
-                info!("Successfully sent SIGTERM to PID: {}", pid);
-            } else {
-                let err = std::io::Error::last_os_error();
-                warn!("Failed to send SIGTERM to PID {}: {}", pid, err);
+        Ok(true)
+    }
 
-                // Try to send SIGKILL
-                if libc::kill(pid, libc::SIGKILL) == 0 {
-                    info!("Successfully sent SIGKILL to PID: {}", pid);
-                } else {
+    /// Ask a running isolated process to wrap up and wait for its outcome. The fork gets a
+    /// SIGINT, which raises `KeyboardInterrupt` inside the function so it can clean up and
+    /// still return a value. A fork that hasn't reported anything within `grace` is killed.
+    /// Returns the function's result when it finished, `Cancelled` when it let the interrupt
+    /// propagate, and `Exited` when it had to be killed. Fails when the fork can't be signaled,
+    /// or the loader doesn't report the killed fork within `KILL_REPORT_TIMEOUT`.
+    pub fn finish_isolated(
+        &self,
+        process_uuid: &str,
+        grace: Duration,
+    ) -> Result<ProcessResult, HotReloadError> {
+        let completion_resolver = self.completion_resolver(process_uuid)?;
+
+        if !completion_resolver.is_resolved() {
+            let environment = self.layer_for_process(process_uuid)?;
+            let pid = environment
+                .lock()
+                .map_err(|e| format!("Failed to lock environment mutex: {}", e))?
+                .forked_processes
+                .lock()
+                .map_err(|e| format!("Failed to lock forked processes: {}", e))?
+                .get(process_uuid)
+                .copied()
+                .ok_or_else(|| HotReloadError::ProcessNotFound(process_uuid.to_string()))?;
+
+            // The lock is only held while signaling, since the monitor needs it to resolve.
+            // A fork that is already gone has its exit report on the way, anything else
+            // means it would never resolve.
+            let signal_fork = |signal: libc::c_int| -> Result<(), HotReloadError> {
+                let env_guard = environment
+                    .lock()
+                    .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+                if !env_guard.has_exited(pid) && unsafe { libc::kill(pid, signal) } != 0 {
                     let err = std::io::Error::last_os_error();
-                    warn!("Failed to send SIGKILL to PID {}: {}", pid, err);
+                    if err.raw_os_error() != Some(libc::ESRCH) {
+                        return Err(format!(
+                            "Failed to send signal {} to PID {}: {}",
+                            signal, pid, err
+                        )
+                        .into());
+                    }
+                }
+                Ok(())
+            };
+
+            info!(
+                "Asking isolated process {} (PID {}) to finish",
+                process_uuid, pid
+            );
+            signal_fork(libc::SIGINT)?;
+            if !matches!(completion_resolver.wait_timeout(grace), Ok(Some(_))) {
+                warn!(
+                    "Isolated process {} didn't finish within {:?}, killing it",
+                    process_uuid, grace
+                );
+                signal_fork(libc::SIGKILL)?;
+
+                // A killed fork resolves once the loader reports its exit
+                if let Ok(None) = completion_resolver.wait_timeout(KILL_REPORT_TIMEOUT) {
+                    return Err(HotReloadError::Timeout {
+                        operation: format!("killed process {} to exit", process_uuid),
+                        after: KILL_REPORT_TIMEOUT,
+                    });
                 }
             }
         }
 
-        // Remove the process from our maps
-        let mut forked_processes = env_guard
-            .forked_processes
-            .lock()
-            .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
-        forked_processes.remove(process_uuid);
-        drop(forked_processes);
+        let completion = completion_resolver.wait();
+        self.completion_outcome(process_uuid, completion)
+    }
 
-        let mut fork_resolvers = env_guard
+    /// Snapshot of the forks that are still running, as (UUID, PID) pairs sorted by UUID.
+    /// Includes forks left running on loaders replaced by a drain reload.
+    pub fn list_forked(&self) -> Result<Vec<(String, i32)>, HotReloadError> {
+        let Some(current) = &self.layer else {
+            return Ok(Vec::new());
+        };
+        let mut layers = vec![Arc::clone(current)];
+        layers.extend(
+            self.draining_layers
+                .lock()
+                .map_err(|e| format!("Failed to lock draining layers: {}", e))?
+                .iter()
+                .cloned(),
+        );
+
+        let mut forked: Vec<(String, i32)> = Vec::new();
+        for layer in &layers {
+            let layer_guard = layer
+                .lock()
+                .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+            let forked_processes = layer_guard
+                .forked_processes
+                .lock()
+                .map_err(|e| format!("Failed to lock forked processes: {}", e))?
+                .clone();
+            forked.extend(
+                forked_processes
+                    .into_iter()
+                    .filter(|(_, pid)| !layer_guard.has_exited(*pid)),
+            );
+        }
+        forked.sort();
+        Ok(forked)
+    }
+
+    /// Stop every running fork, see `stop_isolated`. The forks are listed up front and each
+    /// is stopped on its own, so no lock is held across the whole batch. Returns how many
+    /// were stopped.
+    pub fn stop_all_isolated(&self) -> Result<usize, HotReloadError> {
+        let mut stopped = 0;
+        for (process_uuid, _) in self.list_forked()? {
+            if self.stop_isolated(&process_uuid)? {
+                stopped += 1;
+            }
+        }
+        Ok(stopped)
+    }
+
+    /// Signal a fork of the given loader and forget about it
+    fn stop_fork(env_guard: &Layer, process_uuid: &str) -> Result<bool, HotReloadError> {
+        // Check if the process UUID exists
+        let forked_processes = env_guard
+            .forked_processes
+            .lock()
+            .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+
+        if !forked_processes.contains_key(process_uuid) {
+            warn!("No forked process found with UUID: {}", process_uuid);
+            return Ok(false); // Nothing to stop
+        }
+
+        let pid = forked_processes[process_uuid];
+        info!("Found process with PID: {}", pid);
+        drop(forked_processes);
+
+        // Try to kill the process by PID, unless the loader already reaped it
+        if env_guard.has_exited(pid) {
+            info!("Process with PID {} has already exited", pid);
+        } else {
+            unsafe {
+                if libc::kill(pid, libc::SIGTERM) == 0 {
+                    info!("Successfully sent SIGTERM to PID: {}", pid);
+                } else {
+                    let err = std::io::Error::last_os_error();
+                    warn!("Failed to send SIGTERM to PID {}: {}", pid, err);
+
+                    // Try to send SIGKILL
+                    if libc::kill(pid, libc::SIGKILL) == 0 {
+                        info!("Successfully sent SIGKILL to PID: {}", pid);
+                    } else {
+                        let err = std::io::Error::last_os_error();
+                        warn!("Failed to send SIGKILL to PID {}: {}", pid, err);
+                    }
+                }
+            }
+        }
+
+        // Remove the process from our maps
+        let mut forked_processes = env_guard
+            .forked_processes
+            .lock()
+            .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+        forked_processes.remove(process_uuid);
+        drop(forked_processes);
+
+        let mut fork_resolvers = env_guard
             .fork_resolvers
             .lock()
             .map_err(|e| format!("Failed to lock fork resolvers: {}", e))?;
@@ -536,18 +2090,268 @@ pickled_str = "{}"
         completion_resolvers.remove(process_uuid);
         drop(completion_resolvers);
 
+        // Dropping the output closes any subscriber channels
+        env_guard
+            .process_output
+            .lock()
+            .map_err(|e| format!("Failed to lock process output: {}", e))?
+            .remove(process_uuid);
+
         info!("Removed process UUID: {} from process maps", process_uuid);
 
         Ok(true)
     }
 
+    /// Stream the printed output of an isolated process, line by line. Lines printed before
+    /// subscribing are replayed first, and the channel closes once the process finishes.
+    pub fn subscribe_output(&self, process_uuid: &str) -> Result<Receiver<String>, HotReloadError> {
+        let layer = self.layer_for_process(process_uuid)?;
+        let layer_guard = layer
+            .lock()
+            .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+
+        layer_guard
+            .subscribe_output(process_uuid)
+            .ok_or_else(|| HotReloadError::ProcessNotFound(process_uuid.to_string()))
+    }
+
+    /// The name an isolated process was started with, which prefixes its output. Forks
+    /// started without a name get a generated `fork-<uuid prefix>` one.
+    pub fn process_name(&self, process_uuid: &str) -> Result<String, HotReloadError> {
+        let layer = self.layer_for_process(process_uuid)?;
+        let layer_guard = layer
+            .lock()
+            .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+        let forked_names = layer_guard
+            .forked_names
+            .lock()
+            .map_err(|e| format!("Failed to lock forked names: {}", e))?;
+
+        forked_names
+            .get(process_uuid)
+            .cloned()
+            .ok_or_else(|| HotReloadError::ProcessNotFound(process_uuid.to_string()))
+    }
+
+    /// The lines an isolated process printed before it finished, up to the most recent 1024.
+    /// When a function raises or the fork crashes, this is what it printed leading up to it.
+    /// Kept until the process is stopped, or until 64 newer forks have finished. Stderr can
+    /// trail the result, so use `subscribe_output` to wait for all of it.
+    pub fn captured_output(&self, process_uuid: &str) -> Result<Vec<String>, HotReloadError> {
+        let layer = self.layer_for_process(process_uuid)?;
+        let layer_guard = layer
+            .lock()
+            .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+
+        layer_guard
+            .captured_output(process_uuid)
+            .ok_or_else(|| HotReloadError::ProcessNotFound(process_uuid.to_string()))
+    }
+
     /// Retrieve the result of an isolated execution
-    pub fn communicate_isolated(&self, process_uuid: &str) -> Result<Option<String>, String> {
+    pub fn communicate_isolated(
+        &self,
+        process_uuid: &str,
+    ) -> Result<Option<String>, HotReloadError> {
+        self.communicate_isolated_with_timeout(process_uuid, None)
+    }
+
+    /// Wait for an isolated process to finish, giving up after `timeout` if one is given.
+    /// A timed out process keeps running and stays tracked, so it can be waited on again
+    /// or stopped with `stop_isolated`.
+    pub fn communicate_isolated_with_timeout(
+        &self,
+        process_uuid: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, HotReloadError> {
+        let completed = process_result(
+            process_uuid,
+            self.wait_for_completion(process_uuid, timeout)?,
+        )?;
+        string_result(process_uuid, completed.result)
+    }
+
+    /// Wait for an isolated process and deserialize its return value from JSON. The
+    /// function must return something `json.dumps` can encode, like a dict, list or number.
+    pub fn communicate_isolated_as<T: DeserializeOwned>(
+        &self,
+        process_uuid: &str,
+    ) -> Result<T, HotReloadError> {
+        let invalid_result = |reason: String| HotReloadError::InvalidResult {
+            uuid: process_uuid.to_string(),
+            reason,
+        };
+
+        let completed =
+            process_result(process_uuid, self.wait_for_completion(process_uuid, None)?)?;
+        match completed.result_json {
+            Some(json) => serde_json::from_str(&json).map_err(|e| invalid_result(e.to_string())),
+            None => Err(invalid_result(
+                "the return value is not JSON serializable".to_string(),
+            )),
+        }
+    }
+
+    /// Wait for an isolated process and return its raw return value. The function must
+    /// return `bytes` (or a `bytearray` or `memoryview`). Large values are handed over through
+    /// a temporary file instead of the message stream, so they arrive intact.
+    pub fn communicate_isolated_bytes(
+        &self,
+        process_uuid: &str,
+    ) -> Result<Vec<u8>, HotReloadError> {
+        let completed =
+            process_result(process_uuid, self.wait_for_completion(process_uuid, None)?)?;
+        completed
+            .result_bytes
+            .ok_or_else(|| HotReloadError::InvalidResult {
+                uuid: process_uuid.to_string(),
+                reason: "the return value is not bytes".to_string(),
+            })
+    }
+
+    /// Fork, run the function and wait for it to finish, in one call. A function that raised
+    /// is reported as `ProcessResult::Error` rather than an `Err`. When `timeout` passes first
+    /// this returns a timeout error, and the process keeps running and stays tracked under
+    /// the UUID in the error, so it can be waited on again or stopped.
+    pub fn run_isolated(
+        &self,
+        pickled_data: &str,
+        name: &str,
+        timeout: Option<Duration>,
+    ) -> Result<ProcessResult, HotReloadError> {
+        let process_uuid = self.exec_isolated(pickled_data, name)?;
+        self.wait_for_completion(&process_uuid, timeout)
+    }
+
+    /// Wait for every given process and collect their outcomes, keyed by UUID. A function
+    /// that raised is reported as `ProcessResult::Error` in the map rather than failing the
+    /// whole call, so one bad fork doesn't hide the results of the others.
+    pub fn wait_for_all(
+        &self,
+        process_uuids: &[String],
+    ) -> Result<HashMap<String, ProcessResult>, HotReloadError> {
+        // Waiting doesn't hold any locks, so handling the forks in order can't block the
+        // monitor threads from resolving the ones that finish first
+        process_uuids
+            .iter()
+            .map(|process_uuid| {
+                let result = self.wait_for_completion(process_uuid, None)?;
+                Ok((process_uuid.clone(), result))
+            })
+            .collect()
+    }
+
+    /// Run many calls in isolation with at most `concurrency` forks alive at once, returning
+    /// their outcomes in the same order as `calls`. Each fork is untracked once its result is
+    /// in, so a large batch doesn't pile up finished processes. A call that couldn't be forked
+    /// gets an `Err` in its slot without affecting the rest. A concurrency of 0 is treated as 1.
+    pub fn exec_batch(
+        &self,
+        calls: Vec<PreparedCall>,
+        concurrency: usize,
+    ) -> Vec<Result<ProcessResult, HotReloadError>> {
+        let workers = concurrency.max(1).min(calls.len());
+        let next_call = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Result<ProcessResult, HotReloadError>>>> =
+            Mutex::new((0..calls.len()).map(|_| None).collect());
+
+        // Each worker runs one fork at a time, so the worker count bounds the live forks
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let index = next_call.fetch_add(1, Ordering::SeqCst);
+                    let Some(call) = calls.get(index) else {
+                        break;
+                    };
+
+                    let result = self.exec_isolated(&call.pickled_data, &call.name).and_then(
+                        |process_uuid| {
+                            let result = self.wait_for_completion(&process_uuid, None);
+                            if let Err(e) = self.stop_isolated(&process_uuid) {
+                                debug!("Failed to untrack batched process {}: {}", process_uuid, e);
+                            }
+                            result
+                        },
+                    );
+                    results.lock().unwrap_or_else(PoisonError::into_inner)[index] = Some(result);
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err("Batched call never ran".into())))
+            .collect()
+    }
+
+    /// Block until the process reports its result, including errors raised by the function
+    fn wait_for_completion(
+        &self,
+        process_uuid: &str,
+        timeout: Option<Duration>,
+    ) -> Result<ProcessResult, HotReloadError> {
+        let completion_resolver = self.completion_resolver(process_uuid)?;
+
+        // Wait for the completion
+        debug!("Waiting for process completion: {}", process_uuid);
+        let completion = match timeout {
+            Some(timeout) => match completion_resolver.wait_timeout(timeout) {
+                Ok(Some(result)) => Ok(result),
+                Ok(None) => {
+                    let error = HotReloadError::Timeout {
+                        operation: format!("process: {}", process_uuid),
+                        after: timeout,
+                    };
+                    warn!("{}", error);
+                    return Err(error);
+                }
+                Err(e) => Err(e),
+            },
+            None => completion_resolver.wait(),
+        };
+
+        self.completion_outcome(process_uuid, completion)
+    }
+
+    /// Async version of `communicate_isolated`. Dropping the future stops waiting but leaves
+    /// the process running and tracked, like a timed out `communicate_isolated_with_timeout`.
+    pub async fn await_result_async(
+        &self,
+        process_uuid: &str,
+    ) -> Result<Option<String>, HotReloadError> {
+        let completion_resolver = self.completion_resolver(process_uuid)?;
+
+        debug!("Awaiting process completion: {}", process_uuid);
+        let completion = completion_resolver.future().await;
+        let completed = process_result(
+            process_uuid,
+            self.completion_outcome(process_uuid, completion)?,
+        )?;
+        string_result(process_uuid, completed.result)
+    }
+
+    /// The resolver that fires when the given process finishes. Forks of a retired loader
+    /// get one that is already resolved with their result.
+    fn completion_resolver(
+        &self,
+        process_uuid: &str,
+    ) -> Result<AsyncResolve<ProcessResult>, HotReloadError> {
         // Check if environment is initialized
-        let environment = self
-            .layer
-            .as_ref()
-            .ok_or_else(|| "Environment not initialized. Call boot_main first.".to_string())?;
+        let environment = self.layer_for_process(process_uuid)?;
+
+        // Forks of a retired loader only have their result left
+        if let Some(result) = self
+            .drained_results
+            .lock()
+            .map_err(|e| format!("Failed to lock drained results: {}", e))?
+            .get(process_uuid)
+        {
+            let resolver = AsyncResolve::new();
+            resolver.resolve(result.clone());
+            return Ok(resolver);
+        }
 
         let env_guard = environment
             .lock()
@@ -560,10 +2364,7 @@ pickled_str = "{}"
             .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
 
         if !forked_processes.contains_key(process_uuid) {
-            return Err(format!(
-                "No forked process found with UUID: {}",
-                process_uuid
-            ));
+            return Err(HotReloadError::ProcessNotFound(process_uuid.to_string()));
         }
         drop(forked_processes);
 
@@ -576,68 +2377,523 @@ pickled_str = "{}"
         let completion_resolver = match completion_resolvers.get(process_uuid) {
             Some(resolver) => resolver.clone(),
             None => {
-                return Err(format!(
-                    "No completion resolver found for UUID: {}",
-                    process_uuid
-                ))
+                return Err(
+                    format!("No completion resolver found for UUID: {}", process_uuid).into(),
+                )
             }
         };
         drop(completion_resolvers);
 
-        // Release the environment guard so we don't block other operations
-        drop(env_guard);
+        Ok(completion_resolver)
+    }
 
-        // Wait for the completion
-        debug!("Waiting for process completion: {}", process_uuid);
-        match completion_resolver.wait() {
-            Ok(ProcessResult::Complete(result)) => {
+    fn completion_outcome(
+        &self,
+        process_uuid: &str,
+        completion: Result<ProcessResult, String>,
+    ) -> Result<ProcessResult, HotReloadError> {
+        match completion {
+            Ok(result @ ProcessResult::Complete { .. }) => {
                 debug!("Process completed successfully: {}", process_uuid);
                 Ok(result)
             }
             Ok(ProcessResult::Error(error)) => {
                 error!("Process error for UUID {}: {}", process_uuid, error);
-                Err(error)
+                Ok(ProcessResult::Error(error))
+            }
+            Ok(ProcessResult::TimedOut(error)) => {
+                error!("Process {} timed out: {}", process_uuid, error);
+                Ok(ProcessResult::TimedOut(error))
+            }
+            Ok(ProcessResult::Cancelled) => {
+                debug!("Process was cancelled: {}", process_uuid);
+                Ok(ProcessResult::Cancelled)
+            }
+            Ok(result @ ProcessResult::Exited { .. }) => {
+                error!(
+                    "Process {} exited without reporting a result: {:?}",
+                    process_uuid, result
+                );
+                Ok(result)
             }
             Err(e) => {
                 warn!("Error waiting for process completion: {}", e);
-                Err("Process completion failed with unknown error".to_string())
+                Err("Process completion failed with unknown error".into())
             }
         }
     }
 }
 
+/// The return value of a fork that completed, in each form it was reported in
+struct CompletedResult {
+    result: Option<String>,
+    result_json: Option<String>,
+    result_bytes: Option<Vec<u8>>,
+}
+
+/// The return value of a finished process, or the error for a process that didn't complete
+fn process_result(
+    process_uuid: &str,
+    result: ProcessResult,
+) -> Result<CompletedResult, HotReloadError> {
+    match result {
+        ProcessResult::Complete {
+            result,
+            result_json,
+            result_bytes,
+        } => Ok(CompletedResult {
+            result,
+            result_json,
+            result_bytes,
+        }),
+        ProcessResult::Error(error) => Err(HotReloadError::ProcessFailed(error)),
+        ProcessResult::TimedOut(error) => Err(HotReloadError::ProcessTimedOut(error)),
+        ProcessResult::Exited { exit_code, signal } => {
+            Err(HotReloadError::ProcessExited { exit_code, signal })
+        }
+        ProcessResult::Cancelled => Err(HotReloadError::ProcessCancelled(process_uuid.to_string())),
+    }
+}
+
+/// The string form of a fork's return value. Every result has one, except binary results too
+/// large to send inline, which only arrive as bytes.
+fn string_result(
+    process_uuid: &str,
+    result: Option<String>,
+) -> Result<Option<String>, HotReloadError> {
+    match result {
+        Some(result) => Ok(Some(result)),
+        None => Err(HotReloadError::InvalidResult {
+            uuid: process_uuid.to_string(),
+            reason: "the return value is too large to send as a string, use \
+                     communicate_isolated_bytes"
+                .to_string(),
+        }),
+    }
+}
+
+/// Name for a fork that wasn't given one, so its output can still be told apart
+fn default_fork_name(process_uuid: &str) -> String {
+    format!("fork-{}", &process_uuid[..8])
+}
+
 /// Spawn a Python process that imports the given modules and then waits for commands on stdin.
 /// The Python process prints "IMPORTS_LOADED" to stdout once all imports are complete.
 /// After that, it will listen for commands on stdin, which can include fork requests and code to execute.
-fn spawn_python_loader(modules: &HashSet<String>) -> Result<Child> {
+fn spawn_python_loader(
+    config: &EnvironmentConfig,
+    modules: &HashSet<String>,
+    result_dir: &Path,
+    spawner: &LoaderSpawner,
+) -> Result<Child, HotReloadError> {
     // Convert modules to a JSON list of module names
-    let import_json = serde_json::to_string(&Vec::from_iter(modules.iter().cloned()))
-        .map_err(|e| anyhow!("Failed to serialize module names: {}", e))?;
+    let import_json = serde_json::to_string(&loader_import_order(modules))
+        .map_err(|e| format!("Failed to serialize module names: {}", e))?;
 
     debug!("Module import JSON: {}", import_json);
+    let loader_script = config.loader_script_source()?;
 
     // Spawn Python process with all modules pre-imported
-    let child = Command::new("python")
-        .args(["-c", PYTHON_LOADER_SCRIPT])
+    let mut command = config.python_command()?;
+    command
+        .args(["-c", &loader_script])
         .arg(import_json)
+        .env("FIREHOT_RESULT_DIR", result_dir)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
+        .stderr(Stdio::piped());
+
+    spawn_with_retry(config.spawn_attempts(), || spawner(&mut command))
+        .map_err(HotReloadError::Spawn)
+}
+
+/// Call `spawn` up to `attempts` times, doubling the wait from `SPAWN_RETRY_BACKOFF` after each
+/// transient failure. Returns the last error once the attempts run out.
+fn spawn_with_retry<T>(attempts: u32, mut spawn: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut backoff = SPAWN_RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match spawn() {
+            Ok(spawned) => return Ok(spawned),
+            Err(e) if attempt < attempts && is_transient_spawn_error(&e) => {
+                warn!(
+                    "Failed to spawn Python loader (attempt {}/{}), retrying in {:?}: {}",
+                    attempt, attempts, backoff, e
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Spawn failures that come from the machine being busy rather than from the command itself
+fn is_transient_spawn_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::OutOfMemory
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::ExecutableFileBusy
+    )
+}
+
+/// Read what's left of the loader's stderr after a failed boot, keeping only the last
+/// `MAX_BOOT_STDERR_BYTES`. Gives up after `BOOT_STDERR_TIMEOUT` with whatever arrived by then.
+fn drain_boot_stderr(stderr: FrameReader<BufReader<std::process::ChildStderr>>) -> String {
+    // Longer lines are cut while reading, so a single one can't get past the cap
+    let max_length = stderr.max_length().min(MAX_BOOT_STDERR_BYTES);
+    let mut stderr = stderr.with_max_length(max_length);
+
+    let captured = Arc::new(Mutex::new((VecDeque::<String>::new(), 0usize, false)));
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    {
+        let captured = Arc::clone(&captured);
+        thread::spawn(move || {
+            while let Some(line) = stderr.next() {
+                let Ok(line) = line else {
+                    continue;
+                };
+                let mut captured = captured.lock().unwrap();
+                let (lines, size, truncated) = &mut *captured;
+                *truncated |= stderr.truncated_length().is_some();
+                *size += line.len() + 1;
+                lines.push_back(line);
+                while *size > MAX_BOOT_STDERR_BYTES && lines.len() > 1 {
+                    if let Some(dropped) = lines.pop_front() {
+                        *size -= dropped.len() + 1;
+                    }
+                    *truncated = true;
+                }
+            }
+            let _ = done_tx.send(());
+        });
+    }
+    if done_rx.recv_timeout(BOOT_STDERR_TIMEOUT).is_err() {
+        debug!("Loader stderr still open after it failed to boot");
+    }
+
+    let captured = captured.lock().unwrap();
+    let (lines, _, truncated) = &*captured;
+    let mut output = lines
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if *truncated {
+        output.insert_str(0, "[earlier output truncated]\n");
+    }
+    output
+}
+
+/// Surface the loader's stderr for failures whose error doesn't carry it
+fn log_boot_stderr(stderr: String) {
+    if !stderr.is_empty() {
+        error!("Python loader stderr:\n{}", stderr);
+    }
+}
+
+/// An environment dropped without `shutdown`, say while unwinding from a panic or when the
+/// host program bails out on Ctrl-C, would otherwise leave the loader and its forks running
+/// as orphans. Dropping kills them outright. There's no grace period, since a slow drop would
+/// hold up whatever is tearing the program down.
+impl Drop for Environment {
+    fn drop(&mut self) {
+        let draining: Vec<Arc<Mutex<Layer>>> = self
+            .draining_layers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(..)
+            .collect();
+        for layer in draining.iter().chain(self.layer.take().as_ref()) {
+            kill_layer(layer);
+        }
+    }
+}
+
+/// SIGKILL every fork of a loader and then the loader itself, without waiting on anything
+/// that could block. Poisoned locks are still used, since this runs during unwinding.
+fn kill_layer(layer: &Arc<Mutex<Layer>>) {
+    let mut layer_guard = layer.lock().unwrap_or_else(PoisonError::into_inner);
+    kill_layer_processes(&mut layer_guard);
+}
+
+/// `kill_layer` for a caller already holding the layer lock
+fn kill_layer_processes(layer_guard: &mut Layer) {
+    layer_guard.stopping = true;
+
+    let forks: Vec<i32> = layer_guard
+        .forked_processes
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .values()
+        .copied()
+        .collect();
+    let exited = layer_guard
+        .exited_processes
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .keys()
+        .copied()
+        .collect::<HashSet<i32>>();
+    // Reaped forks are skipped, since their PIDs may already belong to someone else
+    for pid in forks.into_iter().filter(|pid| !exited.contains(pid)) {
+        debug!("Killing fork {} of a stopped loader", pid);
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+
+    // Already stopped loaders have been reaped, which makes these no-ops
+    if let Ok(None) = layer_guard.child.try_wait() {
+        debug!("Killing loader {}", layer_guard.child.id());
+        let _ = layer_guard.child.kill();
+        let _ = layer_guard.child.wait();
+    }
+}
+
+/// Keep the final result of every fork of a drained loader that is about to be retired
+fn record_drained_results(
+    layer: &Arc<Mutex<Layer>>,
+    drained_results: &Mutex<HashMap<String, ProcessResult>>,
+) -> Result<(), HotReloadError> {
+    let layer_guard = layer
+        .lock()
+        .map_err(|e| format!("Failed to lock layer mutex: {}", e))?;
+    let forked_processes = layer_guard
+        .forked_processes
+        .lock()
+        .map_err(|e| format!("Failed to lock forked processes: {}", e))?;
+    let completion_resolvers = layer_guard
+        .completion_resolvers
+        .lock()
+        .map_err(|e| format!("Failed to lock completion resolvers: {}", e))?;
+    let mut drained_results = drained_results
+        .lock()
+        .map_err(|e| format!("Failed to lock drained results: {}", e))?;
+    for uuid in forked_processes.keys() {
+        let result = completion_resolvers
+            .get(uuid)
+            .and_then(|resolver| resolver.get())
+            .unwrap_or_else(|| {
+                ProcessResult::Error("Process exited without reporting a result".to_string())
+            });
+        drained_results.insert(uuid.clone(), result);
+    }
+    Ok(())
+}
+
+/// Whether any fork of the loader is still working on its result
+fn has_running_forks(layer: &Arc<Mutex<Layer>>) -> bool {
+    // A poisoned lock can't tell us, so keep the loader rather than cut forks short
+    let Ok(layer_guard) = layer.lock() else {
+        return true;
+    };
+    let Ok(forked_processes) = layer_guard.forked_processes.lock() else {
+        return true;
+    };
+    let Ok(completion_resolvers) = layer_guard.completion_resolvers.lock() else {
+        return true;
+    };
+
+    forked_processes.iter().any(|(uuid, pid)| {
+        let resolved = completion_resolvers
+            .get(uuid)
+            .is_none_or(|resolver| resolver.is_resolved());
+        !resolved && !layer_guard.has_exited(*pid)
+    })
+}
+
+/// Wait until `deadline` for the forks to exit, then SIGKILL the rest. Returns the UUIDs that
+/// exited on their own and the ones that had to be killed.
+fn wait_or_kill_forks(
+    mut pending: Vec<(String, i32)>,
+    deadline: Instant,
+) -> (Vec<String>, Vec<String>) {
+    let mut exited = Vec::new();
+    loop {
+        pending.retain(|(uuid, pid)| {
+            if is_process_running(*pid) {
+                return true;
+            }
+            exited.push(uuid.clone());
+            false
+        });
+
+        if pending.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut killed = Vec::new();
+    for (uuid, pid) in pending {
+        warn!(
+            "Process {} (PID {}) did not exit in time, sending SIGKILL",
+            uuid, pid
+        );
+        if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+            let err = std::io::Error::last_os_error();
+            warn!("Failed to send SIGKILL to PID {}: {}", pid, err);
+        }
+        killed.push(uuid);
+    }
+    (exited, killed)
+}
+
+/// Wait until `deadline` for a process we spawned to exit, then SIGKILL and reap it.
+/// Returns whether the kill was needed.
+fn wait_or_kill(child: &mut Child, deadline: Instant) -> std::io::Result<bool> {
+    loop {
+        if child.try_wait()?.is_some() {
+            return Ok(false);
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    child.kill()?;
+    child.wait()?;
+    Ok(true)
+}
+
+/// Sorted list of modules for the loader to import, so boots are reproducible across runs.
+/// Importing `a.b` always imports `a` first, so a module is dropped when one of its
+/// submodules is also requested.
+fn loader_import_order(modules: &HashSet<String>) -> Vec<String> {
+    let mut sorted: Vec<&String> = modules.iter().collect();
+    sorted.sort();
+
+    // After sorting, any submodules of a module directly follow it
+    let mut ordered: Vec<String> = Vec::with_capacity(sorted.len());
+    for (i, module) in sorted.iter().enumerate() {
+        let has_submodule = sorted
+            .get(i + 1)
+            .and_then(|next| next.strip_prefix(module.as_str()))
+            .is_some_and(|rest| rest.starts_with('.'));
+        if !has_submodule {
+            ordered.push(module.to_string());
+        }
+    }
+    ordered
+}
+
+/// Modules the loader imports in the background rather than before reporting ready: those
+/// that aren't a critical module or one of their submodules. Mirrors the split the loader makes.
+fn deferred_modules(modules: &HashSet<String>, critical: &HashSet<String>) -> Vec<String> {
+    if critical.is_empty() {
+        return Vec::new();
+    }
+    loader_import_order(modules)
+        .into_iter()
+        .filter(|module| {
+            !critical.iter().any(|critical| {
+                module == critical
+                    || module
+                        .strip_prefix(critical.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+        })
+        .collect()
+}
+
+/// Run a short-lived Python process that resolves each module with `importlib.util.find_spec`
+/// and reports the ones that can't be found. Nothing is imported, so this has no side effects.
+fn find_missing_modules(
+    config: &EnvironmentConfig,
+    modules: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let import_json = serde_json::to_string(&Vec::from_iter(modules.iter().cloned()))
+        .map_err(|e| anyhow!("Failed to serialize module names: {}", e))?;
+
+    let output = config
+        .python_command()
+        .map_err(|e| anyhow!(e))?
+        .args(["-c", PYTHON_PREFLIGHT_SCRIPT])
+        .arg(import_json)
+        .stdin(Stdio::null())
+        .output()
         .map_err(|e| anyhow!("Failed to spawn Python process: {}", e))?;
 
-    Ok(child)
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Preflight check failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let missing: Vec<String> = serde_json::from_str(stdout.trim())
+        .map_err(|e| anyhow!("Failed to parse preflight output: {}", e))?;
+
+    Ok(missing)
+}
+
+/// The modules a rebuild added and removed, like `Reloaded: +pandas, -lxml`. Modules are
+/// sorted so the same delta always reads the same.
+fn reload_summary(added: &HashSet<String>, removed: &HashSet<String>) -> String {
+    let mut added: Vec<&String> = added.iter().collect();
+    added.sort();
+    let mut removed: Vec<&String> = removed.iter().collect();
+    removed.sort();
+
+    let changes: Vec<String> = added
+        .into_iter()
+        .map(|module| format!("+{}", module).green().to_string())
+        .chain(
+            removed
+                .into_iter()
+                .map(|module| format!("-{}", module).red().to_string()),
+        )
+        .collect();
+    format!("{} {}", "Reloaded:".white().bold(), changes.join(", "))
+}
+
+/// Log the slowest imports of a boot at info so slow dependencies stand out
+fn log_slowest_imports(timings: &[ImportTiming]) {
+    if timings.is_empty() {
+        return;
+    }
+    let mut slowest: Vec<&ImportTiming> = timings.iter().collect();
+    slowest.sort_by_key(|timing| std::cmp::Reverse(timing.duration));
+
+    let summary = slowest
+        .iter()
+        .take(SLOWEST_IMPORTS_LOGGED)
+        .map(|timing| {
+            format!(
+                "  {:>8.1}ms  {}",
+                timing.duration.as_secs_f64() * 1000.0,
+                timing.module
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    info!(
+        "Slowest of {} preloaded imports:\n{}",
+        timings.len(),
+        summary
+    );
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast::ImportGranularity;
+    use crate::config::{EnvironmentBuilder, ExecutionMode, ImportFailurePolicy};
+    use crate::messages::io::Framing;
+    use crate::messages::PROTOCOL_VERSION;
 
     use tempfile::TempDir;
 
     use std::fs::File;
     use std::io::Write;
-    use std::path::PathBuf;
+    use std::os::unix::fs::PermissionsExt;
 
     // Helper function to create a temporary Python file
     fn create_temp_py_file(dir: &TempDir, filename: &str, content: &str) -> PathBuf {
@@ -669,61 +2925,541 @@ mod tests {
     }
 
     #[test]
-    fn test_update_environment_with_new_imports() {
+    fn test_check_imports_available_reports_missing() {
         let temp_dir = TempDir::new().unwrap();
         let dir_path = temp_dir.path().to_str().unwrap();
 
-        // Create a simple Python project with initial imports
-        create_temp_py_file(&temp_dir, "main.py", "import os\nimport sys");
+        create_temp_py_file(
+            &temp_dir,
+            "main.py",
+            "import os\nimport json\nimport firehot_missing_module_for_testing",
+        );
 
         let mut runner = Environment::new("test_package", dir_path, None);
+        let missing = runner
+            .check_imports_available()
+            .expect("Preflight check failed");
 
-        // Boot the environment before accessing it
-        runner.boot_main().expect("Failed to boot main environment");
+        assert_eq!(
+            missing,
+            vec!["firehot_missing_module_for_testing".to_string()]
+        );
 
-        // Force first_scan to true to allow update_environment to work
-        runner.first_scan = true;
+        // The preflight shouldn't boot anything
+        assert!(runner.layer.is_none());
+    }
 
-        // Get the PID of the initial Python process
-        let initial_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
-        println!("Initial process PID: {:?}", initial_pid);
+    #[test]
+    fn test_boot_with_explicit_interpreter() {
+        // Ask python for its real executable, since PATH may only hold a shim
+        let output = std::process::Command::new("python")
+            .args(["-c", "import sys; print(sys.executable)"])
+            .output()
+            .expect("python should be available on PATH");
+        let interpreter = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
 
-        // First, prime the system by calling process_all_py_files to establish a baseline
-        let _ = runner.ast_manager.process_all_py_files().unwrap();
+        let python_script = r#"
+import sys
 
-        // Now verify that running update with no changes keeps the same PID
-        let no_change_result = runner.update_environment();
-        assert!(
-            no_change_result.is_ok(),
-            "Failed to update environment: {:?}",
-            no_change_result.err()
-        );
+def main():
+    return sys.executable
+        "#;
 
-        // The environment should NOT have been updated (return false)
-        assert_eq!(
-            no_change_result.unwrap(),
-            false,
-            "Environment should not have been updated when imports didn't change"
-        );
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation_with_interpreter(
+                python_script,
+                "main",
+                &interpreter,
+            )
+            .expect("Failed to prepare script for isolation");
+
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .interpreter(&interpreter)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
 
-        // Get the PID after update with no changes
-        let unchanged_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
-        println!("PID after no changes: {:?}", unchanged_pid);
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "explicit_interpreter")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process")
+            .expect("No result received from isolated process");
 
-        // Verify that the process was NOT restarted (PIDs should be the same)
         assert_eq!(
-            initial_pid, unchanged_pid,
-            "Process should NOT have been restarted when imports didn't change"
+            std::fs::canonicalize(result).unwrap(),
+            std::fs::canonicalize(&interpreter).unwrap(),
+            "Loader should run with the configured interpreter"
         );
 
-        // Create a new file with different imports to trigger a restart
-        create_temp_py_file(
+        runner.stop_main().expect("Failed to stop main runner");
+
+        // A missing interpreter is reported up front rather than as a spawn failure
+        let mut missing = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .interpreter("python-does-not-exist")
+            .build();
+        let err = missing.boot_main().unwrap_err().to_string();
+        assert!(err.contains("was not found on PATH"), "{}", err);
+    }
+
+    /// Stand-in loader that prints the given messages and then hangs. `exec` makes sure a
+    /// SIGKILL lands on the process holding stdout open. Environments using it should set
+    /// `preload_stdlib` and `python_version`, since it can't answer the interpreter queries.
+    fn write_fake_loader(temp_dir: &TempDir, messages: &[String]) -> PathBuf {
+        let fake_loader = temp_dir.path().join("fake_python");
+        let mut script = "#!/bin/sh\n".to_string();
+        for message in messages {
+            script.push_str(&format!("echo '{}'\n", message));
+        }
+        script.push_str("exec sleep 30\n");
+        std::fs::write(&fake_loader, script).unwrap();
+        std::fs::set_permissions(&fake_loader, std::fs::Permissions::from_mode(0o755)).unwrap();
+        fake_loader
+    }
+
+    #[test]
+    fn test_boot_timeout_names_last_import() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("main.py"), "import slow_dependency\n").unwrap();
+
+        // Reports one finished import and then hangs
+        let fake_loader = write_fake_loader(
             &temp_dir,
-            "new_file.py",
-            "import os\nimport sys\nimport json",
+            &[
+                format!(
+                    r#"{{"name": "HELLO", "protocol_version": {}}}"#,
+                    PROTOCOL_VERSION
+                ),
+                r#"{"name": "MODULE_IMPORTED", "module": "fast_dependency"}"#.to_string(),
+            ],
         );
 
-        // Test updating environment with changed imports
+        let mut runner = EnvironmentBuilder::new("test_package", project_dir.to_str().unwrap())
+            .interpreter(&fake_loader)
+            .preload_stdlib(true)
+            .python_version(3, 11, 0)
+            .boot_timeout(Duration::from_millis(500))
+            .build();
+
+        let start = Instant::now();
+        let err = runner.boot_main().unwrap_err();
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Boot should give up at the timeout"
+        );
+        assert!(matches!(err, HotReloadError::Timeout { .. }), "{}", err);
+        let err = err.to_string();
+        assert!(
+            err.contains("last imported module: fast_dependency"),
+            "{}",
+            err
+        );
+        assert!(runner.layer.is_none());
+    }
+
+    #[test]
+    fn test_boot_read_failure_stops_the_loader() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("main.py"), "import json\n").unwrap();
+
+        // Writes a line that isn't UTF-8 and then hangs
+        let pid_file = temp_dir.path().join("loader.pid");
+        let fake_loader = temp_dir.path().join("fake_python");
+        std::fs::write(
+            &fake_loader,
+            format!(
+                "#!/bin/sh\necho $$ > {}\nprintf '\\377\\n'\nexec sleep 30\n",
+                pid_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&fake_loader, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", project_dir.to_str().unwrap())
+            .interpreter(&fake_loader)
+            .preload_stdlib(true)
+            .python_version(3, 11, 0)
+            .boot_timeout(Duration::from_secs(20))
+            .build();
+
+        let start = Instant::now();
+        let err = runner.boot_main().unwrap_err();
+        assert!(err.to_string().contains("Failed to read line"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(runner.layer.is_none());
+
+        // The loader was killed and reaped rather than left running
+        let pid: libc::pid_t = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert_ne!(unsafe { libc::kill(pid, 0) }, 0);
+    }
+
+    #[test]
+    fn test_boot_rejects_unsupported_protocol_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("main.py"), "import json\n").unwrap();
+
+        // Loaders from the past and the future, followed by a message we'd otherwise act on
+        for version in [1, 999] {
+            let fake_loader = write_fake_loader(
+                &temp_dir,
+                &[
+                    format!(r#"{{"name": "HELLO", "protocol_version": {}}}"#, version),
+                    r#"{"name": "IMPORT_COMPLETE"}"#.to_string(),
+                ],
+            );
+
+            let mut runner = EnvironmentBuilder::new("test_package", project_dir.to_str().unwrap())
+                .interpreter(&fake_loader)
+                .preload_stdlib(true)
+                .python_version(3, 11, 0)
+                .boot_timeout(Duration::from_secs(20))
+                .build();
+
+            let start = Instant::now();
+            match runner.boot_main() {
+                Err(HotReloadError::ProtocolMismatch { found, supported }) => {
+                    assert_eq!(found, Some(version));
+                    assert_eq!(supported, SUPPORTED_PROTOCOL_VERSIONS);
+                }
+                other => panic!("Expected ProtocolMismatch, got {:?}", other.map(|_| ())),
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "Boot should fail on the handshake, not the timeout"
+            );
+            assert!(runner.layer.is_none());
+        }
+
+        // A loader that predates the handshake is rejected too
+        let fake_loader =
+            write_fake_loader(&temp_dir, &[r#"{"name": "IMPORT_COMPLETE"}"#.to_string()]);
+        let mut runner = EnvironmentBuilder::new("test_package", project_dir.to_str().unwrap())
+            .interpreter(&fake_loader)
+            .preload_stdlib(true)
+            .python_version(3, 11, 0)
+            .build();
+        let err = runner.boot_main().unwrap_err();
+        assert!(
+            matches!(err, HotReloadError::ProtocolMismatch { found: None, .. }),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_last_boot_duration() {
+        let python_script = r#"
+import json
+
+def main():
+    return "booted"
+        "#;
+        let (_, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        assert_eq!(runner.last_boot_duration(), None);
+
+        runner.boot_main().expect("Failed to boot main environment");
+        let duration = runner.last_boot_duration().expect("Boot should be timed");
+        assert!(duration > Duration::ZERO);
+        assert_eq!(runner.metrics_snapshot().last_boot_duration, Some(duration));
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_spawn_with_retry() {
+        // Fails twice like a busy machine would, then starts the process
+        let mut calls = 0;
+        let start = Instant::now();
+        let mut child = spawn_with_retry(3, || {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::WouldBlock))
+            } else {
+                std::process::Command::new("true").spawn()
+            }
+        })
+        .expect("Spawn should succeed on the last attempt");
+        assert!(child.wait().unwrap().success());
+        assert_eq!(calls, 3);
+        assert!(start.elapsed() >= SPAWN_RETRY_BACKOFF * 3);
+
+        // Running out of attempts returns the last error
+        let mut calls = 0;
+        let err = spawn_with_retry(2, || {
+            calls += 1;
+            Err::<(), _>(io::Error::from(io::ErrorKind::WouldBlock))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(calls, 2);
+
+        // Permanent errors aren't retried
+        let mut calls = 0;
+        let err = spawn_with_retry(3, || {
+            calls += 1;
+            Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_boot_retries_transient_spawn_failures() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import json\n");
+        let project_path = temp_dir.path().to_str().unwrap();
+
+        // Fails twice like a busy machine would, then starts the loader
+        let flaky_spawner = |attempts: Arc<AtomicU32>| {
+            move |command: &mut Command| {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(io::Error::from(io::ErrorKind::WouldBlock))
+                } else {
+                    command.spawn()
+                }
+            }
+        };
+
+        let attempts = Arc::new(AtomicU32::new(0));
+        let mut runner = EnvironmentBuilder::new("test_package", project_path)
+            .spawn_attempts(3)
+            .build();
+        runner.set_spawner(flaky_spawner(Arc::clone(&attempts)));
+        let start = Instant::now();
+        runner
+            .boot_main()
+            .expect("Boot should succeed on the last attempt");
+        assert!(start.elapsed() >= SPAWN_RETRY_BACKOFF * 3);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(runner.is_alive());
+        runner.stop_main().expect("Failed to stop main runner");
+
+        // One attempt too few surfaces the spawn error
+        let mut runner = EnvironmentBuilder::new("test_package", project_path)
+            .spawn_attempts(2)
+            .build();
+        runner.set_spawner(flaky_spawner(Arc::new(AtomicU32::new(0))));
+        match runner.boot_main() {
+            Err(HotReloadError::Spawn(err)) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+            other => panic!("Expected a spawn error, got {:?}", other.map(|_| ())),
+        }
+        assert!(runner.layer.is_none());
+    }
+
+    #[test]
+    fn test_verify_fork_fails_boot_when_loader_cannot_fork() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().join("project");
+        std::fs::create_dir(&project_dir).unwrap();
+        std::fs::write(project_dir.join("main.py"), "import json\n").unwrap();
+
+        // Finishes its imports but never answers a fork request
+        let fake_loader = write_fake_loader(
+            &temp_dir,
+            &[
+                format!(
+                    r#"{{"name": "HELLO", "protocol_version": {}}}"#,
+                    PROTOCOL_VERSION
+                ),
+                r#"{"name": "IMPORT_COMPLETE"}"#.to_string(),
+            ],
+        );
+
+        let mut runner = EnvironmentBuilder::new("test_package", project_dir.to_str().unwrap())
+            .interpreter(&fake_loader)
+            .preload_stdlib(true)
+            .python_version(3, 11, 0)
+            .boot_timeout(Duration::from_millis(500))
+            .verify_fork(true)
+            .build();
+
+        let start = Instant::now();
+        let err = runner.boot_main().unwrap_err();
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "Boot should give up on the fork at the timeout"
+        );
+        assert!(matches!(err, HotReloadError::ForkUnavailable(_)), "{}", err);
+        assert!(runner.layer.is_none());
+
+        // A loader that can fork boots as usual
+        let python_script = r#"
+def main():
+    return "forked"
+        "#;
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .verify_fork(true)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+        assert!(runner.list_forked().unwrap().is_empty());
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "verified")
+            .expect("Failed to execute script in isolation");
+        assert_eq!(
+            runner.communicate_isolated(&process_uuid).unwrap(),
+            Some("forked".to_string())
+        );
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_boot_inside_venv() {
+        let venv_dir = TempDir::new().unwrap();
+        let venv_path = venv_dir.path().join("venv");
+
+        let status = std::process::Command::new("python")
+            .args(["-m", "venv", "--without-pip"])
+            .arg(&venv_path)
+            .status()
+            .expect("Failed to create virtualenv");
+        assert!(status.success(), "Failed to create virtualenv");
+
+        let python_script = r#"
+import sys
+
+def main():
+    return sys.prefix
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .venv(&venv_path)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "venv_prefix")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process")
+            .expect("No result received from isolated process");
+
+        assert_eq!(
+            std::fs::canonicalize(result).unwrap(),
+            std::fs::canonicalize(&venv_path).unwrap(),
+            "Loader should run with the virtualenv's interpreter"
+        );
+
+        runner.stop_main().expect("Failed to stop main process");
+    }
+
+    #[test]
+    fn test_length_prefixed_framing() {
+        let python_script = r#"
+def main():
+    # Stray output on stdout can't corrupt the framed protocol stream
+    print("output before the result")
+
+    # Larger than a single pipe read, and full of newlines
+    return "line of output\n" * 10000
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .framing(Framing::LengthPrefixed)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "framed_result")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process")
+            .expect("No result received from isolated process");
+
+        assert_eq!(result, "line of output\n".repeat(10000));
+
+        runner.stop_main().expect("Failed to stop main process");
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_update_environment_with_new_imports() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().to_str().unwrap();
+
+        // Create a simple Python project with initial imports
+        create_temp_py_file(&temp_dir, "main.py", "import os\nimport sys");
+
+        // The standard library stands in for third-party packages here
+        let mut runner = EnvironmentBuilder::new("test_package", dir_path)
+            .preload_stdlib(true)
+            .build();
+
+        // Boot the environment before accessing it
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Get the PID of the initial Python process
+        let initial_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+        println!("Initial process PID: {:?}", initial_pid);
+
+        // First, prime the system by calling process_all_py_files to establish a baseline
+        let _ = runner.ast_manager.process_all_py_files().unwrap();
+
+        // Now verify that running update with no changes keeps the same PID
+        let no_change_result = runner.update_environment();
+        assert!(
+            no_change_result.is_ok(),
+            "Failed to update environment: {:?}",
+            no_change_result.err()
+        );
+
+        // The environment should NOT have been updated (return false)
+        assert_eq!(
+            no_change_result.unwrap(),
+            false,
+            "Environment should not have been updated when imports didn't change"
+        );
+
+        // Get the PID after update with no changes
+        let unchanged_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+        println!("PID after no changes: {:?}", unchanged_pid);
+
+        // Verify that the process was NOT restarted (PIDs should be the same)
+        assert_eq!(
+            initial_pid, unchanged_pid,
+            "Process should NOT have been restarted when imports didn't change"
+        );
+
+        // Create a new file with different imports to trigger a restart
+        create_temp_py_file(
+            &temp_dir,
+            "new_file.py",
+            "import os\nimport sys\nimport json",
+        );
+
+        // The pending delta previews the change without acting on it
+        let (added, removed) = runner.pending_import_delta().unwrap();
+        assert_eq!(added, HashSet::from(["json".to_string()]));
+        assert!(removed.is_empty());
+
+        // Test updating environment with changed imports
         let update_result = runner.update_environment();
         assert!(
             update_result.is_ok(),
@@ -731,184 +3467,2642 @@ mod tests {
             update_result.err()
         );
 
-        // The environment should have been updated (return true)
-        assert!(
-            update_result.unwrap(),
-            "Environment should have been updated due to import changes"
+        // The environment should have been updated (return true)
+        assert!(
+            update_result.unwrap(),
+            "Environment should have been updated due to import changes"
+        );
+
+        // Get the PID of the new Python process
+        let new_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+        println!("New process PID after import changes: {:?}", new_pid);
+    }
+
+    #[test]
+    fn test_exec_communicate_isolated_basic() {
+        // Create a simple Python script that returns a timestamp
+        let python_script = r#"
+import time
+
+def main():
+    # Return the current timestamp as a string
+    return str(time.time())
+        "#;
+
+        // Prepare the script for isolation
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+
+        // Boot the environment before accessing it
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Execute the script in isolation
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "timestamp_test")
+            .expect("Failed to execute script in isolation");
+
+        // Wait a short time for the process to execute
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // Now call communicate_isolated to get the result
+        let communicate_result = runner.communicate_isolated(&process_uuid);
+        assert!(
+            communicate_result.is_ok(),
+            "communicate_isolated failed: {:?}",
+            communicate_result.err()
+        );
+
+        let result_option = communicate_result.unwrap();
+        assert!(
+            result_option.is_some(),
+            "No result received from isolated process"
+        );
+
+        // The result should be our timestamp string
+        let result_str = result_option.unwrap();
+        println!("Result from time.time(): {}", result_str);
+
+        // Try to parse the result as a float to verify it's a valid timestamp
+        let parsed_result = result_str.parse::<f64>();
+        assert!(
+            parsed_result.is_ok(),
+            "Failed to parse result as a float: {}",
+            result_str
+        );
+
+        // Clean up by stopping the isolated process
+        runner
+            .stop_isolated(&process_uuid)
+            .expect("Failed to stop isolated process");
+    }
+
+    #[test]
+    fn test_exec_isolated_with_options_env() {
+        let python_script = r#"
+import os
+
+def main():
+    return os.environ.get("FIREHOT_TEST_FLAG", "missing")
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let options = ExecOptions {
+            env: HashMap::from([("FIREHOT_TEST_FLAG".to_string(), "enabled".to_string())]),
+            ..ExecOptions::default()
+        };
+        let process_uuid = runner
+            .exec_isolated_with_options(&pickled_data, "env_test", &options)
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("communicate_isolated failed");
+        assert_eq!(result.as_deref(), Some("enabled"));
+
+        // Variables only apply to the fork that asked for them
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "env_test")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("communicate_isolated failed");
+        assert_eq!(result.as_deref(), Some("missing"));
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_exec_module_function() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = TempDir::new().unwrap();
+        let module_dir = temp_dir.path().join("importable_helpers");
+        std::fs::create_dir(&module_dir).unwrap();
+        std::fs::write(module_dir.join("__init__.py"), "").unwrap();
+        std::fs::write(
+            module_dir.join("math_ops.py"),
+            "def describe(a, b, options):\n    return f\"{a + b} {options['unit']}\"\n",
+        )
+        .unwrap();
+
+        // The loader itself imports `firehot` from our working directory, so keep that around
+        let python_path =
+            std::env::join_paths([temp_dir.path(), &std::env::current_dir().unwrap()]).unwrap();
+        let mut runner =
+            EnvironmentBuilder::new("test_package", project_dir.path().to_str().unwrap())
+                .env_var("PYTHONPATH", python_path.to_str().unwrap())
+                .build();
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_module_function(
+                "importable_helpers.math_ops",
+                "describe",
+                &[
+                    serde_json::json!(2),
+                    serde_json::json!(3),
+                    serde_json::json!({"unit": "apples"}),
+                ],
+            )
+            .expect("Failed to execute module function");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("communicate_isolated failed");
+        assert_eq!(result.as_deref(), Some("5 apples"));
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_partial_reload_keeps_loader() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = TempDir::new().unwrap();
+        let module_path = temp_dir.path().join("reloadable_helper.py");
+        std::fs::write(&module_path, "def version():\n    return 'old'\n").unwrap();
+
+        let python_path =
+            std::env::join_paths([temp_dir.path(), &std::env::current_dir().unwrap()]).unwrap();
+        let mut runner =
+            EnvironmentBuilder::new("test_package", project_dir.path().to_str().unwrap())
+                .env_var("PYTHONPATH", python_path.to_str().unwrap())
+                .extra_preload(["reloadable_helper"])
+                .partial_reload(true)
+                .build();
+        runner.boot_main().expect("Failed to boot main environment");
+        let loader_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+
+        let run_version = |runner: &Environment| {
+            let process_uuid = runner
+                .exec_module_function("reloadable_helper", "version", &[])
+                .expect("Failed to execute module function");
+            runner
+                .communicate_isolated(&process_uuid)
+                .expect("communicate_isolated failed")
+        };
+        assert_eq!(run_version(&runner).as_deref(), Some("old"));
+
+        // Files the loader never imported don't need anything
+        assert_eq!(
+            runner
+                .reload_files(&[temp_dir.path().join("unrelated.py")])
+                .unwrap(),
+            ModuleReload::Unchanged
+        );
+
+        // Differently sized, so a stale bytecode cache from the same second can't match
+        std::fs::write(&module_path, "def version():\n    return 'newer'\n").unwrap();
+        assert_eq!(
+            runner
+                .reload_files(std::slice::from_ref(&module_path))
+                .unwrap(),
+            ModuleReload::Partial(vec!["reloadable_helper".to_string()])
+        );
+        assert_eq!(
+            runner.layer.as_ref().unwrap().lock().unwrap().child.id(),
+            loader_pid
+        );
+        assert_eq!(run_version(&runner).as_deref(), Some("newer"));
+
+        // Without partial reloads the loader is rebooted instead
+        runner.config.partial_reload = false;
+        std::fs::write(&module_path, "def version():\n    return 'newest'\n").unwrap();
+        assert_eq!(
+            runner.reload_files(&[module_path]).unwrap(),
+            ModuleReload::Full
+        );
+        assert_ne!(
+            runner.layer.as_ref().unwrap().lock().unwrap().child.id(),
+            loader_pid
+        );
+        assert_eq!(run_version(&runner).as_deref(), Some("newest"));
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_fork_names_label_output() {
+        let python_script = r#"
+def main():
+    print("hello from the fork")
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let named = runner
+            .exec_isolated(&pickled_data, "billing_job")
+            .expect("Failed to execute script in isolation");
+        let unnamed = runner
+            .exec_isolated(&pickled_data, "")
+            .expect("Failed to execute script in isolation");
+        runner.communicate_isolated(&named).unwrap();
+        runner.communicate_isolated(&unnamed).unwrap();
+
+        assert_eq!(runner.process_name(&named).unwrap(), "billing_job");
+        let generated = runner.process_name(&unnamed).unwrap();
+        assert_eq!(generated, format!("fork-{}", &unnamed[..8]));
+
+        let output = runner.get_layer_output().unwrap_or_default();
+        for name in ["billing_job", generated.as_str()] {
+            assert!(
+                output
+                    .lines()
+                    .any(|line| line.contains(name) && line.contains("hello from the fork")),
+                "Expected output labeled with {}: {}",
+                name,
+                output
+            );
+        }
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_python_side_timeout() {
+        let python_script = r#"
+import time
+
+def main(clean_up=False):
+    try:
+        time.sleep(30)
+    except TimeoutError:
+        if not clean_up:
+            raise
+        return "cleaned up"
+    return "finished"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // The Rust-side timeout is only a backstop, well past the one raised in Python
+        let options = ExecOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..ExecOptions::default()
+        };
+        let process_uuid = runner
+            .exec_isolated_with_options(&pickled_data, "timed_out", &options)
+            .expect("Failed to execute script in isolation");
+        match runner.communicate_isolated_with_timeout(&process_uuid, Some(Duration::from_secs(10)))
+        {
+            Err(HotReloadError::ProcessTimedOut(error)) => {
+                assert!(error.contains("timed out after 0.2s"), "{}", error);
+                assert!(error.contains("ForkTimeoutError"), "{}", error);
+            }
+            other => panic!("Expected a Python-side timeout, got {:?}", other),
+        }
+
+        // The function can catch the error and still report a result
+        let clean_up = crate::pickle::serialized_call(
+            &format!("{}.script", python_env.module_name),
+            "main",
+            &[serde_json::json!(true)],
+        );
+        let process_uuid = runner
+            .exec_isolated_with_options(&clean_up, "cleaned_up", &options)
+            .expect("Failed to execute script in isolation");
+        assert_eq!(
+            runner
+                .communicate_isolated_with_timeout(&process_uuid, Some(Duration::from_secs(10)))
+                .unwrap()
+                .as_deref(),
+            Some("cleaned up")
+        );
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_concurrent_forks_with_large_results() {
+        let python_script = r#"
+import time
+
+def main(marker, start_at):
+    # Line the forks up so their results are written at the same time
+    time.sleep(max(0, start_at - time.time()))
+    print(marker * 100_000, flush=True)
+    return marker * 256 * 1024
+        "#;
+
+        let (_, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+        let markers = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+        for framing in [Framing::NewlineDelimited, Framing::LengthPrefixed] {
+            let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+                .preload_stdlib(true)
+                .framing(framing)
+                .build();
+            runner.boot_main().expect("Failed to boot main environment");
+
+            let start_at = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64()
+                + 1.0;
+            let process_uuids: Vec<String> = markers
+                .iter()
+                .map(|marker| {
+                    let call = crate::pickle::serialized_call(
+                        &format!("{}.script", python_env.module_name),
+                        "main",
+                        &[serde_json::json!(marker), serde_json::json!(start_at)],
+                    );
+                    runner
+                        .exec_isolated(&call, marker)
+                        .expect("Failed to execute script in isolation")
+                })
+                .collect();
+
+            for (marker, process_uuid) in markers.iter().zip(&process_uuids) {
+                let result = runner
+                    .communicate_isolated_with_timeout(process_uuid, Some(Duration::from_secs(30)))
+                    .expect("Failed to communicate with isolated process")
+                    .expect("Large result should arrive intact");
+                assert!(
+                    result == marker.repeat(256 * 1024),
+                    "{:?}: result of {} bytes was mangled",
+                    framing,
+                    result.len()
+                );
+
+                // Long output lines keep a single prefix and don't mix with other forks
+                let output: Vec<String> = runner
+                    .subscribe_output(process_uuid)
+                    .unwrap()
+                    .iter()
+                    .collect();
+                assert!(
+                    output == vec![marker.repeat(100_000)],
+                    "{:?}: output was mangled into {} lines",
+                    framing,
+                    output.len()
+                );
+            }
+
+            runner.stop_main().expect("Failed to stop main runner");
+        }
+    }
+
+    #[test]
+    fn test_message_length_is_separate_from_line_length() {
+        let python_script = r#"
+def main(size):
+    print("x" * 5000, flush=True)
+    return "r" * size
+        "#;
+
+        let (_, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        for framing in [Framing::NewlineDelimited, Framing::LengthPrefixed] {
+            let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+                .framing(framing)
+                .max_line_length(1024)
+                .max_message_length(16 * 1024)
+                .build();
+            runner.boot_main().expect("Failed to boot main environment");
+
+            let run = |size: usize| {
+                let call = crate::pickle::serialized_call(
+                    &format!("{}.script", python_env.module_name),
+                    "main",
+                    &[serde_json::json!(size)],
+                );
+                let process_uuid = runner
+                    .exec_isolated(&call, "main")
+                    .expect("Failed to execute script in isolation");
+                let result = runner.communicate_isolated_with_timeout(
+                    &process_uuid,
+                    Some(Duration::from_secs(30)),
+                );
+                (process_uuid, result)
+            };
+
+            // A result past the line limit still arrives whole, while output is cut down
+            let (process_uuid, result) = run(4 * 1024);
+            assert_eq!(result.unwrap(), Some("r".repeat(4 * 1024)), "{:?}", framing);
+            let output: Vec<String> = runner
+                .subscribe_output(&process_uuid)
+                .unwrap()
+                .iter()
+                .collect();
+            assert_eq!(output.len(), 1, "{:?}", framing);
+            assert!(output[0].len() <= 1024 && output[0].chars().all(|c| c == 'x'));
+
+            // A result past the message limit fails loudly instead of resolving as an exit
+            let (_, result) = run(32 * 1024);
+            match result {
+                Err(HotReloadError::ProcessFailed(message)) => {
+                    assert!(
+                        message.contains("exceeds the 16384 byte limit"),
+                        "{}",
+                        message
+                    )
+                }
+                other => panic!("{:?}: expected ProcessFailed, got {:?}", framing, other),
+            }
+
+            runner.stop_main().expect("Failed to stop main runner");
+        }
+    }
+
+    #[test]
+    fn test_spawn_execution_mode() {
+        let python_script = r#"
+import os
+import sys
+
+def main(value):
+    print("spawned", flush=True)
+    if value == "fail":
+        raise ValueError("spawned call failed")
+    # A fork would have inherited the loader's preloaded modules
+    return f"{value} {'wave' in sys.modules} {os.environ.get('SPAWN_ENV')}"
+        "#;
+
+        let (_, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+        std::fs::write(
+            Path::new(&python_env.container_path).join("preloaded.py"),
+            "import wave\n",
+        )
+        .unwrap();
+        let call = |value: &str| {
+            crate::pickle::serialized_call(
+                &format!("{}.script", python_env.module_name),
+                "main",
+                &[serde_json::json!(value)],
+            )
+        };
+
+        for framing in [Framing::NewlineDelimited, Framing::LengthPrefixed] {
+            let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+                .preload_stdlib(true)
+                .framing(framing)
+                .execution_mode(ExecutionMode::Spawn)
+                .build();
+            runner.boot_main().expect("Failed to boot main environment");
+            assert!(runner.loaded_modules().contains("wave"));
+
+            let options = ExecOptions {
+                env: HashMap::from([("SPAWN_ENV".to_string(), "set".to_string())]),
+                ..ExecOptions::default()
+            };
+            let process_uuid = runner
+                .exec_isolated_with_options(&call("hello"), "spawned", &options)
+                .expect("Failed to execute script in isolation");
+            let result = runner
+                .communicate_isolated(&process_uuid)
+                .expect("Failed to communicate with isolated process");
+            assert_eq!(result, Some("hello False set".to_string()));
+            assert!(runner
+                .captured_output(&process_uuid)
+                .unwrap()
+                .iter()
+                .any(|line| line == "spawned"));
+
+            // Errors are reported like a fork's
+            let process_uuid = runner
+                .exec_isolated(&call("fail"), "spawned")
+                .expect("Failed to execute script in isolation");
+            match runner.communicate_isolated(&process_uuid) {
+                Err(HotReloadError::ProcessFailed(error)) => {
+                    assert!(error.contains("spawned call failed"))
+                }
+                other => panic!("Expected the spawned call to fail, got {:?}", other),
+            }
+
+            runner.stop_main().expect("Failed to stop main runner");
+        }
+    }
+
+    #[test]
+    fn test_exec_batch_returns_results_in_order() {
+        let python_script = r#"
+import time
+
+def main(index):
+    # Later calls finish first, so completion order differs from input order
+    time.sleep(0.02 * (10 - index))
+    return f"call {index}"
+        "#;
+
+        let (_, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let calls: Vec<PreparedCall> = (0..10)
+            .map(|index| {
+                PreparedCall::new(
+                    crate::pickle::serialized_call(
+                        &format!("{}.script", python_env.module_name),
+                        "main",
+                        &[serde_json::json!(index)],
+                    ),
+                    format!("batch-{}", index),
+                )
+            })
+            .collect();
+        let results = runner.exec_batch(calls, 3);
+
+        let outputs: Vec<Option<String>> = results
+            .into_iter()
+            .map(|result| match result.unwrap() {
+                ProcessResult::Complete { result, .. } => result,
+                other => panic!("Expected a result, got {:?}", other),
+            })
+            .collect();
+        let expected: Vec<Option<String>> = (0..10)
+            .map(|index| Some(format!("call {}", index)))
+            .collect();
+        assert_eq!(outputs, expected);
+
+        // Finished forks aren't left tracked
+        assert!(runner.list_forked().unwrap().is_empty());
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_drop_kills_loader_and_forks() {
+        let python_script = r#"
+import time
+
+def main():
+    time.sleep(60)
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "sleeper")
+            .expect("Failed to execute script in isolation");
+
+        let (loader_pid, fork_pid) = {
+            let layer = runner.layer.as_ref().unwrap().lock().unwrap();
+            let fork_pid = layer.forked_processes.lock().unwrap()[&process_uuid];
+            (layer.child.id() as i32, fork_pid)
+        };
+        assert!(is_process_running(loader_pid));
+        assert!(is_process_running(fork_pid));
+
+        drop(runner);
+
+        // The fork is reparented once the loader dies, so give its new parent a moment to reap it
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while is_process_running(fork_pid) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!is_process_running(loader_pid));
+        assert!(!is_process_running(fork_pid));
+    }
+
+    #[test]
+    fn test_finish_isolated_returns_result_within_grace() {
+        let python_script = r#"
+import time
+
+def main(stubborn=False):
+    print("ready", flush=True)
+    while True:
+        try:
+            time.sleep(0.05)
+        except KeyboardInterrupt:
+            if not stubborn:
+                return "clean shutdown"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let wait_until_ready = |process_uuid: &str| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !runner
+                .captured_output(process_uuid)
+                .unwrap()
+                .iter()
+                .any(|line| line.contains("ready"))
+            {
+                assert!(Instant::now() < deadline, "Fork never started running");
+                thread::sleep(Duration::from_millis(20));
+            }
+        };
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "graceful")
+            .expect("Failed to execute script in isolation");
+        wait_until_ready(&process_uuid);
+        match runner
+            .finish_isolated(&process_uuid, Duration::from_secs(5))
+            .unwrap()
+        {
+            ProcessResult::Complete { result, .. } => {
+                assert_eq!(result.as_deref(), Some("clean shutdown"))
+            }
+            other => panic!("Expected the function's result, got {:?}", other),
+        }
+
+        // A function that keeps going past the grace period is killed
+        let stubborn = crate::pickle::serialized_call(
+            &format!("{}.script", python_env.module_name),
+            "main",
+            &[serde_json::json!(true)],
+        );
+        let process_uuid = runner
+            .exec_isolated(&stubborn, "stubborn")
+            .expect("Failed to execute script in isolation");
+        wait_until_ready(&process_uuid);
+        match runner
+            .finish_isolated(&process_uuid, Duration::from_millis(200))
+            .unwrap()
+        {
+            ProcessResult::Exited { signal, .. } => assert_eq!(signal, Some(libc::SIGKILL)),
+            other => panic!("Expected the fork to be killed, got {:?}", other),
+        }
+
+        // A loader that can't report the kill doesn't leave us waiting forever
+        let process_uuid = runner
+            .exec_isolated(&stubborn, "unreported")
+            .expect("Failed to execute script in isolation");
+        wait_until_ready(&process_uuid);
+        let loader_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id() as i32;
+        unsafe {
+            libc::kill(loader_pid, libc::SIGSTOP);
+        }
+        let finished = runner.finish_isolated(&process_uuid, Duration::from_millis(200));
+        unsafe {
+            libc::kill(loader_pid, libc::SIGCONT);
+        }
+        match finished {
+            Err(HotReloadError::Timeout { after, .. }) => assert_eq!(after, KILL_REPORT_TIMEOUT),
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_run_isolated() {
+        let python_script = r#"
+def main(should_fail=False):
+    if should_fail:
+        raise ValueError("asked to fail")
+    return "ran in one call"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        match runner
+            .run_isolated(&pickled_data, "one_shot", Some(Duration::from_secs(30)))
+            .expect("run_isolated failed")
+        {
+            ProcessResult::Complete { result, .. } => {
+                assert_eq!(result.as_deref(), Some("ran in one call"))
+            }
+            other => panic!("Expected a completed process, got {:?}", other),
+        }
+
+        let failing = crate::pickle::serialized_call(
+            &format!("{}.script", python_env.module_name),
+            "main",
+            &[serde_json::json!(true)],
+        );
+        match runner
+            .run_isolated(&failing, "one_shot_failure", None)
+            .expect("run_isolated failed")
+        {
+            ProcessResult::Error(error) => assert!(error.contains("asked to fail"), "{}", error),
+            other => panic!("Expected the function to fail, got {:?}", other),
+        }
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_captured_output_survives_error() {
+        let python_script = r#"
+def main():
+    print("step one")
+    print("step two")
+    raise RuntimeError("crashed after two steps")
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "crash_test")
+            .expect("Failed to execute script in isolation");
+        match runner.communicate_isolated(&process_uuid) {
+            Err(HotReloadError::ProcessFailed(error)) => {
+                assert!(error.contains("crashed after two steps"), "{}", error)
+            }
+            other => panic!("Expected the function to fail, got {:?}", other),
+        }
+        assert_eq!(
+            runner.captured_output(&process_uuid).unwrap(),
+            vec!["step one".to_string(), "step two".to_string()]
+        );
+
+        // Stopping the process releases its output
+        runner.stop_isolated(&process_uuid).unwrap();
+        assert!(matches!(
+            runner.captured_output(&process_uuid),
+            Err(HotReloadError::ProcessNotFound(_))
+        ));
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_cwd_defaults_to_project_path() {
+        let python_script = r#"
+def main():
+    with open("data.txt") as f:
+        return f.read().strip()
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+        std::fs::write(
+            std::path::Path::new(&python_env.container_path).join("data.txt"),
+            "from the project",
+        )
+        .unwrap();
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "cwd_test")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("communicate_isolated failed");
+        assert_eq!(result.as_deref(), Some("from the project"));
+        runner.stop_main().expect("Failed to stop main");
+
+        // An explicit cwd wins, and the pickled module still resolves through PYTHONPATH
+        let data_dir = TempDir::new().unwrap();
+        std::fs::write(data_dir.path().join("data.txt"), "from the data dir").unwrap();
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .cwd(data_dir.path())
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "cwd_test")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("communicate_isolated failed");
+        assert_eq!(result.as_deref(), Some("from the data dir"));
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[tokio::test]
+    async fn test_exec_isolated_async() {
+        let python_script = r#"
+def main():
+    return "from the fork"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        fn assert_send<T: Send>(future: T) -> T {
+            future
+        }
+        let process_uuid = assert_send(runner.exec_isolated_async(&pickled_data, "async_test"))
+            .await
+            .expect("Failed to execute script in isolation");
+        let result = assert_send(runner.await_result_async(&process_uuid))
+            .await
+            .expect("await_result_async failed");
+        assert_eq!(result.as_deref(), Some("from the fork"));
+
+        // A request dropped after it was sent, but before the loader forked, leaves no
+        // resolvers behind
+        {
+            let mut request = std::pin::pin!(runner.exec_isolated_async(&pickled_data, "dropped"));
+            std::future::poll_fn(|cx| {
+                assert!(std::future::Future::poll(request.as_mut(), cx).is_pending());
+                std::task::Poll::Ready(())
+            })
+            .await;
+        }
+        let layer = runner.layer.as_ref().unwrap().lock().unwrap();
+        assert_eq!(layer.fork_resolvers.lock().unwrap().len(), 1);
+        assert_eq!(layer.completion_resolvers.lock().unwrap().len(), 1);
+        drop(layer);
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_cancel_isolated() {
+        let python_script = r#"
+import time
+
+def main():
+    time.sleep(30)
+    return "finished"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "cancel_test")
+            .expect("Failed to execute script in isolation");
+        assert!(runner.cancel_isolated(&process_uuid).unwrap());
+
+        let results = runner
+            .wait_for_all(std::slice::from_ref(&process_uuid))
+            .unwrap();
+        assert!(
+            matches!(results[&process_uuid], ProcessResult::Cancelled),
+            "{:?}",
+            results[&process_uuid]
+        );
+        assert!(matches!(
+            runner.communicate_isolated(&process_uuid),
+            Err(HotReloadError::ProcessCancelled(_))
+        ));
+
+        // Already settled, so there's nothing left to cancel
+        assert!(!runner.cancel_isolated(&process_uuid).unwrap());
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_code_of_fork_without_result() {
+        let python_script = r#"
+import os
+
+def main():
+    os._exit(3)
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "exit_test")
+            .expect("Failed to execute script in isolation");
+        let result = runner.communicate_isolated_with_timeout(
+            &process_uuid,
+            Some(std::time::Duration::from_secs(10)),
+        );
+        match result {
+            Err(HotReloadError::ProcessExited { exit_code, signal }) => {
+                assert_eq!(exit_code, Some(3));
+                assert_eq!(signal, None);
+            }
+            other => panic!("Expected the fork's exit code, got {:?}", other),
+        }
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_resource_totals_across_forks() {
+        let python_script = r#"
+def main():
+    # Allocate and touch some memory, and burn a little CPU doing it
+    data = bytearray(16 * 1024 * 1024)
+    total = 0
+    for i in range(0, len(data), 4096):
+        data[i] = 1
+        total += i
+    return str(total)
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        assert_eq!(runner.resource_totals(), ResourceTotals::default());
+
+        for name in ["allocating-1", "allocating-2"] {
+            let process_uuid = runner
+                .exec_isolated(&pickled_data, name)
+                .expect("Failed to execute script in isolation");
+            runner
+                .communicate_isolated(&process_uuid)
+                .expect("Failed to communicate with isolated process");
+        }
+
+        let totals = runner.resource_totals();
+        assert_eq!(totals.total_forks, 2);
+        assert!(
+            totals.cumulative_cpu_time > std::time::Duration::ZERO,
+            "Expected positive CPU time, got {:?}",
+            totals.cumulative_cpu_time
+        );
+        assert!(totals.max_fork_rss_bytes > 0);
+
+        runner.stop_main().expect("Failed to stop main process");
+    }
+
+    #[test]
+    fn test_list_forked_and_stop_all() {
+        let python_script = r#"
+import time
+
+def main():
+    time.sleep(30)
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        assert!(runner.list_forked().unwrap().is_empty());
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let mut process_uuids: Vec<String> = ["sleeper-1", "sleeper-2"]
+            .iter()
+            .map(|name| {
+                runner
+                    .exec_isolated(&pickled_data, name)
+                    .expect("Failed to execute script in isolation")
+            })
+            .collect();
+        process_uuids.sort();
+
+        let forked = runner.list_forked().unwrap();
+        assert_eq!(
+            forked
+                .iter()
+                .map(|(uuid, _)| uuid.clone())
+                .collect::<Vec<_>>(),
+            process_uuids
+        );
+        assert!(forked.iter().all(|(_, pid)| *pid > 0));
+
+        assert_eq!(runner.stop_all_isolated().unwrap(), 2);
+        assert!(runner.list_forked().unwrap().is_empty());
+
+        runner.stop_main().expect("Failed to stop main process");
+    }
+
+    #[test]
+    fn test_metrics_count_forks() {
+        let python_script = r#"
+def main():
+    return "ok"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let metrics = runner.metrics_snapshot();
+        assert_eq!(metrics.forks_started, 0);
+        assert_eq!(metrics.boots, 1);
+        assert!(metrics.last_boot_duration.is_some());
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "metrics_test")
+            .expect("Failed to execute script in isolation");
+        assert_eq!(runner.metrics_snapshot().forks_started, 1);
+
+        runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process");
+        let metrics = runner.metrics_snapshot();
+        assert_eq!(metrics.forks_completed, 1);
+        assert_eq!(metrics.forks_errored, 0);
+
+        runner.stop_main().expect("Failed to stop main process");
+    }
+
+    #[test]
+    fn test_stop_isolated() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().to_str().unwrap();
+
+        let mut runner = Environment::new("test_package", dir_path, None);
+
+        // Boot the environment before accessing it
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Create a test process UUID
+        let env = runner.layer.as_ref().unwrap();
+        let env_guard = env.lock().unwrap();
+
+        // Use a fixed UUID for testing
+        let test_uuid = Uuid::new_v4().to_string();
+        let test_pid = 23456;
+
+        // Add mock process to the forked_processes map
+        let mut forked_processes = env_guard.forked_processes.lock().unwrap();
+        forked_processes.insert(test_uuid.clone(), test_pid);
+        drop(forked_processes);
+
+        // Create the required resolvers
+        let fork_resolver = AsyncResolve::new();
+        let mut fork_resolvers = env_guard.fork_resolvers.lock().unwrap();
+        fork_resolvers.insert(test_uuid.clone(), fork_resolver.clone());
+        drop(fork_resolvers);
+
+        let completion_resolver = AsyncResolve::new();
+        let mut completion_resolvers = env_guard.completion_resolvers.lock().unwrap();
+        completion_resolvers.insert(test_uuid.clone(), completion_resolver.clone());
+        drop(completion_resolvers);
+
+        // Drop the guard so we can call stop_isolated
+        drop(env_guard);
+
+        // Verify the process is in the forked_processes map
+        {
+            let env_guard = runner.layer.as_ref().unwrap().lock().unwrap();
+
+            let forked_processes = env_guard.forked_processes.lock().unwrap();
+            assert!(
+                forked_processes.contains_key(&test_uuid),
+                "Process UUID should be in the forked_processes map"
+            );
+
+            let pid = *forked_processes.get(&test_uuid).unwrap();
+            println!("Process PID: {}", pid);
+            drop(forked_processes);
+        }
+
+        // Now stop the process
+        let stop_result = runner.stop_isolated(&test_uuid);
+        assert!(
+            stop_result.is_ok(),
+            "Failed to stop process: {:?}",
+            stop_result.err()
+        );
+        assert!(
+            stop_result.unwrap(),
+            "stop_isolated should return true for successful termination"
+        );
+
+        // Verify the process is no longer in the forked_processes map
+        {
+            let env_guard = runner.layer.as_ref().unwrap().lock().unwrap();
+
+            let forked_processes = env_guard.forked_processes.lock().unwrap();
+            assert!(
+                !forked_processes.contains_key(&test_uuid),
+                "Process UUID should be removed from the forked_processes map after termination"
+            );
+            drop(forked_processes);
+        }
+
+        // Try to communicate with the terminated process
+        // It should fail since the process is no longer available
+        let communicate_result = runner.communicate_isolated(&test_uuid);
+        assert!(
+            communicate_result.is_err(),
+            "communicate_isolated should fail for a non-existent process"
+        );
+    }
+
+    #[test]
+    fn test_concurrent_forks_map_to_their_own_pid() {
+        let python_script = r#"
+import os
+
+def main():
+    return str(os.getpid())
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Fire both fork requests at once so their responses interleave on the same pipe
+        let runner_ref = &runner;
+        let pickled_ref = &pickled_data;
+        let (first_uuid, second_uuid) = std::thread::scope(|scope| {
+            let first = scope.spawn(move || runner_ref.exec_isolated(pickled_ref, "first"));
+            let second = scope.spawn(move || runner_ref.exec_isolated(pickled_ref, "second"));
+            (
+                first.join().unwrap().expect("Failed to execute first fork"),
+                second
+                    .join()
+                    .unwrap()
+                    .expect("Failed to execute second fork"),
+            )
+        });
+        assert_ne!(first_uuid, second_uuid);
+
+        let pids: HashMap<String, i32> = {
+            let layer = runner.layer.as_ref().unwrap().lock().unwrap();
+            let forked_processes = layer.forked_processes.lock().unwrap();
+            [&first_uuid, &second_uuid]
+                .iter()
+                .map(|uuid| ((*uuid).clone(), forked_processes[*uuid]))
+                .collect()
+        };
+        assert_ne!(pids[&first_uuid], pids[&second_uuid]);
+
+        // Each fork reports its own PID, which must match the PID recorded for its UUID
+        for uuid in [&first_uuid, &second_uuid] {
+            let result = runner
+                .communicate_isolated(uuid)
+                .expect("Failed to communicate with isolated process")
+                .expect("No result received from isolated process");
+            assert_eq!(result.parse::<i32>().unwrap(), pids[uuid]);
+        }
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_pickled_data_is_not_interpolated() {
+        let python_script = r#"
+def main():
+    return "payload intact"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        // base64 decoding skips characters outside its alphabet, so these leave the payload
+        // intact. Spliced into a Python string literal they would have ended it early.
+        let hostile_payload = format!("\"\\\n'''{}\"');\\", pickled_data);
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&hostile_payload, "hostile_payload")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process");
+        assert_eq!(result, Some("payload intact".to_string()));
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_communicate_isolated_timeout() {
+        let python_script = r#"
+import time
+
+def main():
+    time.sleep(30)
+    return "should not finish"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "sleeper")
+            .expect("Failed to execute script in isolation");
+
+        let start = Instant::now();
+        let err = runner
+            .communicate_isolated_with_timeout(&process_uuid, Some(Duration::from_millis(500)))
+            .unwrap_err();
+        assert!(matches!(err, HotReloadError::Timeout { .. }), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // The process is still tracked and can be stopped normally
+        assert!(runner
+            .stop_isolated(&process_uuid)
+            .expect("Failed to stop timed out process"));
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_wait_or_kill_escalates_only_after_grace() {
+        let grace = Duration::from_millis(500);
+
+        // A process that ignores SIGTERM survives until the grace period runs out
+        let mut stubborn = std::process::Command::new("sh")
+            .args(["-c", "trap '' TERM; while true; do sleep 0.05; done"])
+            .spawn()
+            .expect("Failed to spawn stubborn process");
+        thread::sleep(Duration::from_millis(100));
+        unsafe {
+            libc::kill(stubborn.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let start = Instant::now();
+        assert!(wait_or_kill(&mut stubborn, start + grace).unwrap());
+        assert!(start.elapsed() >= grace);
+
+        // A process that exits on its own is never killed
+        let mut compliant = std::process::Command::new("sh")
+            .args(["-c", "sleep 0.1"])
+            .spawn()
+            .expect("Failed to spawn compliant process");
+        let start = Instant::now();
+        assert!(!wait_or_kill(&mut compliant, start + grace).unwrap());
+        assert!(start.elapsed() < grace);
+    }
+
+    #[test]
+    fn test_stop_main_lets_loader_exit_on_request() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("main.py"), "import json\n").unwrap();
+
+        let mut runner =
+            Environment::new_for_test("test_package", temp_dir.path().to_str().unwrap(), None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let layer = Arc::clone(runner.layer.as_ref().unwrap());
+        runner
+            .stop_main_with_grace(Duration::from_secs(5))
+            .expect("Failed to stop main runner");
+
+        // The loader acted on the ExitRequest instead of being killed
+        let status = layer.lock().unwrap().child.try_wait().unwrap();
+        assert_eq!(status.and_then(|status| status.code()), Some(0));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_finished_forks_are_reaped() {
+        let python_script = r#"
+def main():
+    return "done"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "quick_exit")
+            .expect("Failed to execute script in isolation");
+        let layer = Arc::clone(runner.layer.as_ref().unwrap());
+        let pid = layer.lock().unwrap().forked_processes.lock().unwrap()[&process_uuid];
+        assert_eq!(
+            runner.communicate_isolated(&process_uuid).unwrap(),
+            Some("done".to_string())
+        );
+
+        // The loader waits on the fork once it exits and tells us about it
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !layer.lock().unwrap().has_exited(pid) {
+            assert!(Instant::now() < deadline, "Fork {} was never reaped", pid);
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(
+            layer.lock().unwrap().exited_processes.lock().unwrap()[&pid].exit_code,
+            Some(0)
+        );
+
+        // No zombie is left behind, and stopping the reaped fork doesn't signal its old PID
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        assert!(runner.stop_isolated(&process_uuid).unwrap());
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_subscribe_output_streams_lines_in_order() {
+        let python_script = r#"
+import time
+
+def main():
+    print("first line", flush=True)
+    for i in range(2, 5):
+        time.sleep(0.05)
+        print(f"line {i}", flush=True)
+    return "done"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "printer")
+            .expect("Failed to execute script in isolation");
+        let output = runner
+            .subscribe_output(&process_uuid)
+            .expect("Failed to subscribe to output");
+
+        // Collects until the channel closes when the process completes
+        let mut lines = Vec::new();
+        while let Ok(line) = output.recv_timeout(Duration::from_secs(10)) {
+            lines.push(line);
+        }
+        assert_eq!(lines, vec!["first line", "line 2", "line 3", "line 4"]);
+        assert_eq!(
+            runner.communicate_isolated(&process_uuid).unwrap(),
+            Some("done".to_string())
+        );
+
+        assert!(matches!(
+            runner.subscribe_output("unknown-uuid"),
+            Err(HotReloadError::ProcessNotFound(_))
+        ));
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_is_alive_detects_killed_loader() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("main.py"), "import json\n").unwrap();
+
+        let mut runner =
+            Environment::new_for_test("test_package", temp_dir.path().to_str().unwrap(), None);
+        assert!(!runner.is_alive());
+
+        runner.boot_main().expect("Failed to boot main environment");
+        assert!(runner.is_alive());
+        assert!(runner.is_healthy());
+
+        // Kill the loader behind the environment's back
+        let loader_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+        unsafe {
+            libc::kill(loader_pid as libc::pid_t, libc::SIGKILL);
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while runner.is_alive_with_timeout(Duration::from_millis(200)) {
+            assert!(Instant::now() < deadline, "Loader still reported alive");
+        }
+        assert!(!runner.is_healthy());
+
+        // The next update replaces the dead loader even though no imports changed
+        assert!(runner.update_environment().unwrap());
+        assert!(runner.is_alive());
+        assert!(runner.is_healthy());
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_try_new_booted() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import os");
+        let project_path = temp_dir.path().to_str().unwrap();
+
+        let runner = Environment::try_new_booted("test_package", project_path)
+            .expect("Failed to boot environment");
+        assert!(runner.is_alive());
+        runner.stop_main().expect("Failed to stop main runner");
+
+        // A bogus interpreter is reported by name rather than as a raw spawn error
+        let err = EnvironmentBuilder::new("test_package", project_path)
+            .interpreter("/nonexistent/bin/python3")
+            .build_booted()
+            .err()
+            .expect("Booting with a missing interpreter should fail");
+        assert!(matches!(err, HotReloadError::InterpreterNotFound(_)));
+        assert_eq!(
+            err.to_string(),
+            "Python interpreter /nonexistent/bin/python3 does not exist"
+        );
+    }
+
+    #[test]
+    fn test_update_environment_after_fresh_boot() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import os");
+
+        let mut runner =
+            Environment::new_for_test("test_package", temp_dir.path().to_str().unwrap(), None);
+
+        // Nothing to compare against before the first boot
+        assert!(!runner.update_environment().unwrap());
+
+        runner.boot_main().expect("Failed to boot main environment");
+        assert!(!runner.update_environment().unwrap());
+
+        create_temp_py_file(&temp_dir, "main.py", "import os\nimport json");
+        assert!(runner.update_environment().unwrap());
+        assert!(!runner.update_environment().unwrap());
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_reload_summary_names_changed_modules() {
+        // The summary follows the log level, and nothing is printed without a logger
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Warn)
+            .is_test(true)
+            .try_init();
+
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import json\nimport csv");
+
+        let mut runner =
+            Environment::new_for_test("test_package", temp_dir.path().to_str().unwrap(), None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        create_temp_py_file(&temp_dir, "main.py", "import json\nimport uuid");
+        assert!(runner.update_environment().unwrap());
+
+        let output = runner.get_layer_output().unwrap_or_default();
+        let summary = output
+            .lines()
+            .find(|line| line.contains("Reloaded:"))
+            .unwrap_or_else(|| panic!("Expected a reload summary in: {}", output));
+        assert!(summary.contains("+uuid"), "{}", summary);
+        assert!(summary.contains("-csv"), "{}", summary);
+        assert!(!summary.contains("json"), "{}", summary);
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_import_hash_skips_rebuilds() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import os");
+
+        let mut runner =
+            Environment::new_for_test("test_package", temp_dir.path().to_str().unwrap(), None);
+        assert_eq!(runner.import_hash(), None);
+
+        runner.boot_main().expect("Failed to boot main environment");
+        let booted_hash = runner.import_hash().unwrap();
+        let loader_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+
+        // Rewriting a file with different content but the same imports doesn't rebuild
+        create_temp_py_file(&temp_dir, "main.py", "import os\n\nprint('touched')");
+        assert!(!runner.update_environment().unwrap());
+
+        // Another scan moves the delta baseline past the booted imports. Reverting then shows
+        // up as a delta, but the loader already has exactly these imports.
+        create_temp_py_file(&temp_dir, "main.py", "import os\nimport json");
+        runner.preload_modules().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import os");
+        assert!(!runner.update_environment().unwrap());
+
+        assert_eq!(runner.import_hash().unwrap(), booted_hash);
+        assert_eq!(
+            runner.layer.as_ref().unwrap().lock().unwrap().child.id(),
+            loader_pid
+        );
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_drain_reload_lets_running_forks_finish() {
+        // The first call is slow, later calls return right away
+        let python_script = r#"
+import os
+import time
+
+def main():
+    marker = os.path.join(os.path.dirname(__file__), "first_call_done")
+    if os.path.exists(marker):
+        return "quick-result"
+    open(marker, "w").close()
+    time.sleep(5)
+    return "slow-result"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+        let project_dir = PathBuf::from(&python_env.container_path);
+        std::fs::write(project_dir.join("main.py"), "import wave\n").unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .preload_stdlib(true)
+            .reload_mode(ReloadMode::Drain)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+        let old_loader_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+
+        let slow_uuid = runner
+            .exec_isolated(&pickled_data, "slow")
+            .expect("Failed to start slow fork");
+        let marker = project_dir.join("first_call_done");
+        for _ in 0..100 {
+            if marker.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // The reload doesn't wait for the slow fork, and leaves it running on the old loader
+        std::fs::write(
+            project_dir.join("main.py"),
+            "import wave\nimport colorsys\n",
+        )
+        .unwrap();
+        assert!(runner.update_environment().unwrap());
+        assert_eq!(runner.draining_layers.lock().unwrap().len(), 1);
+        let new_loader_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
+        assert_ne!(old_loader_pid, new_loader_pid);
+        assert!(is_process_running(old_loader_pid as i32));
+
+        // New work goes to the new loader
+        let quick_uuid = runner
+            .exec_isolated(&pickled_data, "quick")
+            .expect("Failed to start quick fork");
+        assert_eq!(
+            runner.communicate_isolated(&quick_uuid).unwrap(),
+            Some("quick-result".to_string())
+        );
+
+        assert_eq!(
+            runner.communicate_isolated(&slow_uuid).unwrap(),
+            Some("slow-result".to_string())
+        );
+
+        // With its last fork done, the old loader is retired in the background, without
+        // another call to drive it, but the result stays available
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !runner.draining_layers.lock().unwrap().is_empty()
+            || is_process_running(old_loader_pid as i32)
+        {
+            assert!(
+                Instant::now() < deadline,
+                "Drained loader should have exited"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(
+            runner.communicate_isolated(&slow_uuid).unwrap(),
+            Some("slow-result".to_string())
+        );
+        assert!(runner.stop_isolated(&slow_uuid).unwrap());
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_on_reload_receives_import_delta() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(&temp_dir, "main.py", "import os\nimport wave");
+
+        let mut runner =
+            Environment::new_for_test("test_package", temp_dir.path().to_str().unwrap(), None);
+        let deltas = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&deltas);
+        runner.on_reload(move |delta| recorded.lock().unwrap().push(delta.clone()));
+
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Nothing changed, so nothing is reported
+        assert!(!runner.update_environment().unwrap());
+        assert!(deltas.lock().unwrap().is_empty());
+
+        create_temp_py_file(&temp_dir, "main.py", "import os\nimport json");
+        assert!(runner.update_environment().unwrap());
+
+        assert_eq!(
+            *deltas.lock().unwrap(),
+            vec![ImportDelta {
+                environment_id: runner.id.clone(),
+                added: HashSet::from(["json".to_string()]),
+                removed: HashSet::from(["wave".to_string()]),
+            }]
+        );
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_auto_restart_after_loader_dies() {
+        let python_script = r#"
+import os
+import time
+
+def main():
+    if os.environ.get("LINGER"):
+        time.sleep(30)
+    return "restarted"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .auto_restart(true)
+            .build();
+        let restarts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let restarts_seen = Arc::clone(&restarts);
+        runner.on_restart(move || {
+            restarts_seen.fetch_add(1, Ordering::SeqCst);
+        });
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // A fork that outlives its loader
+        let lingering_uuid = runner
+            .exec_isolated_with_options(
+                &pickled_data,
+                "lingering",
+                &ExecOptions {
+                    env: HashMap::from([("LINGER".to_string(), "1".to_string())]),
+                    ..ExecOptions::default()
+                },
+            )
+            .expect("Failed to execute lingering script");
+        let layer = Arc::clone(runner.layer.as_ref().unwrap());
+        let lingering_pid = layer.lock().unwrap().forked_processes.lock().unwrap()[&lingering_uuid];
+
+        // Kill the loader behind the environment's back and wait until it's gone
+        let old_pid = layer.lock().unwrap().child.id();
+        unsafe {
+            libc::kill(old_pid as libc::pid_t, libc::SIGKILL);
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while layer.lock().unwrap().child.try_wait().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "Loader did not exit");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // The next execution transparently boots a replacement loader
+        let restart_started = Instant::now();
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "after_restart")
+            .expect("Failed to execute script after the loader died");
+        assert_eq!(
+            runner.communicate_isolated(&process_uuid).unwrap(),
+            Some("restarted".to_string())
+        );
+        assert_eq!(restarts.load(Ordering::SeqCst), 1);
+        assert_ne!(layer.lock().unwrap().child.id(), old_pid);
+
+        // The restart stopped the old loader's fork instead of orphaning it, which would also
+        // leave the old monitor threads waiting on its output
+        assert!(restart_started.elapsed() < Duration::from_secs(10));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while unsafe { libc::kill(lingering_pid, 0) } == 0 {
+            assert!(
+                Instant::now() < deadline,
+                "Fork of the dead loader is still running"
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // A loader we stopped ourselves isn't brought back
+        runner.stop_main().expect("Failed to stop main runner");
+        assert!(runner.exec_isolated(&pickled_data, "after_stop").is_err());
+        assert_eq!(restarts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_non_utf8_output_does_not_stop_results() {
+        let python_script = r#"
+import os
+import sys
+
+def main():
+    sys.stdout.flush()
+    os.write(1, b"before \xff\xfe after\n")
+    print("still here", flush=True)
+    return "delivered"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Later results, from the same fork and from the next one, still arrive
+        for name in ["first", "second"] {
+            let process_uuid = runner
+                .exec_isolated(&pickled_data, name)
+                .expect("Failed to execute script in isolation");
+            assert_eq!(
+                runner
+                    .communicate_isolated_with_timeout(&process_uuid, Some(Duration::from_secs(10)))
+                    .unwrap(),
+                Some("delivered".to_string())
+            );
+            let output: Vec<String> = runner
+                .subscribe_output(&process_uuid)
+                .unwrap()
+                .iter()
+                .collect();
+            assert!(
+                output.iter().any(|line| line == "still here"),
+                "{:?}",
+                output
+            );
+        }
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_sibling_packages_are_not_preloaded() {
+        let temp_dir = TempDir::new().unwrap();
+        create_temp_py_file(
+            &temp_dir,
+            "pyproject.toml",
+            "[project]\nname = \"monorepo\"\n",
+        );
+        std::fs::create_dir_all(temp_dir.path().join("pkg_a")).unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("pkg_b")).unwrap();
+        create_temp_py_file(&temp_dir, "pkg_a/__init__.py", "");
+        create_temp_py_file(
+            &temp_dir,
+            "pkg_a/core.py",
+            "from pkg_b.helpers import helper\nimport wave",
+        );
+        create_temp_py_file(&temp_dir, "pkg_b/__init__.py", "");
+        create_temp_py_file(&temp_dir, "pkg_b/helpers.py", "import pkg_a.core");
+
+        let mut runner =
+            Environment::new_for_test("monorepo", temp_dir.path().to_str().unwrap(), None);
+        let modules = runner.preload_modules().unwrap();
+        assert!(modules.contains("wave"), "{:?}", modules);
+        assert!(
+            !modules
+                .iter()
+                .any(|module| module.starts_with("pkg_a") || module.starts_with("pkg_b")),
+            "{:?}",
+            modules
+        );
+    }
+
+    #[test]
+    fn test_auto_restart_does_not_block_other_callers() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("slow_dependency.py"),
+            "import time\ntime.sleep(2)\n\ndef value():\n    return 'restarted'\n",
+        )
+        .unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .extra_preload(["slow_dependency"])
+            .auto_restart(true)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let layer = Arc::clone(runner.layer.as_ref().unwrap());
+        let old_pid = layer.lock().unwrap().child.id();
+        unsafe {
+            libc::kill(old_pid as libc::pid_t, libc::SIGKILL);
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while layer.lock().unwrap().child.try_wait().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "Loader did not exit");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // The replacement takes a couple of seconds to import, which other calls don't wait on
+        let call = crate::pickle::serialized_call("slow_dependency", "value", &[]);
+        let result = thread::scope(|scope| {
+            let fork = scope.spawn(|| {
+                let process_uuid = runner.exec_isolated(&call, "after_restart")?;
+                runner.communicate_isolated(&process_uuid)
+            });
+            thread::sleep(Duration::from_millis(300));
+            let start = Instant::now();
+            runner.list_forked().unwrap();
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "Blocked on the restart for {:?}",
+                start.elapsed()
+            );
+            fork.join().unwrap()
+        });
+        assert_eq!(result.unwrap(), Some("restarted".to_string()));
+        assert_ne!(layer.lock().unwrap().child.id(), old_pid);
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_shutdown_reports_terminated_forks() {
+        let python_script = r#"
+import time
+
+def main():
+    time.sleep(30)
+    return "should not finish"
+        "#;
+
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let mut process_uuids = vec![
+            runner
+                .exec_isolated(&pickled_data, "first_sleeper")
+                .expect("Failed to execute first script"),
+            runner
+                .exec_isolated(&pickled_data, "second_sleeper")
+                .expect("Failed to execute second script"),
+        ];
+
+        let pids: Vec<i32> = {
+            let layer = runner.layer.as_ref().unwrap().lock().unwrap();
+            let forked_processes = layer.forked_processes.lock().unwrap();
+            process_uuids
+                .iter()
+                .map(|uuid| forked_processes[uuid])
+                .collect()
+        };
+
+        let mut report = runner.shutdown().expect("Failed to shut down");
+
+        process_uuids.sort();
+        report.terminated.sort();
+        assert_eq!(report.terminated, process_uuids);
+        assert!(report.killed.is_empty(), "No fork should need SIGKILL");
+        assert!(report.loader_stopped);
+
+        // Nothing is left running or tracked
+        assert!(runner.layer.is_none());
+        for pid in pids {
+            assert!(!is_process_running(pid), "PID {} is still running", pid);
+        }
+
+        // Shutting down twice is a no-op
+        assert_eq!(runner.shutdown().unwrap(), ShutdownReport::default());
+    }
+
+    #[test]
+    fn test_stop_main() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().to_str().unwrap();
+
+        let mut runner = Environment::new("test_package", dir_path, None);
+
+        // Boot the environment before stopping it
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // This should stop the main Python process
+        let result = runner.stop_main();
+        assert!(result.is_ok());
+        assert!(
+            result.unwrap(),
+            "stop_main should return true after successful execution"
+        );
+    }
+
+    #[test]
+    fn test_communicate_isolated_bytes() {
+        let python_script = r#"
+def large():
+    return bytes(range(256)) * (4 * 4096)
+
+def small():
+    return bytearray(b"\x00\xff\nbinary\r\n")
+
+def text():
+    return "not bytes"
+        "#;
+
+        let prepare = |func_name: &str| {
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, func_name)
+                .expect("Failed to prepare script for isolation")
+        };
+        let (large_data, python_env) = prepare("large");
+        let (small_data, _small_env) = prepare("small");
+        let (text_data, _text_env) = prepare("text");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let exec = |pickled_data: &str| {
+            runner
+                .exec_isolated(pickled_data, "binary_result")
+                .expect("Failed to execute script in isolation")
+        };
+
+        // Well past the message size limit, so this one goes through a file
+        let large_uuid = exec(&large_data);
+        let large = runner.communicate_isolated_bytes(&large_uuid).unwrap();
+        let expected: Vec<u8> = (0..4 * 4096).flat_map(|_| 0..=255u8).collect();
+        assert_eq!(large.len(), 4 * 1024 * 1024);
+        assert!(large == expected, "Large binary result was corrupted");
+
+        // Too large to send as a string as well, which is an error rather than a silent None
+        match runner.communicate_isolated(&large_uuid) {
+            Err(HotReloadError::InvalidResult { reason, .. }) => {
+                assert!(reason.contains("communicate_isolated_bytes"), "{}", reason)
+            }
+            other => panic!("Expected an invalid result, got {:?}", other),
+        }
+
+        let small = runner
+            .communicate_isolated_bytes(&exec(&small_data))
+            .unwrap();
+        assert_eq!(small, b"\x00\xff\nbinary\r\n");
+
+        match runner.communicate_isolated_bytes(&exec(&text_data)) {
+            Err(HotReloadError::InvalidResult { reason, .. }) => {
+                assert!(reason.contains("not bytes"), "{}", reason)
+            }
+            other => panic!("Expected an invalid result, got {:?}", other),
+        }
+
+        runner.stop_main().expect("Failed to stop main");
+    }
+
+    #[test]
+    fn test_communicate_isolated_as_typed_values() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Point {
+            x: i64,
+            y: i64,
+            label: String,
+        }
+
+        let python_script = r#"
+def integer():
+    return 42
+
+def point():
+    return {"x": 3, "y": -4, "label": "origin offset"}
+
+def names():
+    return ["alpha", "beta"]
+
+def not_serializable():
+    return object()
+        "#;
+
+        let prepare = |func_name: &str| {
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, func_name)
+                .expect("Failed to prepare script for isolation")
+        };
+        let (integer_data, python_env) = prepare("integer");
+        let (point_data, _point_env) = prepare("point");
+        let (names_data, _names_env) = prepare("names");
+        let (opaque_data, _opaque_env) = prepare("not_serializable");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let exec = |pickled_data: &str| {
+            runner
+                .exec_isolated(pickled_data, "typed_result")
+                .expect("Failed to execute script in isolation")
+        };
+
+        let integer: i64 = runner
+            .communicate_isolated_as(&exec(&integer_data))
+            .unwrap();
+        assert_eq!(integer, 42);
+
+        let point: Point = runner.communicate_isolated_as(&exec(&point_data)).unwrap();
+        assert_eq!(
+            point,
+            Point {
+                x: 3,
+                y: -4,
+                label: "origin offset".to_string()
+            }
         );
 
-        // Get the PID of the new Python process
-        let new_pid = runner.layer.as_ref().unwrap().lock().unwrap().child.id();
-        println!("New process PID after import changes: {:?}", new_pid);
+        let names: Vec<String> = runner.communicate_isolated_as(&exec(&names_data)).unwrap();
+        assert_eq!(names, vec!["alpha".to_string(), "beta".to_string()]);
+
+        // A value of the wrong shape and a value JSON can't encode are both typed errors
+        let mismatched = runner.communicate_isolated_as::<Vec<String>>(&exec(&integer_data));
+        assert!(matches!(
+            mismatched,
+            Err(HotReloadError::InvalidResult { .. })
+        ));
+        let opaque = runner.communicate_isolated_as::<i64>(&exec(&opaque_data));
+        assert!(matches!(opaque, Err(HotReloadError::InvalidResult { .. })));
+
+        runner.stop_main().expect("Failed to stop main runner");
     }
 
     #[test]
-    fn test_exec_communicate_isolated_basic() {
-        // Create a simple Python script that returns a timestamp
+    fn test_wait_for_all_collects_every_outcome() {
         let python_script = r#"
 import time
 
+def quick():
+    return "quick result"
+
+def fails():
+    raise RuntimeError("expected failure")
+
+def slow():
+    time.sleep(1)
+    return "slow result"
+        "#;
+
+        let prepare = |func_name: &str| {
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, func_name)
+                .expect("Failed to prepare script for isolation")
+        };
+        let (quick_data, python_env) = prepare("quick");
+        let (fails_data, _fails_env) = prepare("fails");
+        let (slow_data, _slow_env) = prepare("slow");
+
+        let mut runner =
+            Environment::new_for_test("test_package", &python_env.container_path, None);
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // The slow fork goes first, so the failing one has errored before we get to it
+        let uuids: Vec<String> = [
+            (&slow_data, "slow"),
+            (&fails_data, "fails"),
+            (&quick_data, "quick"),
+        ]
+        .iter()
+        .map(|(pickled_data, name)| {
+            runner
+                .exec_isolated(pickled_data, name)
+                .expect("Failed to execute script in isolation")
+        })
+        .collect();
+
+        let results = runner
+            .wait_for_all(&uuids)
+            .expect("Failed to wait for forks");
+        assert_eq!(results.len(), 3);
+
+        match &results[&uuids[0]] {
+            ProcessResult::Complete { result, .. } => {
+                assert_eq!(result.as_deref(), Some("slow result"))
+            }
+            other => panic!("Unexpected result for slow fork: {:?}", other),
+        }
+        match &results[&uuids[1]] {
+            ProcessResult::Error(error) => assert!(error.contains("expected failure"), "{}", error),
+            other => panic!("Unexpected result for failing fork: {:?}", other),
+        }
+        match &results[&uuids[2]] {
+            ProcessResult::Complete { result, .. } => {
+                assert_eq!(result.as_deref(), Some("quick result"))
+            }
+            other => panic!("Unexpected result for quick fork: {:?}", other),
+        }
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_preload_overrides() {
+        let python_script = r#"
+import sys
+
 def main():
-    # Return the current timestamp as a string
-    return str(time.time())
+    return ",".join(
+        f"{name}={name in sys.modules}" for name in ("wave", "colorsys")
+    )
         "#;
 
-        // Prepare the script for isolation
         let (pickled_data, python_env) =
             crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
                 .expect("Failed to prepare script for isolation");
 
-        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        // Imported by the project, but never executed by the function we run
+        std::fs::write(
+            PathBuf::from(&python_env.container_path).join("unused_helper.py"),
+            "import colorsys\n",
+        )
+        .unwrap();
 
-        // Boot the environment before accessing it
-        runner.boot_main().expect("Failed to boot main environment");
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .extra_preload(["wave"])
+            .exclude_preload(["colorsys"])
+            .build();
 
-        // Execute the script in isolation
+        let modules = runner.preload_modules().unwrap();
+        assert!(modules.contains("wave"));
+        assert!(!modules.contains("colorsys"));
+
+        // Forks inherit the loader's sys.modules, so this shows what the loader imported
+        runner.boot_main().expect("Failed to boot main environment");
         let process_uuid = runner
-            .exec_isolated(&pickled_data, "timestamp_test")
+            .exec_isolated(&pickled_data, "preload_overrides")
             .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process");
+        assert_eq!(result, Some("wave=True,colorsys=False".to_string()));
 
-        // Wait a short time for the process to execute
-        std::thread::sleep(std::time::Duration::from_millis(100));
+        runner.stop_main().expect("Failed to stop main runner");
+    }
 
-        // Now call communicate_isolated to get the result
-        let communicate_result = runner.communicate_isolated(&process_uuid);
+    #[test]
+    fn test_boot_failure_includes_loader_stderr() {
+        let temp_dir = TempDir::new().unwrap();
+        let loader_path = temp_dir.path().join("broken_loader.py");
+        std::fs::write(
+            &loader_path,
+            "def load():\n    raise RuntimeError('loader broke before importing anything')\n\nload()\n",
+        )
+        .unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .loader_script(&loader_path)
+            .build();
+        let error = runner.boot_main().unwrap_err();
+        assert!(matches!(error, HotReloadError::LoaderExited { .. }));
+
+        let stderr = error.loader_stderr().unwrap();
+        assert!(stderr.starts_with("Traceback (most recent call last):"));
+        assert!(stderr.contains("in load"));
+        assert!(stderr.ends_with("RuntimeError: loader broke before importing anything"));
+        assert!(error.to_string().contains(stderr));
+
+        // A loader that closes stdout but never exits, after one huge line of stderr
+        std::fs::write(
+            &loader_path,
+            "import os, sys, time\nos.close(1)\nsys.stderr.write('x' * 65536 + '\\nstuck\\n')\nsys.stderr.flush()\ntime.sleep(60)\n",
+        )
+        .unwrap();
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .loader_script(&loader_path)
+            .build();
+        let start = Instant::now();
+        let error = runner.boot_main().unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let stderr = error.loader_stderr().unwrap();
         assert!(
-            communicate_result.is_ok(),
-            "communicate_isolated failed: {:?}",
-            communicate_result.err()
+            stderr.len() <= MAX_BOOT_STDERR_BYTES + 64,
+            "{}",
+            stderr.len()
         );
+        assert!(stderr.starts_with("[earlier output truncated]"));
+        assert!(stderr.ends_with("stuck"));
+    }
 
-        let result_option = communicate_result.unwrap();
-        assert!(
-            result_option.is_some(),
-            "No result received from isolated process"
-        );
+    #[test]
+    fn test_custom_multiplex_format() {
+        let python_script = r#"
+def main():
+    print("tagged", flush=True)
+    return "done"
+        "#;
 
-        // The result should be our timestamp string
-        let result_str = result_option.unwrap();
-        println!("Result from time.time(): {}", result_str);
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
 
-        // Try to parse the result as a float to verify it's a valid timestamp
-        let parsed_result = result_str.parse::<f64>();
-        assert!(
-            parsed_result.is_ok(),
-            "Failed to parse result as a float: {}",
-            result_str
-        );
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .multiplex_format("<{stream}|{pid}> ")
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
 
-        // Clean up by stopping the isolated process
-        runner
-            .stop_isolated(&process_uuid)
-            .expect("Failed to stop isolated process");
+        // Both the printed line and the result message come back through the custom prefix
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "custom_prefix")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process");
+        assert_eq!(result, Some("done".to_string()));
+        assert!(runner
+            .captured_output(&process_uuid)
+            .unwrap()
+            .iter()
+            .any(|line| line == "tagged"));
+        runner.stop_main().expect("Failed to stop main runner");
+
+        // A template that can't be parsed back is caught before the loader starts
+        let mut invalid = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .multiplex_format("{pid}{stream}")
+            .build();
+        let error = invalid.boot_main().unwrap_err();
+        assert!(error.to_string().contains("Invalid multiplex format"));
     }
 
     #[test]
-    fn test_stop_isolated() {
-        let temp_dir = TempDir::new().unwrap();
-        let dir_path = temp_dir.path().to_str().unwrap();
+    fn test_boot_with_loader_script_override() {
+        let python_script = r#"
+import os
 
-        let mut runner = Environment::new("test_package", dir_path, None);
+def main():
+    return os.environ.get("FIREHOT_CUSTOM_LOADER", "embedded")
+        "#;
 
-        // Boot the environment before accessing it
+        let (pickled_data, python_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(python_script, "main")
+                .expect("Failed to prepare script for isolation");
+
+        // A customized copy of the embedded loader that leaves a mark for forks to find
+        let temp_dir = TempDir::new().unwrap();
+        let loader_path = temp_dir.path().join("custom_loader.py");
+        std::fs::write(
+            &loader_path,
+            format!(
+                "import os\nos.environ['FIREHOT_CUSTOM_LOADER'] = 'custom'\n{}",
+                crate::scripts::PYTHON_LOADER_SCRIPT
+            ),
+        )
+        .unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", &python_env.container_path)
+            .loader_script(&loader_path)
+            .build();
         runner.boot_main().expect("Failed to boot main environment");
 
-        // Create a test process UUID
-        let env = runner.layer.as_ref().unwrap();
-        let env_guard = env.lock().unwrap();
+        let process_uuid = runner
+            .exec_isolated(&pickled_data, "custom_loader")
+            .expect("Failed to execute script in isolation");
+        let result = runner
+            .communicate_isolated(&process_uuid)
+            .expect("Failed to communicate with isolated process");
+        assert_eq!(result, Some("custom".to_string()));
 
-        // Use a fixed UUID for testing
-        let test_uuid = Uuid::new_v4().to_string();
-        let test_pid = 23456;
+        runner.stop_main().expect("Failed to stop main runner");
+    }
 
-        // Add mock process to the forked_processes map
-        let mut forked_processes = env_guard.forked_processes.lock().unwrap();
-        forked_processes.insert(test_uuid.clone(), test_pid);
-        drop(forked_processes);
+    #[test]
+    fn test_boot_skips_stdlib_imports() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("main.py"),
+            "import os\nimport json\nimport requests\n",
+        )
+        .unwrap();
+
+        let mut runner =
+            EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap()).build();
+        runner.load_stdlib_modules();
+        let modules = runner.preload_modules().unwrap();
+        assert_eq!(modules, HashSet::from(["requests".to_string()]));
+
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .preload_stdlib(true)
+            .build();
+        runner.load_stdlib_modules();
+        let modules = runner.preload_modules().unwrap();
+        assert!(modules.contains("os") && modules.contains("requests"));
+    }
 
-        // Create the required resolvers
-        let fork_resolver = AsyncResolve::new();
-        let mut fork_resolvers = env_guard.fork_resolvers.lock().unwrap();
-        fork_resolvers.insert(test_uuid.clone(), fork_resolver.clone());
-        drop(fork_resolvers);
+    #[test]
+    fn test_preload_declared_dependencies() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("main.py"), "import requests\n").unwrap();
+        std::fs::write(
+            temp_dir.path().join("requirements.txt"),
+            "requests==2.31.0\ngunicorn>=21  # only run as a server\nPyYAML==6.0.1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            temp_dir.path().join("pyproject.toml"),
+            "[project]\nname = \"demo\"\ndependencies = [\"beautifulsoup4>=4\"]\n",
+        )
+        .unwrap();
+
+        let expected = |modules: &[&str]| -> HashSet<String> {
+            modules.iter().map(|module| module.to_string()).collect()
+        };
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .preload_requirements("requirements.txt")
+            .exclude_preload(["gunicorn"])
+            .build();
+        assert_eq!(
+            runner.preload_modules().unwrap(),
+            expected(&["requests", "yaml"])
+        );
 
-        let completion_resolver = AsyncResolve::new();
-        let mut completion_resolvers = env_guard.completion_resolvers.lock().unwrap();
-        completion_resolvers.insert(test_uuid.clone(), completion_resolver.clone());
-        drop(completion_resolvers);
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .preload_requirements("requirements.txt")
+            .preload_pyproject_dependencies(true)
+            .build();
+        assert_eq!(
+            runner.preload_modules().unwrap(),
+            expected(&["requests", "gunicorn", "yaml", "bs4"])
+        );
+    }
 
-        // Drop the guard so we can call stop_isolated
-        drop(env_guard);
+    #[test]
+    fn test_import_timings_cover_each_preloaded_module() {
+        let temp_dir = TempDir::new().unwrap();
+        // Both email submodules pull in the email package, which is only charged once
+        std::fs::write(
+            temp_dir.path().join("main.py"),
+            "import json\nimport email.parser\nimport email.mime.text\n",
+        )
+        .unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .import_granularity(ImportGranularity::FullPath)
+            .preload_stdlib(true)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
 
-        // Verify the process is in the forked_processes map
-        {
-            let env_guard = runner.layer.as_ref().unwrap().lock().unwrap();
+        let timed: Vec<&str> = runner
+            .import_timings()
+            .iter()
+            .map(|timing| timing.module.as_str())
+            .collect();
+        assert_eq!(timed, vec!["email.mime.text", "email.parser", "json"]);
 
-            let forked_processes = env_guard.forked_processes.lock().unwrap();
-            assert!(
-                forked_processes.contains_key(&test_uuid),
-                "Process UUID should be in the forked_processes map"
-            );
+        runner.stop_main().expect("Failed to stop main runner");
+    }
 
-            let pid = *forked_processes.get(&test_uuid).unwrap();
-            println!("Process PID: {}", pid);
-            drop(forked_processes);
+    #[test]
+    fn test_loader_import_order_is_sorted_and_collapsed() {
+        let modules: HashSet<String> =
+            ["requests", "numpy", "numpy.linalg", "numpy_extras", "attr"]
+                .into_iter()
+                .map(String::from)
+                .collect();
+
+        let expected = vec!["attr", "numpy.linalg", "numpy_extras", "requests"];
+        assert_eq!(loader_import_order(&modules), expected);
+
+        // HashSet iteration order varies between instances, the generated order must not
+        for _ in 0..10 {
+            let reordered: HashSet<String> = modules.iter().cloned().collect();
+            assert_eq!(loader_import_order(&reordered), expected);
         }
+    }
 
-        // Now stop the process
-        let stop_result = runner.stop_isolated(&test_uuid);
-        assert!(
-            stop_result.is_ok(),
-            "Failed to stop process: {:?}",
-            stop_result.err()
+    #[test]
+    fn test_import_failures_name_each_module() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().to_str().unwrap();
+        std::fs::write(temp_dir.path().join("main.py"), "import json\n").unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", project_dir)
+            .extra_preload(["firehot_missing_module_a", "firehot_missing_module_b"])
+            .build();
+        match runner.boot_main() {
+            Err(HotReloadError::ImportFailed(failures)) => {
+                let modules: Vec<_> = failures
+                    .iter()
+                    .filter_map(|f| f.module.as_deref())
+                    .collect();
+                assert!(modules.contains(&"firehot_missing_module_a"));
+                assert!(modules.contains(&"firehot_missing_module_b"));
+                assert!(failures
+                    .iter()
+                    .all(|failure| failure.error.contains("No module named")));
+            }
+            other => panic!("Expected ImportFailed, got {:?}", other.map(|_| ())),
+        }
+
+        // Under the warn policy the loader boots with everything that did import
+        let mut runner = EnvironmentBuilder::new("test_package", project_dir)
+            .extra_preload(["firehot_missing_module_a"])
+            .import_failure_policy(ImportFailurePolicy::Warn)
+            .build();
+        runner.boot_main().expect("Failed to boot main environment");
+        let failures = runner.import_failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(
+            failures[0].module.as_deref(),
+            Some("firehot_missing_module_a")
+        );
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_loaded_modules_exclude_failures() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(
+            temp_dir.path().join("main.py"),
+            "import json\nimport email.parser\n",
+        )
+        .unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .import_granularity(ImportGranularity::FullPath)
+            .preload_stdlib(true)
+            .extra_preload(["email", "firehot_missing_module_a"])
+            .import_failure_policy(ImportFailurePolicy::Warn)
+            .build();
+        assert!(runner.loaded_modules().is_empty());
+        runner.boot_main().expect("Failed to boot main environment");
+
+        let to_set = |modules: &[&str]| -> HashSet<String> {
+            modules.iter().map(|module| module.to_string()).collect()
+        };
+        assert_eq!(
+            runner.requested_modules(),
+            &to_set(&["json", "email", "email.parser", "firehot_missing_module_a"])
+        );
+        // `email` is loaded through `email.parser`, even though it isn't imported on its own
+        assert_eq!(
+            runner.loaded_modules(),
+            to_set(&["json", "email", "email.parser"])
+        );
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_import_progress_callback() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .preload_stdlib(true)
+            .extra_preload(["json", "csv", "email.parser"])
+            .build();
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&progress);
+        runner.on_import_progress(move |update| recorded.lock().unwrap().push(update.clone()));
+        runner.boot_main().expect("Failed to boot main environment");
+
+        // Reported once per module, in the order the loader imports them
+        assert_eq!(
+            *progress.lock().unwrap(),
+            vec![
+                ImportProgress::new("csv".to_string(), 0, 3),
+                ImportProgress::new("email.parser".to_string(), 1, 3),
+                ImportProgress::new("json".to_string(), 2, 3),
+            ]
         );
+
+        runner.stop_main().expect("Failed to stop main runner");
+    }
+
+    #[test]
+    fn test_critical_modules_boot_before_background_imports() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("fast_dependency.py"), "VALUE = 1\n").unwrap();
+        std::fs::write(
+            temp_dir.path().join("slow_dependency.py"),
+            "import time\ntime.sleep(3)\nVALUE = 2\n\ndef value():\n    return VALUE\n",
+        )
+        .unwrap();
+
+        let mut runner = EnvironmentBuilder::new("test_package", temp_dir.path().to_str().unwrap())
+            .extra_preload(["fast_dependency", "slow_dependency"])
+            .critical_modules(["fast_dependency"])
+            .build();
+
+        let start = Instant::now();
+        runner.boot_main().expect("Failed to boot main environment");
         assert!(
-            stop_result.unwrap(),
-            "stop_isolated should return true for successful termination"
+            start.elapsed() < Duration::from_secs(3),
+            "Boot waited on the background import: {:?}",
+            start.elapsed()
         );
 
-        // Verify the process is no longer in the forked_processes map
-        {
-            let env_guard = runner.layer.as_ref().unwrap().lock().unwrap();
+        let loaded = runner.loaded_modules();
+        assert!(loaded.contains("fast_dependency"));
+        assert!(!loaded.contains("slow_dependency"));
+        assert!(!runner.background_imports().complete);
+
+        // A fork waits for the imports, but the loader still answers pings in the meantime
+        let call = crate::pickle::serialized_call("slow_dependency", "value", &[]);
+        let result = thread::scope(|scope| {
+            let fork = scope.spawn(|| {
+                let process_uuid = runner.exec_isolated(&call, "waits_for_imports")?;
+                runner.communicate_isolated(&process_uuid)
+            });
+            thread::sleep(Duration::from_millis(200));
+            assert!(!runner.background_imports().complete);
+            assert!(
+                runner.is_alive(),
+                "Ping went unanswered during background imports"
+            );
+            fork.join().unwrap()
+        });
+        assert_eq!(result.unwrap(), Some("2".to_string()));
 
-            let forked_processes = env_guard.forked_processes.lock().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !runner.background_imports().complete {
             assert!(
-                !forked_processes.contains_key(&test_uuid),
-                "Process UUID should be removed from the forked_processes map after termination"
+                Instant::now() < deadline,
+                "Background imports never finished"
             );
-            drop(forked_processes);
+            thread::sleep(Duration::from_millis(50));
         }
-
-        // Try to communicate with the terminated process
-        // It should fail since the process is no longer available
-        let communicate_result = runner.communicate_isolated(&test_uuid);
-        assert!(
-            communicate_result.is_err(),
-            "communicate_isolated should fail for a non-existent process"
+        let background_imports = runner.background_imports();
+        assert_eq!(
+            background_imports
+                .timings
+                .iter()
+                .map(|timing| timing.module.as_str())
+                .collect::<Vec<_>>(),
+            vec!["slow_dependency"]
         );
+        assert!(background_imports.failures.is_empty());
+        assert!(runner.loaded_modules().contains("slow_dependency"));
+
+        runner.stop_main().expect("Failed to stop main runner");
     }
 
     #[test]
-    fn test_stop_main() {
+    fn test_error_variants_for_unbooted_and_unknown_processes() {
         let temp_dir = TempDir::new().unwrap();
         let dir_path = temp_dir.path().to_str().unwrap();
 
-        let mut runner = Environment::new("test_package", dir_path, None);
+        let mut runner = Environment::new_for_test("test_package", dir_path, None);
+
+        // Nothing can run before the loader is booted
+        assert!(matches!(
+            runner.exec_isolated("", "not_booted"),
+            Err(HotReloadError::NotBooted)
+        ));
+        assert!(matches!(
+            runner.communicate_isolated("missing"),
+            Err(HotReloadError::NotBooted)
+        ));
+        assert!(matches!(
+            runner.stop_isolated("missing"),
+            Err(HotReloadError::NotBooted)
+        ));
 
-        // Boot the environment before stopping it
         runner.boot_main().expect("Failed to boot main environment");
 
-        // This should stop the main Python process
-        let result = runner.stop_main();
-        assert!(result.is_ok());
-        assert!(
-            result.unwrap(),
-            "stop_main should return true after successful execution"
-        );
+        match runner.communicate_isolated("unknown-uuid") {
+            Err(HotReloadError::ProcessNotFound(uuid)) => assert_eq!(uuid, "unknown-uuid"),
+            other => panic!("Expected ProcessNotFound, got {:?}", other),
+        }
+
+        runner.stop_main().expect("Failed to stop main runner");
     }
 
     #[test]
-    fn test_python_value_error_handling() -> Result<(), String> {
+    fn test_python_value_error_handling() -> Result<(), HotReloadError> {
         // Create a temporary directory for our test
         let temp_dir = TempDir::new().unwrap();
         let dir_path = temp_dir.path().to_str().unwrap();
@@ -946,7 +6140,13 @@ def main():
         assert!(result.is_err(), "Expected an error but got: {:?}", result);
 
         // Get the error message
-        let error_message = result.err().unwrap();
+        let error = result.err().unwrap();
+        assert!(
+            matches!(error, HotReloadError::ProcessFailed(_)),
+            "Expected a ProcessFailed error but got: {:?}",
+            error
+        );
+        let error_message = error.to_string();
 
         // The error should contain the specific error message
         assert!(
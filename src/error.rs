@@ -0,0 +1,144 @@
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// Errors returned by `Environment`. Failures without a dedicated variant carry their
+/// message in `Other`, so callers only need to match on the cases they handle.
+#[derive(Debug, Error)]
+pub enum HotReloadError {
+    /// An operation that needs the loader was called before `boot_main`
+    #[error("Environment not initialized. Call boot_main first.")]
+    NotBooted,
+
+    /// The configured Python interpreter (or virtualenv) doesn't exist. Holds a message
+    /// naming what we looked for.
+    #[error("{0}")]
+    InterpreterNotFound(String),
+
+    /// The loader process could not be started
+    #[error("Failed to spawn Python loader: {0}")]
+    Spawn(#[source] io::Error),
+
+    /// One or more preloaded modules raised while being imported
+    #[error("Failed to import {}", format_import_failures(.0))]
+    ImportFailed(Vec<ImportFailure>),
+
+    /// The loader exited before its imports finished, without reporting why. Holds the end of
+    /// what it printed to stderr, which is where uncaught tracebacks end up.
+    #[error("Python loader exited before its imports finished{}", format_stderr(.stderr))]
+    LoaderExited { stderr: String },
+
+    /// The loader speaks a protocol version this build doesn't support. `found` is None when
+    /// the loader predates the handshake.
+    #[error("Python loader speaks protocol version {}, but this build supports versions {}-{}", .found.map(|version| version.to_string()).unwrap_or_else(|| "unknown".to_string()), .supported.start(), .supported.end())]
+    ProtocolMismatch {
+        found: Option<u32>,
+        supported: RangeInclusive<u32>,
+    },
+
+    /// The loader booted, but couldn't run a no-op call in a fork. Holds why.
+    #[error("Python loader is unable to fork: {0}")]
+    ForkUnavailable(String),
+
+    /// No isolated process is tracked under the given UUID
+    #[error("No forked process found with UUID: {0}")]
+    ProcessNotFound(String),
+
+    /// The isolated function raised. Holds the error message and traceback.
+    #[error("{0}")]
+    ProcessFailed(String),
+
+    /// The isolated function ran past its Python-side timeout and didn't handle the
+    /// `TimeoutError` raised inside it. Holds the error message and traceback.
+    #[error("{0}")]
+    ProcessTimedOut(String),
+
+    /// The isolated process exited before reporting a result, with the status from `waitpid`
+    #[error("Process exited without reporting a result ({})", format_exit_status(*.exit_code, *.signal))]
+    ProcessExited {
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+    },
+
+    /// The isolated process was cancelled before it finished
+    #[error("Process {0} was cancelled")]
+    ProcessCancelled(String),
+
+    /// The isolated function's return value couldn't be deserialized into the requested type
+    #[error("Result of process {uuid} could not be deserialized: {reason}")]
+    InvalidResult { uuid: String, reason: String },
+
+    /// Gave up waiting on the loader or an isolated process
+    #[error("Timed out after {after:?} waiting for {operation}")]
+    Timeout { operation: String, after: Duration },
+
+    #[error("{0}")]
+    Other(String),
+}
+
+/// A module the loader failed to import while booting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportFailure {
+    /// The module being imported, or None for failures not tied to a single module
+    pub module: Option<String>,
+    pub error: String,
+    pub traceback: Option<String>,
+}
+
+impl fmt::Display for ImportFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.module {
+            Some(module) => write!(f, "{}: {}", module, self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+fn format_import_failures(failures: &[ImportFailure]) -> String {
+    failures
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn format_stderr(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!(":\n{}", stderr)
+    }
+}
+
+fn format_exit_status(exit_code: Option<i32>, signal: Option<i32>) -> String {
+    match (exit_code, signal) {
+        (_, Some(signal)) => format!("killed by signal {}", signal),
+        (Some(code), None) => format!("exit code {}", code),
+        (None, None) => "unknown status".to_string(),
+    }
+}
+
+impl HotReloadError {
+    /// What the loader printed to stderr before it failed to boot, if it was captured
+    pub fn loader_stderr(&self) -> Option<&str> {
+        match self {
+            HotReloadError::LoaderExited { stderr } => Some(stderr),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for HotReloadError {
+    fn from(message: String) -> Self {
+        HotReloadError::Other(message)
+    }
+}
+
+impl From<&str> for HotReloadError {
+    fn from(message: &str) -> Self {
+        HotReloadError::Other(message.to_string())
+    }
+}
@@ -1,17 +1,42 @@
+use base64::Engine;
 use log::{debug, error, info, trace, warn};
 use owo_colors::OwoColorize;
 use serde_json::{self};
-use std::collections::HashMap;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::Child;
-use std::sync::mpsc::{self, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
 
 use crate::async_resolve::AsyncResolve;
-use crate::messages::Message;
-use crate::multiplex_logs::parse_multiplexed_line;
+use crate::config::{LogFormat, DEFAULT_MAX_LINE_LENGTH};
+use crate::error::ImportFailure;
+use crate::messages::io::FrameReader;
+use crate::messages::{ChildComplete, ChildErrorKind, ChildExited, Message, ReloadResponse};
+use crate::metrics::Metrics;
+use crate::multiplex_logs::{MultiplexFormat, MultiplexedLogLine};
+use crate::resources::ResourceTotals;
+
+/// Prefix of the files forks write large binary results to, followed by the fork's PID
+const RESULT_FILE_PREFIX: &str = "firehot-result-";
+
+/// Upper bound on lines buffered for a PID we haven't seen a ForkResponse for. Past this the
+/// held lines are logged as unmatched, so output from unknown processes can't grow without
+/// bound. They're also logged once the PID is reaped.
+const MAX_PENDING_LINES_PER_PID: usize = 1024;
+
+/// Most recent output lines kept per fork for subscribers that attach after it started printing
+const MAX_CAPTURED_LINES_PER_PROCESS: usize = 1024;
+
+/// Finished forks whose output we keep for `captured_output`. Older ones are forgotten, so a
+/// long-running loader doesn't hold on to the output of every fork it ever ran.
+const MAX_FINISHED_OUTPUTS: usize = 64;
 
 /// Buffer for capturing logs in test mode
 #[derive(Clone, Debug, Default)]
@@ -37,6 +62,60 @@ impl OutputBuffer {
     }
 }
 
+/// Output captured from a single fork, along with the channels streaming it to subscribers
+#[derive(Debug, Default)]
+pub struct ProcessOutput {
+    pub lines: VecDeque<String>,
+    subscribers: Vec<Sender<String>>,
+    // The fork's output travels over two pipes, so it's only complete once the fork has
+    // exited and we've read up to the loader's marker on stderr
+    exited: bool,
+    stderr_closed: bool,
+    finished_at: Option<Instant>,
+}
+
+impl ProcessOutput {
+    fn push(&mut self, line: &str) {
+        if self.lines.len() == MAX_CAPTURED_LINES_PER_PROCESS {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+        // Subscribers that dropped their receiver are pruned as we go
+        self.subscribers
+            .retain(|subscriber| subscriber.send(line.to_string()).is_ok());
+    }
+
+    /// Stream the output seen so far and everything after it. The channel closes once the
+    /// process finishes.
+    fn subscribe(&mut self) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        for line in &self.lines {
+            let _ = tx.send(line.clone());
+        }
+        if self.finished_at.is_none() {
+            self.subscribers.push(tx);
+        }
+        rx
+    }
+
+    fn mark_exited(&mut self) {
+        self.exited = true;
+        self.finish_when_drained();
+    }
+
+    fn mark_stderr_closed(&mut self) {
+        self.stderr_closed = true;
+        self.finish_when_drained();
+    }
+
+    fn finish_when_drained(&mut self) {
+        if self.exited && self.stderr_closed && self.finished_at.is_none() {
+            self.finished_at = Some(Instant::now());
+            self.subscribers.clear();
+        }
+    }
+}
+
 /// Result from the initial fork
 #[derive(Debug, Clone)]
 pub enum ForkResult {
@@ -49,30 +128,109 @@ pub enum ForkResult {
 /// Result from a forked process
 #[derive(Debug, Clone)]
 pub enum ProcessResult {
-    /// Process completed successfully with an optional return value
-    Complete(Option<String>),
+    /// Process completed successfully with an optional return value, also encoded as JSON
+    /// when the value is JSON serializable, or kept as raw bytes when it's binary
+    Complete {
+        result: Option<String>,
+        result_json: Option<String>,
+        /// The raw return value, when the function returned `bytes`
+        result_bytes: Option<Vec<u8>>,
+    },
     /// Process failed with an error message
     Error(String),
+    /// Process was interrupted by its Python-side timeout, which raised `TimeoutError` inside
+    /// the function. Holds the error message and traceback.
+    TimedOut(String),
+    /// Process exited without reporting a result, e.g. through `os._exit` or a signal.
+    /// Holds the status the loader collected with `waitpid`.
+    Exited {
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+    },
+    /// Process was cancelled with `cancel_isolated` before it finished
+    Cancelled,
     // Raw log output from the process
     //Log(MultiplexedLogLine),
 }
 
+/// How long a preloaded module took to import during the last boot
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportTiming {
+    pub module: String,
+    /// Dependencies are charged to the first preloaded module that imports them, so the
+    /// durations add up to the total import time without double counting
+    pub duration: Duration,
+}
+
+/// Preloads outside `critical_modules`, which the loader imports in a background thread after
+/// it reported ready
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackgroundImports {
+    /// Modules imported so far, in import order
+    pub timings: Vec<ImportTiming>,
+    /// Modules that failed to import. These never fail the boot, whatever the import failure
+    /// policy, since it has already returned.
+    pub failures: Vec<ImportFailure>,
+    /// Whether the loader is done with every background import
+    pub complete: bool,
+}
+
 /// Runtime layer for executing Python code. This is a single "built" layer that should be immutable. Any client executed code will be in a forked process and any
 pub struct Layer {
     pub child: Child,                    // The forkable process with all imports loaded
     pub stdin: std::process::ChildStdin, // The stdin of the forkable process
-    pub reader: Option<std::io::Lines<BufReader<std::process::ChildStdout>>>, // The reader of the forkable process
-    pub stderr_reader: Option<std::io::Lines<BufReader<std::process::ChildStderr>>>, // The stderr reader of the forkable process
+    pub reader: Option<FrameReader<BufReader<std::process::ChildStdout>>>, // The message reader of the forkable process
+    pub stderr_reader: Option<FrameReader<BufReader<std::process::ChildStderr>>>, // The stderr reader of the forkable process
 
     pub forked_processes: Arc<Mutex<HashMap<String, i32>>>, // Map of UUID to PID
     pub forked_names: Arc<Mutex<HashMap<String, String>>>,  // Map of UUID to name
 
+    // A fork can start writing before its ForkResponse is processed. Lines from those PIDs
+    // are held here and replayed once the PID is mapped to a UUID, or logged as unmatched once
+    // the PID is reaped without one.
+    pub pending_lines: Arc<Mutex<HashMap<i32, Vec<String>>>>, // Map of PID to buffered lines
+
     // These are pinged when the forked process finishes startup - either successful or failure
     pub fork_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<ForkResult>>>>, // Map of UUID to fork resolver
 
     // These are pinged when the process completes execution
     pub completion_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<ProcessResult>>>>, // Map of UUID to completion resolver
 
+    // These are pinged when the loader answers a liveness check
+    pub pong_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<()>>>>, // Map of ping ID to pong resolver
+
+    // These are pinged when the loader answers a request to reload modules
+    pub reload_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<ReloadResponse>>>>, // Map of request ID to reload resolver
+
+    // Resource usage reported by finished forks. Owned by the Environment so totals survive rebuilds
+    pub resource_totals: Arc<Mutex<ResourceTotals>>,
+
+    // Fork counters. Owned by the Environment like the resource totals
+    pub metrics: Arc<Mutex<Metrics>>,
+
+    // Preloads the loader is still importing after it reported ready
+    pub background_imports: Arc<Mutex<BackgroundImports>>,
+
+    // Forks the loader has reaped. Their PIDs are free for reuse, so they must not be signaled.
+    pub exited_processes: Arc<Mutex<HashMap<i32, ChildExited>>>, // Map of PID to exit status
+
+    // Printed output of each fork, streamed to anyone subscribed through `subscribe_output`
+    pub process_output: Arc<Mutex<HashMap<String, ProcessOutput>>>, // Map of UUID to output
+
+    // Private directory forks write large binary results to. Removed along with the layer, so
+    // results that were never collected don't outlive it.
+    pub result_dir: Option<TempDir>,
+    // Forks whose result file is still being read. They have reported, so their exit isn't
+    // a crash.
+    pub loading_results: Arc<Mutex<HashSet<String>>>, // Set of UUIDs
+
+    // Set by the stdout monitor once the loader's output closes
+    pub loader_exited: Arc<AtomicBool>,
+    // Set when we're shutting the loader down ourselves, so its exit isn't treated as a crash
+    pub stopping: bool,
+    // Set while a replacement for this crashed loader boots, so only one caller restarts it
+    pub restarting: bool,
+
     pub stdout_thread: Option<JoinHandle<()>>, // Thread handle for stdout monitoring
     pub stderr_thread: Option<JoinHandle<()>>, // Thread handle for stderr monitoring
     pub thread_terminate_tx: Arc<Mutex<Option<Sender<()>>>>, // Channel to signal thread termination
@@ -82,6 +240,37 @@ pub struct Layer {
     pub output_buffer: Arc<Mutex<Option<OutputBuffer>>>,
     // Flag to control whether output is printed or buffered
     pub buffer_output: bool,
+    // Whether output is printed as colored text or JSON
+    pub log_format: LogFormat,
+    // Prefix the loader tags fork output with
+    pub multiplex_format: MultiplexFormat,
+    // Longest output line we keep, since stdout is read with the larger message limit
+    pub max_line_length: usize,
+}
+
+/// What the stdout and stderr monitor threads share with the layer and each other. Cloning
+/// hands out another reference to the same maps.
+#[derive(Clone, Default)]
+struct MonitorState {
+    fork_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<ForkResult>>>>,
+    completion_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<ProcessResult>>>>,
+    forked_processes: Arc<Mutex<HashMap<String, i32>>>,
+    forked_names: Arc<Mutex<HashMap<String, String>>>,
+    pending_lines: Arc<Mutex<HashMap<i32, Vec<String>>>>,
+    resource_totals: Arc<Mutex<ResourceTotals>>,
+    metrics: Arc<Mutex<Metrics>>,
+    exited_processes: Arc<Mutex<HashMap<i32, ChildExited>>>,
+    process_output: Arc<Mutex<HashMap<String, ProcessOutput>>>,
+    result_dir: Option<PathBuf>,
+    loading_results: Arc<Mutex<HashSet<String>>>,
+    pong_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<()>>>>,
+    reload_resolvers: Arc<Mutex<HashMap<String, AsyncResolve<ReloadResponse>>>>,
+    background_imports: Arc<Mutex<BackgroundImports>>,
+    output_buffer: Arc<Mutex<Option<OutputBuffer>>>,
+    buffer_output: bool,
+    log_format: LogFormat,
+    multiplex_format: MultiplexFormat,
+    max_line_length: usize,
 }
 
 impl Layer {
@@ -89,8 +278,8 @@ impl Layer {
     pub fn new(
         child: Child,
         stdin: std::process::ChildStdin,
-        reader: std::io::Lines<BufReader<std::process::ChildStdout>>,
-        stderr_reader: std::io::Lines<BufReader<std::process::ChildStderr>>,
+        reader: FrameReader<BufReader<std::process::ChildStdout>>,
+        stderr_reader: FrameReader<BufReader<std::process::ChildStderr>>,
     ) -> Self {
         Self {
             child,
@@ -99,14 +288,30 @@ impl Layer {
             stderr_reader: Some(stderr_reader),
             forked_processes: Arc::new(Mutex::new(HashMap::new())),
             forked_names: Arc::new(Mutex::new(HashMap::new())),
+            pending_lines: Arc::new(Mutex::new(HashMap::new())),
             fork_resolvers: Arc::new(Mutex::new(HashMap::new())),
             completion_resolvers: Arc::new(Mutex::new(HashMap::new())),
+            pong_resolvers: Arc::new(Mutex::new(HashMap::new())),
+            reload_resolvers: Arc::new(Mutex::new(HashMap::new())),
+            background_imports: Arc::new(Mutex::new(BackgroundImports::default())),
+            resource_totals: Arc::new(Mutex::new(ResourceTotals::new())),
+            metrics: Arc::new(Mutex::new(Metrics::new())),
+            exited_processes: Arc::new(Mutex::new(HashMap::new())),
+            process_output: Arc::new(Mutex::new(HashMap::new())),
+            result_dir: None,
+            loading_results: Arc::new(Mutex::new(HashSet::new())),
+            loader_exited: Arc::new(AtomicBool::new(false)),
+            stopping: false,
+            restarting: false,
             stdout_thread: None,
             stderr_thread: None,
             thread_terminate_tx: Arc::new(Mutex::new(None)),
             stderr_terminate_tx: Arc::new(Mutex::new(None)),
             output_buffer: Arc::new(Mutex::new(None)),
             buffer_output: false,
+            log_format: LogFormat::default(),
+            multiplex_format: MultiplexFormat::default(),
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
         }
     }
 
@@ -114,8 +319,8 @@ impl Layer {
     pub fn new_for_test(
         child: Child,
         stdin: std::process::ChildStdin,
-        reader: std::io::Lines<BufReader<std::process::ChildStdout>>,
-        stderr_reader: std::io::Lines<BufReader<std::process::ChildStderr>>,
+        reader: FrameReader<BufReader<std::process::ChildStdout>>,
+        stderr_reader: FrameReader<BufReader<std::process::ChildStderr>>,
     ) -> Self {
         let mut layer = Self::new(child, stdin, reader, stderr_reader);
         layer.buffer_output = true;
@@ -142,22 +347,33 @@ impl Layer {
         }
     }
 
-    /// Helper function to output a line either to stdout or the buffer based on buffer_output setting
-    fn output_line(
-        buffer_output: bool,
-        output_buffer: &Arc<Mutex<Option<OutputBuffer>>>,
-        line: String,
-    ) {
-        if buffer_output {
-            // Write to buffer if buffer_output is true
-            if let Ok(mut buffer_guard) = output_buffer.lock() {
-                if let Some(buffer) = &mut *buffer_guard {
-                    buffer.add_line(line);
-                }
-            }
-        } else {
-            // Print to stdout (default behavior)
-            println!("{}", line);
+    /// Print a line of our own alongside the fork output, or buffer it in test mode
+    pub(crate) fn write_output_line(&self, line: String) {
+        self.monitor_state().output_line(line);
+    }
+
+    /// Handles to the state the monitor threads share with the layer
+    fn monitor_state(&self) -> MonitorState {
+        MonitorState {
+            fork_resolvers: Arc::clone(&self.fork_resolvers),
+            completion_resolvers: Arc::clone(&self.completion_resolvers),
+            forked_processes: Arc::clone(&self.forked_processes),
+            forked_names: Arc::clone(&self.forked_names),
+            pending_lines: Arc::clone(&self.pending_lines),
+            resource_totals: Arc::clone(&self.resource_totals),
+            metrics: Arc::clone(&self.metrics),
+            exited_processes: Arc::clone(&self.exited_processes),
+            process_output: Arc::clone(&self.process_output),
+            result_dir: self.result_dir.as_ref().map(|dir| dir.path().to_path_buf()),
+            loading_results: Arc::clone(&self.loading_results),
+            pong_resolvers: Arc::clone(&self.pong_resolvers),
+            reload_resolvers: Arc::clone(&self.reload_resolvers),
+            background_imports: Arc::clone(&self.background_imports),
+            output_buffer: Arc::clone(&self.output_buffer),
+            buffer_output: self.buffer_output,
+            log_format: self.log_format,
+            multiplex_format: self.multiplex_format.clone(),
+            max_line_length: self.max_line_length,
         }
     }
 
@@ -183,34 +399,19 @@ impl Layer {
             .take()
             .expect("Stderr reader should be available");
 
-        // Clone the shared resolver maps for the monitor threads
-        let fork_resolvers_stdout = Arc::clone(&self.fork_resolvers);
-        let completion_resolvers_stdout = Arc::clone(&self.completion_resolvers);
-        let forked_processes_stdout = Arc::clone(&self.forked_processes);
-        let forked_names_stdout = Arc::clone(&self.forked_names);
-        let output_buffer_stdout = Arc::clone(&self.output_buffer);
-        let buffer_output_stdout = self.buffer_output;
-
-        let fork_resolvers_stderr = Arc::clone(&self.fork_resolvers);
-        let completion_resolvers_stderr = Arc::clone(&self.completion_resolvers);
-        let forked_processes_stderr = Arc::clone(&self.forked_processes);
-        let forked_names_stderr = Arc::clone(&self.forked_names);
-        let output_buffer_stderr = Arc::clone(&self.output_buffer);
-        let buffer_output_stderr = self.buffer_output;
+        // Both threads share the same maps, so each gets its own handle to them
+        let stderr_state = self.monitor_state();
+        let stdout_state = self.monitor_state();
+        let loader_exited = Arc::clone(&self.loader_exited);
 
         // Start a separate thread for stderr monitoring
         let stderr_thread = thread::spawn(move || {
-            Self::monitor_stream(
+            stderr_state.monitor_stream(
                 stderr_reader,
                 "stderr",
                 stderr_terminate_rx,
-                &fork_resolvers_stderr,
-                &completion_resolvers_stderr,
-                &forked_processes_stderr,
-                &forked_names_stderr,
                 None, // No need to send termination to other threads
-                buffer_output_stderr,
-                &output_buffer_stderr,
+                None,
             );
         });
 
@@ -219,17 +420,12 @@ impl Layer {
 
         // Start the stdout monitor thread
         let stdout_thread = thread::spawn(move || {
-            Self::monitor_stream(
+            stdout_state.monitor_stream(
                 stdout_reader,
                 "stdout",
                 stdout_terminate_rx,
-                &fork_resolvers_stdout,
-                &completion_resolvers_stdout,
-                &forked_processes_stdout,
-                &forked_names_stdout,
                 Some(stderr_terminate_tx), // Ability to terminate stderr thread
-                buffer_output_stdout,
-                &output_buffer_stdout,
+                Some(&loader_exited),
             );
 
             info!("Stdout monitor thread exiting");
@@ -238,20 +434,32 @@ impl Layer {
         // Store the stdout thread handle
         self.stdout_thread = Some(stdout_thread);
     }
+}
+
+impl MonitorState {
+    /// Helper function to output a line either to stdout or the buffer based on buffer_output setting
+    fn output_line(&self, line: String) {
+        if self.buffer_output {
+            // Write to buffer if buffer_output is true
+            if let Ok(mut buffer_guard) = self.output_buffer.lock() {
+                if let Some(buffer) = &mut *buffer_guard {
+                    buffer.add_line(line);
+                }
+            }
+        } else {
+            // Print to stdout (default behavior)
+            println!("{}", line);
+        }
+    }
 
     /// Common function to monitor a stream (stdout or stderr)
-    #[allow(clippy::too_many_arguments)]
     fn monitor_stream<R: BufRead>(
-        reader: std::io::Lines<R>,
+        &self,
+        reader: FrameReader<R>,
         stream_name: &str,
         terminate_rx: mpsc::Receiver<()>,
-        fork_resolvers: &Arc<Mutex<HashMap<String, AsyncResolve<ForkResult>>>>,
-        completion_resolvers: &Arc<Mutex<HashMap<String, AsyncResolve<ProcessResult>>>>,
-        forked_processes: &Arc<Mutex<HashMap<String, i32>>>,
-        forked_names: &Arc<Mutex<HashMap<String, String>>>,
         stderr_terminate_tx: Option<mpsc::Sender<()>>,
-        buffer_output: bool,
-        output_buffer: &Arc<Mutex<Option<OutputBuffer>>>,
+        loader_exited: Option<&Arc<AtomicBool>>,
     ) {
         info!("Monitor thread for {} started", stream_name);
         let mut reader = reader;
@@ -270,15 +478,20 @@ impl Layer {
             match reader.next() {
                 Some(Ok(line)) => {
                     trace!("{} monitor thread read line: {}", stream_name, line);
-                    Self::process_output_line(
-                        &line,
-                        fork_resolvers,
-                        completion_resolvers,
-                        forked_processes,
-                        forked_names,
-                        buffer_output,
-                        output_buffer,
-                    );
+                    match reader.truncated_length() {
+                        Some(length) => self.process_oversized_line(
+                            &line,
+                            length,
+                            reader.max_length(),
+                            stream_name,
+                        ),
+                        None => self.process_output_line(&line, stream_name),
+                    }
+                }
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    // A fork wrote bytes that aren't UTF-8. The line is already consumed, so
+                    // skip it rather than stop delivering everyone else's output and results.
+                    warn!("Dropped a line from {} that isn't valid UTF-8", stream_name);
                 }
                 Some(Err(e)) => {
                     error!("Error reading from child process {}: {}", stream_name, e);
@@ -294,6 +507,9 @@ impl Layer {
                         "End of child process {} stream detected, exiting {} monitor thread",
                         stream_name, stream_name
                     );
+                    if let Some(loader_exited) = loader_exited {
+                        loader_exited.store(true, Ordering::SeqCst);
+                    }
                     // Terminate stderr thread if needed
                     if let Some(tx) = &stderr_terminate_tx {
                         let _ = tx.send(());
@@ -306,22 +522,64 @@ impl Layer {
         info!("{} monitor thread exiting", stream_name);
     }
 
-    /// Process output line from either stdout or stderr
-    fn process_output_line(
+    /// Handle a line the reader cut short. Output is still shown in truncated form, but a
+    /// partial message can't be decoded, so the fork that sent it fails with an explanation
+    /// instead of being reported as exited without a result.
+    fn process_oversized_line(
+        &self,
         line: &str,
-        fork_resolvers: &Arc<Mutex<HashMap<String, AsyncResolve<ForkResult>>>>,
-        completion_resolvers: &Arc<Mutex<HashMap<String, AsyncResolve<ProcessResult>>>>,
-        forked_processes: &Arc<Mutex<HashMap<String, i32>>>,
-        forked_names: &Arc<Mutex<HashMap<String, String>>>,
-        buffer_output: bool,
-        output_buffer: &Arc<Mutex<Option<OutputBuffer>>>,
+        length: usize,
+        max_length: usize,
+        stream_name: &str,
     ) {
+        // Messages only travel over stdout; stderr is always plain output
+        let log_line = match self.multiplex_format.parse(line) {
+            Ok(log_line) if stream_name == "stdout" && log_line.content.starts_with('{') => {
+                log_line
+            }
+            Ok(_) => return self.process_output_line(line, stream_name),
+            Err(_) => {
+                error!(
+                    "Dropped a {} byte line from the loader, over the {} byte limit",
+                    length, max_length
+                );
+                return;
+            }
+        };
+
+        let uuid = self
+            .forked_processes
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, pid)| **pid == log_line.pid as i32)
+            .map(|(uuid, _)| uuid.clone());
+        let message = format!(
+            "Message of {} bytes from PID {} exceeds the {} byte limit. Return a smaller \
+             result or raise EnvironmentBuilder::max_message_length.",
+            length, log_line.pid, max_length
+        );
+        error!("{}", message);
+
+        let Some(uuid) = uuid else {
+            return;
+        };
+        if let Some(resolver) = self.completion_resolvers.lock().unwrap().get(&uuid) {
+            if !resolver.is_resolved() {
+                self.metrics.lock().unwrap().forks_errored += 1;
+                resolver.resolve(ProcessResult::Error(message));
+            }
+        }
+    }
+
+    /// Process output line from either stdout or stderr
+    fn process_output_line(&self, line: &str, stream_name: &str) {
         // All lines streamed from the forked process (even our own messages)
         // should be multiplexed lines
-        match parse_multiplexed_line(line) {
+        match self.multiplex_format.parse(line) {
             Ok(log_line) => {
                 // Find which process this log belongs to based on PID
-                let forked_definitions = forked_processes.lock().unwrap();
+                let forked_definitions = self.forked_processes.lock().unwrap();
                 let mut process_uuid = None;
 
                 for (uuid, pid) in forked_definitions.iter() {
@@ -333,96 +591,349 @@ impl Layer {
 
                 // Just print the log, don't store it
                 if let Some(uuid) = process_uuid {
+                    drop(forked_definitions);
+
                     // If we're resolved a UUID from the PID, we should also have a name
-                    let forked_names_guard = forked_names.lock().unwrap();
+                    let forked_names_guard = self.forked_names.lock().unwrap();
                     let process_name = forked_names_guard.get(&uuid.clone());
 
-                    match Self::handle_message(
-                        &log_line.content,
-                        Some(&uuid),
-                        fork_resolvers,
-                        completion_resolvers,
-                        forked_processes,
-                        forked_names,
-                    ) {
+                    match self.handle_message(&log_line.content, Some(&uuid)) {
                         Ok(_) => {
-                            // Successfully handled the message, nothing more to do
+                            if self.log_format == LogFormat::Json {
+                                if let Some(event) = lifecycle_event(&log_line.content, Some(&uuid))
+                                {
+                                    self.output_line(event);
+                                }
+                            }
                         }
                         Err(_e) => {
                             // Expected error condition in the case that we didn't receive a message
                             // but instead standard stdout
-                            let output_line = format!(
-                                "[{}]: {}",
-                                process_name
-                                    .unwrap_or(&String::from("unknown"))
-                                    .cyan()
-                                    .bold(),
-                                log_line.content
-                            );
+                            let content = self.truncate_output(&log_line.content, log_line.pid);
+                            if let Some(output) = self.process_output.lock().unwrap().get_mut(&uuid)
+                            {
+                                output.push(content);
+                            }
+
+                            // Tag with the stream the fork wrote to, which is what log
+                            // routing cares about, rather than the loader pipe it arrived on
+                            let output_line = match self.log_format {
+                                LogFormat::Text => format!(
+                                    "[{}:{}]: {}",
+                                    process_name
+                                        .unwrap_or(&String::from("unknown"))
+                                        .cyan()
+                                        .bold(),
+                                    log_line.stream_name,
+                                    content
+                                ),
+                                LogFormat::Json => serde_json::json!({
+                                    "pid": log_line.pid,
+                                    "uuid": uuid,
+                                    "name": process_name,
+                                    "stream": log_line.stream_name,
+                                    "message": content,
+                                })
+                                .to_string(),
+                            };
 
                             // Use the buffering mechanism
-                            Self::output_line(buffer_output, output_buffer, output_line);
+                            self.output_line(output_line);
                         }
                     }
                 } else {
-                    // If we can't match it to a specific process, log it with PID
-                    let output_line = format!(
-                        "Unmatched log: [{}] {}",
-                        format!("{}:{}", log_line.pid, log_line.stream_name)
-                            .cyan()
-                            .bold(),
-                        log_line.content
-                    );
+                    // The fork may have written this before we processed its ForkResponse. Hold
+                    // the line until the PID is known. We still hold the forked processes lock,
+                    // so a ForkResponse can't land between the lookup above and the buffering.
+                    // The loader announces forks before reporting their exit, so a PID that was
+                    // reaped without one will never be matched.
+                    let pid = log_line.pid as i32;
+                    let reaped = self.exited_processes.lock().unwrap().contains_key(&pid);
+                    let mut pending_guard = self.pending_lines.lock().unwrap();
+                    if !reaped {
+                        let pending = pending_guard.entry(pid).or_default();
+                        if pending.len() < MAX_PENDING_LINES_PER_PID {
+                            pending.push(line.to_string());
+                            return;
+                        }
+                    }
 
-                    // Use the buffering mechanism
-                    Self::output_line(buffer_output, output_buffer, output_line);
+                    // Past the bound, show what we held for this PID along with this line
+                    let held = pending_guard.remove(&pid).unwrap_or_default();
+                    drop(pending_guard);
+                    drop(forked_definitions);
+                    for held_line in &held {
+                        if let Ok(held_line) = self.multiplex_format.parse(held_line) {
+                            self.output_unmatched(&held_line);
+                        }
+                    }
+                    self.output_unmatched(&log_line);
                 }
             }
             Err(_e) => {
                 // If parsing fails, treat the line as a raw message. We will log the contents
                 // separately if we fail processing
-                if let Err(_e) = Self::handle_message(
-                    line,
-                    None,
-                    fork_resolvers,
-                    completion_resolvers,
-                    forked_processes,
-                    forked_names,
-                ) {
-                    // Unable to parse the line as a message, so log it as a raw line
-                    error!("{}", line);
+                match self.handle_message(line, None) {
+                    Ok(_) => {
+                        if self.log_format == LogFormat::Json {
+                            if let Some(event) = lifecycle_event(line, None) {
+                                self.output_line(event);
+                            }
+                        }
+
+                        // A ForkResponse may have just mapped a PID we were holding lines for
+                        self.replay_pending_lines(stream_name);
+                    }
+                    Err(_e) => {
+                        // Unable to parse the line as a message, so log it as a raw line
+                        error!("[loader:{}] {}", stream_name, line);
+                    }
                 }
             }
         }
     }
 
+    /// Record progress towards a fork's output being complete. Once it is, subscribers are
+    /// closed and the oldest finished output past `MAX_FINISHED_OUTPUTS` is forgotten.
+    fn settle_output(&self, uuid: &str, settle: fn(&mut ProcessOutput)) {
+        let mut outputs = self.process_output.lock().unwrap();
+        let Some(output) = outputs.get_mut(uuid) else {
+            return;
+        };
+        settle(output);
+        if output.finished_at.is_none() {
+            return;
+        }
+
+        let finished: Vec<(Instant, &String)> = outputs
+            .iter()
+            .filter_map(|(uuid, output)| output.finished_at.map(|at| (at, uuid)))
+            .collect();
+        if finished.len() > MAX_FINISHED_OUTPUTS {
+            if let Some((_, oldest)) = finished.into_iter().min() {
+                let oldest = oldest.clone();
+                outputs.remove(&oldest);
+            }
+        }
+    }
+
+    /// Resolve a fork's completion, unless it was cancelled just before it finished
+    fn resolve_completion(&self, uuid: &str, result: ProcessResult) {
+        match self.completion_resolvers.lock().unwrap().get(uuid) {
+            Some(resolver) if !resolver.is_resolved() => resolver.resolve(result),
+            Some(_) => {}
+            None => error!("No resolver found for UUID: {}", uuid),
+        }
+    }
+
+    /// Read a large result from the file the fork wrote it to, then remove the file. The
+    /// read happens on its own thread so it doesn't hold up output from other forks.
+    fn load_result_file(&self, uuid: &str, path: PathBuf, complete: ChildComplete) {
+        // The path comes from the fork, which could name any file we're able to delete
+        if !self.is_result_file(&path) {
+            error!(
+                "Fork {} sent an unexpected result file: {}",
+                uuid,
+                path.display()
+            );
+            self.resolve_completion(
+                uuid,
+                ProcessResult::Error(format!(
+                    "Result file {} is outside of the loader's result directory",
+                    path.display()
+                )),
+            );
+            return;
+        }
+
+        self.loading_results
+            .lock()
+            .unwrap()
+            .insert(uuid.to_string());
+        let state = self.clone();
+        let uuid = uuid.to_string();
+        thread::spawn(move || {
+            let result = match fs::read(&path) {
+                Ok(bytes) => ProcessResult::Complete {
+                    result: complete.result,
+                    result_json: complete.result_json,
+                    result_bytes: Some(bytes),
+                },
+                Err(e) => ProcessResult::Error(format!(
+                    "Failed to read binary result from {}: {}",
+                    path.display(),
+                    e
+                )),
+            };
+            if let Err(e) = fs::remove_file(&path) {
+                warn!(
+                    "Failed to remove binary result file {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+
+            // Resolve before clearing the flag, so a ChildExited in between sees one or the other
+            state.resolve_completion(&uuid, result);
+            state.loading_results.lock().unwrap().remove(&uuid);
+        });
+    }
+
+    /// Whether `path` is a result file the loader's forks could have written
+    fn is_result_file(&self, path: &Path) -> bool {
+        let in_result_dir = self
+            .result_dir
+            .as_deref()
+            .is_some_and(|dir| path.parent() == Some(dir));
+        let named_like_result = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(RESULT_FILE_PREFIX));
+        in_result_dir && named_like_result
+    }
+
+    /// Remove result files left by a fork that died before reporting them
+    fn remove_result_files(&self, pid: i32) {
+        let Some(entries) = self
+            .result_dir
+            .as_ref()
+            .and_then(|dir| fs::read_dir(dir).ok())
+        else {
+            return;
+        };
+        let prefix = format!("{}{}-", RESULT_FILE_PREFIX, pid);
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                debug!("Removing orphaned result file {}", entry.path().display());
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Cut fork output down to the line limit. Stdout is read with the much larger message
+    /// limit, so output lines on it haven't been truncated yet.
+    fn truncate_output<'a>(&self, content: &'a str, pid: u32) -> &'a str {
+        if content.len() <= self.max_line_length {
+            return content;
+        }
+        let mut end = self.max_line_length;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        warn!(
+            "Truncated a {} byte line from PID {} to {} bytes",
+            content.len(),
+            pid,
+            end
+        );
+        &content[..end]
+    }
+
+    /// Show a line from a PID we couldn't match to a fork
+    fn output_unmatched(&self, log_line: &MultiplexedLogLine) {
+        // Messages from an unknown PID have nobody waiting on them, like the loader's marker
+        // for a reaped fork's output
+        if serde_json::from_str::<Message>(&log_line.content).is_ok() {
+            debug!(
+                "Dropping message from unmatched PID {}: {}",
+                log_line.pid, log_line.content
+            );
+            return;
+        }
+
+        let content = self.truncate_output(&log_line.content, log_line.pid);
+        let output_line = match self.log_format {
+            LogFormat::Text => format!(
+                "Unmatched log: [{}] {}",
+                format!("{}:{}", log_line.pid, log_line.stream_name)
+                    .cyan()
+                    .bold(),
+                content
+            ),
+            LogFormat::Json => serde_json::json!({
+                "pid": log_line.pid,
+                "uuid": null,
+                "name": null,
+                "stream": log_line.stream_name,
+                "message": content,
+            })
+            .to_string(),
+        };
+
+        // Use the buffering mechanism
+        self.output_line(output_line);
+    }
+
+    /// Show the lines held for a PID that turned out not to be one of our forks
+    fn flush_unmatched(&self, pid: i32) {
+        let held = self
+            .pending_lines
+            .lock()
+            .unwrap()
+            .remove(&pid)
+            .unwrap_or_default();
+        for line in held {
+            if let Ok(log_line) = self.multiplex_format.parse(&line) {
+                self.output_unmatched(&log_line);
+            }
+        }
+    }
+
+    /// Re-process buffered lines for any PID that now has a known UUID
+    fn replay_pending_lines(&self, stream_name: &str) {
+        // Same lock order as process_output_line: forked processes, then pending lines
+        let ready_lines: Vec<String> = {
+            let forked_definitions = self.forked_processes.lock().unwrap();
+            let mut pending_guard = self.pending_lines.lock().unwrap();
+            let ready_pids: Vec<i32> = pending_guard
+                .keys()
+                .filter(|pid| forked_definitions.values().any(|known| known == *pid))
+                .copied()
+                .collect();
+            ready_pids
+                .iter()
+                .flat_map(|pid| pending_guard.remove(pid).unwrap_or_default())
+                .collect()
+        };
+
+        for line in ready_lines {
+            debug!("Replaying buffered line: {}", line);
+            self.process_output_line(&line, stream_name);
+        }
+    }
+
     /// Handle various messages from the child process
-    fn handle_message(
-        content: &str,
-        uuid: Option<&String>,
-        fork_resolvers: &Arc<Mutex<HashMap<String, AsyncResolve<ForkResult>>>>,
-        completion_resolvers: &Arc<Mutex<HashMap<String, AsyncResolve<ProcessResult>>>>,
-        forked_processes: &Arc<Mutex<HashMap<String, i32>>>,
-        forked_names: &Arc<Mutex<HashMap<String, String>>>,
-    ) -> Result<(), String> {
+    fn handle_message(&self, content: &str, uuid: Option<&String>) -> Result<(), String> {
         if let Ok(message) = serde_json::from_str::<Message>(content) {
             match message {
                 Message::ForkResponse(response) => {
                     // Handle fork response and update the forked processes map
                     debug!("Monitor thread received fork response: {:?}", response);
 
+                    // A reaped PID can be reused by the kernel, so any old exit status is stale
+                    self.exited_processes
+                        .lock()
+                        .unwrap()
+                        .remove(&response.child_pid);
+                    self.metrics.lock().unwrap().forks_started += 1;
+
                     // Store the PID in the forked processes map
-                    let mut forked_processes_guard = forked_processes.lock().unwrap();
+                    let mut forked_processes_guard = self.forked_processes.lock().unwrap();
                     forked_processes_guard.insert(response.request_id.clone(), response.child_pid);
                     drop(forked_processes_guard);
 
                     // Store the process name in the forked names map
-                    let mut forked_names_guard = forked_names.lock().unwrap();
+                    let mut forked_names_guard = self.forked_names.lock().unwrap();
                     forked_names_guard.insert(response.request_id.clone(), response.request_name);
                     drop(forked_names_guard);
 
+                    self.process_output
+                        .lock()
+                        .unwrap()
+                        .insert(response.request_id.clone(), ProcessOutput::default());
+
                     // Resolve the fork status
-                    let fork_resolvers_guard = fork_resolvers.lock().unwrap();
+                    let fork_resolvers_guard = self.fork_resolvers.lock().unwrap();
                     if let Some(resolver) = fork_resolvers_guard.get(&response.request_id) {
                         resolver
                             .resolve(ForkResult::Complete(Some(response.child_pid.to_string())));
@@ -439,14 +950,26 @@ impl Layer {
                     // from the child process
                     let uuid = uuid.expect("UUID should be known");
 
-                    // Resolve the completion
-                    let completion_resolvers_guard = completion_resolvers.lock().unwrap();
-                    if let Some(resolver) = completion_resolvers_guard.get(uuid) {
-                        resolver.resolve(ProcessResult::Complete(complete.result.clone()));
-                    } else {
-                        error!("No resolver found for UUID: {}", uuid);
+                    // Account for the fork's resource usage before anyone waiting on the result wakes up
+                    if let Some(rusage) = &complete.rusage {
+                        self.resource_totals.lock().unwrap().record(rusage);
                     }
-                    drop(completion_resolvers_guard);
+                    self.metrics.lock().unwrap().forks_completed += 1;
+
+                    // Large binary results arrive in a file, which could take a while to read
+                    if let Some(path) = &complete.result_path {
+                        self.load_result_file(uuid, PathBuf::from(path), complete.clone());
+                        return Ok(());
+                    }
+
+                    self.resolve_completion(
+                        uuid,
+                        ProcessResult::Complete {
+                            result: complete.result.clone(),
+                            result_json: complete.result_json.clone(),
+                            result_bytes: decode_result_bytes(&complete),
+                        },
+                    );
                     Ok(())
                 }
                 Message::ChildError(error) => {
@@ -456,8 +979,13 @@ impl Layer {
                     // from the child process
                     let uuid = uuid.expect("UUID should be known");
 
+                    if let Some(rusage) = &error.rusage {
+                        self.resource_totals.lock().unwrap().record(rusage);
+                    }
+                    self.metrics.lock().unwrap().forks_errored += 1;
+
                     // Resolve the completion with an error, include both error message and traceback
-                    let completion_resolvers_guard = completion_resolvers.lock().unwrap();
+                    let completion_resolvers_guard = self.completion_resolvers.lock().unwrap();
                     if let Some(resolver) = completion_resolvers_guard.get(uuid) {
                         // Create a complete error message with both the error text and traceback if available
                         let full_error = if let Some(traceback) = &error.traceback {
@@ -465,7 +993,13 @@ impl Layer {
                         } else {
                             error.error.clone()
                         };
-                        resolver.resolve(ProcessResult::Error(full_error));
+                        if !resolver.is_resolved() {
+                            resolver.resolve(match error.kind {
+                                ChildErrorKind::Exception => ProcessResult::Error(full_error),
+                                ChildErrorKind::Timeout => ProcessResult::TimedOut(full_error),
+                                ChildErrorKind::Interrupted => ProcessResult::Cancelled,
+                            });
+                        }
                     } else {
                         error!("No resolver found for UUID: {}", uuid);
                     }
@@ -479,12 +1013,138 @@ impl Layer {
                     );
 
                     // Resolve the fork status with an error
-                    let fork_resolvers_guard = fork_resolvers.lock().unwrap();
+                    let fork_resolvers_guard = self.fork_resolvers.lock().unwrap();
                     if let Some(resolver) = fork_resolvers_guard.get(&error.request_id) {
                         resolver.resolve(ForkResult::Error(error.error.clone()));
                     }
                     drop(fork_resolvers_guard);
                 }*/
+                Message::Pong(pong) => {
+                    trace!("Monitor thread received pong: {}", pong.request_id);
+                    if let Some(resolver) =
+                        self.pong_resolvers.lock().unwrap().get(&pong.request_id)
+                    {
+                        resolver.resolve(());
+                    }
+                    Ok(())
+                }
+                Message::ReloadResponse(response) => {
+                    debug!("Monitor thread received reload response: {:?}", response);
+                    if let Some(resolver) = self
+                        .reload_resolvers
+                        .lock()
+                        .unwrap()
+                        .get(&response.request_id)
+                    {
+                        resolver.resolve(response.clone());
+                    }
+                    Ok(())
+                }
+                Message::OutputClosed(_) => {
+                    // Written by the loader, but tagged with the fork it's about
+                    if let Some(uuid) = uuid {
+                        self.settle_output(uuid, ProcessOutput::mark_stderr_closed);
+                    }
+                    Ok(())
+                }
+                Message::ChildExited(exited) => {
+                    // Sent by the loader itself once it has waited on the fork
+                    debug!("Monitor thread received child exit: {:?}", exited);
+
+                    let uuid = self
+                        .forked_processes
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|(_, pid)| **pid == exited.child_pid)
+                        .map(|(uuid, _)| uuid.clone());
+                    if let Some(uuid) = &uuid {
+                        // Its stdout is ahead of this message, stderr may still be in flight
+                        self.settle_output(uuid, ProcessOutput::mark_exited);
+
+                        // Results are sent before the fork exits, so an unresolved fork here
+                        // died without reporting one, unless its result file is still loading.
+                        // Any other result file it left behind will never be collected.
+                        let loading_result = self.loading_results.lock().unwrap().contains(uuid);
+                        if !loading_result {
+                            self.remove_result_files(exited.child_pid);
+                        }
+                        if let Some(resolver) = self.completion_resolvers.lock().unwrap().get(uuid)
+                        {
+                            if !resolver.is_resolved() && !loading_result {
+                                self.metrics.lock().unwrap().forks_errored += 1;
+                                resolver.resolve(ProcessResult::Exited {
+                                    exit_code: exited.exit_code,
+                                    signal: exited.signal,
+                                });
+                            }
+                        }
+                    }
+
+                    let child_pid = exited.child_pid;
+                    self.exited_processes
+                        .lock()
+                        .unwrap()
+                        .insert(child_pid, exited);
+
+                    // Lines held for a PID that was never announced can't be matched anymore.
+                    // Recording the exit first means later lines for it aren't held either.
+                    if uuid.is_none() {
+                        self.flush_unmatched(child_pid);
+                    }
+                    Ok(())
+                }
+                // Boot-time imports are consumed before the monitor starts, so these only come
+                // from the imports the loader deferred past the critical modules
+                Message::ModuleImported(imported) => {
+                    debug!(
+                        "Imported module {} in the background in {:.1}ms",
+                        imported.module, imported.duration_ms
+                    );
+                    self.background_imports
+                        .lock()
+                        .unwrap()
+                        .timings
+                        .push(ImportTiming {
+                            module: imported.module,
+                            duration: Duration::from_secs_f64(
+                                imported.duration_ms.max(0.0) / 1000.0,
+                            ),
+                        });
+                    Ok(())
+                }
+                Message::ImportProgress(progress) => {
+                    trace!(
+                        "Importing module {} in the background ({}/{})",
+                        progress.module,
+                        progress.index + 1,
+                        progress.total
+                    );
+                    Ok(())
+                }
+                Message::ImportError(error) => {
+                    let failure = ImportFailure {
+                        module: error.module,
+                        error: error.error,
+                        traceback: error.traceback,
+                    };
+                    error!(
+                        "Background import error: {}\n{}",
+                        failure,
+                        failure.traceback.as_deref().unwrap_or_default()
+                    );
+                    self.background_imports
+                        .lock()
+                        .unwrap()
+                        .failures
+                        .push(failure);
+                    Ok(())
+                }
+                Message::ImportComplete(_) => {
+                    info!("Background imports loaded");
+                    self.background_imports.lock().unwrap().complete = true;
+                    Ok(())
+                }
                 Message::UnknownError(error) => {
                     // For unknown errors, we don't have a UUID, so we can't resolve a specific promise
                     // Only log the error for now
@@ -506,6 +1166,35 @@ impl Layer {
             ))
         }
     }
+}
+
+impl Layer {
+    /// Stream a fork's printed output, starting with the lines it has already printed. The
+    /// channel closes once the process finishes. Returns None for unknown UUIDs.
+    pub fn subscribe_output(&self, uuid: &str) -> Option<Receiver<String>> {
+        self.process_output
+            .lock()
+            .unwrap()
+            .get_mut(uuid)
+            .map(ProcessOutput::subscribe)
+    }
+
+    /// Snapshot of the most recent lines a fork has printed. These stay around after the fork
+    /// finishes, for the last `MAX_FINISHED_OUTPUTS` forks, so they're available alongside an
+    /// error or crash. Returns None for unknown UUIDs.
+    pub fn captured_output(&self, uuid: &str) -> Option<Vec<String>> {
+        self.process_output
+            .lock()
+            .unwrap()
+            .get(uuid)
+            .map(|output| output.lines.iter().cloned().collect())
+    }
+
+    /// Whether the loader has reaped this PID. Signaling it afterwards could hit an unrelated
+    /// process that was handed the same PID.
+    pub fn has_exited(&self, pid: i32) -> bool {
+        self.exited_processes.lock().unwrap().contains_key(&pid)
+    }
 
     /// Stop the monitoring threads if they're running
     pub fn stop_monitor_thread(&mut self) {
@@ -613,13 +1302,368 @@ impl Layer {
     }
 }
 
+/// The binary return value of a finished fork, when it was small enough to arrive inline
+fn decode_result_bytes(complete: &ChildComplete) -> Option<Vec<u8>> {
+    let encoded = complete.result_bytes.as_ref()?;
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| warn!("Failed to decode binary result: {}", e))
+        .ok()
+}
+
+/// The JSON line describing a lifecycle message from the loader or a fork, if it is one.
+/// `uuid` is the fork the message came from, when it was sent by the fork itself.
+fn lifecycle_event(content: &str, uuid: Option<&String>) -> Option<String> {
+    let event = match serde_json::from_str::<Message>(content).ok()? {
+        Message::ForkResponse(response) => serde_json::json!({
+            "event": "fork_started",
+            "uuid": response.request_id,
+            "name": response.request_name,
+            "pid": response.child_pid,
+        }),
+        Message::ChildComplete(_) => serde_json::json!({
+            "event": "fork_completed",
+            "uuid": uuid,
+        }),
+        Message::ChildError(error) => serde_json::json!({
+            "event": "fork_errored",
+            "uuid": uuid,
+            "message": error.error,
+            "kind": error.kind,
+        }),
+        Message::ChildExited(exited) => serde_json::json!({
+            "event": "fork_exited",
+            "pid": exited.child_pid,
+            "exit_code": exited.exit_code,
+            "signal": exited.signal,
+        }),
+        _ => return None,
+    };
+    Some(event.to_string())
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::environment::Environment;
+    use crate::error::HotReloadError;
     use tempfile::TempDir;
 
+    impl MonitorState {
+        /// Fresh state that buffers its output, like a layer in test mode
+        fn for_test(log_format: LogFormat) -> Self {
+            Self {
+                output_buffer: Arc::new(Mutex::new(Some(OutputBuffer::new()))),
+                buffer_output: true,
+                log_format,
+                max_line_length: DEFAULT_MAX_LINE_LENGTH,
+                ..Self::default()
+            }
+        }
+
+        fn buffered_lines(&self) -> Vec<String> {
+            let buffer = self.output_buffer.lock().unwrap();
+            buffer.as_ref().unwrap().lines.clone()
+        }
+    }
+
+    #[test]
+    fn test_child_output_before_fork_response_is_replayed() {
+        let state = MonitorState::for_test(LogFormat::Text);
+
+        let fork_resolver = AsyncResolve::new();
+        let completion_resolver = AsyncResolve::new();
+        state
+            .fork_resolvers
+            .lock()
+            .unwrap()
+            .insert("uuid-a".to_string(), fork_resolver.clone());
+        state
+            .completion_resolvers
+            .lock()
+            .unwrap()
+            .insert("uuid-a".to_string(), completion_resolver.clone());
+
+        let process = |line: &str| state.process_output_line(line, "stdout");
+
+        // The child finishes before the loader's ForkResponse reaches us
+        process("[PID:4242:stdout]child says hi");
+        process(r#"[PID:4242:stdout]{"name": "CHILD_COMPLETE", "result": "done"}"#);
+        assert!(!completion_resolver.is_resolved());
+        assert_eq!(state.pending_lines.lock().unwrap()[&4242].len(), 2);
+
+        process(
+            r#"{"name": "FORK_RESPONSE", "request_id": "uuid-a", "request_name": "early", "child_pid": 4242}"#,
+        );
+
+        assert!(fork_resolver.is_resolved());
+        match completion_resolver.get() {
+            Some(ProcessResult::Complete { result, .. }) => {
+                assert_eq!(result, Some("done".to_string()))
+            }
+            other => panic!("Unexpected completion: {:?}", other),
+        }
+        assert!(state.pending_lines.lock().unwrap().is_empty());
+
+        let output = state.buffered_lines().join("\n");
+        assert!(output.contains("child says hi"), "{}", output);
+        assert!(!output.contains("Unmatched log"), "{}", output);
+    }
+
     #[test]
-    fn test_stderr_handling() -> Result<(), String> {
+    fn test_result_files_are_confined_to_the_result_dir() {
+        let result_dir = tempfile::tempdir().unwrap();
+        let outside_dir = tempfile::tempdir().unwrap();
+        let state = MonitorState {
+            result_dir: Some(result_dir.path().to_path_buf()),
+            ..MonitorState::for_test(LogFormat::Text)
+        };
+
+        let completion_resolvers: Vec<AsyncResolve<ProcessResult>> = ["uuid-a", "uuid-b"]
+            .iter()
+            .zip([4242, 4343])
+            .map(|(uuid, pid)| {
+                let resolver = AsyncResolve::new();
+                state
+                    .completion_resolvers
+                    .lock()
+                    .unwrap()
+                    .insert(uuid.to_string(), resolver.clone());
+                state
+                    .fork_resolvers
+                    .lock()
+                    .unwrap()
+                    .insert(uuid.to_string(), AsyncResolve::new());
+                state.process_output_line(
+                    &format!(
+                        r#"{{"name": "FORK_RESPONSE", "request_id": "{}", "request_name": "f", "child_pid": {}}}"#,
+                        uuid, pid
+                    ),
+                    "stdout",
+                );
+                resolver
+            })
+            .collect();
+
+        // A fork can't get us to read and delete a file of its choosing
+        let victim = outside_dir.path().join("firehot-result-4242-victim");
+        std::fs::write(&victim, b"keep me").unwrap();
+        state.process_output_line(
+            &format!(
+                r#"[PID:4242:stdout]{{"name": "CHILD_COMPLETE", "result_path": {}}}"#,
+                serde_json::json!(victim)
+            ),
+            "stdout",
+        );
+        match completion_resolvers[0].get() {
+            Some(ProcessResult::Error(error)) => {
+                assert!(
+                    error.contains("outside of the loader's result directory"),
+                    "{}",
+                    error
+                )
+            }
+            other => panic!("Unexpected completion: {:?}", other),
+        }
+        assert!(victim.exists());
+
+        // A fork that died before reporting its result file doesn't leave it behind
+        let orphan = result_dir.path().join("firehot-result-4343-orphan");
+        std::fs::write(&orphan, b"never collected").unwrap();
+        state.process_output_line(
+            r#"{"name": "CHILD_EXITED", "child_pid": 4343, "exit_code": 1, "signal": null}"#,
+            "stdout",
+        );
+        assert!(matches!(
+            completion_resolvers[1].get(),
+            Some(ProcessResult::Exited { .. })
+        ));
+        assert!(!orphan.exists());
+    }
+
+    #[test]
+    fn test_output_finishes_once_both_streams_drain() {
+        let state = MonitorState::for_test(LogFormat::Text);
+        let start_fork = |uuid: &str, pid: i32| {
+            state
+                .fork_resolvers
+                .lock()
+                .unwrap()
+                .insert(uuid.to_string(), AsyncResolve::new());
+            state
+                .completion_resolvers
+                .lock()
+                .unwrap()
+                .insert(uuid.to_string(), AsyncResolve::new());
+            state.process_output_line(
+                &format!(
+                    r#"{{"name": "FORK_RESPONSE", "request_id": "{}", "request_name": "f", "child_pid": {}}}"#,
+                    uuid, pid
+                ),
+                "stdout",
+            );
+        };
+        let exit_fork = |pid: i32| {
+            state.process_output_line(
+                &format!(
+                    r#"{{"name": "CHILD_EXITED", "child_pid": {}, "exit_code": 0, "signal": null}}"#,
+                    pid
+                ),
+                "stdout",
+            );
+        };
+        let close_output = |pid: i32| {
+            state.process_output_line(
+                &format!(r#"[PID:{}:stderr]{{"name": "OUTPUT_CLOSED"}}"#, pid),
+                "stderr",
+            );
+        };
+
+        start_fork("uuid-a", 4242);
+        let output = state
+            .process_output
+            .lock()
+            .unwrap()
+            .get_mut("uuid-a")
+            .unwrap()
+            .subscribe();
+
+        // Stderr is a separate pipe, so it can trail the result and even the exit
+        state.process_output_line(
+            r#"[PID:4242:stdout]{"name": "CHILD_COMPLETE", "result": "done"}"#,
+            "stdout",
+        );
+        exit_fork(4242);
+        state.process_output_line("[PID:4242:stderr]late warning", "stderr");
+        assert_eq!(output.try_recv(), Ok("late warning".to_string()));
+        assert_eq!(output.try_recv(), Err(mpsc::TryRecvError::Empty));
+
+        close_output(4242);
+        assert_eq!(output.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+
+        // Finished output is kept for the most recent forks only
+        for pid in 5000..5000 + MAX_FINISHED_OUTPUTS as i32 {
+            start_fork(&format!("uuid-{}", pid), pid);
+            close_output(pid);
+            exit_fork(pid);
+        }
+        let outputs = state.process_output.lock().unwrap();
+        assert_eq!(outputs.len(), MAX_FINISHED_OUTPUTS);
+        assert!(!outputs.contains_key("uuid-a"));
+    }
+
+    #[test]
+    fn test_unmatched_lines_are_flushed_when_the_pid_is_reaped() {
+        let state = MonitorState::for_test(LogFormat::Text);
+        let process = |line: &str, stream_name: &str| state.process_output_line(line, stream_name);
+
+        process("[PID:4242:stdout]from a stranger", "stdout");
+        assert!(state.buffered_lines().is_empty());
+
+        // Reaped without ever being announced, so the held line can't be matched anymore
+        process(
+            r#"{"name": "CHILD_EXITED", "child_pid": 4242, "exit_code": 0, "signal": null}"#,
+            "stdout",
+        );
+        assert!(state.pending_lines.lock().unwrap().is_empty());
+
+        // Stderr can trail the exit, but isn't held once the PID is known to be gone
+        process("[PID:4242:stderr]late stranger", "stderr");
+        process(r#"[PID:4242:stderr]{"name": "OUTPUT_CLOSED"}"#, "stderr");
+        assert!(state.pending_lines.lock().unwrap().is_empty());
+
+        let output = state.buffered_lines();
+        assert_eq!(output.len(), 2, "{:?}", output);
+        assert!(output[0].contains("Unmatched log") && output[0].contains("from a stranger"));
+        assert!(output[1].contains("late stranger"));
+
+        // A PID that never exits is flushed once it hits the bound
+        for i in 0..=MAX_PENDING_LINES_PER_PID {
+            process(&format!("[PID:4343:stdout]line {}", i), "stdout");
+        }
+        assert!(state.pending_lines.lock().unwrap().is_empty());
+        assert_eq!(
+            state.buffered_lines().len(),
+            2 + MAX_PENDING_LINES_PER_PID + 1
+        );
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        let state = MonitorState::for_test(LogFormat::Json);
+
+        state
+            .fork_resolvers
+            .lock()
+            .unwrap()
+            .insert("uuid-a".to_string(), AsyncResolve::new());
+
+        let process = |line: &str| state.process_output_line(line, "stdout");
+
+        process(
+            r#"{"name": "FORK_RESPONSE", "request_id": "uuid-a", "request_name": "worker", "child_pid": 4242}"#,
+        );
+        process("[PID:4242:stderr]something went sideways");
+
+        let lines: Vec<serde_json::Value> = state
+            .buffered_lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                serde_json::json!({
+                    "event": "fork_started",
+                    "uuid": "uuid-a",
+                    "name": "worker",
+                    "pid": 4242,
+                }),
+                serde_json::json!({
+                    "pid": 4242,
+                    "uuid": "uuid-a",
+                    "name": "worker",
+                    "stream": "stderr",
+                    "message": "something went sideways",
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_forks_receive_their_own_results() -> Result<(), HotReloadError> {
+        let script_returning = |value: &str| format!("def main():\n    return {:?}\n", value);
+        let first_script = script_returning("first-result");
+        let second_script = script_returning("second-result");
+
+        let (first_pickled, first_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(&first_script, "main")?;
+        let (second_pickled, _second_env) =
+            crate::test_utils::harness::prepare_script_for_isolation(&second_script, "main")?;
+
+        let mut runner = Environment::new_for_test("test_package", &first_env.container_path, None);
+        runner.boot_main()?;
+
+        let first_uuid = runner.exec_isolated(&first_pickled, "first")?;
+        let second_uuid = runner.exec_isolated(&second_pickled, "second")?;
+
+        // Wait in the opposite order they were started, from separate threads
+        let runner_ref = &runner;
+        let (second_result, first_result) = std::thread::scope(|scope| {
+            let second = scope.spawn(|| runner_ref.communicate_isolated(&second_uuid));
+            let first = scope.spawn(|| runner_ref.communicate_isolated(&first_uuid));
+            (second.join().unwrap(), first.join().unwrap())
+        });
+
+        assert_eq!(first_result?, Some("first-result".to_string()));
+        assert_eq!(second_result?, Some("second-result".to_string()));
+
+        runner.stop_main()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stderr_handling() -> Result<(), HotReloadError> {
         // Create a temporary directory for our test
         let temp_dir = TempDir::new().unwrap();
         let dir_path = temp_dir.path().to_str().unwrap();
@@ -673,23 +1717,34 @@ def main():
         // Get the buffered output from the layer
         let output = runner.get_layer_output().unwrap_or_default();
 
-        // This assertion should PASS because stdout is being properly captured
-        assert!(
-            output.contains("UNIQUE_STDOUT_OUTPUT_FOR_TESTING_67890"),
-            "Expected to find stdout message in the captured output"
-        );
-
-        // This assertion should now PASS because stderr should also be captured by our buffer
-        assert!(
-            output.contains("UNIQUE_STDERR_OUTPUT_FOR_TESTING_12345"),
-            "Failed to find stderr message in the captured output"
-        );
+        // Both streams of the fork are multiplexed back to us, attributed to the process
+        // by name and tagged with the stream they were written to
+        for (marker, stream_tag) in [
+            ("UNIQUE_STDOUT_OUTPUT_FOR_TESTING_67890", ":stdout]"),
+            ("UNIQUE_STDERR_OUTPUT_FOR_TESTING_12345", ":stderr]"),
+        ] {
+            let line = output
+                .lines()
+                .find(|line| line.contains(marker))
+                .unwrap_or_else(|| panic!("Expected to find {} in the captured output", marker));
+            assert!(
+                line.contains("test_stderr_script"),
+                "Output should be prefixed with the process name: {}",
+                line
+            );
+            assert!(
+                line.contains(stream_tag),
+                "Output should be tagged with {}: {}",
+                stream_tag,
+                line
+            );
+        }
 
         Ok(())
     }
 
     #[test]
-    fn test_debug_log_handling() -> Result<(), String> {
+    fn test_debug_log_handling() -> Result<(), HotReloadError> {
         // Configure logging for this test
         let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Debug)
@@ -1,8 +1,11 @@
 use anstream::eprintln;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use once_cell::sync::Lazy;
 use owo_colors::OwoColorize;
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
@@ -13,17 +16,36 @@ use uuid::Uuid;
 
 pub mod ast;
 pub mod async_resolve;
+pub mod config;
 pub mod environment;
+pub mod error;
 pub mod layer;
 pub mod messages;
+pub mod metrics;
 pub mod multiplex_logs;
+pub mod pickle;
 pub mod process;
+pub mod requirements;
+pub mod resources;
 pub mod scripts;
 pub mod test_utils;
+pub mod watcher;
 
 // Export types from messages and scripts for public use
+pub use config::{
+    EnvironmentBuilder, EnvironmentConfig, ExecutionMode, ImportFailurePolicy, LogFormat,
+    ReloadMode,
+};
+pub use environment::{
+    Environment, ExecOptions, ImportDelta, ModuleReload, PreparedCall, ReloadCallback,
+    RestartCallback, ShutdownReport,
+};
+pub use error::{HotReloadError, ImportFailure};
+pub use layer::{BackgroundImports, ImportTiming};
 pub use messages::{ExitRequest, ForkRequest, Message};
+pub use metrics::Metrics;
 use scripts::PYTHON_CALL_SCRIPT;
+pub use watcher::{ProjectWatcher, ReloadEvent};
 
 // Replace RUNNERS and other new collections with IMPORT_RUNNERS
 static ENVIRONMENTS: Lazy<Mutex<HashMap<String, environment::Environment>>> =
@@ -73,11 +95,14 @@ fn firehot(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(start_import_runner, m)?)?;
     m.add_function(wrap_pyfunction!(update_environment, m)?)?;
     m.add_function(wrap_pyfunction!(stop_import_runner, m)?)?;
+    m.add_function(wrap_pyfunction!(last_boot_duration, m)?)?;
 
     // Isolated (child, post-fork) process management
     m.add_function(wrap_pyfunction!(exec_isolated, m)?)?;
     m.add_function(wrap_pyfunction!(communicate_isolated, m)?)?;
     m.add_function(wrap_pyfunction!(stop_isolated, m)?)?;
+    m.add_function(wrap_pyfunction!(cancel_isolated, m)?)?;
+    m.add_function(wrap_pyfunction!(captured_output, m)?)?;
 
     m.add_function(wrap_pyfunction!(get_total_thread_count, m)?)?;
 
@@ -114,7 +139,7 @@ fn start_import_runner(
 
     runner.boot_main().map_err(|e| {
         error!("Failed to boot main: {}", e);
-        PyRuntimeError::new_err(e)
+        PyRuntimeError::new_err(e.to_string())
     })?;
 
     // Store in global registry
@@ -155,6 +180,21 @@ fn update_environment(_py: Python, env_id: &str) -> PyResult<bool> {
     Ok(updated)
 }
 
+/// Seconds the last boot of the environment took, or None if it hasn't booted yet
+#[pyfunction]
+fn last_boot_duration(_py: Python, env_id: &str) -> PyResult<Option<f64>> {
+    let environments = ENVIRONMENTS.lock().unwrap();
+    if let Some(environment) = environments.get(env_id) {
+        Ok(environment
+            .last_boot_duration()
+            .map(|duration| duration.as_secs_f64()))
+    } else {
+        let err_msg = format!("No import environment found with ID: {}", env_id);
+        error!("{}", err_msg);
+        Err(PyRuntimeError::new_err(err_msg))
+    }
+}
+
 /// Stop the import runner with the given ID
 #[pyfunction]
 fn stop_import_runner(_py: Python, env_id: &str) -> PyResult<()> {
@@ -168,14 +208,21 @@ fn stop_import_runner(_py: Python, env_id: &str) -> PyResult<()> {
     let start_time = Instant::now();
 
     let mut environments = ENVIRONMENTS.lock().unwrap();
-    if let Some(environment) = environments.remove(env_id) {
+    if let Some(mut environment) = environments.remove(env_id) {
         // Clean up resources
-        environment.stop_main().map_err(|e| {
+        let report = environment.shutdown().map_err(|e| {
             let err_msg = format!("Failed to stop environment: {}", e);
             error!("{}", err_msg);
             PyRuntimeError::new_err(err_msg)
         })?;
 
+        if !report.killed.is_empty() {
+            warn!(
+                "{} isolated processes did not exit gracefully and were killed",
+                report.killed.len()
+            );
+        }
+
         // Calculate and log cleanup time
         let elapsed_ms = start_time.elapsed().as_millis();
         eprintln!(
@@ -198,14 +245,20 @@ fn stop_import_runner(_py: Python, env_id: &str) -> PyResult<()> {
     }
 }
 
-/// Execute a Python function in an isolated process
+/// Execute a Python function in an isolated process, with optional environment variables
+/// that are only set in that process. The call is pickled with `pickle_protocol` (the
+/// interpreter's default when unset), or with cloudpickle when requested and installed so
+/// closures and lambdas can be sent by value.
 #[pyfunction]
+#[pyo3(signature = (env_id, name, func, args=None, env=None, pickle_protocol=None, use_cloudpickle=false))]
 fn exec_isolated<'py>(
-    py: Python<'py>,
     env_id: &str,
     name: &str,
-    func: PyObject,
+    func: &'py PyAny,
     args: Option<PyObject>,
+    env: Option<HashMap<String, String>>,
+    pickle_protocol: Option<u8>,
+    use_cloudpickle: bool,
 ) -> PyResult<&'py PyAny> {
     debug!(
         "Executing function in isolated process for runner: {}",
@@ -213,9 +266,12 @@ fn exec_isolated<'py>(
     );
 
     // Create a dict to hold our function and args for pickling
+    let py = func.py();
     let locals = PyDict::new(py);
     locals.set_item("func", func)?;
     locals.set_item("args", args.unwrap_or_else(|| py.None()))?;
+    locals.set_item("pickle_protocol", pickle_protocol)?;
+    locals.set_item("use_cloudpickle", use_cloudpickle)?;
 
     py.run(PYTHON_CALL_SCRIPT, None, Some(locals))?;
 
@@ -232,14 +288,18 @@ fn exec_isolated<'py>(
     let environments = ENVIRONMENTS.lock().unwrap();
     if let Some(environment) = environments.get(env_id) {
         // Convert Rust Result<String, String> to PyResult
-        match environment.exec_isolated(&pickled_data, name) {
+        let options = ExecOptions {
+            env: env.unwrap_or_default(),
+            ..ExecOptions::default()
+        };
+        match environment.exec_isolated_with_options(&pickled_data, name, &options) {
             Ok(result) => {
                 debug!("Function executed successfully in isolated process");
                 Ok(py.eval(&format!("'{}'", result), None, None)?)
             }
             Err(err) => {
                 error!("Error executing function in isolated process: {}", err);
-                Err(PyRuntimeError::new_err(err))
+                Err(PyRuntimeError::new_err(err.to_string()))
             }
         }
     } else {
@@ -270,19 +330,35 @@ fn stop_isolated(_py: Python, env_id: &str, process_uuid: &str) -> PyResult<bool
     }
 }
 
-/// Get output from an isolated process
+/// Cancel an isolated process, so waiting on it reports the cancellation
 #[pyfunction]
-fn communicate_isolated(_py: Python, env_id: &str, process_uuid: &str) -> PyResult<Option<String>> {
-    debug!(
-        "Communicating with isolated process {} for environment {}",
+fn cancel_isolated(_py: Python, env_id: &str, process_uuid: &str) -> PyResult<bool> {
+    info!(
+        "Cancelling isolated process {} for runner {}",
         process_uuid, env_id
     );
     let environments = ENVIRONMENTS.lock().unwrap();
     if let Some(environment) = environments.get(env_id) {
-        environment.communicate_isolated(process_uuid).map_err(|e| {
-            let err_msg = format!("Child process error: {}", e);
+        environment.cancel_isolated(process_uuid).map_err(|e| {
+            let err_msg = format!("Failed to cancel isolated process: {}", e);
+            error!("{}", err_msg);
+            PyRuntimeError::new_err(err_msg)
+        })
+    } else {
+        let err_msg = format!("No import environment found with ID: {}", env_id);
+        error!("{}", err_msg);
+        Err(PyRuntimeError::new_err(err_msg))
+    }
+}
+
+/// Get the lines an isolated process printed, which are kept after it fails or crashes
+#[pyfunction]
+fn captured_output(_py: Python, env_id: &str, process_uuid: &str) -> PyResult<Vec<String>> {
+    let environments = ENVIRONMENTS.lock().unwrap();
+    if let Some(environment) = environments.get(env_id) {
+        environment.captured_output(process_uuid).map_err(|e| {
+            let err_msg = format!("Failed to get captured output: {}", e);
             error!("{}", err_msg);
-            // Use the standard PyRuntimeError instead of custom exception
             PyRuntimeError::new_err(err_msg)
         })
     } else {
@@ -292,6 +368,40 @@ fn communicate_isolated(_py: Python, env_id: &str, process_uuid: &str) -> PyResu
     }
 }
 
+/// Get output from an isolated process, optionally giving up after `timeout` seconds
+#[pyfunction]
+#[pyo3(signature = (env_id, process_uuid, timeout=None))]
+fn communicate_isolated(
+    _py: Python,
+    env_id: &str,
+    process_uuid: &str,
+    timeout: Option<f64>,
+) -> PyResult<Option<String>> {
+    debug!(
+        "Communicating with isolated process {} for environment {}",
+        process_uuid, env_id
+    );
+    let timeout = timeout
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| PyRuntimeError::new_err(format!("Invalid timeout: {}", e)))?;
+    let environments = ENVIRONMENTS.lock().unwrap();
+    if let Some(environment) = environments.get(env_id) {
+        environment
+            .communicate_isolated_with_timeout(process_uuid, timeout)
+            .map_err(|e| {
+                let err_msg = format!("Child process error: {}", e);
+                error!("{}", err_msg);
+                // Use the standard PyRuntimeError instead of custom exception
+                PyRuntimeError::new_err(err_msg)
+            })
+    } else {
+        let err_msg = format!("No import environment found with ID: {}", env_id);
+        error!("{}", err_msg);
+        Err(PyRuntimeError::new_err(err_msg))
+    }
+}
+
 #[pyfunction]
 fn get_total_thread_count() -> PyResult<u32> {
     process::get_total_thread_count()
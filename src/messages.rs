@@ -1,4 +1,15 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+/// Version of the message protocol spoken by this build. Bump it whenever `Message` changes in
+/// a way an older loader script can't understand, and keep the Python loader's
+/// `PROTOCOL_VERSION` in sync.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Loader protocol versions this build can talk to. Version 1 loaders never send
+/// `OUTPUT_CLOSED` or report oversized results, so they're no longer accepted.
+pub const SUPPORTED_PROTOCOL_VERSIONS: RangeInclusive<u32> = PROTOCOL_VERSION..=PROTOCOL_VERSION;
 
 /// Represents the different types of messages that can be sent between parent and child processes
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -8,11 +19,20 @@ pub enum MessageType {
     ForkResponse,
     ChildComplete,
     ChildError,
+    ChildExited,
+    OutputClosed,
     UnknownCommand,
     UnknownError,
     ImportError,
     ImportComplete,
+    ModuleImported,
+    ImportProgress,
+    Hello,
+    Ping,
+    Pong,
     ExitRequest,
+    ReloadRequest,
+    ReloadResponse,
 }
 
 /// Base trait for all messages
@@ -21,12 +41,38 @@ pub trait MessageBase {
 }
 
 /// Request to fork a process and execute code
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ForkRequest {
     pub request_id: String,
     pub request_name: String,
 
     pub code: String,
+    /// Serialized call handed to the child as `pickled_str`. Sent as data rather than
+    /// spliced into `code`, so its contents never need escaping.
+    #[serde(default)]
+    pub pickled_data: Option<String>,
+    /// Environment variables set in the child before the code runs. They often carry
+    /// secrets, so `Debug` only shows their names.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Seconds the function may run before the child raises `TimeoutError` inside it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+}
+
+impl std::fmt::Debug for ForkRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut env_names: Vec<&String> = self.env.keys().collect();
+        env_names.sort();
+        f.debug_struct("ForkRequest")
+            .field("request_id", &self.request_id)
+            .field("request_name", &self.request_name)
+            .field("code", &self.code)
+            .field("pickled_data", &self.pickled_data)
+            .field("env", &env_names)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
 }
 
 impl MessageBase for ForkRequest {
@@ -41,6 +87,9 @@ impl ForkRequest {
             request_id,
             code,
             request_name,
+            pickled_data: None,
+            env: HashMap::new(),
+            timeout: None,
         }
     }
 }
@@ -67,6 +116,98 @@ impl ExitRequest {
     }
 }
 
+/// Liveness check sent to the loader, which answers with a `Pong` carrying the same ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ping {
+    pub request_id: String,
+}
+
+impl MessageBase for Ping {
+    fn name(&self) -> MessageType {
+        MessageType::Ping
+    }
+}
+
+impl Ping {
+    pub fn new(request_id: String) -> Self {
+        Self { request_id }
+    }
+}
+
+/// The loader's answer to a `Ping`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pong {
+    pub request_id: String,
+}
+
+impl MessageBase for Pong {
+    fn name(&self) -> MessageType {
+        MessageType::Pong
+    }
+}
+
+impl Pong {
+    pub fn new(request_id: String) -> Self {
+        Self { request_id }
+    }
+}
+
+/// Ask the loader to bring the modules it loaded from these files up to date. With
+/// `in_place` set, pure-Python modules are reloaded with `importlib.reload`. Otherwise the
+/// loader only reports which modules are affected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadRequest {
+    pub request_id: String,
+    pub paths: Vec<String>,
+    pub in_place: bool,
+}
+
+impl MessageBase for ReloadRequest {
+    fn name(&self) -> MessageType {
+        MessageType::ReloadRequest
+    }
+}
+
+impl ReloadRequest {
+    pub fn new(request_id: String, paths: Vec<String>, in_place: bool) -> Self {
+        Self {
+            request_id,
+            paths,
+            in_place,
+        }
+    }
+}
+
+/// The loader's answer to a `ReloadRequest`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReloadResponse {
+    pub request_id: String,
+    /// Loaded modules whose source is one of the requested files, sorted by name
+    pub modules: Vec<String>,
+    /// Whether the modules were reloaded in place
+    pub reloaded: bool,
+    /// Why the modules couldn't be reloaded in place, like one of them being a C extension
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl MessageBase for ReloadResponse {
+    fn name(&self) -> MessageType {
+        MessageType::ReloadResponse
+    }
+}
+
+impl ReloadResponse {
+    pub fn new(request_id: String, modules: Vec<String>, reloaded: bool) -> Self {
+        Self {
+            request_id,
+            modules,
+            reloaded,
+            error: None,
+        }
+    }
+}
+
 /// Response to a fork request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ForkResponse {
@@ -92,10 +233,32 @@ impl ForkResponse {
     }
 }
 
+/// Resource usage of a forked process, as reported by `getrusage` from within the child
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// User CPU time in seconds
+    pub user_time: f64,
+    /// System CPU time in seconds
+    pub system_time: f64,
+    /// Peak resident set size in bytes
+    pub max_rss_bytes: u64,
+}
+
 /// Message indicating a child process has completed successfully
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChildComplete {
     pub result: Option<String>,
+    /// The return value encoded as JSON, when it's JSON serializable
+    #[serde(default)]
+    pub result_json: Option<String>,
+    /// A small `bytes` return value, base64 encoded
+    #[serde(default)]
+    pub result_bytes: Option<String>,
+    /// File holding a large `bytes` return value. Whoever reads it deletes it.
+    #[serde(default)]
+    pub result_path: Option<String>,
+    #[serde(default)]
+    pub rusage: Option<ResourceUsage>,
 }
 
 impl MessageBase for ChildComplete {
@@ -106,15 +269,38 @@ impl MessageBase for ChildComplete {
 
 impl ChildComplete {
     pub fn new(result: Option<String>) -> Self {
-        Self { result }
+        Self {
+            result,
+            result_json: None,
+            result_bytes: None,
+            result_path: None,
+            rusage: None,
+        }
     }
 }
 
+/// Why a child process reported an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChildErrorKind {
+    /// The function raised
+    #[default]
+    Exception,
+    /// The function was interrupted by the timeout from its `ForkRequest`
+    Timeout,
+    /// The function didn't handle the `KeyboardInterrupt` used to ask it to finish
+    Interrupted,
+}
+
 /// Message indicating a child process has encountered an error
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChildError {
     pub error: String,
     pub traceback: Option<String>,
+    #[serde(default)]
+    pub rusage: Option<ResourceUsage>,
+    #[serde(default)]
+    pub kind: ChildErrorKind,
 }
 
 impl MessageBase for ChildError {
@@ -125,7 +311,50 @@ impl MessageBase for ChildError {
 
 impl ChildError {
     pub fn new(error: String, traceback: Option<String>) -> Self {
-        Self { error, traceback }
+        Self {
+            error,
+            traceback,
+            rusage: None,
+            kind: ChildErrorKind::Exception,
+        }
+    }
+}
+
+/// Sent by the loader after it reaps a forked process, so its PID is no longer valid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChildExited {
+    pub child_pid: i32,
+    /// Exit code, if the process exited normally
+    pub exit_code: Option<i32>,
+    /// Terminating signal, if the process was killed by one
+    pub signal: Option<i32>,
+}
+
+impl MessageBase for ChildExited {
+    fn name(&self) -> MessageType {
+        MessageType::ChildExited
+    }
+}
+
+impl ChildExited {
+    pub fn new(child_pid: i32, exit_code: Option<i32>, signal: Option<i32>) -> Self {
+        Self {
+            child_pid,
+            exit_code,
+            signal,
+        }
+    }
+}
+
+/// Written by the loader to its stderr after it reaps a fork, tagged with the fork's PID.
+/// Everything the fork wrote to stderr is ahead of it in the pipe, so once we read it we've
+/// seen all of the fork's output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputClosed {}
+
+impl MessageBase for OutputClosed {
+    fn name(&self) -> MessageType {
+        MessageType::OutputClosed
     }
 }
 
@@ -171,6 +400,9 @@ impl UnknownError {
 pub struct ImportError {
     pub error: String,
     pub traceback: Option<String>,
+    /// The module whose import failed, when the failure is tied to one
+    #[serde(default)]
+    pub module: Option<String>,
 }
 
 impl MessageBase for ImportError {
@@ -181,7 +413,11 @@ impl MessageBase for ImportError {
 
 impl ImportError {
     pub fn new(error: String, traceback: Option<String>) -> Self {
-        Self { error, traceback }
+        Self {
+            error,
+            traceback,
+            module: None,
+        }
     }
 }
 
@@ -207,6 +443,73 @@ impl ImportComplete {
     }
 }
 
+/// Progress message sent by the loader after each preloaded module finishes importing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleImported {
+    pub module: String,
+    /// Time spent importing the module, in milliseconds
+    #[serde(default)]
+    pub duration_ms: f64,
+}
+
+impl MessageBase for ModuleImported {
+    fn name(&self) -> MessageType {
+        MessageType::ModuleImported
+    }
+}
+
+impl ModuleImported {
+    pub fn new(module: String, duration_ms: f64) -> Self {
+        Self {
+            module,
+            duration_ms,
+        }
+    }
+}
+
+/// Progress message sent by the loader as each preloaded module starts importing. `index`
+/// counts from zero, so the last module of a boot has `index == total - 1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportProgress {
+    pub module: String,
+    pub index: usize,
+    pub total: usize,
+}
+
+impl MessageBase for ImportProgress {
+    fn name(&self) -> MessageType {
+        MessageType::ImportProgress
+    }
+}
+
+impl ImportProgress {
+    pub fn new(module: String, index: usize, total: usize) -> Self {
+        Self {
+            module,
+            index,
+            total,
+        }
+    }
+}
+
+/// First message sent by the loader, announcing which protocol version it speaks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+}
+
+impl MessageBase for Hello {
+    fn name(&self) -> MessageType {
+        MessageType::Hello
+    }
+}
+
+impl Hello {
+    pub fn new(protocol_version: u32) -> Self {
+        Self { protocol_version }
+    }
+}
+
 /// Enum that can hold any message type
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "name")]
@@ -219,6 +522,10 @@ pub enum Message {
     ChildComplete(ChildComplete),
     #[serde(rename = "CHILD_ERROR")]
     ChildError(ChildError),
+    #[serde(rename = "CHILD_EXITED")]
+    ChildExited(ChildExited),
+    #[serde(rename = "OUTPUT_CLOSED")]
+    OutputClosed(OutputClosed),
     #[serde(rename = "UNKNOWN_COMMAND")]
     UnknownCommand(UnknownCommandError),
     #[serde(rename = "UNKNOWN_ERROR")]
@@ -227,8 +534,22 @@ pub enum Message {
     ImportError(ImportError),
     #[serde(rename = "IMPORT_COMPLETE")]
     ImportComplete(ImportComplete),
+    #[serde(rename = "MODULE_IMPORTED")]
+    ModuleImported(ModuleImported),
+    #[serde(rename = "IMPORT_PROGRESS")]
+    ImportProgress(ImportProgress),
+    #[serde(rename = "HELLO")]
+    Hello(Hello),
+    #[serde(rename = "PING")]
+    Ping(Ping),
+    #[serde(rename = "PONG")]
+    Pong(Pong),
     #[serde(rename = "EXIT_REQUEST")]
     ExitRequest(ExitRequest),
+    #[serde(rename = "RELOAD_REQUEST")]
+    ReloadRequest(ReloadRequest),
+    #[serde(rename = "RELOAD_RESPONSE")]
+    ReloadResponse(ReloadResponse),
 }
 
 impl Message {
@@ -238,11 +559,20 @@ impl Message {
             Message::ForkResponse(_) => MessageType::ForkResponse,
             Message::ChildComplete(_) => MessageType::ChildComplete,
             Message::ChildError(_) => MessageType::ChildError,
+            Message::ChildExited(_) => MessageType::ChildExited,
+            Message::OutputClosed(_) => MessageType::OutputClosed,
             Message::UnknownCommand(_) => MessageType::UnknownCommand,
             Message::UnknownError(_) => MessageType::UnknownError,
             Message::ImportError(_) => MessageType::ImportError,
             Message::ImportComplete(_) => MessageType::ImportComplete,
+            Message::ModuleImported(_) => MessageType::ModuleImported,
+            Message::ImportProgress(_) => MessageType::ImportProgress,
+            Message::Hello(_) => MessageType::Hello,
+            Message::Ping(_) => MessageType::Ping,
+            Message::Pong(_) => MessageType::Pong,
             Message::ExitRequest(_) => MessageType::ExitRequest,
+            Message::ReloadRequest(_) => MessageType::ReloadRequest,
+            Message::ReloadResponse(_) => MessageType::ReloadResponse,
         }
     }
 }
@@ -250,32 +580,252 @@ impl Message {
 /// Helper functions for serialization and deserialization of messages
 pub mod io {
     use super::*;
+    use crate::multiplex_logs::parse_multiplexed_line;
+    use log::warn;
     use serde_json;
-    use std::io::{Read, Write};
+    use std::io::{BufRead, ErrorKind, Read, Write};
+
+    /// Longest frame we keep in memory. A fork that prints gigabytes without a newline would
+    /// otherwise be buffered whole, so anything past this is dropped.
+    pub const DEFAULT_MAX_FRAME_LENGTH: usize = 4 * 1024 * 1024;
+
+    /// Longest protocol message we accept from the loader. Results travel inline, so this is
+    /// also the largest pickled return value a fork can hand back.
+    pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 256 * 1024 * 1024;
+
+    /// How messages are delimited on the wire between Rust and the Python loader
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Framing {
+        /// One JSON document per line. Human readable and interleaves cleanly with logs.
+        /// Newlines inside payloads (tracebacks, pickled data) are escaped by the JSON
+        /// encoding, so they never split a message.
+        #[default]
+        NewlineDelimited,
+        /// 4-byte big-endian length followed by the JSON bytes. Robust to arbitrary
+        /// content, since nothing in the payload is treated as a delimiter.
+        ///
+        /// Printed output from forks is moved to stderr in this mode, so it is not ordered
+        /// relative to their results. Prefer the default when consuming `subscribe_output`.
+        LengthPrefixed,
+    }
+
+    impl Framing {
+        /// Value passed to the Python loader through `FIREHOT_MESSAGE_FRAMING`
+        pub fn as_env_value(&self) -> &'static str {
+            match self {
+                Framing::NewlineDelimited => "ndjson",
+                Framing::LengthPrefixed => "length_prefixed",
+            }
+        }
+    }
+
+    /// Write a single frame with the given payload
+    pub fn write_frame<W: Write>(
+        writer: &mut W,
+        payload: &[u8],
+        framing: Framing,
+    ) -> std::io::Result<()> {
+        match framing {
+            Framing::NewlineDelimited => {
+                writer.write_all(payload)?;
+                writer.write_all(b"\n")?;
+            }
+            Framing::LengthPrefixed => {
+                let length = u32::try_from(payload.len()).map_err(|_| {
+                    std::io::Error::new(ErrorKind::InvalidInput, "Frame exceeds 4GB limit")
+                })?;
+                writer.write_all(&length.to_be_bytes())?;
+                writer.write_all(payload)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Read a single frame. Returns `None` once the stream is exhausted.
+    pub fn read_frame<R: BufRead>(
+        reader: &mut R,
+        framing: Framing,
+    ) -> std::io::Result<Option<String>> {
+        read_frame_with_limit(reader, framing, DEFAULT_MAX_FRAME_LENGTH)
+    }
+
+    /// Read a single frame, keeping at most `max_length` bytes of it. The rest of an oversized
+    /// frame is read and discarded so the next frame still starts in the right place.
+    pub fn read_frame_with_limit<R: BufRead>(
+        reader: &mut R,
+        framing: Framing,
+        max_length: usize,
+    ) -> std::io::Result<Option<String>> {
+        Ok(read_frame_with_length(reader, framing, max_length)?.map(|(frame, _)| frame))
+    }
+
+    /// Same as `read_frame_with_limit`, also returning the length of the frame before it was
+    /// truncated
+    fn read_frame_with_length<R: BufRead>(
+        reader: &mut R,
+        framing: Framing,
+        max_length: usize,
+    ) -> std::io::Result<Option<(String, usize)>> {
+        let (payload, length) = match framing {
+            Framing::NewlineDelimited => match read_line_with_limit(reader, max_length)? {
+                Some(line) => line,
+                None => return Ok(None),
+            },
+            Framing::LengthPrefixed => {
+                let mut length_bytes = [0u8; 4];
+                match reader.read_exact(&mut length_bytes) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+                let length = u32::from_be_bytes(length_bytes) as usize;
+
+                let mut payload = vec![0u8; length.min(max_length)];
+                reader.read_exact(&mut payload)?;
+                let skipped = (length - payload.len()) as u64;
+                let discarded = std::io::copy(&mut reader.take(skipped), &mut std::io::sink())?;
+                if discarded < skipped {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                (payload, length)
+            }
+        };
+
+        if length <= max_length {
+            return String::from_utf8(payload)
+                .map(|frame| Some((frame, length)))
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e));
+        }
+
+        // The cut may land inside a multi-byte character, so decode leniently
+        let truncated = String::from_utf8_lossy(&payload).into_owned();
+        match parse_multiplexed_line(&truncated) {
+            Ok(log_line) => warn!(
+                "Truncated a {} byte line from PID {} to {} bytes",
+                length, log_line.pid, max_length
+            ),
+            Err(_) => warn!(
+                "Truncated a {} byte line from the loader to {} bytes",
+                length, max_length
+            ),
+        }
+        Ok(Some((truncated, length)))
+    }
+
+    /// Read up to the next newline, keeping at most `max_length` bytes. Returns the kept bytes
+    /// without the line ending, along with the full length of the line.
+    fn read_line_with_limit<R: BufRead>(
+        reader: &mut R,
+        max_length: usize,
+    ) -> std::io::Result<Option<(Vec<u8>, usize)>> {
+        let mut line = Vec::new();
+        let mut length = 0;
+        let mut read_any = false;
+
+        loop {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                break;
+            }
+            read_any = true;
+
+            let newline = available.iter().position(|byte| *byte == b'\n');
+            let content = &available[..newline.unwrap_or(available.len())];
+            let keep = content.len().min(max_length - line.len());
+            line.extend_from_slice(&content[..keep]);
+            length += content.len();
+
+            let consumed = newline.map_or(available.len(), |position| position + 1);
+            reader.consume(consumed);
+            if newline.is_some() {
+                break;
+            }
+        }
+
+        if !read_any {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\r') && length == line.len() {
+            line.pop();
+            length -= 1;
+        }
+        Ok(Some((line, length)))
+    }
 
     /// Write a message to the given writer
     pub fn write_message<W: Write, M: Serialize>(
         writer: &mut W,
         message: &M,
+        framing: Framing,
     ) -> std::io::Result<()> {
         let json = serde_json::to_string(message)?;
-        writeln!(writer, "{}", json)?;
-        Ok(())
+        write_frame(writer, json.as_bytes(), framing)
     }
 
     /// Read a message from the given reader
-    pub fn read_message<R: Read>(
+    pub fn read_message<R: BufRead>(
         reader: &mut R,
+        framing: Framing,
     ) -> Result<Option<Message>, Box<dyn std::error::Error>> {
-        let mut buffer = String::new();
-        let bytes_read = reader.read_to_string(&mut buffer)?;
+        match read_frame(reader, framing)? {
+            Some(frame) => Ok(Some(serde_json::from_str(&frame)?)),
+            None => Ok(None),
+        }
+    }
 
-        if bytes_read == 0 {
-            return Ok(None);
+    /// Iterator over the frames of a stream, the framing-aware equivalent of `std::io::Lines`.
+    /// Frames longer than the limit are truncated instead of buffered whole.
+    pub struct FrameReader<R> {
+        reader: R,
+        framing: Framing,
+        max_length: usize,
+        last_length: usize,
+    }
+
+    impl<R: BufRead> FrameReader<R> {
+        pub fn new(reader: R, framing: Framing) -> Self {
+            Self {
+                reader,
+                framing,
+                max_length: DEFAULT_MAX_FRAME_LENGTH,
+                last_length: 0,
+            }
         }
 
-        let message: Message = serde_json::from_str(&buffer)?;
-        Ok(Some(message))
+        /// Keep at most `max_length` bytes of each frame
+        pub fn with_max_length(mut self, max_length: usize) -> Self {
+            self.max_length = max_length;
+            self
+        }
+
+        /// Full length of the frame last returned, if it was cut down to the limit
+        pub fn truncated_length(&self) -> Option<usize> {
+            (self.last_length > self.max_length).then_some(self.last_length)
+        }
+
+        /// Most bytes kept from a single frame
+        pub fn max_length(&self) -> usize {
+            self.max_length
+        }
+    }
+
+    impl<R: BufRead> Iterator for FrameReader<R> {
+        type Item = std::io::Result<String>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            match read_frame_with_length(&mut self.reader, self.framing, self.max_length) {
+                Ok(Some((frame, length))) => {
+                    self.last_length = length;
+                    Some(Ok(frame))
+                }
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            }
+        }
     }
 }
 
@@ -362,6 +912,19 @@ mod tests {
             parsed.err()
         );
 
+        // Test ChildComplete with resource usage
+        let json = r#"{"name": "CHILD_COMPLETE", "result": "success", "rusage": {"user_time": 0.5, "system_time": 0.25, "max_rss_bytes": 1024}}"#;
+        let parsed: Result<Message, _> = serde_json::from_str(json);
+        match parsed {
+            Ok(Message::ChildComplete(complete)) => {
+                let rusage = complete.rusage.expect("Expected resource usage");
+                assert_eq!(rusage.user_time, 0.5);
+                assert_eq!(rusage.system_time, 0.25);
+                assert_eq!(rusage.max_rss_bytes, 1024);
+            }
+            other => panic!("Failed to parse ChildComplete with rusage: {:?}", other),
+        }
+
         // Test ChildError
         let json = r#"{"name": "CHILD_ERROR", "error": "Something went wrong", "traceback": "Traceback (most recent call last):\n  File \"<stdin>\", line 1, in <module>\nSomething went wrong"}"#;
         let parsed: Result<Message, _> = serde_json::from_str(json);
@@ -371,4 +934,90 @@ mod tests {
             parsed.err()
         );
     }
+
+    #[test]
+    fn test_length_prefixed_round_trip_with_newlines() {
+        use super::io::{read_message, write_message, FrameReader, Framing};
+
+        let traceback =
+            "Traceback (most recent call last):\n  File \"<stdin>\", line 1\nValueError: bad\n";
+        let message = Message::ChildError(ChildError::new(
+            "multi\nline\nerror".to_string(),
+            Some(traceback.to_string()),
+        ));
+
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &message, Framing::LengthPrefixed).unwrap();
+        write_message(
+            &mut buffer,
+            &Message::ImportComplete(ImportComplete::new()),
+            Framing::LengthPrefixed,
+        )
+        .unwrap();
+
+        // The frame header is the big-endian length of the JSON payload that follows
+        let payload_length = u32::from_be_bytes(buffer[..4].try_into().unwrap()) as usize;
+        let payload = std::str::from_utf8(&buffer[4..4 + payload_length]).unwrap();
+        assert_eq!(payload, serde_json::to_string(&message).unwrap());
+
+        let mut reader = std::io::Cursor::new(buffer.clone());
+        match read_message(&mut reader, Framing::LengthPrefixed).unwrap() {
+            Some(Message::ChildError(error)) => {
+                assert_eq!(error.error, "multi\nline\nerror");
+                assert_eq!(error.traceback.as_deref(), Some(traceback));
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        assert!(matches!(
+            read_message(&mut reader, Framing::LengthPrefixed).unwrap(),
+            Some(Message::ImportComplete(_))
+        ));
+        assert!(read_message(&mut reader, Framing::LengthPrefixed)
+            .unwrap()
+            .is_none());
+
+        // Raw frames may contain literal newlines, which NDJSON can't carry
+        let mut buffer = Vec::new();
+        super::io::write_frame(&mut buffer, b"first\nsecond", Framing::LengthPrefixed).unwrap();
+        let frames: Vec<String> =
+            FrameReader::new(std::io::Cursor::new(buffer), Framing::LengthPrefixed)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(frames, vec!["first\nsecond".to_string()]);
+    }
+
+    #[test]
+    fn test_oversized_lines_are_truncated() {
+        use super::io::{FrameReader, Framing};
+
+        // Only the kept prefix of the runaway line is ever buffered
+        let runaway = format!("[PID:4242:stdout]{}", "x".repeat(1024 * 1024));
+        let input = format!("{}\r\nshort\r\nlast", runaway);
+        let frames: Vec<String> = FrameReader::new(
+            std::io::BufReader::with_capacity(64, input.as_bytes()),
+            Framing::NewlineDelimited,
+        )
+        .with_max_length(32)
+        .collect::<Result<_, _>>()
+        .unwrap();
+        assert_eq!(
+            frames,
+            vec![
+                runaway[..32].to_string(),
+                "short".to_string(),
+                "last".to_string()
+            ]
+        );
+
+        // Oversized length-prefixed frames are skipped past, so the next frame still parses
+        let mut buffer = Vec::new();
+        super::io::write_frame(&mut buffer, &[b'y'; 100], Framing::LengthPrefixed).unwrap();
+        super::io::write_frame(&mut buffer, b"next", Framing::LengthPrefixed).unwrap();
+        let frames: Vec<String> =
+            FrameReader::new(std::io::Cursor::new(buffer), Framing::LengthPrefixed)
+                .with_max_length(10)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(frames, vec!["y".repeat(10), "next".to_string()]);
+    }
 }
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+/// Counters for an Environment's loader boots and forks, for callers that want to push them
+/// to their own monitoring. Like `ResourceTotals`, these outlive individual layers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Forks the loader reported as started
+    pub forks_started: u64,
+    /// Forks whose function returned a result
+    pub forks_completed: u64,
+    /// Forks whose function raised, or that exited without reporting a result
+    pub forks_errored: u64,
+    /// Loaders that finished importing their modules, including automatic restarts
+    pub boots: u64,
+    /// Time the most recent loader took to import its modules
+    pub last_boot_duration: Option<Duration>,
+    /// Time spent importing modules across every boot. Divide by `boots` for the mean.
+    pub total_boot_duration: Duration,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a loader that finished booting
+    pub fn record_boot(&mut self, duration: Duration) {
+        self.boots += 1;
+        self.last_boot_duration = Some(duration);
+        self.total_boot_duration += duration;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_boot() {
+        let mut metrics = Metrics::new();
+        metrics.record_boot(Duration::from_millis(300));
+        metrics.record_boot(Duration::from_millis(100));
+
+        assert_eq!(metrics.boots, 2);
+        assert_eq!(metrics.last_boot_duration, Some(Duration::from_millis(100)));
+        assert_eq!(metrics.total_boot_duration, Duration::from_millis(400));
+    }
+}
@@ -102,6 +102,178 @@ pub fn parse_multiplexed_line(line: &str) -> Result<MultiplexedLogLine, Multiple
     })
 }
 
+/// Template of the default multiplex prefix, as written by the embedded child scripts
+pub const DEFAULT_MULTIPLEX_FORMAT: &str = "[PID:{pid}:{stream}]";
+
+/// The prefix forks put in front of every line they write, so the loader's output can be
+/// attributed to the process and stream it came from. The template holds `{pid}` and
+/// `{stream}` placeholders, each exactly once, and is used as is with Python's `str.format` by
+/// the child scripts. Loaders that tag lines differently configure a matching template so
+/// their output still parses.
+///
+/// Literal text has to start the template, separate the placeholders and end it, so each
+/// value can be found again, and can't contain braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiplexFormat {
+    template: String,
+}
+
+/// A template split around its placeholders
+struct FormatParts<'a> {
+    leading: &'a str,
+    separator: &'a str,
+    trailing: &'a str,
+    pid_first: bool,
+}
+
+impl Default for MultiplexFormat {
+    fn default() -> Self {
+        Self::new(DEFAULT_MULTIPLEX_FORMAT)
+    }
+}
+
+impl MultiplexFormat {
+    /// Wrap a template. It's checked by `validate`, which the environment runs at boot.
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Whether this is the format the embedded child scripts use unless told otherwise
+    pub fn is_default(&self) -> bool {
+        self.template == DEFAULT_MULTIPLEX_FORMAT
+    }
+
+    /// The prefix for a line written by `pid` to `stream_name`
+    pub fn format_prefix(&self, pid: u32, stream_name: &str) -> String {
+        self.template
+            .replace("{pid}", &pid.to_string())
+            .replace("{stream}", stream_name)
+    }
+
+    /// A whole multiplexed line, the inverse of `parse`
+    pub fn format_line(&self, pid: u32, stream_name: &str, content: &str) -> String {
+        format!("{}{}", self.format_prefix(pid, stream_name), content)
+    }
+
+    /// Parse a line tagged with this format's prefix
+    pub fn parse(&self, line: &str) -> Result<MultiplexedLogLine, MultiplexedLogLineError> {
+        // The default keeps its dedicated parser and the exact errors it reports
+        if self.is_default() {
+            return parse_multiplexed_line(line);
+        }
+
+        let parts = self.parts()?;
+        let expected = || {
+            MultiplexedLogLineError::InvalidFormat(format!(
+                "Expected format {}, got {}",
+                self.template, line
+            ))
+        };
+        let rest = line.strip_prefix(parts.leading).ok_or_else(|| {
+            MultiplexedLogLineError::InvalidFormat(format!(
+                "Line does not start with {}",
+                parts.leading
+            ))
+        })?;
+        let (first, rest) = rest.split_once(parts.separator).ok_or_else(expected)?;
+        let (second, content) = rest.split_once(parts.trailing).ok_or_else(expected)?;
+        let (pid, stream_name) = if parts.pid_first {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        let pid = pid
+            .parse::<u32>()
+            .map_err(MultiplexedLogLineError::PidParseError)?;
+        if stream_name.is_empty() {
+            return Err(MultiplexedLogLineError::MissingComponent(
+                "Stream name is empty".to_string(),
+            ));
+        }
+
+        Ok(MultiplexedLogLine {
+            pid,
+            stream_name: stream_name.to_string(),
+            content: content.to_string(),
+        })
+    }
+
+    /// Check the template is well formed and that `parse` reads back what `format_line` writes
+    pub fn validate(&self) -> Result<(), MultiplexedLogLineError> {
+        self.parts()?;
+
+        let expected = MultiplexedLogLine {
+            pid: 4242,
+            stream_name: "stderr".to_string(),
+            content: "[note] key: value".to_string(),
+        };
+        let line = self.format_line(expected.pid, &expected.stream_name, &expected.content);
+        match self.parse(&line) {
+            Ok(parsed) if parsed == expected => Ok(()),
+            Ok(parsed) => Err(MultiplexedLogLineError::InvalidFormat(format!(
+                "{} does not round-trip: {:?} was parsed back as {:?}",
+                self.template, expected, parsed
+            ))),
+            Err(e) => Err(MultiplexedLogLineError::InvalidFormat(format!(
+                "{} does not round-trip: {}",
+                self.template, e
+            ))),
+        }
+    }
+
+    /// Split the template around its placeholders
+    fn parts(&self) -> Result<FormatParts<'_>, MultiplexedLogLineError> {
+        let invalid = |reason: &str| {
+            MultiplexedLogLineError::InvalidFormat(format!("Template {} {}", self.template, reason))
+        };
+
+        let find_once = |placeholder: &str| {
+            self.template
+                .find(placeholder)
+                .filter(|_| self.template.matches(placeholder).count() == 1)
+                .ok_or_else(|| invalid(&format!("must contain {} exactly once", placeholder)))
+        };
+        let pid = find_once("{pid}")?;
+        let stream = find_once("{stream}")?;
+
+        let pid_first = pid < stream;
+        let (first, first_len, second, second_len) = if pid_first {
+            (pid, "{pid}".len(), stream, "{stream}".len())
+        } else {
+            (stream, "{stream}".len(), pid, "{pid}".len())
+        };
+        let parts = FormatParts {
+            leading: &self.template[..first],
+            separator: &self.template[first + first_len..second],
+            trailing: &self.template[second + second_len..],
+            pid_first,
+        };
+
+        if [parts.leading, parts.separator, parts.trailing]
+            .iter()
+            .any(|literal| literal.is_empty())
+        {
+            return Err(invalid(
+                "needs literal text before, between and after its placeholders",
+            ));
+        }
+        if [parts.leading, parts.separator, parts.trailing]
+            .iter()
+            .any(|literal| literal.contains(['{', '}']))
+        {
+            return Err(invalid("can't contain braces outside its placeholders"));
+        }
+        Ok(parts)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,6 +303,58 @@ mod tests {
         assert_eq!(result.content, "");
     }
 
+    #[test]
+    fn test_custom_format_round_trips() {
+        let format = MultiplexFormat::new("<{stream}|{pid}> ");
+        format.validate().unwrap();
+
+        let line = format.format_line(31337, "stdout", "ready: 3 workers");
+        assert_eq!(line, "<stdout|31337> ready: 3 workers");
+        assert_eq!(
+            format.parse(&line).unwrap(),
+            MultiplexedLogLine {
+                pid: 31337,
+                stream_name: "stdout".to_string(),
+                content: "ready: 3 workers".to_string(),
+            }
+        );
+
+        // Lines from the default format aren't mistaken for this one
+        assert!(format.parse("[PID:31337:stdout]ready").is_err());
+        assert!(matches!(
+            format.parse("<stdout|abc> ready"),
+            Err(MultiplexedLogLineError::PidParseError(_))
+        ));
+
+        // The default format parses exactly like parse_multiplexed_line
+        let default = MultiplexFormat::default();
+        default.validate().unwrap();
+        let line = default.format_line(12345, "stderr", "Error message");
+        assert_eq!(line, "[PID:12345:stderr]Error message");
+        assert_eq!(
+            default.parse(&line).unwrap(),
+            parse_multiplexed_line(&line).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_formats_fail_validation() {
+        for template in [
+            "[{pid}:{stream}",
+            "{pid}:{stream}]",
+            "[{pid}{stream}]",
+            "[{pid}:{pid}]",
+            "[PID:{pid}]",
+            "{{[{pid}:{stream}]",
+        ] {
+            assert!(
+                MultiplexFormat::new(template).validate().is_err(),
+                "{} should be rejected",
+                template
+            );
+        }
+    }
+
     #[test]
     fn test_missing_prefix() {
         let test_line = "Hello, world!";
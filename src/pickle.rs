@@ -0,0 +1,221 @@
+/*
+ * Minimal pickle writer, so calls into already importable modules can be serialized without
+ * spinning up an interpreter. Only the JSON data model is supported, which is all a call
+ * payload needs.
+ */
+
+use base64::Engine;
+use serde_json::Value;
+
+const PROTO: u8 = 0x80;
+const STOP: u8 = b'.';
+const MARK: u8 = b'(';
+const NONE: u8 = b'N';
+const NEWTRUE: u8 = 0x88;
+const NEWFALSE: u8 = 0x89;
+const BININT: u8 = b'J';
+const LONG1: u8 = 0x8a;
+const BINFLOAT: u8 = b'G';
+const BINUNICODE: u8 = b'X';
+const EMPTY_LIST: u8 = b']';
+const APPENDS: u8 = b'e';
+const TUPLE: u8 = b't';
+const EMPTY_DICT: u8 = b'}';
+const SETITEMS: u8 = b'u';
+
+/// Pickle protocol we write. Every Python 3 interpreter can load it.
+const PROTOCOL: u8 = 2;
+
+/// Build the base64 encoded `SerializedCall` payload that the child entrypoint unpickles,
+/// pointing at `func_name` in the importable module `module_path`. `args` are passed to the
+/// function positionally.
+pub fn serialized_call(module_path: &str, func_name: &str, args: &[Value]) -> String {
+    let mut writer = PickleWriter::new();
+    writer.dict(&[
+        ("func_module_path", Item::Str(module_path)),
+        ("func_name", Item::Str(func_name)),
+        ("func_qualname", Item::Str(func_name)),
+        ("args", Item::Tuple(args)),
+    ]);
+    base64::engine::general_purpose::STANDARD.encode(writer.finish())
+}
+
+/// Pickle a single JSON value. Arrays become lists and objects become dicts.
+pub fn dumps(value: &Value) -> Vec<u8> {
+    let mut writer = PickleWriter::new();
+    writer.value(value);
+    writer.finish()
+}
+
+/// Values of the payload dict, which mixes plain strings with a tuple of JSON values
+enum Item<'a> {
+    Str(&'a str),
+    Tuple(&'a [Value]),
+}
+
+struct PickleWriter {
+    bytes: Vec<u8>,
+}
+
+impl PickleWriter {
+    fn new() -> Self {
+        Self {
+            bytes: vec![PROTO, PROTOCOL],
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(STOP);
+        self.bytes
+    }
+
+    fn dict(&mut self, items: &[(&str, Item)]) {
+        self.bytes.extend([EMPTY_DICT, MARK]);
+        for (key, item) in items {
+            self.str(key);
+            match item {
+                Item::Str(value) => self.str(value),
+                Item::Tuple(values) => self.tuple(values),
+            }
+        }
+        self.bytes.push(SETITEMS);
+    }
+
+    fn tuple(&mut self, values: &[Value]) {
+        self.bytes.push(MARK);
+        for value in values {
+            self.value(value);
+        }
+        self.bytes.push(TUPLE);
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.bytes.push(NONE),
+            Value::Bool(true) => self.bytes.push(NEWTRUE),
+            Value::Bool(false) => self.bytes.push(NEWFALSE),
+            Value::Number(number) => {
+                if let Some(int) = number.as_i64() {
+                    self.int(int as i128);
+                } else if let Some(int) = number.as_u64() {
+                    self.int(int as i128);
+                } else {
+                    self.bytes.push(BINFLOAT);
+                    self.bytes
+                        .extend(number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+                }
+            }
+            Value::String(string) => self.str(string),
+            Value::Array(values) => {
+                self.bytes.extend([EMPTY_LIST, MARK]);
+                for value in values {
+                    self.value(value);
+                }
+                self.bytes.push(APPENDS);
+            }
+            Value::Object(map) => {
+                self.bytes.extend([EMPTY_DICT, MARK]);
+                for (key, value) in map {
+                    self.str(key);
+                    self.value(value);
+                }
+                self.bytes.push(SETITEMS);
+            }
+        }
+    }
+
+    fn int(&mut self, int: i128) {
+        if let Ok(small) = i32::try_from(int) {
+            self.bytes.push(BININT);
+            self.bytes.extend(small.to_le_bytes());
+            return;
+        }
+
+        // Little-endian two's complement, trimmed to the shortest form that keeps the sign
+        let mut encoded = int.to_le_bytes().to_vec();
+        while encoded.len() > 1 {
+            let last = encoded[encoded.len() - 1];
+            let sign_bit = encoded[encoded.len() - 2] & 0x80;
+            if (last == 0x00 && sign_bit == 0) || (last == 0xff && sign_bit != 0) {
+                encoded.pop();
+            } else {
+                break;
+            }
+        }
+        self.bytes.push(LONG1);
+        self.bytes.push(encoded.len() as u8);
+        self.bytes.extend(encoded);
+    }
+
+    fn str(&mut self, string: &str) {
+        self.bytes.push(BINUNICODE);
+        self.bytes.extend((string.len() as u32).to_le_bytes());
+        self.bytes.extend(string.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::process::Command;
+
+    /// Unpickle with the interpreter and evaluate `expr` against the loaded `value`
+    fn python_eval(pickled: &[u8], expr: &str) -> Value {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(pickled);
+        let output = Command::new("python")
+            .arg("-c")
+            .arg(
+                "import base64, json, pickle, sys\n\
+                 value = pickle.loads(base64.b64decode(sys.argv[1]))\n\
+                 print(json.dumps(eval(sys.argv[2])))",
+            )
+            .arg(encoded)
+            .arg(expr)
+            .output()
+            .expect("Failed to run python");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_dumps_json_values() {
+        let value = json!({
+            "none": null,
+            "flags": [true, false],
+            "small": -5,
+            "large": 1_u64 << 40,
+            "negative": -(1_i64 << 40),
+            "huge": u64::MAX,
+            "float": 1.5,
+            "text": "héllo",
+        });
+        assert_eq!(python_eval(&dumps(&value), "value"), value);
+    }
+
+    #[test]
+    fn test_serialized_call() {
+        let payload = serialized_call("package.module", "run", &[json!(1), json!("two")]);
+        let pickled = base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .unwrap();
+        assert_eq!(
+            python_eval(&pickled, "value"),
+            json!({
+                "func_module_path": "package.module",
+                "func_name": "run",
+                "func_qualname": "run",
+                "args": [1, "two"],
+            })
+        );
+        // The child entrypoint only unpacks tuples into positional arguments
+        assert_eq!(
+            python_eval(&pickled, "type(value['args']).__name__"),
+            json!("tuple")
+        );
+    }
+}
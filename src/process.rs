@@ -92,6 +92,32 @@ pub fn get_total_thread_count() -> Result<u32, io::Error> {
     }
 }
 
+/// Whether the given process is still running. Zombies (exited but not yet reaped by their
+/// parent) count as stopped, since our forks are children of the loader and not of us.
+///
+/// Zombie detection relies on procfs, so on other platforms an exited but unreaped process
+/// is still reported as running.
+pub fn is_process_running(pid: i32) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        // The state follows the parenthesized command name, which may itself contain spaces
+        if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            if let Some(state) = stat
+                .rfind(')')
+                .and_then(|index| stat[index + 1..].split_whitespace().next())
+            {
+                return !matches!(state, "Z" | "X");
+            }
+        }
+    }
+
+    // Signal 0 performs the existence and permission checks without delivering anything
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -127,4 +153,28 @@ mod tests {
             handle.join().unwrap();
         }
     }
+
+    #[test]
+    fn test_is_process_running() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        let pid = child.id() as i32;
+        assert!(is_process_running(pid));
+
+        child.kill().unwrap();
+
+        // Before we reap it, the process is a zombie, which already counts as stopped on Linux
+        if cfg!(target_os = "linux") {
+            let start = std::time::Instant::now();
+            while is_process_running(pid) && start.elapsed().as_secs() < 5 {
+                thread::sleep(std::time::Duration::from_millis(10));
+            }
+            assert!(!is_process_running(pid));
+        }
+
+        child.wait().unwrap();
+        assert!(!is_process_running(pid));
+    }
 }
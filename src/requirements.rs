@@ -0,0 +1,238 @@
+/*
+ * Dependencies declared in requirements files and pyproject.toml. Some packages are only
+ * used through entry points or plugins and never imported by the project itself, so the
+ * scan can't find them. Preloading their top-level module keeps forks warm regardless.
+ */
+
+use log::debug;
+
+/// Distributions whose top-level module isn't just their normalized name, keyed by the
+/// PEP 503 normalized distribution name
+const IMPORT_NAMES: &[(&str, &str)] = &[
+    ("attrs", "attr"),
+    ("beautifulsoup4", "bs4"),
+    ("faiss-cpu", "faiss"),
+    ("faiss-gpu", "faiss"),
+    ("google-cloud-storage", "google.cloud.storage"),
+    ("grpcio", "grpc"),
+    ("msgpack-python", "msgpack"),
+    ("mysqlclient", "MySQLdb"),
+    ("opencv-contrib-python", "cv2"),
+    ("opencv-python", "cv2"),
+    ("opencv-python-headless", "cv2"),
+    ("pillow", "PIL"),
+    ("protobuf", "google.protobuf"),
+    ("psycopg2-binary", "psycopg2"),
+    ("pycryptodome", "Crypto"),
+    ("pygithub", "github"),
+    ("pyjwt", "jwt"),
+    ("pyserial", "serial"),
+    ("python-dateutil", "dateutil"),
+    ("python-dotenv", "dotenv"),
+    ("python-multipart", "multipart"),
+    ("pyqt5", "PyQt5"),
+    ("pyqt6", "PyQt6"),
+    ("pyyaml", "yaml"),
+    ("pyzmq", "zmq"),
+    ("scikit-image", "skimage"),
+    ("scikit-learn", "sklearn"),
+    ("tensorflow-gpu", "tensorflow"),
+];
+
+/// Distribution names listed in a requirements file. Version specifiers, extras, environment
+/// markers, comments and hashes are stripped. Options like `-r other.txt` or `-e .` and
+/// bare URLs or paths don't name a distribution, so they're skipped.
+pub fn parse_requirements(content: &str) -> Vec<String> {
+    // Backslashes continue a requirement onto the next line
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = match line
+                .find(" #")
+                .or_else(|| line.find('#').filter(|&i| i == 0))
+            {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('-') {
+                return None;
+            }
+
+            let name = requirement_name(line);
+            if name.is_none() {
+                debug!("Skipping requirement without a distribution name: {}", line);
+            }
+            name
+        })
+        .collect()
+}
+
+/// Distribution names of the dependencies declared in a pyproject.toml, from both the
+/// standard `[project]` table and Poetry's `[tool.poetry.dependencies]`
+pub fn pyproject_dependencies(content: &str) -> Vec<String> {
+    let table = match content.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(e) => {
+            debug!("Failed to parse pyproject.toml: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let project_dependencies = table
+        .get("project")
+        .and_then(|project| project.get("dependencies"))
+        .and_then(|dependencies| dependencies.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|dependency| dependency.as_str())
+        .filter_map(requirement_name);
+
+    // Poetry keys dependencies by name, alongside the python version constraint
+    let poetry_dependencies = table
+        .get("tool")
+        .and_then(|tool| tool.get("poetry"))
+        .and_then(|poetry| poetry.get("dependencies"))
+        .and_then(|dependencies| dependencies.as_table())
+        .into_iter()
+        .flat_map(|dependencies| dependencies.keys())
+        .filter(|name| !name.eq_ignore_ascii_case("python"))
+        .cloned();
+
+    project_dependencies.chain(poetry_dependencies).collect()
+}
+
+/// The top-level module a distribution installs. Most distributions are imported by their
+/// lowercased name with dashes turned into underscores, and dotted names like `zope.interface`
+/// are namespace packages. The rest are looked up in a table.
+pub fn import_name(distribution: &str) -> String {
+    let normalized = normalize_distribution(distribution);
+    IMPORT_NAMES
+        .iter()
+        .find(|(name, _)| *name == normalized)
+        .map(|(_, module)| module.to_string())
+        .unwrap_or_else(|| distribution.to_ascii_lowercase().replace('-', "_"))
+}
+
+/// The leading distribution name of a PEP 508 requirement
+fn requirement_name(requirement: &str) -> Option<String> {
+    let requirement = requirement.trim();
+    let end = requirement
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(requirement.len());
+    let name = &requirement[..end];
+
+    // Names start and end with a letter or digit, which also rules out paths like `./pkg`
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_ascii_alphanumeric());
+    // A URL's scheme looks like a name, but isn't followed by a specifier
+    let is_url = requirement[end..].starts_with("://") || requirement[end..].starts_with(':');
+    (valid && !is_url).then(|| name.to_string())
+}
+
+/// PEP 503 normalization: lowercase, with runs of `-`, `_` and `.` collapsed to a dash
+fn normalize_distribution(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.push(c.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requirements() {
+        let content = "\
+# Pinned by pip-compile
+requests==2.31.0
+Django>=4.2,<5  # the web framework
+uvicorn[standard]~=0.23
+PyYAML==6.0.1 ; python_version >= \"3.8\"
+scikit-learn \\
+    --hash=sha256:abc123
+gunicorn
+-r dev-requirements.txt
+-e .
+--index-url https://pypi.example.com/simple
+./vendored/package
+https://example.com/archive.tar.gz
+mypackage @ https://example.com/mypackage.whl
+";
+        let distributions = parse_requirements(content);
+        assert_eq!(
+            distributions,
+            vec![
+                "requests",
+                "Django",
+                "uvicorn",
+                "PyYAML",
+                "scikit-learn",
+                "gunicorn",
+                "mypackage",
+            ]
+        );
+
+        let modules: Vec<String> = distributions.iter().map(|name| import_name(name)).collect();
+        assert_eq!(
+            modules,
+            vec![
+                "requests",
+                "django",
+                "uvicorn",
+                "yaml",
+                "sklearn",
+                "gunicorn",
+                "mypackage",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pyproject_dependencies() {
+        let content = r#"
+[project]
+name = "demo"
+dependencies = ["httpx[http2]>=0.25", "beautifulsoup4"]
+
+[tool.poetry.dependencies]
+python = "^3.10"
+Pillow = "^10.0"
+"#;
+        let distributions = pyproject_dependencies(content);
+        assert_eq!(distributions, vec!["httpx", "beautifulsoup4", "Pillow"]);
+        assert_eq!(
+            distributions
+                .iter()
+                .map(|name| import_name(name))
+                .collect::<Vec<_>>(),
+            vec!["httpx", "bs4", "PIL"]
+        );
+
+        assert!(pyproject_dependencies("not [valid toml").is_empty());
+    }
+
+    #[test]
+    fn test_import_name_normalizes_distributions() {
+        assert_eq!(import_name("typing-extensions"), "typing_extensions");
+        assert_eq!(import_name("Python_DateUtil"), "dateutil");
+        assert_eq!(import_name("ruamel.yaml"), "ruamel.yaml");
+        assert_eq!(import_name("PyQt5"), "PyQt5");
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use crate::messages::ResourceUsage;
+
+/// Resource usage aggregated over every fork of an Environment. These totals outlive
+/// individual layers, so they keep accumulating across `update_environment` rebuilds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceTotals {
+    /// Number of forks that have reported their resource usage
+    pub total_forks: u64,
+    /// Cumulative user + system CPU time across all forks
+    pub cumulative_cpu_time: Duration,
+    /// Largest `ru_maxrss` reported by any single fork, in bytes. This is the high-water mark of
+    /// the biggest fork, not the combined memory of forks that ran at the same time, and it
+    /// includes pages shared with the loader.
+    pub max_fork_rss_bytes: u64,
+}
+
+impl ResourceTotals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold the usage reported by one finished fork into the totals
+    pub fn record(&mut self, usage: &ResourceUsage) {
+        self.total_forks += 1;
+
+        let cpu_seconds = (usage.user_time + usage.system_time).max(0.0);
+        self.cumulative_cpu_time += Duration::from_secs_f64(cpu_seconds);
+
+        self.max_fork_rss_bytes = self.max_fork_rss_bytes.max(usage.max_rss_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates() {
+        let mut totals = ResourceTotals::new();
+
+        totals.record(&ResourceUsage {
+            user_time: 1.0,
+            system_time: 0.5,
+            max_rss_bytes: 2048,
+        });
+        totals.record(&ResourceUsage {
+            user_time: 0.25,
+            system_time: 0.25,
+            max_rss_bytes: 1024,
+        });
+
+        assert_eq!(totals.total_forks, 2);
+        assert_eq!(totals.cumulative_cpu_time, Duration::from_secs(2));
+        assert_eq!(totals.max_fork_rss_bytes, 2048);
+    }
+}
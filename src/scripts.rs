@@ -2,3 +2,4 @@
 pub const PYTHON_LOADER_SCRIPT: &str = include_str!("../firehot/embedded/parent_entrypoint.py");
 pub const PYTHON_CHILD_SCRIPT: &str = include_str!("../firehot/embedded/child_entrypoint.py");
 pub const PYTHON_CALL_SCRIPT: &str = include_str!("../firehot/embedded/call_serializer.py");
+pub const PYTHON_PREFLIGHT_SCRIPT: &str = include_str!("../firehot/embedded/preflight_check.py");
@@ -7,22 +7,29 @@ use log::{debug, info};
 
 use serde_json::{self, json};
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tempfile::TempDir;
 use uuid::Uuid;
 
+use crate::pickle::serialized_call;
+
 use std::env;
 
+/// Name of the file the prepared script is written to, inside the generated package
+const SCRIPT_FILE_NAME: &str = "script.py";
+
 /// Python env guard that restores the original PYTHONPATH when dropped
 pub struct PythonPathGuard {
     pub module_name: String,
     pub container_path: String,
 
-    _temp_dir: TempDir,
+    /// None once the directory has been kept, so dropping the guard leaves it on disk
+    _temp_dir: Option<TempDir>,
 }
 
 impl PythonPathGuard {
-    fn new(module_name: String, temp_dir: TempDir) -> Self {
+    fn new(module_name: String, temp_dir: TempDir, keep_temp: bool) -> Self {
         // Get the path from temp_dir
         let container_path = temp_dir
             .path()
@@ -43,6 +50,14 @@ impl PythonPathGuard {
             env::set_var("PYTHONPATH", &container_path);
         }
 
+        let temp_dir = if keep_temp {
+            let kept_path = temp_dir.into_path();
+            info!("Keeping isolation directory at {}", kept_path.display());
+            None
+        } else {
+            Some(temp_dir)
+        };
+
         Self {
             module_name,
             container_path,
@@ -51,6 +66,22 @@ impl PythonPathGuard {
     }
 }
 
+impl PythonPathGuard {
+    /// Importable path of the prepared script, like `pymodule1234.script`
+    pub fn script_module(&self) -> String {
+        format!(
+            "{}.{}",
+            self.module_name,
+            SCRIPT_FILE_NAME.trim_end_matches(".py")
+        )
+    }
+
+    /// Payload for `exec_isolated` that calls `func_name` in the prepared script
+    pub fn pickled_call(&self, func_name: &str) -> String {
+        serialized_call(&self.script_module(), func_name, &[])
+    }
+}
+
 impl Drop for PythonPathGuard {
     fn drop(&mut self) {
         // Get the current PYTHONPATH
@@ -99,37 +130,58 @@ pub fn prepare_script_for_isolation(
     python_script: &str,
     func_name: &str,
 ) -> Result<(String, PythonPathGuard), String> {
-    // Create a temporary directory for the script
-    let temp_dir =
-        TempDir::new().map_err(|e| format!("Failed to create temporary directory: {}", e))?;
-
-    // Create a valid Python module name (no dashes, start with letter)
-    let module_name = format!("pymodule{}", Uuid::new_v4().to_string().replace("-", ""));
+    prepare_script_for_isolation_with_interpreter(python_script, func_name, Path::new("python"))
+}
 
-    // Create the module directory inside the temp directory
-    let module_dir = temp_dir.path().join(&module_name);
-    fs::create_dir(&module_dir).map_err(|e| format!("Failed to create module directory: {}", e))?;
+/// Same as `prepare_script_for_isolation`, but pickles the payload with the given interpreter.
+/// Use this when the loader runs under a non-default python so both sides agree on the
+/// pickle protocol.
+pub fn prepare_script_for_isolation_with_interpreter(
+    python_script: &str,
+    func_name: &str,
+    interpreter: &Path,
+) -> Result<(String, PythonPathGuard), String> {
+    prepare_script_for_isolation_with_options(
+        python_script,
+        func_name,
+        &IsolationOptions {
+            interpreter: interpreter.to_path_buf(),
+            ..IsolationOptions::default()
+        },
+    )
+}
 
-    // Create __init__.py inside the module directory to make it a proper package
-    let init_path = module_dir.join("__init__.py");
-    fs::write(&init_path, "# Package initialization")
-        .map_err(|e| format!("Failed to write __init__.py file: {}", e))?;
+/// How `prepare_script_for_isolation_with_options` builds the isolated module
+#[derive(Debug, Clone)]
+pub struct IsolationOptions {
+    /// Interpreter that pickles the payload
+    pub interpreter: PathBuf,
+    /// Leave the generated module on disk when the guard is dropped, so a misbehaving
+    /// execution can be inspected. The location is logged and kept in `container_path`.
+    pub keep_temp: bool,
+}
 
-    // Create the script file inside the module directory (using a standard name)
-    let script_file_name = "script.py";
-    let script_path = module_dir.join(script_file_name);
-    fs::write(&script_path, python_script)
-        .map_err(|e| format!("Failed to write script to file: {}", e))?;
+impl Default for IsolationOptions {
+    fn default() -> Self {
+        Self {
+            interpreter: PathBuf::from("python"),
+            keep_temp: false,
+        }
+    }
+}
 
-    // At this point our directory looks like:
-    // pymodule
-    // - __init__.py
-    // - script.py
+/// Same as `prepare_script_for_isolation`, with control over the interpreter and whether the
+/// temporary directory outlives the guard
+pub fn prepare_script_for_isolation_with_options(
+    python_script: &str,
+    func_name: &str,
+    options: &IsolationOptions,
+) -> Result<(String, PythonPathGuard), String> {
+    let python_path_guard = prepare_module_for_isolation_with_options(python_script, options)?;
 
     // Build the payload according to the SerializedCall TypedDict format
-    // The module import path is module_name.script (without the .py extension)
     let isolation_payload = json!({
-        "func_module_path": format!("{}.{}", module_name, script_file_name.trim_end_matches(".py")),
+        "func_module_path": python_path_guard.script_module(),
         "func_name": func_name,
         "func_qualname": func_name,
         "args": serde_json::Value::Null,
@@ -154,22 +206,16 @@ print(pickled_data)
     "#;
 
     // Write the pickle script directly to the temp directory (not in the module)
-    let pickle_script_path = temp_dir.path().join("pickle_helper.py");
+    let pickle_script_path = Path::new(&python_path_guard.container_path).join("pickle_helper.py");
     fs::write(&pickle_script_path, pickle_script)
         .map_err(|e| format!("Failed to write pickle script to temporary file: {}", e))?;
 
     // Serialize the payload to a JSON string
     let json_payload = isolation_payload.to_string();
-
-    // Create a path we can use after transferring ownership of temp_dir
     let pickle_script_path_string = pickle_script_path.to_string_lossy().to_string();
 
-    // Create the PythonPathGuard which takes ownership of temp_dir, updates PYTHONPATH,
-    // and will handle cleanup when dropped
-    let python_path_guard = PythonPathGuard::new(module_name, temp_dir);
-
     // Run the pickle script with the payload as an argument
-    let child = Command::new("python")
+    let child = Command::new(&options.interpreter)
         .arg(&pickle_script_path_string)
         .arg(&json_payload)
         .stdout(Stdio::piped())
@@ -196,15 +242,65 @@ print(pickled_data)
     // Parse the output (base64 encoded pickled data)
     let pickled_output = String::from_utf8_lossy(&output.stdout).trim().to_string();
 
-    // Return both the pickled output and the PythonPathGuard which now owns the temp_dir
+    // Return both the pickled output and the PythonPathGuard which owns the temp_dir
     info!("Successfully prepared script for isolation");
     Ok((pickled_output, python_path_guard))
 }
 
+/// Write a Python script into a temporary package that isolated processes can import, without
+/// targeting any function in it yet. Build a payload for each function with
+/// `PythonPathGuard::pickled_call`, so one module serves many `exec_isolated` calls. The same
+/// PYTHONPATH caveat as `prepare_script_for_isolation` applies.
+pub fn prepare_module_for_isolation(python_script: &str) -> Result<PythonPathGuard, String> {
+    prepare_module_for_isolation_with_options(python_script, &IsolationOptions::default())
+}
+
+/// Same as `prepare_module_for_isolation`, with control over whether the temporary directory
+/// outlives the guard. The interpreter isn't used, since nothing is pickled yet.
+pub fn prepare_module_for_isolation_with_options(
+    python_script: &str,
+    options: &IsolationOptions,
+) -> Result<PythonPathGuard, String> {
+    // Create a temporary directory for the script
+    let temp_dir =
+        TempDir::new().map_err(|e| format!("Failed to create temporary directory: {}", e))?;
+
+    // Create a valid Python module name (no dashes, start with letter)
+    let module_name = format!("pymodule{}", Uuid::new_v4().to_string().replace("-", ""));
+
+    // Create the module directory inside the temp directory
+    let module_dir = temp_dir.path().join(&module_name);
+    fs::create_dir(&module_dir).map_err(|e| format!("Failed to create module directory: {}", e))?;
+
+    // Create __init__.py inside the module directory to make it a proper package
+    let init_path = module_dir.join("__init__.py");
+    fs::write(&init_path, "# Package initialization")
+        .map_err(|e| format!("Failed to write __init__.py file: {}", e))?;
+
+    // Create the script file inside the module directory (using a standard name)
+    let script_path = module_dir.join(SCRIPT_FILE_NAME);
+    fs::write(&script_path, python_script)
+        .map_err(|e| format!("Failed to write script to file: {}", e))?;
+
+    // At this point our directory looks like:
+    // pymodule
+    // - __init__.py
+    // - script.py
+
+    // The guard takes ownership of temp_dir, updates PYTHONPATH, and handles cleanup when
+    // dropped
+    Ok(PythonPathGuard::new(
+        module_name,
+        temp_dir,
+        options.keep_temp,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::environment::Environment;
+    use crate::error::HotReloadError;
     use base64;
     use base64::Engine;
 
@@ -237,7 +333,54 @@ def main():
     }
 
     #[test]
-    fn test_prepare_and_exec_isolation() -> Result<(), String> {
+    fn test_prepare_script_keeps_temp_dir() -> Result<(), String> {
+        let options = IsolationOptions {
+            keep_temp: true,
+            ..IsolationOptions::default()
+        };
+        let (pickled_data, python_env) =
+            prepare_script_for_isolation_with_options("def main():\n    pass\n", "main", &options)?;
+        assert!(!pickled_data.is_empty());
+
+        let container_path = PathBuf::from(&python_env.container_path);
+        let script_path = container_path
+            .join(&python_env.module_name)
+            .join("script.py");
+        drop(python_env);
+
+        // The generated module is still there to inspect
+        assert!(script_path.is_file());
+        fs::remove_dir_all(&container_path).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepared_module_runs_several_functions() -> Result<(), HotReloadError> {
+        let python_script = r#"
+def greet():
+    return "Hello, World!"
+
+def farewell():
+    return "Goodbye, World!"
+        "#;
+
+        let python_env = prepare_module_for_isolation(python_script)?;
+        let mut runner = Environment::new("test_package", &python_env.container_path, None);
+        runner.boot_main()?;
+
+        for (func_name, expected) in [("greet", "Hello, World!"), ("farewell", "Goodbye, World!")] {
+            let process_uuid =
+                runner.exec_isolated(&python_env.pickled_call(func_name), func_name)?;
+            let result = runner.communicate_isolated(&process_uuid)?;
+            assert_eq!(result, Some(expected.to_string()));
+        }
+
+        runner.stop_main()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_and_exec_isolation() -> Result<(), HotReloadError> {
         // Create a sample Python script
         let python_script = r#"
 def greet(name):
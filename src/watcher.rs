@@ -0,0 +1,318 @@
+use log::{debug, error, info, warn};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::environment::{Environment, ImportDelta, ModuleReload};
+
+/// How long the filesystem has to be quiet before we act on a burst of changes
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Passed to the reload callback whenever a watched change rebuilt the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReloadEvent {
+    /// Python files changed during the debounce window that triggered the reload
+    pub changed_paths: Vec<PathBuf>,
+    /// Combined import delta of every change in the window when the loader was rebuilt for
+    /// it, or `None` when the changed modules were reloaded without touching the imports
+    pub delta: Option<ImportDelta>,
+}
+
+enum WatchMessage {
+    Changed(Vec<PathBuf>),
+    Stop,
+}
+
+/// Watches a project for Python file changes and calls `update_environment` once each
+/// burst of changes settles. Watching stops when the handle is stopped or dropped.
+pub struct ProjectWatcher {
+    // Held for its side effect: dropping it unregisters the filesystem watches
+    _watcher: RecommendedWatcher,
+    stop_tx: Sender<WatchMessage>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProjectWatcher {
+    /// Start watching the environment's project directory. `on_reload` runs on the watcher
+    /// thread after a reload, once the environment lock has been released.
+    pub fn start<F>(
+        environment: Arc<Mutex<Environment>>,
+        debounce: Duration,
+        on_reload: F,
+    ) -> Result<Self, String>
+    where
+        F: Fn(&ReloadEvent) + Send + 'static,
+    {
+        let (project_path, excluded_dirs) = {
+            let environment = environment
+                .lock()
+                .map_err(|e| format!("Failed to lock environment mutex: {}", e))?;
+            (
+                PathBuf::from(environment.ast_manager.get_project_path()),
+                environment.ast_manager.excluded_dirs().clone(),
+            )
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let event_tx = tx.clone();
+        let watch_root = project_path.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) => {
+                    let changed: Vec<PathBuf> = event
+                        .paths
+                        .into_iter()
+                        .filter(|path| is_watched_file(path, &watch_root, &excluded_dirs))
+                        .collect();
+                    if !changed.is_empty() {
+                        let _ = event_tx.send(WatchMessage::Changed(changed));
+                    }
+                }
+                Err(e) => warn!("File watcher error: {}", e),
+            })
+            .map_err(|e| format!("Failed to create file watcher: {}", e))?;
+
+        watcher
+            .watch(&project_path, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", project_path.display(), e))?;
+        info!("Watching {} for changes", project_path.display());
+
+        let thread = thread::spawn(move || {
+            let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+            loop {
+                // Block until something changes, then keep extending the window while
+                // events keep arriving so a burst only triggers a single reload
+                let message = if pending.is_empty() {
+                    rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                } else {
+                    rx.recv_timeout(debounce)
+                };
+
+                match message {
+                    Ok(WatchMessage::Changed(paths)) => {
+                        debug!("Watched files changed: {:?}", paths);
+                        pending.extend(paths);
+                    }
+                    Ok(WatchMessage::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Changes made while this reload runs wait in the channel, and open
+                        // the next debounce window once it's done
+                        let changed_paths = std::mem::take(&mut pending).into_iter().collect();
+                        if let Some(event) = reload(&environment, changed_paths) {
+                            on_reload(&event);
+                        }
+                    }
+                }
+            }
+            info!("Stopped watching {}", project_path.display());
+        });
+
+        Ok(Self {
+            _watcher: watcher,
+            stop_tx: tx,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stop watching and wait for any in-progress reload to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.stop_tx.send(WatchMessage::Stop);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("File watcher thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ProjectWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Run a single `update_environment` for every file changed in the debounce window,
+/// returning the event to report when the environment was reloaded. With partial reloads
+/// enabled, edits that leave the imports alone still reload the modules the loader has
+/// imported from the changed files.
+fn reload(
+    environment: &Arc<Mutex<Environment>>,
+    changed_paths: Vec<PathBuf>,
+) -> Option<ReloadEvent> {
+    info!(
+        "Detected changes to {} file(s), checking for import updates",
+        changed_paths.len()
+    );
+    let mut environment = match environment.lock() {
+        Ok(environment) => environment,
+        Err(e) => {
+            error!("Failed to lock environment mutex: {}", e);
+            return None;
+        }
+    };
+    let delta = match environment.update_environment_with_delta() {
+        Ok(Some(delta)) => Some(delta),
+        Ok(None) if environment.config.partial_reload => {
+            match environment.reload_files(&changed_paths) {
+                Ok(ModuleReload::Unchanged) => return None,
+                Ok(_) => None,
+                Err(e) => {
+                    error!("Failed to reload changed modules: {}", e);
+                    return None;
+                }
+            }
+        }
+        Ok(None) => return None,
+        Err(e) => {
+            error!("Failed to update environment: {}", e);
+            return None;
+        }
+    };
+    Some(ReloadEvent {
+        changed_paths,
+        delta,
+    })
+}
+
+/// Only Python sources outside of excluded directories can change the import graph
+fn is_watched_file(path: &Path, project_path: &Path, excluded_dirs: &HashSet<String>) -> bool {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("py") {
+        return false;
+    }
+    let relative = path.strip_prefix(project_path).unwrap_or(path);
+    !relative
+        .components()
+        .any(|component| excluded_dirs.contains(component.as_os_str().to_string_lossy().as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_is_watched_file() {
+        let project = Path::new("/project");
+        let excluded: HashSet<String> = [".venv".to_string()].into_iter().collect();
+
+        assert!(is_watched_file(
+            Path::new("/project/pkg/main.py"),
+            project,
+            &excluded
+        ));
+        assert!(!is_watched_file(
+            Path::new("/project/pkg/main.py.swp"),
+            project,
+            &excluded
+        ));
+        assert!(!is_watched_file(
+            Path::new("/project/.venv/lib/site.py"),
+            project,
+            &excluded
+        ));
+    }
+
+    #[test]
+    fn test_watcher_reloads_once_after_debounce() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().canonicalize().unwrap();
+        fs::write(project_dir.join("main.py"), "def main():\n    pass\n").unwrap();
+
+        let mut environment =
+            Environment::new_for_test("test_package", project_dir.to_str().unwrap(), None);
+        environment
+            .boot_main()
+            .expect("Failed to boot main environment");
+        let environment = Arc::new(Mutex::new(environment));
+
+        let (reload_tx, reload_rx) = mpsc::channel();
+        let watcher = ProjectWatcher::start(
+            Arc::clone(&environment),
+            Duration::from_millis(200),
+            move |event| {
+                let _ = reload_tx.send(event.clone());
+            },
+        )
+        .expect("Failed to start watcher");
+
+        // A burst of writes, like an editor saving through a temp file, settles into one reload
+        let changed = project_dir.join("main.py");
+        for contents in ["import json\n", "import json\n\n", "import json\n\n\n"] {
+            fs::write(&changed, contents).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let event = reload_rx
+            .recv_timeout(Duration::from_secs(20))
+            .expect("Expected a reload after the debounce window");
+        assert_eq!(event.changed_paths, vec![changed]);
+
+        // Nothing else is pending, so no second reload fires
+        assert!(reload_rx.recv_timeout(Duration::from_secs(1)).is_err());
+
+        watcher.stop();
+        environment.lock().unwrap().stop_main().unwrap();
+    }
+
+    #[test]
+    fn test_watcher_batches_many_files_into_one_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let project_dir = temp_dir.path().canonicalize().unwrap();
+        fs::write(project_dir.join("main.py"), "def main():\n    pass\n").unwrap();
+
+        let mut environment =
+            Environment::new_for_test("test_package", project_dir.to_str().unwrap(), None);
+        environment
+            .boot_main()
+            .expect("Failed to boot main environment");
+        let environment = Arc::new(Mutex::new(environment));
+
+        let (reload_tx, reload_rx) = mpsc::channel();
+        let watcher = ProjectWatcher::start(
+            Arc::clone(&environment),
+            Duration::from_millis(200),
+            move |event| {
+                let _ = reload_tx.send(event.clone());
+            },
+        )
+        .expect("Failed to start watcher");
+
+        // Like a checkout rewriting several files at once, each adding its own import
+        let modules = ["json", "csv", "uuid", "shlex", "base64"];
+        let mut written = Vec::new();
+        for (index, module) in modules.iter().enumerate() {
+            let path = project_dir.join(format!("module_{}.py", index));
+            fs::write(&path, format!("import {}\n", module)).unwrap();
+            written.push(path);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let event = reload_rx
+            .recv_timeout(Duration::from_secs(20))
+            .expect("Expected a reload after the debounce window");
+        assert_eq!(event.changed_paths, written);
+        let delta = event
+            .delta
+            .expect("The imports changed, so the loader is rebuilt");
+        assert_eq!(
+            delta.added,
+            modules.iter().map(|module| module.to_string()).collect()
+        );
+        assert!(delta.removed.is_empty());
+
+        // Every file was covered by the one reload
+        assert!(reload_rx.recv_timeout(Duration::from_secs(1)).is_err());
+
+        watcher.stop();
+        environment.lock().unwrap().stop_main().unwrap();
+    }
+}